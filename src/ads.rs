@@ -0,0 +1,93 @@
+use anyhow::Result;
+use serde::Serialize;
+use std::io;
+
+use crate::filter::FileFilter;
+use crate::mft::{self, FileRecord};
+use crate::output::{self, ColumnType, Field, Fields, TableRow};
+use crate::OutputFormat;
+
+/// One named $DATA stream of a file
+#[derive(Debug, Serialize)]
+pub struct StreamEntry {
+    pub path: String,
+    pub stream: String,
+    pub size: u64,
+    /// `path:stream`, the form Windows APIs and most tools open the stream by
+    pub stream_path: String,
+    pub record_number: u64,
+}
+
+impl StreamEntry {
+    /// Fields accepted by --fields, with the types of their CSV columns
+    pub const FIELDS: &'static [Field] = &[
+        ("path", ColumnType::String),
+        ("stream", ColumnType::String),
+        ("size", ColumnType::Size),
+        ("stream_path", ColumnType::String),
+        ("record_number", ColumnType::Int),
+    ];
+}
+
+impl TableRow for StreamEntry {
+    fn table_header() -> Vec<&'static str> {
+        vec!["SIZE", "STREAM", "PATH"]
+    }
+
+    fn table_row(&self) -> Vec<String> {
+        vec![
+            output::format_size(self.size),
+            self.stream.clone(),
+            self.path.clone(),
+        ]
+    }
+
+    fn streaming_widths() -> Vec<usize> {
+        vec![12, 20, 0]
+    }
+}
+
+/// Lists every alternate data stream (named $DATA attribute) of the files
+/// and directories matching `filter`, one entry per stream. `limit` counts
+/// streams, not files.
+pub fn list_streams(
+    volume: &str,
+    filter: &FileFilter,
+    limit: Option<usize>,
+    output: OutputFormat,
+    fields: &Fields,
+) -> Result<()> {
+    let mft = mft::open_mft(volume)?;
+    let mut streams = Vec::new();
+
+    eprintln!("Enumerating streams...");
+    mft::iterate_files(&mft, |file| {
+        if limit.is_some_and(|limit| streams.len() >= limit) {
+            return;
+        }
+        let Some(info) = mft::parse_file_info(&mft, file) else {
+            return;
+        };
+        if !filter.matches(&info, file) {
+            return;
+        }
+        let record = FileRecord::from_file_info(&info, &mft, file);
+
+        for stream in record.alternate_data_streams {
+            streams.push(StreamEntry {
+                stream_path: format!("{}:{}", record.path, stream.name),
+                path: record.path.clone(),
+                stream: stream.name,
+                size: stream.size,
+                record_number: record.record_number,
+            });
+        }
+    });
+    if let Some(limit) = limit {
+        streams.truncate(limit);
+    }
+    eprintln!("Found {} streams", streams.len());
+
+    let stdout = io::stdout();
+    output::write_report(&mut stdout.lock(), &streams, output, fields, StreamEntry::FIELDS)
+}
@@ -0,0 +1,144 @@
+use ntfs_reader::file::NtfsFile;
+use serde::{Deserialize, Serialize};
+
+use crate::output::{self, TableRow};
+use crate::raw;
+
+/// Attribute type codes and their names
+const ATTRIBUTE_TYPES: &[(u32, &str)] = &[
+    (0x10, "$STANDARD_INFORMATION"),
+    (0x20, "$ATTRIBUTE_LIST"),
+    (0x30, "$FILE_NAME"),
+    (0x40, "$OBJECT_ID"),
+    (0x50, "$SECURITY_DESCRIPTOR"),
+    (0x60, "$VOLUME_NAME"),
+    (0x70, "$VOLUME_INFORMATION"),
+    (0x80, "$DATA"),
+    (0x90, "$INDEX_ROOT"),
+    (0xA0, "$INDEX_ALLOCATION"),
+    (0xB0, "$BITMAP"),
+    (0xC0, "$REPARSE_POINT"),
+    (0xD0, "$EA_INFORMATION"),
+    (0xE0, "$EA"),
+    (0x100, "$LOGGED_UTILITY_STREAM"),
+];
+
+/// Attribute header flags and the names they are reported under
+const ATTRIBUTE_FLAGS: &[(&str, u16)] = &[
+    ("compressed", 0x0001),
+    ("encrypted", 0x4000),
+    ("sparse", 0x8000),
+];
+
+/// One attribute of a file record, as listed by `file-info --attributes`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AttributeInfo {
+    pub type_id: u32,
+    pub type_name: String,
+    /// Stream or index name; absent for unnamed attributes
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub name: Option<String>,
+    /// Attribute ID, unique within the record
+    pub id: u16,
+    pub resident: bool,
+    /// Bytes the attribute takes in the record, header included
+    pub record_length: u32,
+    /// Size of the value; for non-resident attributes the logical data size
+    pub size: u64,
+    /// Clusters reserved for a non-resident value, in bytes
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub allocated_size: Option<u64>,
+    /// Bytes of a non-resident value that have been written; the rest reads as zeros
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub initialized_size: Option<u64>,
+    /// Header flags bitmask
+    pub flags: u16,
+    /// Names of the flags set in `flags`: compressed, encrypted, sparse
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    pub flag_names: Vec<String>,
+}
+
+impl TableRow for AttributeInfo {
+    fn table_header() -> Vec<&'static str> {
+        vec!["ID", "TYPE", "NAME", "RESIDENT", "SIZE", "ALLOCATED", "FLAGS"]
+    }
+
+    fn table_row(&self) -> Vec<String> {
+        vec![
+            self.id.to_string(),
+            self.type_name.clone(),
+            self.name.clone().unwrap_or_default(),
+            if self.resident { "yes" } else { "no" }.to_string(),
+            output::format_size(self.size),
+            self.allocated_size.map(output::format_size).unwrap_or_default(),
+            self.flag_names.join(","),
+        ]
+    }
+
+    fn streaming_widths() -> Vec<usize> {
+        vec![4, 22, 12, 8, 12, 12, 0]
+    }
+}
+
+/// Every attribute in the record, in the order it stores them
+pub fn list(file: &NtfsFile) -> Vec<AttributeInfo> {
+    let mut attributes = Vec::new();
+    file.attributes(|attr| {
+        if let Some(info) = parse(attr.data()) {
+            attributes.push(info);
+        }
+    });
+    attributes
+}
+
+/// Name of an attribute type, or its code in hex when it isn't a known one
+pub fn type_name(type_id: u32) -> String {
+    ATTRIBUTE_TYPES
+        .iter()
+        .find(|(id, _)| *id == type_id)
+        .map_or_else(|| format!("0x{:X}", type_id), |(_, name)| name.to_string())
+}
+
+/// Reads an attribute header, or `None` when it is too short for its kind
+fn parse(attr: &[u8]) -> Option<AttributeInfo> {
+    if attr.len() < 0x18 {
+        return None;
+    }
+    let type_id = raw::u32_at(attr, 0)?;
+    let resident = attr[8] == 0;
+    let name_length = attr[9] as usize;
+    let name_offset = raw::u16_at(attr, 0x0A)? as usize;
+    let flags = raw::u16_at(attr, 0x0C)?;
+
+    let name = (name_length > 0)
+        .then(|| attr.get(name_offset..name_offset + name_length * 2))
+        .flatten()
+        .map(raw::utf16_lossy);
+    let (size, allocated_size, initialized_size) = if resident {
+        (raw::u32_at(attr, 0x10)? as u64, None, None)
+    } else {
+        (
+            raw::u64_at(attr, 0x30)?,
+            Some(raw::u64_at(attr, 0x28)?),
+            Some(raw::u64_at(attr, 0x38)?),
+        )
+    };
+
+    Some(AttributeInfo {
+        type_id,
+        type_name: type_name(type_id),
+        name,
+        id: raw::u16_at(attr, 0x0E)?,
+        resident,
+        record_length: raw::u32_at(attr, 4)?,
+        size,
+        allocated_size,
+        initialized_size,
+        flags,
+        flag_names: ATTRIBUTE_FLAGS
+            .iter()
+            .filter(|(_, flag)| flags & flag != 0)
+            .map(|(name, _)| name.to_string())
+            .collect(),
+    })
+}
@@ -0,0 +1,213 @@
+use anyhow::{bail, Context, Result};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::mft::{self, FileRecord};
+use crate::output::{self, Warning};
+use crate::seal::SealOptions;
+use crate::snapshot;
+
+/// What one audit run found, over every volume
+#[derive(Debug, Serialize)]
+pub struct AuditReport {
+    pub started: String,
+    pub finished: String,
+    pub volumes: Vec<VolumeAudit>,
+}
+
+/// Changes on one volume since its previous snapshot
+#[derive(Debug, Serialize)]
+pub struct VolumeAudit {
+    pub volume: String,
+    /// Snapshot taken by this run
+    pub snapshot: Option<String>,
+    /// Snapshot it was compared to; none on the first run for the volume
+    pub previous: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub created: usize,
+    pub deleted: usize,
+    pub modified: usize,
+    pub changes: Vec<SnapshotChange>,
+}
+
+/// A file that differs between two snapshots of a volume
+#[derive(Debug, Serialize)]
+pub struct SnapshotChange {
+    /// created, deleted or modified
+    pub change: &'static str,
+    pub path: String,
+    pub is_directory: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub modified: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub previous_size: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub previous_modified: Option<String>,
+}
+
+/// Snapshots every volume in parallel, once or every `every`, compares
+/// each snapshot with the previous one of its volume in `out_dir`, and
+/// writes one report per run to stdout as a JSON line. A volume that
+/// fails is reported with its error without holding up the others; a
+/// single run then fails once its report is written.
+pub fn run(
+    volumes: &[String],
+    out_dir: &Path,
+    every: Option<Duration>,
+    retain: Option<usize>,
+    seal: &SealOptions,
+) -> Result<()> {
+    fs::create_dir_all(out_dir)
+        .context(format!("Failed to create snapshot directory {}", out_dir.display()))?;
+
+    loop {
+        let started = Instant::now();
+        let report = audit_once(volumes, out_dir, seal);
+        println!("{}", output::to_json(&report)?);
+
+        let failed = report.volumes.iter().filter(|audit| audit.error.is_some()).count();
+        if every.is_none() && failed > 0 {
+            bail!("Audit failed on {} of {} volumes", failed, volumes.len());
+        }
+        if let Some(retain) = retain {
+            // The snapshot just taken is the next run's baseline
+            for volume in volumes {
+                snapshot::prune(volume, out_dir, retain.max(1))?;
+            }
+        }
+
+        let Some(interval) = every else {
+            return Ok(());
+        };
+        let wait = interval.saturating_sub(started.elapsed());
+        eprintln!("Next audit in {}s", wait.as_secs());
+        thread::sleep(wait);
+    }
+}
+
+fn audit_once(volumes: &[String], out_dir: &Path, seal: &SealOptions) -> AuditReport {
+    let started = mft::format_time(time::OffsetDateTime::now_utc());
+    eprintln!("Auditing {} volumes...", volumes.len());
+
+    let volumes: Vec<VolumeAudit> = thread::scope(|scope| {
+        let workers: Vec<_> = volumes
+            .iter()
+            .map(|volume| scope.spawn(move || audit_volume(volume, out_dir, seal)))
+            .collect();
+        workers
+            .into_iter()
+            .map(|worker| worker.join().expect("audit worker panicked"))
+            .collect()
+    });
+
+    AuditReport {
+        started,
+        finished: mft::format_time(time::OffsetDateTime::now_utc()),
+        volumes,
+    }
+}
+
+fn audit_volume(volume: &str, out_dir: &Path, seal: &SealOptions) -> VolumeAudit {
+    let mut audit = VolumeAudit {
+        volume: volume.to_string(),
+        snapshot: None,
+        previous: None,
+        error: None,
+        created: 0,
+        deleted: 0,
+        modified: 0,
+        changes: Vec::new(),
+    };
+    if let Err(e) = compare_with_previous(volume, out_dir, seal, &mut audit) {
+        let message = format!("audit of {} failed: {:#}", volume, e);
+        Warning::new("snapshot_failed", message).emit();
+        audit.error = Some(format!("{:#}", e));
+    }
+    audit
+}
+
+fn compare_with_previous(
+    volume: &str,
+    out_dir: &Path,
+    seal: &SealOptions,
+    audit: &mut VolumeAudit,
+) -> Result<()> {
+    let previous = snapshot::list_snapshots(volume, out_dir)?.pop();
+    let path = snapshot::take_snapshot(volume, out_dir, seal)?;
+    audit.snapshot = Some(path.display().to_string());
+    let Some(previous) = previous else {
+        eprintln!("First snapshot of {}; changes are reported from the next run", volume);
+        return Ok(());
+    };
+    audit.previous = Some(previous.display().to_string());
+
+    let key = seal.encrypt_key.as_ref();
+    let before = snapshot::load_snapshot(&previous, key)?;
+    let after = snapshot::load_snapshot(&path, key)?;
+    audit.changes = diff(before, after);
+    for change in &audit.changes {
+        match change.change {
+            "created" => audit.created += 1,
+            "deleted" => audit.deleted += 1,
+            _ => audit.modified += 1,
+        }
+    }
+    eprintln!(
+        "{}: {} created, {} deleted, {} modified",
+        volume, audit.created, audit.deleted, audit.modified
+    );
+    Ok(())
+}
+
+/// Files created, deleted or modified between two snapshots, matched by
+/// path, in path order. Directories only count as created or deleted,
+/// since every change inside them touches their own timestamps.
+fn diff(before: Vec<FileRecord>, after: Vec<FileRecord>) -> Vec<SnapshotChange> {
+    let mut before: HashMap<String, FileRecord> =
+        before.into_iter().map(|record| (mft::path_key(&record.path), record)).collect();
+
+    let mut changes = Vec::new();
+    for record in after {
+        let old = before.remove(&mft::path_key(&record.path));
+        let change = match &old {
+            None => "created",
+            Some(old)
+                if !record.is_directory
+                    && (old.size != record.size
+                        || old.modified != record.modified
+                        || old.file_attributes != record.file_attributes) =>
+            {
+                "modified"
+            }
+            Some(_) => continue,
+        };
+        changes.push(SnapshotChange {
+            change,
+            path: record.path,
+            is_directory: record.is_directory,
+            size: Some(record.size),
+            modified: record.modified,
+            previous_size: old.as_ref().map(|old| old.size),
+            previous_modified: old.and_then(|old| old.modified),
+        });
+    }
+    changes.extend(before.into_values().map(|old| SnapshotChange {
+        change: "deleted",
+        path: old.path,
+        is_directory: old.is_directory,
+        size: None,
+        modified: None,
+        previous_size: Some(old.size),
+        previous_modified: old.modified,
+    }));
+
+    changes.sort_by(|a, b| a.path.cmp(&b.path));
+    changes
+}
@@ -0,0 +1,139 @@
+use anyhow::{bail, Result};
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+use crate::journal::{
+    self, USN_REASON_CLOSE, USN_REASON_FILE_CREATE, USN_REASON_FILE_DELETE,
+    USN_REASON_RENAME_NEW_NAME, USN_REASON_RENAME_OLD_NAME,
+};
+use crate::mft::{self, FileRecord};
+use crate::output;
+use crate::snapshot;
+
+#[derive(Debug, Serialize)]
+pub struct DriftAlert {
+    /// appeared, changed or disappeared
+    pub alert: &'static str,
+    pub path: String,
+    pub usn: i64,
+    pub timestamp_ms: u128,
+    pub reason: u32,
+    pub reason_str: String,
+    pub in_baseline: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub baseline_size: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub baseline_modified: Option<String>,
+}
+
+/// Tails the journal and reports changes under the protected paths relative
+/// to a snapshot baseline. Alerts are written to stdout as JSON Lines.
+pub fn monitor(
+    volume: &str,
+    baseline: Option<&Path>,
+    snapshot_dir: Option<&Path>,
+    protected: &[String],
+    from_capture: Option<&Path>,
+    encrypt_key: Option<&[u8; 32]>,
+) -> Result<()> {
+    let baseline_path = match (baseline, snapshot_dir) {
+        (Some(path), _) => path.to_path_buf(),
+        (None, Some(dir)) => match snapshot::list_snapshots(volume, dir)?.pop() {
+            Some(latest) => latest,
+            None => bail!("No snapshot of {} found in {}", volume, dir.display()),
+        },
+        (None, None) => bail!("Either --baseline or --snapshot-dir is required"),
+    };
+    
+    eprintln!("Loading baseline: {}", baseline_path.display());
+    let records = snapshot::load_snapshot(&baseline_path, encrypt_key)?;
+    let protected: Vec<String> = protected.iter().map(|p| mft::path_key(p)).collect();
+    
+    let known: HashMap<String, FileRecord> = records
+        .into_iter()
+        .map(|record| (mft::path_key(&record.path), record))
+        .filter(|(key, _)| is_protected(key, &protected))
+        .collect();
+    eprintln!("Baseline holds {} protected entries", known.len());
+    
+    // Reasons accumulate per file until its handle is closed
+    let mut pending: HashMap<String, u32> = HashMap::new();
+    // Paths that appeared since the baseline, so later writes count as changes
+    let mut appeared: HashSet<String> = HashSet::new();
+    
+    journal::follow(volume, from_capture, encrypt_key, |event| {
+        let key = mft::path_key(&event.path);
+        if !is_protected(&key, &protected) {
+            return Ok(true);
+        }
+        
+        // A rename away from a protected path is only visible on the old-name record
+        let reason = if event.reason & USN_REASON_RENAME_OLD_NAME != 0 {
+            event.reason
+        } else {
+            let reason = pending.remove(&event.file_id).unwrap_or(0) | event.reason;
+            if reason & USN_REASON_CLOSE == 0 {
+                pending.insert(event.file_id.clone(), reason);
+                return Ok(true);
+            }
+            reason
+        };
+        
+        let baseline = known.get(&key);
+        let alert = if reason & (USN_REASON_FILE_DELETE | USN_REASON_RENAME_OLD_NAME) != 0 {
+            appeared.remove(&key);
+            "disappeared"
+        } else if reason & (USN_REASON_FILE_CREATE | USN_REASON_RENAME_NEW_NAME) != 0
+            || (baseline.is_none() && !appeared.contains(&key))
+        {
+            appeared.insert(key);
+            "appeared"
+        } else {
+            "changed"
+        };
+        
+        let drift = DriftAlert {
+            alert,
+            path: event.path.clone(),
+            usn: event.usn,
+            timestamp_ms: event.timestamp_ms,
+            reason,
+            reason_str: ntfs_reader::journal::Journal::get_reason_str(reason),
+            in_baseline: baseline.is_some(),
+            baseline_size: baseline.map(|r| r.size),
+            baseline_modified: baseline.and_then(|r| r.modified.clone()),
+        };
+        println!("{}", output::to_json(&drift)?);
+        
+        Ok(true)
+    })
+}
+
+/// Whether a path key (see `mft::path_key`) is one of the protected roots
+/// or lies below one; the key of the volume root, `\`, covers everything
+fn is_protected(key: &str, protected: &[String]) -> bool {
+    protected.iter().any(|root| {
+        key == root
+            || root == "\\"
+            || (key.starts_with(root.as_str()) && key[root.len()..].starts_with('\\'))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn protected_roots_match_however_paths_are_spelled() {
+        let protected: Vec<String> = ["C:\\Windows\\System32\\", "\\\\?\\C:\\Boot"]
+            .iter()
+            .map(|path| mft::path_key(path))
+            .collect();
+        assert!(is_protected(&mft::path_key("\\\\?\\C:\\WINDOWS\\System32\\drivers\\etc\\hosts"), &protected));
+        assert!(is_protected(&mft::path_key("C:\\boot"), &protected));
+        assert!(!is_protected(&mft::path_key("C:\\Windows\\System32x"), &protected));
+        assert!(!is_protected(&mft::path_key("C:\\Windows"), &protected));
+        assert!(is_protected(&mft::path_key("C:\\Users"), &[mft::path_key("C:\\")]));
+    }
+}
@@ -0,0 +1,108 @@
+use anyhow::{Context, Result};
+use ntfs_reader::journal::{HistorySize, Journal, JournalOptions, NextUsn};
+use serde::Serialize;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::journal;
+use crate::output;
+use crate::volume::{self, query_usn_journal};
+
+const MIB: u64 = 1024 * 1024;
+
+/// The recommended size covers this many times the observed rate, for
+/// bursts the sampling window didn't see
+const HEADROOM: u64 = 2;
+
+/// Windows' own defaults, which recommendations never go below
+const MIN_MAXIMUM_SIZE: u64 = 32 * MIB;
+const MIN_ALLOCATION_DELTA: u64 = 4 * MIB;
+
+#[derive(Debug, Serialize)]
+pub struct JournalCapacity {
+    pub window_ms: u128,
+    pub events: u64,
+    /// Journal bytes written during the window, from the advance of NextUsn
+    pub bytes: u64,
+    pub events_per_second: f64,
+    pub bytes_per_second: f64,
+    pub maximum_size: u64,
+    pub allocation_delta: u64,
+    /// Bytes of records currently in the journal, between FirstUsn and NextUsn
+    pub retained_bytes: u64,
+    /// How long the current maximum size lasts at the observed rate
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub coverage_seconds: Option<u64>,
+    /// The gap between runs of the consumer (backup, indexer) the journal must cover
+    pub retention_seconds: u64,
+    pub recommended_maximum_size: u64,
+    pub recommended_allocation_delta: u64,
+    /// Whether the current maximum size already covers the retention
+    pub sufficient: bool,
+    /// Command that applies the recommendation (needs an elevated prompt)
+    pub fsutil_command: String,
+}
+
+/// Counts the journal records written during `window` and recommends a
+/// maximum size that holds `retention` worth of them, with headroom, so a
+/// consumer that reads the journal that often doesn't miss events.
+pub fn report(volume: &str, window: Duration, retention: Duration) -> Result<()> {
+    let volume_path = journal::normalize_volume_path(volume);
+    let start = query_usn_journal(&volume_path)?;
+
+    let options = JournalOptions {
+        reason_mask: 0xFFFFFFFF,
+        next_usn: NextUsn::Next,
+        max_history_size: HistorySize::Limited(1000),
+    };
+    let mut journal = Journal::new(volume::open(&volume_path)?, options)
+        .context("Failed to open USN journal")?;
+
+    eprintln!("Sampling journal activity for {}s...", window.as_secs());
+    let started = Instant::now();
+    let mut events = 0u64;
+    while started.elapsed() < window {
+        let read = journal.read().context("Failed to read journal events")?;
+        if read.is_empty() {
+            thread::sleep(Duration::from_millis(500).min(window.saturating_sub(started.elapsed())));
+        }
+        events += read.len() as u64;
+    }
+    let elapsed = started.elapsed();
+    let end = query_usn_journal(&volume_path)?;
+
+    let seconds = elapsed.as_secs_f64().max(0.001);
+    let bytes = end.next_usn.saturating_sub(start.next_usn).max(0) as u64;
+    let bytes_per_second = bytes as f64 / seconds;
+
+    let needed = (bytes_per_second * retention.as_secs_f64()) as u64 * HEADROOM;
+    let recommended_maximum_size = needed.next_multiple_of(MIB).max(MIN_MAXIMUM_SIZE);
+    // fsutil's guidance: a delta of about an eighth of the maximum size
+    let recommended_allocation_delta =
+        (recommended_maximum_size / 8).next_multiple_of(MIB).max(MIN_ALLOCATION_DELTA);
+
+    let capacity = JournalCapacity {
+        window_ms: elapsed.as_millis(),
+        events,
+        bytes,
+        events_per_second: events as f64 / seconds,
+        bytes_per_second,
+        maximum_size: end.maximum_size,
+        allocation_delta: end.allocation_delta,
+        retained_bytes: end.next_usn.saturating_sub(end.first_usn).max(0) as u64,
+        coverage_seconds: (bytes > 0)
+            .then(|| (end.maximum_size as f64 / bytes_per_second) as u64),
+        retention_seconds: retention.as_secs(),
+        recommended_maximum_size,
+        recommended_allocation_delta,
+        sufficient: end.maximum_size >= needed,
+        fsutil_command: format!(
+            "fsutil usn createjournal m={} a={} {}",
+            recommended_maximum_size,
+            recommended_allocation_delta,
+            volume.trim_end_matches('\\')
+        ),
+    };
+    println!("{}", output::to_json_pretty(&capacity)?);
+    Ok(())
+}
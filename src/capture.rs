@@ -0,0 +1,275 @@
+use anyhow::{bail, Context, Result};
+use ed25519_dalek::SigningKey;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::fs::File;
+use std::io::{self, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+use crate::output::Warning;
+use crate::seal::{self, SealedWriter};
+
+/// Signature at the start of every capture file
+const MAGIC: &[u8; 8] = b"USNCAP\0\0";
+const VERSION: u32 = 1;
+
+/// Upper bound for a single frame, to reject corrupt length prefixes early
+const MAX_FRAME: u32 = 64 * 1024 * 1024;
+
+/// Describes where the events of a capture come from
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CaptureHeader {
+    pub version: u32,
+    pub volume: String,
+    /// Journal instance the events belong to; USNs are only comparable
+    /// within one instance
+    pub journal_id: Option<u64>,
+    pub captured_at: String,
+}
+
+impl CaptureHeader {
+    pub fn new(volume: &str, journal_id: Option<u64>) -> Self {
+        CaptureHeader {
+            version: VERSION,
+            volume: volume.to_string(),
+            journal_id,
+            captured_at: time::OffsetDateTime::now_utc()
+                .format(&time::format_description::well_known::Rfc3339)
+                .unwrap_or_default(),
+        }
+    }
+}
+
+/// A journal event as ntfs-reader decodes it, before any filtering,
+/// coalescing or sampling, so captures can be re-analyzed with different
+/// settings. The path is already resolved and the file IDs are in their
+/// text form; the raw USN_RECORD bytes aren't exposed by ntfs-reader.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CapturedEvent {
+    pub usn: i64,
+    /// Time since the Unix epoch, in nanoseconds
+    pub timestamp_ns: u64,
+    pub file_id: String,
+    pub parent_id: String,
+    pub reason: u32,
+    pub path: String,
+}
+
+impl CapturedEvent {
+    pub fn from_usn_record(record: &ntfs_reader::journal::UsnRecord) -> Self {
+        CapturedEvent {
+            usn: record.usn,
+            timestamp_ns: record.timestamp.as_nanos() as u64,
+            file_id: format!("{:?}", record.file_id),
+            parent_id: format!("{:?}", record.parent_id),
+            reason: record.reason,
+            path: record.path.to_string_lossy().to_string(),
+        }
+    }
+}
+
+/// Writes a capture: the magic, a header frame, then one frame per event.
+/// Each frame is a little-endian u32 length followed by a bincode payload.
+/// With an encryption key the whole stream is encrypted.
+pub struct CaptureWriter {
+    writer: SealedWriter<BufWriter<File>>,
+    path: PathBuf,
+}
+
+impl CaptureWriter {
+    pub fn create(path: &Path, header: &CaptureHeader, encrypt_key: Option<&[u8; 32]>) -> Result<Self> {
+        let file = File::create(path)
+            .context(format!("Failed to create capture file {}", path.display()))?;
+        let mut writer = SealedWriter::new(BufWriter::new(file), encrypt_key)?;
+        writer.write_all(MAGIC)?;
+        write_frame(&mut writer, header)?;
+        Ok(CaptureWriter {
+            writer,
+            path: path.to_path_buf(),
+        })
+    }
+
+    pub fn write(&mut self, event: &CapturedEvent) -> Result<()> {
+        write_frame(&mut self.writer, event)
+    }
+
+    pub fn flush(&mut self) -> Result<()> {
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    /// Completes the capture and signs it when a signing key is given
+    pub fn finish(self, sign_key: Option<&SigningKey>) -> Result<()> {
+        self.writer.finish()?;
+        if let Some(key) = sign_key {
+            let signature = seal::sign_file(&self.path, key)?;
+            eprintln!("Capture signed: {}", signature.display());
+        }
+        Ok(())
+    }
+}
+
+pub struct CaptureReader {
+    reader: Box<dyn Read>,
+    pub header: CaptureHeader,
+}
+
+impl CaptureReader {
+    pub fn open(path: &Path, encrypt_key: Option<&[u8; 32]>) -> Result<Self> {
+        let mut reader = seal::open_reader(path, encrypt_key)?;
+
+        let mut magic = [0u8; 8];
+        reader.read_exact(&mut magic).context("Failed to read capture signature")?;
+        if &magic != MAGIC {
+            bail!("{} is not a journal capture", path.display());
+        }
+        let header: CaptureHeader = read_frame(&mut reader)?
+            .context("Capture file has no header")?;
+        if header.version != VERSION {
+            bail!("Unsupported capture version {} (expected {})", header.version, VERSION);
+        }
+
+        Ok(CaptureReader { reader, header })
+    }
+
+    /// Returns the next event, or `None` at the end of the capture. A
+    /// truncated last frame, as left by an interrupted capture, also ends it;
+    /// an encrypted capture cut short is an error instead (see `seal`).
+    pub fn next_event(&mut self) -> Result<Option<CapturedEvent>> {
+        match read_frame(&mut self.reader) {
+            Ok(event) => Ok(event),
+            Err(e) if is_truncated(&e) => {
+                Warning::new("truncated_capture", "capture ends with a truncated event").emit();
+                Ok(None)
+            }
+            Err(e) => Err(e),
+        }
+    }
+}
+
+fn write_frame<T: Serialize>(w: &mut impl Write, value: &T) -> Result<()> {
+    let payload = bincode::serialize(value)?;
+    w.write_all(&(payload.len() as u32).to_le_bytes())?;
+    w.write_all(&payload)?;
+    Ok(())
+}
+
+/// Reads one frame; `None` if the stream ends cleanly before it
+fn read_frame<T: DeserializeOwned>(r: &mut impl Read) -> Result<Option<T>> {
+    let mut length = [0u8; 4];
+    match r.read(&mut length[..1])? {
+        0 => return Ok(None),
+        _ => r.read_exact(&mut length[1..])?,
+    }
+    let length = u32::from_le_bytes(length);
+    if length > MAX_FRAME {
+        bail!("Corrupt capture frame length: {}", length);
+    }
+
+    let mut payload = vec![0u8; length as usize];
+    r.read_exact(&mut payload)?;
+    Ok(Some(bincode::deserialize(&payload).context("Corrupt capture event")?))
+}
+
+fn is_truncated(e: &anyhow::Error) -> bool {
+    e.downcast_ref::<io::Error>()
+        .is_some_and(|e| e.kind() == io::ErrorKind::UnexpectedEof)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    const KEY: [u8; 32] = [3; 32];
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("ntfs-reader-cli-{}-{}.usncap", std::process::id(), name))
+    }
+
+    fn event(usn: i64) -> CapturedEvent {
+        CapturedEvent {
+            usn,
+            timestamp_ns: 1,
+            file_id: "1".to_string(),
+            parent_id: "5".to_string(),
+            reason: 0x100,
+            path: format!("C:\\file{}.txt", usn),
+        }
+    }
+
+    /// A complete capture of `count` events
+    fn capture(name: &str, count: i64, encrypt_key: Option<&[u8; 32]>) -> PathBuf {
+        let path = temp_path(name);
+        let mut writer = CaptureWriter::create(&path, &CaptureHeader::new("C:", Some(9)), encrypt_key).unwrap();
+        for usn in 0..count {
+            writer.write(&event(usn)).unwrap();
+        }
+        writer.finish(None).unwrap();
+        path
+    }
+
+    fn read_all(path: &Path, encrypt_key: Option<&[u8; 32]>) -> Result<Vec<i64>> {
+        let mut reader = CaptureReader::open(path, encrypt_key)?;
+        let mut usns = Vec::new();
+        while let Some(event) = reader.next_event()? {
+            usns.push(event.usn);
+        }
+        Ok(usns)
+    }
+
+    fn truncate(path: &Path, bytes: usize) {
+        let data = fs::read(path).unwrap();
+        fs::write(path, &data[..data.len() - bytes]).unwrap();
+    }
+
+    #[test]
+    fn captures_read_back() {
+        for (name, key) in [("plain", None), ("encrypted", Some(&KEY))] {
+            let path = capture(name, 3, key);
+            let reader = CaptureReader::open(&path, key).unwrap();
+            assert_eq!(reader.header.journal_id, Some(9));
+            assert_eq!(read_all(&path, key).unwrap(), vec![0, 1, 2]);
+            fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn interrupted_plain_captures_end_at_the_last_whole_event() {
+        let path = capture("interrupted", 3, None);
+        truncate(&path, 5);
+        assert_eq!(read_all(&path, None).unwrap(), vec![0, 1]);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn truncated_encrypted_captures_are_errors() {
+        let path = capture("sealed", 3, Some(&KEY));
+        truncate(&path, 5);
+        assert!(read_all(&path, Some(&KEY)).is_err());
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn damaged_captures_are_refused() {
+        let path = temp_path("damaged");
+        for data in [&b""[..], b"USNCAP", b"NOTACAPT\x01\x00\x00\x00\x00", b"USNCAP\0\0\xFF\xFF\xFF\xFF"] {
+            fs::write(&path, data).unwrap();
+            assert!(CaptureReader::open(&path, None).is_err(), "{:?}", data);
+        }
+
+        // An event frame whose length prefix is garbage
+        let valid = capture("damaged", 1, None);
+        let mut data = fs::read(&valid).unwrap();
+        data.extend_from_slice(&u32::MAX.to_le_bytes());
+        fs::write(&path, &data).unwrap();
+        assert!(read_all(&path, None).is_err());
+
+        // A frame that isn't an event
+        data.truncate(data.len() - 4);
+        data.extend_from_slice(&2u32.to_le_bytes());
+        data.extend_from_slice(&[0xFF, 0xFF]);
+        fs::write(&path, &data).unwrap();
+        assert!(read_all(&path, None).is_err());
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,141 @@
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::journal::{
+    JournalEvent, USN_REASON_CLOSE, USN_REASON_FILE_CREATE, USN_REASON_FILE_DELETE,
+    USN_REASON_RENAME_NEW_NAME, USN_REASON_RENAME_OLD_NAME,
+};
+use crate::output::Fields;
+
+/// Name consumers see as the producer of the change events
+const CONNECTOR: &str = "ntfs-reader-cli";
+
+/// A journal event as a change event in the envelope Debezium uses, so
+/// stream processors built for database CDC can consume file changes
+#[derive(Debug, Serialize)]
+pub struct Envelope {
+    /// The file before the change: its old name for renames, the deleted
+    /// file for deletions, otherwise unknown
+    pub before: Value,
+    /// The file after the change; null for deletions
+    pub after: Value,
+    pub source: Source,
+    /// c (create), u (update) or d (delete)
+    pub op: &'static str,
+    /// When the envelope was produced, in milliseconds since the Unix epoch
+    pub ts_ms: u128,
+    pub transaction: Transaction,
+}
+
+/// Where a change event comes from
+#[derive(Debug, Serialize)]
+pub struct Source {
+    pub connector: &'static str,
+    pub version: &'static str,
+    /// Absent for events read back from an event database
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub volume: Option<String>,
+    pub usn: i64,
+    pub file_id: String,
+    /// When the change happened, from the journal record
+    pub ts_ms: u128,
+    pub seq: u64,
+}
+
+/// Groups the events of one file from its first change to the CLOSE record
+/// that ends it, the nearest thing the journal has to a transaction
+#[derive(Debug, Clone, Serialize)]
+pub struct Transaction {
+    /// File ID and USN of the first event of the group
+    pub id: String,
+    /// Position of the event in its group, starting at 1
+    pub total_order: u64,
+    /// Same as `total_order`: a volume is a single data collection
+    pub data_collection_order: u64,
+}
+
+/// Wraps journal events in change envelopes, pairing the two records of a
+/// rename and tracking the open transaction of each file
+pub struct CdcEncoder {
+    volume: Option<String>,
+    /// Open transaction of each file, by file ID
+    transactions: HashMap<String, Transaction>,
+    /// Row of files whose RENAME_OLD_NAME record was seen, by file ID
+    renaming: HashMap<String, Value>,
+}
+
+impl CdcEncoder {
+    pub fn new(volume: Option<&str>) -> Self {
+        CdcEncoder {
+            volume: volume.map(str::to_string),
+            transactions: HashMap::new(),
+            renaming: HashMap::new(),
+        }
+    }
+
+    /// The change envelope of an event, with rows made of the `fields`
+    /// chosen for output. The old-name half of a rename has none: it
+    /// becomes the `before` of the new-name event that follows.
+    pub fn envelope(&mut self, event: &JournalEvent, fields: &Fields) -> serde_json::Result<Option<Value>> {
+        let row = serde_json::to_value(fields.select(event))?;
+        let transaction = self.transaction(event);
+
+        let (op, before, after) = if event.reason & USN_REASON_FILE_DELETE != 0 {
+            self.renaming.remove(&event.file_id);
+            ("d", row, Value::Null)
+        } else if event.reason & USN_REASON_RENAME_OLD_NAME != 0 {
+            self.renaming.insert(event.file_id.clone(), row);
+            return Ok(None);
+        } else if event.reason & USN_REASON_RENAME_NEW_NAME != 0 {
+            let before = self.renaming.remove(&event.file_id).unwrap_or(Value::Null);
+            ("u", before, row)
+        } else if event.reason & USN_REASON_FILE_CREATE != 0 {
+            ("c", Value::Null, row)
+        } else {
+            ("u", Value::Null, row)
+        };
+
+        let envelope = Envelope {
+            before,
+            after,
+            source: Source {
+                connector: CONNECTOR,
+                version: env!("CARGO_PKG_VERSION"),
+                volume: self.volume.clone(),
+                usn: event.usn,
+                file_id: event.file_id.clone(),
+                ts_ms: event.timestamp_ms,
+                seq: event.seq,
+            },
+            op,
+            ts_ms: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |elapsed| elapsed.as_millis()),
+            transaction,
+        };
+        serde_json::to_value(envelope).map(Some)
+    }
+
+    /// Places an event in its file's open transaction, starting one if
+    /// needed; a CLOSE record ends the transaction
+    fn transaction(&mut self, event: &JournalEvent) -> Transaction {
+        let transaction = self
+            .transactions
+            .entry(event.file_id.clone())
+            .or_insert_with(|| Transaction {
+                id: format!("{}:{}", event.file_id, event.usn),
+                total_order: 0,
+                data_collection_order: 0,
+            });
+        transaction.total_order += 1;
+        transaction.data_collection_order += 1;
+        let transaction = transaction.clone();
+
+        if event.reason & USN_REASON_CLOSE != 0 {
+            self.transactions.remove(&event.file_id);
+        }
+        transaction
+    }
+}
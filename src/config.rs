@@ -0,0 +1,228 @@
+use anyhow::{bail, Context, Result};
+use clap::{Arg, Command};
+use std::ffi::OsString;
+use std::fs;
+use std::path::PathBuf;
+
+/// Location of the config file when `--config` isn't given:
+/// `%APPDATA%\ntfs-reader-cli\config.toml`, used only if it exists
+pub fn default_path() -> Option<PathBuf> {
+    let path = PathBuf::from(std::env::var_os("APPDATA")?)
+        .join("ntfs-reader-cli")
+        .join("config.toml");
+    path.is_file().then_some(path)
+}
+
+/// Inserts the `[defaults.<subcommand>]` options of the config file into the
+/// command line, right after the subcommand name. Options already given on
+/// the command line are left alone, so flags always override the file:
+///
+/// ```toml
+/// [defaults.list-files]
+/// output = "csv"
+/// ext = ["pdf", "docx"]
+/// human-sizes = true
+/// ```
+///
+/// A `--pipeline NAME` on the command line first inserts the options of
+/// `[pipelines.NAME]` the same way, so a named set of filters and sinks can
+/// be reused; they take precedence over the defaults.
+pub fn apply_defaults(command: &Command, mut args: Vec<OsString>) -> Result<Vec<OsString>> {
+    let pipeline = option_value(&args, "pipeline");
+    let Some(path) = config_path(&args).or_else(default_path) else {
+        if let Some(pipeline) = pipeline {
+            bail!("--pipeline {} needs a config file defining [pipelines.{}]", pipeline, pipeline);
+        }
+        return Ok(args);
+    };
+    let text = fs::read_to_string(&path)
+        .context(format!("Failed to read config file {}", path.display()))?;
+    let config: toml::Table = text
+        .parse()
+        .context(format!("Invalid config file {}", path.display()))?;
+
+    let Some(position) = args
+        .iter()
+        .skip(1)
+        .position(|arg| {
+            arg.to_str()
+                .is_some_and(|arg| command.find_subcommand(arg).is_some())
+        })
+        .map(|i| i + 1)
+    else {
+        return Ok(args);
+    };
+    let name = args[position].to_string_lossy().to_string();
+    let Some(subcommand) = command.find_subcommand(&name) else {
+        return Ok(args);
+    };
+
+    // Global options may come before the subcommand as well as after it
+    let mut given: Vec<String> = args[1..]
+        .iter()
+        .map(|arg| arg.to_string_lossy().to_string())
+        .collect();
+    let mut inserted = Vec::new();
+
+    if let Some(pipeline) = pipeline {
+        let options = config
+            .get("pipelines")
+            .and_then(|pipelines| pipelines.get(&pipeline))
+            .context(format!("No [pipelines.{}] in {}", pipeline, path.display()))?;
+        let section = format!("pipelines.{}", pipeline);
+        insert_options(command, subcommand, options, &section, &given, &mut inserted)?;
+        given.extend(inserted.iter().map(|arg| arg.to_string_lossy().to_string()));
+    }
+    if let Some(defaults) = config
+        .get("defaults")
+        .and_then(|defaults| defaults.get(subcommand.get_name()))
+    {
+        let section = format!("defaults.{}", subcommand.get_name());
+        insert_options(command, subcommand, defaults, &section, &given, &mut inserted)?;
+    }
+
+    args.splice(position + 1..position + 1, inserted);
+    Ok(args)
+}
+
+/// Adds the options of a config section that aren't in `given` to `inserted`
+fn insert_options(
+    command: &Command,
+    subcommand: &Command,
+    options: &toml::Value,
+    section: &str,
+    given: &[String],
+    inserted: &mut Vec<OsString>,
+) -> Result<()> {
+    let options = options
+        .as_table()
+        .context(format!("[{}] must be a table", section))?;
+
+    for (key, value) in options {
+        let long = key.replace('_', "-");
+        let arg = subcommand
+            .get_arguments()
+            .chain(command.get_arguments().filter(|arg| arg.is_global_set()))
+            .find(|arg| arg.get_long() == Some(long.as_str()))
+            .context(format!("Unknown option '{}' in [{}]", key, section))?;
+        if is_given(arg, given) {
+            continue;
+        }
+
+        let values = match value {
+            toml::Value::Boolean(true) => {
+                inserted.push(OsString::from(format!("--{}", long)));
+                continue;
+            }
+            toml::Value::Boolean(false) => continue,
+            toml::Value::Array(items) => items.iter().map(scalar).collect::<Result<Vec<_>>>()?,
+            other => vec![scalar(other)?],
+        };
+        for value in values {
+            // The `=` form keeps values that start with '-' from being read as flags
+            inserted.push(OsString::from(format!("--{}={}", long, value)));
+        }
+    }
+    Ok(())
+}
+
+/// The file given with `--config`, looked up before clap parses anything
+fn config_path(args: &[OsString]) -> Option<PathBuf> {
+    option_value(args, "config").map(PathBuf::from)
+}
+
+/// Value of a `--name VALUE` or `--name=VALUE` option among raw arguments
+fn option_value(args: &[OsString], name: &str) -> Option<String> {
+    let flag = format!("--{}", name);
+    let mut args = args.iter().skip(1);
+    while let Some(arg) = args.next() {
+        let arg = arg.to_string_lossy();
+        if arg == flag {
+            return args.next().map(|value| value.to_string_lossy().to_string());
+        }
+        if let Some(value) = arg.strip_prefix(&flag).and_then(|rest| rest.strip_prefix('=')) {
+            return Some(value.to_string());
+        }
+    }
+    None
+}
+
+/// Whether an option appears among the command line arguments
+fn is_given(arg: &Arg, given: &[String]) -> bool {
+    given.iter().any(|token| {
+        let long = arg.get_long().is_some_and(|long| {
+            token.strip_prefix("--").is_some_and(|rest| {
+                rest == long || rest.strip_prefix(long).is_some_and(|v| v.starts_with('='))
+            })
+        });
+        let short = arg.get_short().is_some_and(|short| {
+            !token.starts_with("--")
+                && token.strip_prefix('-').is_some_and(|rest| rest.starts_with(short))
+        });
+        long || short
+    })
+}
+
+fn scalar(value: &toml::Value) -> Result<String> {
+    match value {
+        toml::Value::String(s) => Ok(s.clone()),
+        toml::Value::Integer(n) => Ok(n.to_string()),
+        toml::Value::Float(n) => Ok(n.to_string()),
+        toml::Value::Boolean(b) => Ok(b.to_string()),
+        other => bail!("Unsupported config value: {}", other),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn command() -> Command {
+        Command::new("ntfs-reader-cli")
+            .arg(Arg::new("output").long("output").short('o').global(true))
+            .subcommand(Command::new("list-files").arg(Arg::new("ext").long("ext")))
+    }
+
+    fn apply(config: &str, args: &[&str]) -> Result<Vec<String>> {
+        // Tests run in parallel, so each call gets its own file
+        static CALLS: AtomicUsize = AtomicUsize::new(0);
+        let call = CALLS.fetch_add(1, Ordering::Relaxed);
+        let name = format!("config-test-{}-{}.toml", std::process::id(), call);
+        let path = std::env::temp_dir().join(name);
+        fs::write(&path, config)?;
+        let mut args: Vec<OsString> = args.iter().map(OsString::from).collect();
+        args.extend([OsString::from("--config"), path.clone().into_os_string()]);
+        let result = apply_defaults(&command(), args);
+        fs::remove_file(&path)?;
+        let args = result?;
+        Ok(args[..args.len() - 2].iter().map(|arg| arg.to_string_lossy().to_string()).collect())
+    }
+
+    #[test]
+    fn options_given_anywhere_on_the_command_line_win() {
+        let config = "[defaults.list-files]\noutput = \"csv\"\next = [\"pdf\", \"-docx\"]\n";
+        assert_eq!(
+            apply(config, &["cli", "list-files"]).unwrap(),
+            ["cli", "list-files", "--ext=pdf", "--ext=-docx", "--output=csv"]
+        );
+        assert_eq!(
+            apply(config, &["cli", "--output", "json", "list-files"]).unwrap(),
+            ["cli", "--output", "json", "list-files", "--ext=pdf", "--ext=-docx"]
+        );
+        assert_eq!(
+            apply(config, &["cli", "-ojson", "list-files", "--ext=txt"]).unwrap(),
+            ["cli", "-ojson", "list-files", "--ext=txt"]
+        );
+    }
+
+    #[test]
+    fn malformed_configs_are_rejected() {
+        let args = ["cli", "list-files"];
+        assert!(apply("[defaults.list-files]\nbogus = 1\n", &args).is_err());
+        assert!(apply("[defaults]\nlist-files = 3\n", &args).is_err());
+        assert!(apply("[defaults.list-files]\next = [{ a = 1 }]\n", &args).is_err());
+        assert!(apply("[defaults.list-files\n", &args).is_err());
+        assert!(apply("", &["cli", "--pipeline", "nightly", "list-files"]).is_err());
+    }
+}
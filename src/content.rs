@@ -0,0 +1,225 @@
+use anyhow::Result;
+use ntfs_reader::file::NtfsFile;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::io;
+
+use crate::filter::FileFilter;
+use crate::mft;
+use crate::output::{self, ColumnType, Field, Fields, TableRow, Warning};
+use crate::raw::{self, RawVolume};
+use crate::OutputFormat;
+
+/// Bytes read from the start of each file, one sector on most volumes
+const SAMPLE_LEN: usize = 512;
+
+/// Magic numbers: offset, bytes, type and category. The first match wins,
+/// so more specific signatures come before the ones they share a prefix with.
+const SIGNATURES: &[(usize, &[u8], &str, &str)] = &[
+    (0, b"\x89PNG\r\n\x1a\n", "png", "image"),
+    (0, b"\xff\xd8\xff", "jpeg", "image"),
+    (0, b"GIF87a", "gif", "image"),
+    (0, b"GIF89a", "gif", "image"),
+    (8, b"WEBP", "webp", "image"),
+    (0, b"II*\0", "tiff", "image"),
+    (0, b"MM\0*", "tiff", "image"),
+    (0, b"\0\0\x01\0", "ico", "image"),
+    (0, b"BM", "bmp", "image"),
+    (0, b"PK\x03\x04", "zip", "archive"),
+    (0, b"7z\xbc\xaf\x27\x1c", "7z", "archive"),
+    (0, b"Rar!\x1a\x07", "rar", "archive"),
+    (0, b"\x1f\x8b", "gzip", "archive"),
+    (0, b"BZh", "bzip2", "archive"),
+    (0, b"\xfd7zXZ\0", "xz", "archive"),
+    (0, b"\x28\xb5\x2f\xfd", "zstd", "archive"),
+    (0, b"MSCF", "cab", "archive"),
+    (0, b"MZ", "pe", "executable"),
+    (0, b"\x7fELF", "elf", "executable"),
+    (0, b"\xfe\xed\xfa\xce", "mach-o", "executable"),
+    (0, b"\xfe\xed\xfa\xcf", "mach-o", "executable"),
+    (0, b"\xcf\xfa\xed\xfe", "mach-o", "executable"),
+    (0, b"%PDF", "pdf", "document"),
+    (0, b"\xd0\xcf\x11\xe0\xa1\xb1\x1a\xe1", "ole", "document"),
+    (0, b"SQLite format 3\0", "sqlite", "database"),
+    (4, b"ftyp", "mp4", "media"),
+    (0, b"ID3", "mp3", "media"),
+    (0, b"OggS", "ogg", "media"),
+    (0, b"fLaC", "flac", "media"),
+    (8, b"WAVE", "wav", "media"),
+    (8, b"AVI ", "avi", "media"),
+    (0, b"\x1a\x45\xdf\xa3", "matroska", "media"),
+];
+
+/// Files of one detected type
+#[derive(Debug, Serialize)]
+pub struct ContentType {
+    /// Detected format, e.g. png, pe or text
+    #[serde(rename = "type")]
+    pub kind: String,
+    /// image, archive, executable, document, database, media, text, empty,
+    /// unknown or unreadable
+    pub category: String,
+    pub files: u64,
+    pub size: u64,
+    /// Most common extensions among the files, most frequent first
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub extensions: Vec<String>,
+}
+
+impl ContentType {
+    /// Fields accepted by --fields, with the types of their CSV columns
+    pub const FIELDS: &'static [Field] = &[
+        ("type", ColumnType::String),
+        ("category", ColumnType::String),
+        ("files", ColumnType::Int),
+        ("size", ColumnType::Size),
+        ("extensions", ColumnType::String),
+    ];
+}
+
+impl TableRow for ContentType {
+    fn table_header() -> Vec<&'static str> {
+        vec!["TYPE", "CATEGORY", "FILES", "SIZE", "EXTENSIONS"]
+    }
+
+    fn table_row(&self) -> Vec<String> {
+        vec![
+            self.kind.clone(),
+            self.category.clone(),
+            self.files.to_string(),
+            output::format_size(self.size),
+            self.extensions.join(","),
+        ]
+    }
+
+    fn streaming_widths() -> Vec<usize> {
+        vec![10, 12, 10, 12, 0]
+    }
+}
+
+/// Files of one type counted so far
+#[derive(Default)]
+struct Tally {
+    category: &'static str,
+    files: u64,
+    size: u64,
+    /// Files per lowercase extension
+    extensions: HashMap<String, u64>,
+}
+
+/// Counts the files matching `filter` and their sizes per type, detected
+/// from the first bytes of their data read straight from the volume, so
+/// renamed or extensionless files land where they belong. Compressed and
+/// encrypted files can't be sampled this way and are reported as unreadable.
+pub fn report(volume: &str, filter: &FileFilter, output: OutputFormat, fields: &Fields) -> Result<()> {
+    let mft = mft::open_mft(volume)?;
+    let mut raw = RawVolume::open(&mft::normalize_volume_path(volume))?;
+    let mut types: HashMap<&'static str, Tally> = HashMap::new();
+    let mut failed = 0u64;
+
+    eprintln!("Sampling file content...");
+    mft::iterate_files(&mft, |file| {
+        if file.is_directory() {
+            return;
+        }
+        let Some(info) = mft::parse_file_info(&mft, file) else {
+            return;
+        };
+        if !filter.matches(&info, file) {
+            return;
+        }
+
+        let (kind, category) = match unnamed_data(file).map(|attr| raw.read_head(&attr, SAMPLE_LEN)) {
+            Some(Ok(head)) => classify(&head),
+            Some(Err(_)) => {
+                failed += 1;
+                ("unreadable", "unreadable")
+            }
+            None => ("empty", "empty"),
+        };
+        let tally = types.entry(kind).or_insert_with(|| Tally {
+            category,
+            ..Tally::default()
+        });
+        tally.files += 1;
+        tally.size += info.size;
+        if let Some((_, extension)) = info.name.rsplit_once('.') {
+            *tally.extensions.entry(extension.to_lowercase()).or_default() += 1;
+        }
+    });
+    if failed > 0 {
+        let message = format!("{} compressed or encrypted files couldn't be sampled", failed);
+        Warning::new("content_unreadable", message).emit();
+    }
+
+    let mut rows: Vec<ContentType> = types
+        .into_iter()
+        .map(|(kind, tally)| {
+            let mut extensions: Vec<(String, u64)> = tally.extensions.into_iter().collect();
+            extensions.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+            ContentType {
+                kind: kind.to_string(),
+                category: tally.category.to_string(),
+                files: tally.files,
+                size: tally.size,
+                extensions: extensions.into_iter().take(5).map(|(extension, _)| extension).collect(),
+            }
+        })
+        .collect();
+    rows.sort_by(|a, b| b.size.cmp(&a.size).then_with(|| a.kind.cmp(&b.kind)));
+    eprintln!("{} types", rows.len());
+
+    let stdout = io::stdout();
+    output::write_report(&mut stdout.lock(), &rows, output, fields, ContentType::FIELDS)
+}
+
+/// The unnamed $DATA attribute of a file, or `None` for empty files
+fn unnamed_data(file: &NtfsFile) -> Option<Vec<u8>> {
+    let mut data = None;
+    file.attributes(|attr| {
+        if data.is_none() && attr.header.type_id == raw::ATTR_DATA && attr.header.name_length == 0 {
+            data = Some(attr.data().to_vec());
+        }
+    });
+    data.filter(|attr| {
+        let size = if attr.get(8) == Some(&0) {
+            raw::u32_at(attr, 0x10).map(u64::from)
+        } else {
+            raw::u64_at(attr, 0x30)
+        };
+        size.is_some_and(|size| size > 0)
+    })
+}
+
+/// Type and category of content starting with `head`
+fn classify(head: &[u8]) -> (&'static str, &'static str) {
+    if head.is_empty() {
+        return ("empty", "empty");
+    }
+    for (offset, magic, kind, category) in SIGNATURES {
+        if head.get(*offset..offset + magic.len()) == Some(*magic) {
+            return (*kind, *category);
+        }
+    }
+    if head.iter().all(|&byte| byte == 0) {
+        return ("zeros", "empty");
+    }
+    if head.starts_with(b"\xff\xfe") || head.starts_with(b"\xfe\xff") {
+        return ("utf-16", "text");
+    }
+    if is_text(head) {
+        return ("text", "text");
+    }
+    ("binary", "unknown")
+}
+
+/// No NUL bytes, and UTF-8 apart from a character cut at the end of the sample
+fn is_text(head: &[u8]) -> bool {
+    if head.contains(&0) {
+        return false;
+    }
+    match std::str::from_utf8(head) {
+        Ok(_) => true,
+        Err(e) => e.error_len().is_none(),
+    }
+}
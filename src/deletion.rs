@@ -0,0 +1,215 @@
+use anyhow::{Context, Result};
+use ntfs_reader::journal::{HistorySize, Journal, JournalOptions, NextUsn};
+use std::collections::HashMap;
+use time::OffsetDateTime;
+
+use crate::journal::{self, USN_REASON_FILE_DELETE};
+use crate::mft;
+use crate::output::{self, Warning};
+use crate::raw::ParsedRecord;
+use crate::volume;
+
+/// When a deleted file was deleted, as far as can be told
+#[derive(Clone, Copy, Debug)]
+pub struct DeletionEstimate {
+    pub time: OffsetDateTime,
+    /// high: the journal's record of the deletion; medium: the last change
+    /// of the parent directory, which removing the entry updated; low: the
+    /// file's own last timestamp, before which it can't have been deleted
+    pub confidence: &'static str,
+}
+
+/// Estimates deletion times for deleted-file listings. A free record holds
+/// the last file that used it, so the last FILE_DELETE journal record of
+/// its number is that file's deletion, provided the record's sequence number
+/// shows it hasn't been reused since. Without one (the journal has wrapped
+/// or isn't readable), the parent directory's modification time is the
+/// latest the deletion can have happened, as long as it isn't older than
+/// the file itself and the parent record still holds the same directory.
+///
+/// Sibling files aren't consulted: removing a directory entry updates the
+/// directory's own $STANDARD_INFORMATION, not that of the files next to it,
+/// so their timestamps say nothing about when the entry went away.
+pub struct DeletionClock {
+    /// Time and file reference sequence number of the last FILE_DELETE
+    /// journal record of each record number
+    deletes: HashMap<u64, (OffsetDateTime, Option<u16>)>,
+    /// Sequence number and modification time of the parent directories met
+    /// so far; `None` for records that aren't directories in use
+    parents: HashMap<u64, Option<(u16, Option<OffsetDateTime>)>>,
+}
+
+impl DeletionClock {
+    /// Reads the FILE_DELETE records of the volume's journal. A journal that
+    /// can't be read only costs the high-confidence estimates, with a warning.
+    pub fn load(volume: &str) -> Self {
+        let deletes = match read_deletes(volume) {
+            Ok(deletes) => deletes,
+            Err(e) => {
+                let message = format!("deletion times are estimated without the journal: {:#}", e);
+                Warning::new("journal_unavailable", message).emit();
+                HashMap::new()
+            }
+        };
+        DeletionClock { deletes, parents: HashMap::new() }
+    }
+
+    /// For MFTs read without their volume, which have no journal to go by
+    pub fn without_journal() -> Self {
+        DeletionClock { deletes: HashMap::new(), parents: HashMap::new() }
+    }
+
+    /// The deletion time of record `number`, whose header holds `sequence`
+    /// if it could be read. `last_own` is the latest of the file's own
+    /// timestamps and `parent` the file reference of its parent directory
+    /// from its $FILE_NAME; `read` parses another record, for the parent's
+    /// state.
+    pub fn estimate(
+        &mut self,
+        number: u64,
+        sequence: Option<u16>,
+        last_own: Option<OffsetDateTime>,
+        parent: Option<u64>,
+        mut read: impl FnMut(u64) -> Option<ParsedRecord>,
+    ) -> Option<DeletionEstimate> {
+        if let Some(&(time, deleted)) = self.deletes.get(&number) {
+            let same_file = match (deleted, sequence) {
+                (Some(deleted), Some(current)) => freed_after(deleted, current),
+                (None, _) => false,
+                // Without the record's own sequence number the event can't be checked
+                (Some(_), None) => true,
+            };
+            if same_file {
+                return Some(DeletionEstimate { time, confidence: "high" });
+            }
+        }
+
+        let parent_time = parent.and_then(|reference| {
+            let (number, sequence) = (reference & 0xFFFF_FFFF_FFFF, (reference >> 48) as u16);
+            let state = *self.parents.entry(number).or_insert_with(|| {
+                read(number)
+                    .filter(|parent| parent.in_use && parent.is_directory)
+                    .map(|parent| (parent.sequence, parent.modified.and_then(output::from_filetime)))
+            });
+            // A parent record since reused by another directory would lend
+            // that directory's time
+            state.filter(|&(current, _)| sequence == 0 || current == sequence)?.1
+        });
+        match (parent_time, last_own) {
+            (Some(time), own) if own.is_none_or(|own| time >= own) => {
+                Some(DeletionEstimate { time, confidence: "medium" })
+            }
+            (_, Some(time)) => Some(DeletionEstimate { time, confidence: "low" }),
+            _ => None,
+        }
+    }
+}
+
+/// Whether a record whose header now holds `current` was last used by the
+/// file with sequence number `deleted`. NTFS increments the number when it
+/// frees a record, skipping 0, so a record that wasn't reused since holds
+/// the next one; some versions leave it unchanged until the record is reused.
+fn freed_after(deleted: u16, current: u16) -> bool {
+    let next = match deleted.wrapping_add(1) {
+        0 => 1,
+        next => next,
+    };
+    current == deleted || current == next
+}
+
+fn read_deletes(volume: &str) -> Result<HashMap<u64, (OffsetDateTime, Option<u16>)>> {
+    let volume_path = journal::normalize_volume_path(volume);
+    let options = JournalOptions {
+        reason_mask: USN_REASON_FILE_DELETE,
+        next_usn: NextUsn::First,
+        max_history_size: HistorySize::Limited(1000),
+    };
+    let mut journal = Journal::new(volume::open(&volume_path)?, options)
+        .context("Failed to open USN journal")?;
+
+    eprintln!("Reading deletions from the USN journal...");
+    let mut deletes = HashMap::new();
+    loop {
+        let records = journal.read().context("Failed to read journal events")?;
+        if records.is_empty() {
+            break;
+        }
+        for record in records {
+            let Some((number, sequence)) = mft::usn_record_frn(&record.file_id) else {
+                continue;
+            };
+            if let Ok(time) = OffsetDateTime::from_unix_timestamp_nanos(record.timestamp.as_nanos() as i128) {
+                // Records come in USN order, so the last one wins
+                deletes.insert(number, (time, sequence));
+            }
+        }
+    }
+    eprintln!("Found {} deletions in the journal", deletes.len());
+    Ok(deletes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn clock(deletes: &[(u64, i64, Option<u16>)]) -> DeletionClock {
+        let deletes = deletes
+            .iter()
+            .map(|&(number, time, sequence)| (number, (at(time), sequence)))
+            .collect();
+        DeletionClock { deletes, parents: HashMap::new() }
+    }
+
+    fn at(seconds: i64) -> OffsetDateTime {
+        OffsetDateTime::from_unix_timestamp(seconds).unwrap()
+    }
+
+    /// A directory in use whose $STANDARD_INFORMATION was modified at `seconds`
+    fn directory(sequence: u16, seconds: i64) -> ParsedRecord {
+        ParsedRecord {
+            in_use: true,
+            is_directory: true,
+            sequence,
+            modified: Some((seconds as u64 + 11_644_473_600) * 10_000_000),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn freed_records_hold_the_same_or_next_sequence_number() {
+        assert!(freed_after(5, 6));
+        assert!(freed_after(5, 5));
+        assert!(!freed_after(5, 7));
+        assert!(!freed_after(6, 5));
+        assert!(freed_after(u16::MAX, 1));
+    }
+
+    #[test]
+    fn journal_deletes_of_reused_records_are_ignored() {
+        let mut clock = clock(&[(40, 2000, Some(3))]);
+        let estimate = clock.estimate(40, Some(4), Some(at(1000)), None, |_| None).unwrap();
+        assert_eq!((estimate.time, estimate.confidence), (at(2000), "high"));
+
+        // The record was freed again by a later file the journal no longer has
+        let estimate = clock.estimate(40, Some(9), Some(at(1000)), None, |_| None).unwrap();
+        assert_eq!((estimate.time, estimate.confidence), (at(1000), "low"));
+    }
+
+    #[test]
+    fn reused_parent_records_lend_no_time() {
+        let parent = 5 | 2 << 48;
+        let mut clock = clock(&[]);
+        let estimate = clock.estimate(40, Some(1), Some(at(1000)), Some(parent), |_| Some(directory(2, 1500)));
+        assert_eq!(estimate.map(|e| e.confidence), Some("medium"));
+
+        let mut clock = DeletionClock::without_journal();
+        let estimate = clock.estimate(40, Some(1), Some(at(1000)), Some(parent), |_| Some(directory(3, 1500)));
+        assert_eq!(estimate.map(|e| e.confidence), Some("low"));
+
+        // A parent modified before the file can't date its deletion
+        let mut clock = DeletionClock::without_journal();
+        let estimate = clock.estimate(40, Some(1), Some(at(1000)), Some(parent), |_| Some(directory(2, 500)));
+        assert_eq!(estimate.map(|e| e.confidence), Some("low"));
+        assert!(clock.estimate(41, None, None, None, |_| None).is_none());
+    }
+}
@@ -0,0 +1,267 @@
+use anyhow::{Context, Result};
+use ntfs_reader::mft::Mft;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::io;
+use std::path::PathBuf;
+
+use crate::filter;
+use crate::mft;
+use crate::output::{self, ColumnType, Field, Fields, TableRow};
+use crate::usage;
+use crate::OutputFormat;
+
+/// Record number of the volume's root directory
+pub const ROOT_RECORD: u64 = 5;
+
+/// Deepest level of subdirectories followed, so a corrupt loop of parent
+/// references can't hang the walk
+const MAX_DEPTH: usize = 1024;
+
+/// Totals of one directory and everything below it
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct DirectoryUsage {
+    pub path: String,
+    pub files: u64,
+    pub directories: u64,
+    /// Sum of the file sizes
+    pub size: u64,
+    /// Space the files take on disk, after compression
+    pub allocated_size: u64,
+    pub record_number: u64,
+}
+
+impl DirectoryUsage {
+    /// Fields accepted by --fields, with the types of their CSV columns
+    pub const FIELDS: &'static [Field] = &[
+        ("path", ColumnType::String),
+        ("files", ColumnType::Int),
+        ("directories", ColumnType::Int),
+        ("size", ColumnType::Size),
+        ("allocated_size", ColumnType::Int),
+        ("record_number", ColumnType::Int),
+    ];
+}
+
+impl TableRow for DirectoryUsage {
+    fn table_header() -> Vec<&'static str> {
+        vec!["SIZE", "ON DISK", "FILES", "PATH"]
+    }
+
+    fn table_row(&self) -> Vec<String> {
+        vec![
+            output::format_size(self.size),
+            output::format_size(self.allocated_size),
+            self.files.to_string(),
+            self.path.clone(),
+        ]
+    }
+
+    fn streaming_widths() -> Vec<usize> {
+        vec![12, 12, 10, 0]
+    }
+}
+
+/// A file kept in the hierarchy when its entries are listed
+pub struct FileEntry {
+    pub name: String,
+    pub size: u64,
+    pub record_number: u64,
+}
+
+/// A directory as seen from the MFT: where it hangs and what is directly in it
+#[derive(Default)]
+pub struct Directory {
+    pub parent: u64,
+    pub name: String,
+    pub children: Vec<u64>,
+    /// Files directly inside, when loaded with them
+    pub entries: Vec<FileEntry>,
+    pub files: u64,
+    pub size: u64,
+    pub allocated_size: u64,
+}
+
+/// Recursive totals of a directory
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Totals {
+    pub files: u64,
+    pub directories: u64,
+    pub size: u64,
+    pub allocated_size: u64,
+}
+
+/// The directory tree of a volume, rebuilt from the parent reference in
+/// each record's $FILE_NAME in a single pass over the MFT rather than by
+/// walking directories. Files with several hard links count once, under
+/// their first name; NTFS metadata files aren't part of it.
+pub struct Hierarchy {
+    pub directories: HashMap<u64, Directory>,
+}
+
+impl Hierarchy {
+    /// Reads every directory and adds up the files directly in each. With
+    /// `with_files`, the name and size of each file are kept as well.
+    pub fn load(mft: &Mft, with_files: bool) -> Self {
+        let mut directories: HashMap<u64, Directory> = HashMap::new();
+        directories.insert(ROOT_RECORD, Directory { parent: ROOT_RECORD, ..Default::default() });
+        // Files by parent directory, until every directory is known
+        let mut files: Vec<(u64, FileEntry, u64)> = Vec::new();
+
+        mft::iterate_files(mft, |file| {
+            let Some((parent, name)) = mft::first_link(file) else {
+                return;
+            };
+            if file.is_directory() {
+                let directory = directories.entry(file.number()).or_default();
+                directory.parent = parent;
+                directory.name = name;
+            } else {
+                let (size, allocated_size) = usage::data_sizes(file);
+                let entry = FileEntry { name, size, record_number: file.number() };
+                files.push((parent, entry, allocated_size));
+            }
+        });
+
+        let numbers: Vec<u64> = directories.keys().copied().filter(|&n| n != ROOT_RECORD).collect();
+        for number in numbers {
+            let parent = directories[&number].parent;
+            if let Some(parent) = directories.get_mut(&parent) {
+                parent.children.push(number);
+            }
+        }
+        for (parent, entry, allocated_size) in files {
+            if let Some(directory) = directories.get_mut(&parent) {
+                directory.files += 1;
+                directory.size += entry.size;
+                directory.allocated_size += allocated_size;
+                if with_files {
+                    directory.entries.push(entry);
+                }
+            }
+        }
+
+        Hierarchy { directories }
+    }
+
+    /// Record number of the directory at `path`, found by following names
+    /// down from the root
+    pub fn find(&self, path: &str) -> Option<u64> {
+        let mut number = ROOT_RECORD;
+        for component in filter::components(path) {
+            number = self.directories[&number]
+                .children
+                .iter()
+                .copied()
+                .find(|child| self.directories[child].name.eq_ignore_ascii_case(component))?;
+        }
+        Some(number)
+    }
+
+    /// Record number of the file or directory at `path`. Files are only
+    /// found when the hierarchy was loaded with them.
+    pub fn find_entry(&self, path: &str) -> Option<u64> {
+        if let Some(number) = self.find(path) {
+            return Some(number);
+        }
+        let (parent, name) = path.trim_end_matches(['\\', '/']).rsplit_once(['\\', '/'])?;
+        let parent = if parent.ends_with(':') { ROOT_RECORD } else { self.find(parent)? };
+        self.directories[&parent]
+            .entries
+            .iter()
+            .find(|entry| entry.name.eq_ignore_ascii_case(name))
+            .map(|entry| entry.record_number)
+    }
+
+    /// Record number of `path`, or of the root without one
+    pub fn resolve(&self, path: Option<&str>) -> Result<u64> {
+        match path {
+            Some(path) => self.find(path).context(format!("Directory not found: {}", path)),
+            None => Ok(ROOT_RECORD),
+        }
+    }
+
+    /// Totals of `root` and of every directory below it
+    pub fn totals(&self, root: u64) -> HashMap<u64, Totals> {
+        // Directories in an order where each comes after its parent
+        let mut order = vec![(root, 0)];
+        let mut next = 0;
+        while next < order.len() {
+            let (number, level) = order[next];
+            next += 1;
+            if level < MAX_DEPTH {
+                order.extend(self.directories[&number].children.iter().map(|&child| (child, level + 1)));
+            }
+        }
+
+        let mut totals: HashMap<u64, Totals> = HashMap::with_capacity(order.len());
+        for &(number, _) in order.iter().rev() {
+            let directory = &self.directories[&number];
+            let mut total = Totals {
+                files: directory.files,
+                directories: 0,
+                size: directory.size,
+                allocated_size: directory.allocated_size,
+            };
+            for child in &directory.children {
+                if let Some(child) = totals.get(child) {
+                    total.files += child.files;
+                    total.directories += child.directories + 1;
+                    total.size += child.size;
+                    total.allocated_size += child.allocated_size;
+                }
+            }
+            totals.insert(number, total);
+        }
+        totals
+    }
+}
+
+/// Path of a directory record, as ntfs-reader builds it
+pub fn directory_path(mft: &Mft, number: u64) -> Result<PathBuf> {
+    mft.get_record(number)
+        .and_then(|file| mft::parse_file_info(mft, &file))
+        .map(|info| info.path)
+        .context("Failed to read the directory record")
+}
+
+/// Reports the total size of `path` (the volume root when not given) and of
+/// every directory up to `depth` levels below it, from a `Hierarchy`
+pub fn report(
+    volume: &str,
+    path: Option<&str>,
+    depth: usize,
+    output: OutputFormat,
+    fields: &Fields,
+) -> Result<()> {
+    let mft = mft::open_mft(volume)?;
+    eprintln!("Adding up sizes...");
+    let hierarchy = Hierarchy::load(&mft, false);
+    let scope = hierarchy.resolve(path)?;
+    let totals = hierarchy.totals(scope);
+
+    let mut rows = Vec::new();
+    let mut pending = vec![(scope, output::display_path(&directory_path(&mft, scope)?), 0)];
+    while let Some((number, path, level)) = pending.pop() {
+        let total = totals.get(&number).copied().unwrap_or_default();
+        if level < depth {
+            for &child in &hierarchy.directories[&number].children {
+                let name = &hierarchy.directories[&child].name;
+                pending.push((child, format!("{}\\{}", path.trim_end_matches('\\'), name), level + 1));
+            }
+        }
+        rows.push(DirectoryUsage {
+            path,
+            files: total.files,
+            directories: total.directories,
+            size: total.size,
+            allocated_size: total.allocated_size,
+            record_number: number,
+        });
+    }
+    rows.sort_by_key(|row| row.path.to_lowercase());
+    eprintln!("{} directories", rows.len());
+
+    let stdout = io::stdout();
+    output::write_report(&mut stdout.lock(), &rows, output, fields, DirectoryUsage::FIELDS)
+}
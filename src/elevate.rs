@@ -0,0 +1,283 @@
+use anyhow::Result;
+use std::fs::{self, File};
+use std::io;
+use std::path::PathBuf;
+
+/// Hidden global option telling the elevated run where to take its input
+/// from and write its output to: `<prefix>.in`, `<prefix>.out` and `<prefix>.err`
+pub const IO_PREFIX_FLAG: &str = "--elevated-io";
+
+/// Win32 errors elevating can fix: ERROR_ACCESS_DENIED and ERROR_PRIVILEGE_NOT_HELD
+const ACCESS_DENIED_ERRORS: &[i32] = &[5, 1314];
+
+/// Whether the current process already runs with administrator rights
+pub fn is_elevated() -> bool {
+    imp::is_elevated()
+}
+
+/// Whether a failed command could succeed with administrator rights, rather
+/// than failing the same way elevated. Errors without an OS code are checked
+/// by opening the command's volume, if it has one.
+pub fn needs_elevation(error: &anyhow::Error, volume_path: Option<&str>) -> bool {
+    let denied = |error: &io::Error| {
+        error.kind() == io::ErrorKind::PermissionDenied
+            || error
+                .raw_os_error()
+                .is_some_and(|code| ACCESS_DENIED_ERRORS.contains(&code))
+    };
+    let mut causes = error
+        .chain()
+        .filter_map(|cause| cause.downcast_ref::<io::Error>())
+        .peekable();
+    if causes.peek().is_some() {
+        return causes.any(denied);
+    }
+    volume_path.is_some_and(|path| File::open(path).is_err_and(|error| denied(&error)))
+}
+
+/// A possible rerun of the current command line through a UAC prompt
+/// ("runas"). The elevated process can't inherit our console handles, so
+/// its output goes through temporary files that are copied back once it
+/// exits. Piped input is saved to a file up front and read from there by
+/// both runs, so the rerun gets all of it even if the first run had started
+/// reading; it is only available once the input has ended.
+pub struct Relaunch {
+    prefix: PathBuf,
+}
+
+impl Relaunch {
+    pub fn prepare() -> Result<Self> {
+        let prefix = std::env::temp_dir().join(format!("ntfs-reader-cli-{}", std::process::id()));
+        imp::spool_stdin(&prefix.with_extension("in"))?;
+        Ok(Relaunch { prefix })
+    }
+
+    /// Reruns the command elevated and forwards its output, returning its exit code
+    pub fn run(self) -> Result<i32> {
+        let mut args = vec![IO_PREFIX_FLAG.to_string(), self.prefix.to_string_lossy().to_string()];
+        args.extend(rerun_args(std::env::args().skip(1)));
+        let code = imp::relaunch(&args)?;
+
+        // Missing when the elevated run failed before it could redirect
+        if let Ok(mut file) = File::open(self.prefix.with_extension("out")) {
+            io::copy(&mut file, &mut io::stdout())?;
+        }
+        if let Ok(mut file) = File::open(self.prefix.with_extension("err")) {
+            io::copy(&mut file, &mut io::stderr())?;
+        }
+        Ok(code)
+    }
+}
+
+impl Drop for Relaunch {
+    fn drop(&mut self) {
+        for extension in ["in", "out", "err"] {
+            let _ = fs::remove_file(self.prefix.with_extension(extension));
+        }
+    }
+}
+
+/// Sends the standard streams of the elevated run to the files its
+/// unelevated parent reads back, before anything is printed
+pub fn redirect(prefix: &std::path::Path) -> Result<()> {
+    imp::redirect(prefix)
+}
+
+/// The arguments of the current command line to pass to the elevated run,
+/// without --elevate so it can't prompt again
+fn rerun_args(args: impl Iterator<Item = String>) -> Vec<String> {
+    let mut args: Vec<String> = args.collect();
+    let end = args.iter().position(|arg| arg == "--").unwrap_or(args.len());
+    let mut i = 0;
+    args.retain(|arg| {
+        i += 1;
+        i > end || (arg != "--elevate" && !arg.starts_with("--elevate="))
+    });
+    args
+}
+
+/// Quotes an argument for a Windows command line, following the MSVC
+/// runtime's backslash/quote rules. ShellExecuteEx passes the command line
+/// on as is, so nothing else (like cmd's `%` and `^`) needs escaping.
+#[cfg_attr(not(windows), allow(dead_code))]
+fn quote(arg: &str) -> String {
+    if !arg.is_empty() && !arg.contains([' ', '\t', '"']) {
+        return arg.to_string();
+    }
+
+    let mut quoted = String::from("\"");
+    let mut backslashes = 0;
+    for c in arg.chars() {
+        if c == '\\' {
+            backslashes += 1;
+            continue;
+        }
+        // Backslashes only need escaping when they precede a quote
+        let escaped = if c == '"' { backslashes * 2 + 1 } else { backslashes };
+        quoted.push_str(&"\\".repeat(escaped));
+        quoted.push(c);
+        backslashes = 0;
+    }
+    quoted.push_str(&"\\".repeat(backslashes * 2));
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(windows)]
+mod imp {
+    use super::quote;
+    use anyhow::{bail, Context, Result};
+    use std::ffi::OsStr;
+    use std::fs::File;
+    use std::io::{self, IsTerminal};
+    use std::os::windows::ffi::OsStrExt;
+    use std::os::windows::io::IntoRawHandle;
+    use std::path::Path;
+    use windows_sys::Win32::Foundation::CloseHandle;
+    use windows_sys::Win32::System::Console::{
+        SetStdHandle, STD_ERROR_HANDLE, STD_HANDLE, STD_INPUT_HANDLE, STD_OUTPUT_HANDLE,
+    };
+    use windows_sys::Win32::System::Threading::{
+        GetExitCodeProcess, WaitForSingleObject, INFINITE,
+    };
+    use windows_sys::Win32::UI::Shell::{
+        IsUserAnAdmin, ShellExecuteExW, SEE_MASK_NOCLOSEPROCESS, SEE_MASK_NO_CONSOLE,
+        SHELLEXECUTEINFOW,
+    };
+    use windows_sys::Win32::UI::WindowsAndMessaging::SW_HIDE;
+
+    fn wide(s: &OsStr) -> Vec<u16> {
+        s.encode_wide().chain(std::iter::once(0)).collect()
+    }
+
+    pub fn is_elevated() -> bool {
+        // SAFETY: no arguments, no preconditions
+        unsafe { IsUserAnAdmin() != 0 }
+    }
+
+    /// Makes `file` the process's standard stream `id`. Rust's standard
+    /// streams look their handle up on every access, so this takes effect
+    /// for everything read or printed afterwards.
+    fn set_std_handle(id: STD_HANDLE, file: File) -> Result<()> {
+        // SAFETY: the handle is leaked out of `file`, so it stays open for
+        // the rest of the process
+        if unsafe { SetStdHandle(id, file.into_raw_handle()) } == 0 {
+            bail!("Failed to redirect a standard stream: {}", io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    pub fn spool_stdin(path: &Path) -> Result<()> {
+        if io::stdin().is_terminal() {
+            return Ok(());
+        }
+        let mut file = File::create(path).context("Failed to save the piped input")?;
+        io::copy(&mut io::stdin().lock(), &mut file).context("Failed to save the piped input")?;
+        set_std_handle(STD_INPUT_HANDLE, File::open(path)?)
+    }
+
+    pub fn redirect(prefix: &Path) -> Result<()> {
+        if let Ok(file) = File::open(prefix.with_extension("in")) {
+            set_std_handle(STD_INPUT_HANDLE, file)?;
+        }
+        set_std_handle(STD_OUTPUT_HANDLE, File::create(prefix.with_extension("out"))?)?;
+        set_std_handle(STD_ERROR_HANDLE, File::create(prefix.with_extension("err"))?)
+    }
+
+    pub fn relaunch(args: &[String]) -> Result<i32> {
+        let exe = std::env::current_exe().context("Failed to locate the executable")?;
+        let parameters: Vec<String> = args.iter().map(|arg| quote(arg)).collect();
+
+        eprintln!("Requesting elevation...");
+        let verb = wide(OsStr::new("runas"));
+        let file = wide(exe.as_os_str());
+        let parameters = wide(OsStr::new(&parameters.join(" ")));
+
+        // SAFETY: the wide strings outlive the call, and the process handle
+        // returned through SEE_MASK_NOCLOSEPROCESS is closed below.
+        let exit_code = unsafe {
+            let mut info: SHELLEXECUTEINFOW = std::mem::zeroed();
+            info.cbSize = std::mem::size_of::<SHELLEXECUTEINFOW>() as u32;
+            info.fMask = SEE_MASK_NOCLOSEPROCESS | SEE_MASK_NO_CONSOLE;
+            info.lpVerb = verb.as_ptr();
+            info.lpFile = file.as_ptr();
+            info.lpParameters = parameters.as_ptr();
+            info.nShow = SW_HIDE;
+
+            if ShellExecuteExW(&mut info) == 0 {
+                bail!("Elevation was cancelled or failed: {}", io::Error::last_os_error());
+            }
+            if info.hProcess.is_null() {
+                bail!("Elevated process handle is not available");
+            }
+
+            WaitForSingleObject(info.hProcess, INFINITE);
+            let mut code = 1u32;
+            GetExitCodeProcess(info.hProcess, &mut code);
+            CloseHandle(info.hProcess);
+            code as i32
+        };
+        Ok(exit_code)
+    }
+}
+
+#[cfg(not(windows))]
+mod imp {
+    use anyhow::{bail, Result};
+    use std::path::Path;
+
+    pub fn is_elevated() -> bool {
+        false
+    }
+
+    pub fn spool_stdin(_path: &Path) -> Result<()> {
+        Ok(())
+    }
+
+    pub fn redirect(_prefix: &Path) -> Result<()> {
+        bail!("{} is only supported on Windows", super::IO_PREFIX_FLAG)
+    }
+
+    pub fn relaunch(_args: &[String]) -> Result<i32> {
+        bail!("--elevate is only supported on Windows")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn reruns_drop_only_the_elevate_flag() {
+        let given = args(&["--elevate=true", "list-files", "--elevate", "--volume", "C:", "--", "--elevate"]);
+        assert_eq!(
+            rerun_args(given.into_iter()),
+            args(&["list-files", "--volume", "C:", "--", "--elevate"])
+        );
+        assert_eq!(rerun_args(args(&["--elevated"]).into_iter()), args(&["--elevated"]));
+    }
+
+    #[test]
+    fn arguments_are_quoted_for_the_msvc_runtime() {
+        assert_eq!(quote("C:"), "C:");
+        assert_eq!(quote("100%"), "100%");
+        assert_eq!(quote("a&b"), "a&b");
+        assert_eq!(quote(""), "\"\"");
+        assert_eq!(quote("My Files\\"), "\"My Files\\\\\"");
+        assert_eq!(quote("say \"hi\""), "\"say \\\"hi\\\"\"");
+        assert_eq!(quote("a\\\\b c"), "\"a\\\\b c\"");
+    }
+
+    #[test]
+    fn only_access_errors_ask_for_elevation() {
+        let denied = anyhow::Error::new(io::Error::from_raw_os_error(5)).context("Failed to open volume");
+        assert!(needs_elevation(&denied, None));
+        let missing = anyhow::Error::new(io::Error::new(io::ErrorKind::NotFound, "no such volume"));
+        assert!(!needs_elevation(&missing, None));
+        assert!(!needs_elevation(&anyhow::anyhow!("Invalid filter"), None));
+    }
+}
@@ -0,0 +1,493 @@
+use anyhow::{bail, Context, Result};
+use flate2::read::ZlibDecoder;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use crate::raw;
+
+/// Signature of EWF version 1 segment files (.E01)
+const SIGNATURE: &[u8] = b"EVF\x09\x0d\x0a\xff\x00";
+
+/// Signature of EWF version 2 segment files (.Ex01)
+const SIGNATURE_V2: &[u8] = b"EVF2\x0d\x0a\x81\x00";
+
+const FILE_HEADER_SIZE: u64 = 13;
+const SECTION_DESCRIPTOR_SIZE: u64 = 76;
+
+/// Size of a table section's header, before its entries
+const TABLE_HEADER_SIZE: u64 = 24;
+
+/// Set in a table entry when its chunk is zlib-compressed
+const CHUNK_COMPRESSED: u32 = 0x8000_0000;
+
+/// Largest chunk size accepted from a volume section; EnCase writes 32 KiB
+/// chunks and lets it be raised to a few MiB at most
+const MAX_CHUNK_SIZE: u64 = 64 * 1024 * 1024;
+
+/// Where one chunk of the media is stored
+struct Chunk {
+    segment: usize,
+    offset: u64,
+    /// Stored size, including the checksum that follows uncompressed chunks
+    size: u64,
+    compressed: bool,
+}
+
+/// The media held by an EnCase image (EWF version 1, .E01), split across
+/// the segment files .E01, .E02 and so on, read as one seekable stream.
+/// Chunks are decompressed as they are read; the last one is kept, since
+/// reads are mostly much smaller than a chunk.
+pub struct EwfReader {
+    segments: Vec<File>,
+    chunks: Vec<Chunk>,
+    chunk_size: u64,
+    media_size: u64,
+    position: u64,
+    cached: Option<(usize, Vec<u8>)>,
+}
+
+impl EwfReader {
+    /// Opens an image from its first segment file, following the segments
+    /// named after it until one ends with a "done" section
+    pub fn open(path: &Path) -> Result<Self> {
+        let mut reader = EwfReader {
+            segments: Vec::new(),
+            chunks: Vec::new(),
+            chunk_size: 0,
+            media_size: 0,
+            position: 0,
+            cached: None,
+        };
+
+        let mut path = path.to_path_buf();
+        loop {
+            let mut file = File::open(&path).context(format!("Failed to open EWF segment {}", path.display()))?;
+            let more = reader
+                .read_segment(&mut file)
+                .context(format!("Failed to read EWF segment {}", path.display()))?;
+            reader.segments.push(file);
+            if !more {
+                break;
+            }
+            path = next_segment(&path).context(format!("No segment file can follow {}", path.display()))?;
+        }
+
+        if reader.chunk_size == 0 {
+            bail!("The image has no volume section giving its chunk size");
+        }
+        eprintln!(
+            "Opened EWF image: {} segments, {} chunks, {} bytes of media",
+            reader.segments.len(),
+            reader.chunks.len(),
+            reader.media_size
+        );
+        Ok(reader)
+    }
+
+    /// Walks the section chain of a segment file, collecting the chunks its
+    /// tables list. Returns whether another segment file follows.
+    fn read_segment(&mut self, file: &mut File) -> Result<bool> {
+        let header = read_at(file, 0, FILE_HEADER_SIZE as usize)?;
+        if &header[0..8] == SIGNATURE_V2 {
+            bail!("Ex01 (EWF version 2) images aren't supported; convert the image with ewfexport");
+        }
+        if &header[0..8] != SIGNATURE {
+            bail!("Not an EWF segment file");
+        }
+
+        let segment = self.segments.len();
+        let file_size = file.metadata()?.len();
+        let mut offset = FILE_HEADER_SIZE;
+        // Chunk data is stored in a sectors section just before the table
+        // listing it, whose end bounds the table's last chunk
+        let mut sectors_end = None;
+        loop {
+            let descriptor = read_at(file, offset, SECTION_DESCRIPTOR_SIZE as usize)?;
+            let kind = String::from_utf8_lossy(&descriptor[0..16]).trim_end_matches('\0').to_string();
+            let next = raw::u64_at(&descriptor, 16).unwrap_or(0);
+            // Sections can't extend past the end of their file
+            let end = raw::u64_at(&descriptor, 24)
+                .and_then(|size| offset.checked_add(size))
+                .unwrap_or(u64::MAX)
+                .min(file_size);
+            let data = offset + SECTION_DESCRIPTOR_SIZE;
+
+            match kind.as_str() {
+                "volume" | "disk" => {
+                    let volume = read_at(file, data, 24)?;
+                    let field = |offset| raw::u32_at(&volume, offset).unwrap_or(0) as u64;
+                    let bytes_per_sector = field(12);
+                    self.chunk_size = field(8) * bytes_per_sector;
+                    if self.chunk_size > MAX_CHUNK_SIZE {
+                        bail!("Invalid chunk size {} in the volume section", self.chunk_size);
+                    }
+                    self.media_size = raw::u64_at(&volume, 16)
+                        .and_then(|sectors| sectors.checked_mul(bytes_per_sector))
+                        .context("Invalid media size in the volume section")?;
+                }
+                "sectors" => sectors_end = Some(end),
+                "table" => {
+                    // Images from EnCase 1 keep the chunks in the table section itself
+                    let end = sectors_end.take().unwrap_or(end);
+                    self.read_table(file, segment, data, end)?;
+                }
+                "next" => return Ok(true),
+                "done" => return Ok(false),
+                _ => {}
+            }
+
+            if next <= offset || next >= file_size {
+                bail!("Broken section chain after the {} section at offset {}", kind, offset);
+            }
+            offset = next;
+        }
+    }
+
+    /// Adds the chunks of a table section, whose data starts at `data`;
+    /// `end` is where the last chunk stops
+    fn read_table(&mut self, file: &mut File, segment: usize, data: u64, end: u64) -> Result<()> {
+        let header = read_at(file, data, TABLE_HEADER_SIZE as usize)?;
+        let count = raw::u32_at(&header, 0).unwrap_or(0) as u64;
+        let base = raw::u64_at(&header, 8).unwrap_or(0);
+        // A damaged count mustn't make the entries reach past the file
+        let file_size = file.metadata()?.len();
+        if data + TABLE_HEADER_SIZE + count * 4 > file_size {
+            bail!("Table section at offset {} lists more chunks than fit in the file", data);
+        }
+        let entries = read_at(file, data + TABLE_HEADER_SIZE, count as usize * 4)?;
+
+        let starts: Vec<(u64, bool)> = entries
+            .chunks_exact(4)
+            .map(|entry| {
+                let entry = u32::from_le_bytes([entry[0], entry[1], entry[2], entry[3]]);
+                (base.saturating_add((entry & !CHUNK_COMPRESSED) as u64), entry & CHUNK_COMPRESSED != 0)
+            })
+            .collect();
+        for (i, &(offset, compressed)) in starts.iter().enumerate() {
+            let chunk_end = starts.get(i + 1).map_or(end, |&(next, _)| next);
+            self.chunks.push(Chunk {
+                segment,
+                offset,
+                size: chunk_end.saturating_sub(offset),
+                compressed,
+            });
+        }
+        Ok(())
+    }
+
+    /// The media bytes of chunk `index`, decompressed
+    fn chunk(&mut self, index: usize) -> io::Result<&[u8]> {
+        if !matches!(&self.cached, Some((cached, _)) if *cached == index) {
+            let chunk = &self.chunks[index];
+            // Stored chunks are never much larger than the chunk size, even
+            // when compression made them grow
+            if chunk.size > 2 * self.chunk_size + 64 {
+                let message = format!("chunk {} is stored in {} bytes", index, chunk.size);
+                return Err(io::Error::new(io::ErrorKind::InvalidData, message));
+            }
+            let file = &mut self.segments[chunk.segment];
+            let mut stored = vec![0u8; chunk.size as usize];
+            file.seek(SeekFrom::Start(chunk.offset))?;
+            file.read_exact(&mut stored)?;
+
+            let data = if chunk.compressed {
+                // A damaged chunk mustn't inflate to more than one chunk of media
+                let mut data = Vec::with_capacity(self.chunk_size as usize);
+                ZlibDecoder::new(&stored[..]).take(self.chunk_size).read_to_end(&mut data)?;
+                data
+            } else {
+                stored.truncate(self.chunk_size as usize);
+                stored
+            };
+            self.cached = Some((index, data));
+        }
+        Ok(self.cached.as_ref().map(|(_, data)| data.as_slice()).unwrap_or_default())
+    }
+}
+
+impl Read for EwfReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.position >= self.media_size || buf.is_empty() {
+            return Ok(0);
+        }
+        let index = (self.position / self.chunk_size) as usize;
+        let within = (self.position % self.chunk_size) as usize;
+        if index >= self.chunks.len() {
+            let message = format!("chunk {} is missing from the image", index);
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, message));
+        }
+
+        let wanted = (self.media_size - self.position).min(buf.len() as u64) as usize;
+        let chunk = self.chunk(index)?;
+        let len = chunk.len().saturating_sub(within).min(wanted);
+        if len == 0 {
+            let message = format!("chunk {} is shorter than the image's chunk size", index);
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, message));
+        }
+        buf[..len].copy_from_slice(&chunk[within..within + len]);
+        self.position += len as u64;
+        Ok(len)
+    }
+}
+
+impl Seek for EwfReader {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(delta) => self.media_size.checked_add_signed(delta),
+            SeekFrom::Current(delta) => self.position.checked_add_signed(delta),
+        };
+        let position = position.ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "seek before the start of the image")
+        })?;
+        self.position = position;
+        Ok(position)
+    }
+}
+
+/// The segment file after `path`: .E01 to .E99, then .EAA to .EZZ, .FAA and
+/// so on, keeping the case of the extension
+fn next_segment(path: &Path) -> Option<PathBuf> {
+    let extension = path.extension()?.to_str()?;
+    if extension.len() != 3 || !extension.is_ascii() {
+        return None;
+    }
+    let lowercase = extension.as_bytes()[0].is_ascii_lowercase();
+
+    let next = match extension[1..].parse::<u32>() {
+        Ok(99) => format!("{}{}", &extension[..1], if lowercase { "aa" } else { "AA" }),
+        Ok(number) => format!("{}{:02}", &extension[..1], number + 1),
+        Err(_) => {
+            let mut letters = extension.as_bytes().to_vec();
+            for letter in letters.iter_mut().rev() {
+                match *letter {
+                    b'z' => *letter = b'a',
+                    b'Z' => *letter = b'A',
+                    _ => {
+                        *letter += 1;
+                        break;
+                    }
+                }
+            }
+            String::from_utf8(letters).ok()?
+        }
+    };
+    Some(path.with_extension(next))
+}
+
+fn read_at(file: &mut File, offset: u64, len: usize) -> Result<Vec<u8>> {
+    let mut buffer = vec![0u8; len];
+    file.seek(SeekFrom::Start(offset))?;
+    file.read_exact(&mut buffer)
+        .context(format!("Failed to read {} bytes at offset {}", len, offset))?;
+    Ok(buffer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::ZlibEncoder;
+    use flate2::Compression;
+    use std::fs;
+    use std::io::Write;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    const SECTOR: u32 = 4;
+    const SECTORS_PER_CHUNK: u32 = 4;
+    const CHUNK: usize = (SECTOR * SECTORS_PER_CHUNK) as usize;
+
+    /// A segment file under construction, one section at a time
+    struct Segment {
+        bytes: Vec<u8>,
+    }
+
+    impl Segment {
+        fn new() -> Self {
+            let mut bytes = SIGNATURE.to_vec();
+            bytes.extend_from_slice(&[1, 1, 0, 0, 0]);
+            Segment { bytes }
+        }
+
+        /// Appends a section whose next section starts right after it
+        fn section(&mut self, kind: &str, data: &[u8]) -> &mut Self {
+            let offset = self.bytes.len() as u64;
+            let size = SECTION_DESCRIPTOR_SIZE + data.len() as u64;
+            let next = if kind == "done" { offset } else { offset + size };
+            let mut descriptor = vec![0u8; SECTION_DESCRIPTOR_SIZE as usize];
+            descriptor[..kind.len()].copy_from_slice(kind.as_bytes());
+            descriptor[16..24].copy_from_slice(&next.to_le_bytes());
+            descriptor[24..32].copy_from_slice(&size.to_le_bytes());
+            self.bytes.extend_from_slice(&descriptor);
+            self.bytes.extend_from_slice(data);
+            self
+        }
+
+        fn volume(&mut self, sectors: u64) -> &mut Self {
+            let mut data = vec![0u8; 24];
+            data[8..12].copy_from_slice(&SECTORS_PER_CHUNK.to_le_bytes());
+            data[12..16].copy_from_slice(&SECTOR.to_le_bytes());
+            data[16..24].copy_from_slice(&sectors.to_le_bytes());
+            self.section("volume", &data)
+        }
+
+        /// A sectors section holding `chunks`, followed by the table listing them
+        fn chunks(&mut self, chunks: &[(&[u8], bool)]) -> &mut Self {
+            let base = self.bytes.len() as u64 + SECTION_DESCRIPTOR_SIZE;
+            let mut stored = Vec::new();
+            let mut entries = Vec::new();
+            for &(data, compressed) in chunks {
+                let mut entry = stored.len() as u32;
+                if compressed {
+                    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+                    encoder.write_all(data).unwrap();
+                    stored.extend(encoder.finish().unwrap());
+                    entry |= CHUNK_COMPRESSED;
+                } else {
+                    stored.extend_from_slice(data);
+                    // Adler-32 checksum, which isn't checked
+                    stored.extend_from_slice(&[0; 4]);
+                }
+                entries.extend_from_slice(&entry.to_le_bytes());
+            }
+            self.section("sectors", &stored);
+
+            let mut table = vec![0u8; TABLE_HEADER_SIZE as usize];
+            table[0..4].copy_from_slice(&(chunks.len() as u32).to_le_bytes());
+            table[8..16].copy_from_slice(&base.to_le_bytes());
+            table.extend(entries);
+            self.section("table", &table)
+        }
+
+        fn write(&self) -> PathBuf {
+            // Tests run in parallel, so each image gets its own file
+            static IMAGES: AtomicUsize = AtomicUsize::new(0);
+            let image = IMAGES.fetch_add(1, Ordering::Relaxed);
+            let name = format!("ntfs-reader-cli-{}-{}.E01", std::process::id(), image);
+            let path = std::env::temp_dir().join(name);
+            fs::write(&path, &self.bytes).unwrap();
+            path
+        }
+    }
+
+    fn open(segment: &Segment) -> Result<EwfReader> {
+        let path = segment.write();
+        let reader = EwfReader::open(&path);
+        fs::remove_file(path).unwrap();
+        reader
+    }
+
+    fn media(chunks: usize) -> Vec<u8> {
+        (0..chunks * CHUNK).map(|i| i as u8).collect()
+    }
+
+    #[test]
+    fn media_reads_across_stored_and_compressed_chunks() {
+        let media = media(3);
+        let mut segment = Segment::new();
+        segment
+            .volume(3 * SECTORS_PER_CHUNK as u64)
+            .chunks(&[(&media[..CHUNK], false), (&media[CHUNK..2 * CHUNK], true), (&media[2 * CHUNK..], false)])
+            .section("done", &[]);
+        let mut reader = open(&segment).unwrap();
+
+        let mut all = Vec::new();
+        reader.read_to_end(&mut all).unwrap();
+        assert_eq!(all, media);
+
+        let mut middle = [0u8; 8];
+        reader.seek(SeekFrom::Start(CHUNK as u64 - 4)).unwrap();
+        reader.read_exact(&mut middle).unwrap();
+        assert_eq!(middle, media[CHUNK - 4..CHUNK + 4]);
+        assert!(reader.seek(SeekFrom::Current(-100)).is_err());
+    }
+
+    #[test]
+    fn malformed_segments_are_refused() {
+        let mut segment = Segment::new();
+        segment.volume(4).chunks(&[(&media(1), false)]).section("done", &[]);
+        assert!(open(&segment).is_ok());
+
+        let mut wrong_signature = Segment::new();
+        wrong_signature.bytes[0] = b'X';
+        wrong_signature.volume(4).section("done", &[]);
+        assert!(open(&wrong_signature).is_err());
+
+        let mut version_2 = Segment { bytes: SIGNATURE_V2.to_vec() };
+        version_2.bytes.extend_from_slice(&[0; 5]);
+        assert!(open(&version_2).is_err());
+
+        let header_only = Segment { bytes: SIGNATURE[..5].to_vec() };
+        assert!(open(&header_only).is_err());
+
+        // No volume section, so no chunk size
+        let mut no_volume = Segment::new();
+        no_volume.section("done", &[]);
+        assert!(open(&no_volume).is_err());
+
+        // A section chain that loops back, and one that ends without "done"
+        let mut looping = Segment::new();
+        looping.volume(4).section("header", &[]);
+        let last = looping.bytes.len() - SECTION_DESCRIPTOR_SIZE as usize;
+        looping.bytes[last + 16..last + 24].copy_from_slice(&13u64.to_le_bytes());
+        assert!(open(&looping).is_err());
+        let mut unterminated = Segment::new();
+        unterminated.volume(4).section("header", &[]);
+        assert!(open(&unterminated).is_err());
+
+        // A chunk size past the limit, and a media size that overflows
+        let mut huge_chunks = Segment::new();
+        huge_chunks.volume(4);
+        huge_chunks.bytes[13 + 76 + 8..13 + 76 + 12].copy_from_slice(&u32::MAX.to_le_bytes());
+        huge_chunks.section("done", &[]);
+        assert!(open(&huge_chunks).is_err());
+        let mut huge_media = Segment::new();
+        huge_media.volume(u64::MAX).section("done", &[]);
+        assert!(open(&huge_media).is_err());
+
+        // A table listing more chunks than the file holds
+        let mut long_table = Segment::new();
+        long_table.volume(4).chunks(&[(&media(1), false)]);
+        let count = long_table.bytes.len() - 4 - TABLE_HEADER_SIZE as usize;
+        long_table.bytes[count..count + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+        long_table.section("done", &[]);
+        assert!(open(&long_table).is_err());
+    }
+
+    #[test]
+    fn damaged_chunks_fail_the_read() {
+        let read = |segment: &Segment| {
+            let mut all = Vec::new();
+            open(segment).unwrap().read_to_end(&mut all).map(|_| all)
+        };
+
+        // The media is larger than the chunks listed
+        let mut missing = Segment::new();
+        missing.volume(8).chunks(&[(&media(1), false)]).section("done", &[]);
+        assert!(read(&missing).is_err());
+
+        // A compressed chunk that isn't zlib data
+        let mut garbage = Segment::new();
+        garbage.volume(4).chunks(&[(&media(1), true)]);
+        let stored = 13 + 2 * 76 + 24;
+        garbage.bytes[stored..stored + 4].copy_from_slice(b"junk");
+        garbage.section("done", &[]);
+        assert!(read(&garbage).is_err());
+
+        // One that inflates to far more than a chunk is cut to one chunk
+        let mut bomb = Segment::new();
+        bomb.volume(4).chunks(&[(&[0u8; 4096], true)]).section("done", &[]);
+        assert_eq!(open(&bomb).unwrap().chunk(0).unwrap(), [0u8; CHUNK]);
+    }
+
+    #[test]
+    fn segment_names_count_up_then_through_letters() {
+        let next = |name: &str| next_segment(Path::new(name)).map(|path| path.display().to_string());
+        assert_eq!(next("image.E01").as_deref(), Some("image.E02"));
+        assert_eq!(next("image.e99").as_deref(), Some("image.eaa"));
+        assert_eq!(next("image.EAZ").as_deref(), Some("image.EBA"));
+        assert_eq!(next("image.EZZ").as_deref(), Some("image.FAA"));
+        assert_eq!(next("image.raw1"), None);
+        assert_eq!(next("image"), None);
+    }
+}
@@ -0,0 +1,55 @@
+use anyhow::{bail, Context, Result};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use crate::mft;
+use crate::raw::{self, RawVolume};
+
+/// Copies the $DATA of record `number` (or its named `stream`) straight from
+/// the volume's clusters to `out`, or to stdout without one. Going around
+/// the Win32 open path means files other processes hold open exclusively,
+/// such as registry hives, can be copied too.
+pub fn run(volume: &str, number: u64, stream: Option<&str>, out: Option<&Path>) -> Result<()> {
+    let mut raw = RawVolume::open(&mft::normalize_volume_path(volume))?;
+    let runs = raw.mft_runs()?;
+    let record = raw
+        .read_file_record(&runs, number)
+        .context(format!("Failed to read file record {}", number))?;
+    let parsed = raw::parse_file_record(&record);
+    if !parsed.in_use {
+        bail!("Record {} isn't in use; deleted files are read with recover --record", number);
+    }
+    if parsed.is_directory {
+        bail!("Record {} is a directory", number);
+    }
+    let attr = match stream {
+        Some(name) => raw::find_named_attribute(&record, raw::ATTR_DATA, name)
+            .context(format!("Record {} has no stream named {}", number, name))?,
+        // Files with very many runs keep $DATA in an extension record
+        None => raw::find_attribute(&record, raw::ATTR_DATA)
+            .context(format!("Record {} has no $DATA attribute in its base record", number))?,
+    };
+
+    let mut writer: Box<dyn Write> = match out {
+        Some(path) => {
+            let file = File::create(path).context(format!("Failed to create {}", path.display()))?;
+            Box::new(BufWriter::new(file))
+        }
+        None => Box::new(BufWriter::new(io::stdout().lock())),
+    };
+    let mut written = 0u64;
+    raw.read_value(attr, |chunk| {
+        writer.write_all(chunk)?;
+        written += chunk.len() as u64;
+        Ok(())
+    })?;
+    writer.flush()?;
+
+    eprintln!(
+        "Extracted {} bytes of {}",
+        written,
+        parsed.name.as_deref().unwrap_or("record")
+    );
+    Ok(())
+}
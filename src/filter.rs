@@ -0,0 +1,581 @@
+use anyhow::{Context, Result};
+use clap::Args;
+use globset::{GlobBuilder, GlobMatcher};
+use ntfs_reader::file::NtfsFile;
+use ntfs_reader::file_info::FileInfo;
+use regex::{Regex, RegexBuilder};
+use time::OffsetDateTime;
+
+use crate::output::{self, Warning};
+use crate::raw;
+use crate::units;
+
+/// Selection criteria shared by commands that enumerate the MFT
+#[derive(Args, Debug)]
+pub struct FilterArgs {
+    /// Filter by path: substring, glob (*.pdf, Users/**/*.docx) or regex (^..., re:...) (repeatable)
+    #[arg(short, long, value_name = "PATTERN")]
+    pub filter: Vec<String>,
+
+    /// Skip paths matching this pattern, whatever the other criteria say (repeatable)
+    #[arg(short = 'x', long, value_name = "PATTERN")]
+    pub exclude: Vec<String>,
+
+    /// Treat every --filter and --exclude pattern as a regex
+    #[arg(long, conflicts_with = "glob")]
+    pub regex: bool,
+
+    /// Treat every --filter and --exclude pattern as a glob
+    #[arg(long)]
+    pub glob: bool,
+
+    /// Match --filter and --exclude patterns case-sensitively
+    #[arg(long)]
+    pub case_sensitive: bool,
+
+    /// Only match entries below this directory, e.g. C:\Users or Users\alice
+    #[arg(long, value_name = "DIR")]
+    pub under: Option<String>,
+
+    /// Only match entries at most N levels below --under (or the volume root)
+    #[arg(long, value_name = "N")]
+    pub max_depth: Option<usize>,
+
+    /// Only match these file name extensions, e.g. pdf,docx (case-insensitive)
+    #[arg(long, value_name = "EXT", value_delimiter = ',')]
+    pub ext: Vec<String>,
+
+    /// Minimum size, e.g. 10M or 1.5G
+    #[arg(long, value_name = "SIZE", value_parser = units::parse_size)]
+    pub min_size: Option<u64>,
+
+    /// Maximum size, e.g. 10M or 1.5G
+    #[arg(long, value_name = "SIZE", value_parser = units::parse_size)]
+    pub max_size: Option<u64>,
+
+    /// Only match entries modified at or after this date (YYYY-MM-DD or RFC 3339)
+    #[arg(long, value_name = "DATE", value_parser = units::parse_timestamp)]
+    pub modified_after: Option<OffsetDateTime>,
+
+    /// Only match entries modified before this date
+    #[arg(long, value_name = "DATE", value_parser = units::parse_timestamp)]
+    pub modified_before: Option<OffsetDateTime>,
+
+    /// Only match entries created at or after this date
+    #[arg(long, value_name = "DATE", value_parser = units::parse_timestamp)]
+    pub created_after: Option<OffsetDateTime>,
+
+    /// Only match entries created before this date
+    #[arg(long, value_name = "DATE", value_parser = units::parse_timestamp)]
+    pub created_before: Option<OffsetDateTime>,
+
+    /// Only match files with at least N hard links (8.3 short names don't count)
+    #[arg(long, value_name = "N")]
+    pub min_links: Option<u64>,
+
+    /// Only match entries with at least N alternate data streams
+    #[arg(long, value_name = "N")]
+    pub min_streams: Option<u64>,
+
+    /// Match entries that satisfy any criterion instead of all of them
+    #[arg(long, overrides_with = "all")]
+    pub any: bool,
+
+    /// Match entries that satisfy every criterion (default). Overrides an
+    /// earlier --any, such as one set in the config file.
+    #[arg(long, overrides_with = "any")]
+    pub all: bool,
+}
+
+/// How path patterns are interpreted
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum PatternMode {
+    /// Guessed from the pattern, see `PathPattern::guess`
+    Auto,
+    Regex,
+    Glob,
+}
+
+/// A path pattern as given on the command line
+enum PathPattern {
+    Regex(Regex),
+    Glob(GlobMatcher),
+    Substring { text: String, case_sensitive: bool },
+}
+
+impl PathPattern {
+    /// Compiles a pattern in the given mode. Invalid regexes and globs are
+    /// errors when the mode was chosen explicitly.
+    fn new(pattern: &str, mode: PatternMode, case_sensitive: bool) -> Result<Self> {
+        match mode {
+            PatternMode::Regex => compile_regex(pattern, case_sensitive)
+                .context(format!("Invalid regex '{}'", pattern))
+                .map(PathPattern::Regex),
+            PatternMode::Glob => compile_glob(pattern, case_sensitive)
+                .context(format!("Invalid glob '{}'", pattern))
+                .map(PathPattern::Glob),
+            PatternMode::Auto => Ok(Self::guess(pattern, case_sensitive)),
+        }
+    }
+
+    /// Picks the mode of a pattern, first match wins:
+    ///
+    /// 1. `re:` prefix, a leading `^` or a `(`: regex, searched anywhere in the path
+    /// 2. `*`, `?`, `[` or `{`: glob, matched against whole path components
+    /// 3. anything else: substring
+    ///
+    /// Patterns that fail to compile are searched as substrings, with a warning.
+    fn guess(pattern: &str, case_sensitive: bool) -> Self {
+        let substring = || PathPattern::Substring {
+            text: if case_sensitive { pattern.to_string() } else { pattern.to_lowercase() },
+            case_sensitive,
+        };
+
+        let regex = match pattern.strip_prefix("re:") {
+            Some(regex) => Some(regex),
+            None if pattern.starts_with('^') || pattern.contains('(') => Some(pattern),
+            None => None,
+        };
+        if let Some(regex) = regex {
+            return match compile_regex(regex, case_sensitive) {
+                Ok(regex) => PathPattern::Regex(regex),
+                Err(e) => {
+                    let message = format!(
+                        "'{}' isn't a valid regex, matching it as a substring: {}",
+                        pattern, e
+                    );
+                    Warning::new("pattern", message).emit();
+                    substring()
+                }
+            };
+        }
+
+        if pattern.contains(['*', '?', '[', '{']) {
+            return match compile_glob(pattern, case_sensitive) {
+                Ok(glob) => PathPattern::Glob(glob),
+                Err(e) => {
+                    let message = format!(
+                        "'{}' isn't a valid glob, matching it as a substring: {}",
+                        pattern, e
+                    );
+                    Warning::new("pattern", message).emit();
+                    substring()
+                }
+            };
+        }
+        substring()
+    }
+
+    fn is_match(&self, path: &str, path_lower: &str) -> bool {
+        match self {
+            PathPattern::Regex(regex) => regex.is_match(path),
+            PathPattern::Glob(glob) => glob.is_match(path.replace('\\', "/")),
+            PathPattern::Substring { text, case_sensitive: true } => path.contains(text.as_str()),
+            PathPattern::Substring { text, case_sensitive: false } => path_lower.contains(text.as_str()),
+        }
+    }
+}
+
+fn compile_regex(pattern: &str, case_sensitive: bool) -> Result<Regex, regex::Error> {
+    RegexBuilder::new(pattern).case_insensitive(!case_sensitive).build()
+}
+
+/// Compiles a glob where `*` and `?` stay within one path component and
+/// `**` spans any number of them. Both slashes separate components, and
+/// doubled separators (as typed for escaping shells) count once. Unless
+/// it starts at a drive (`C:/...`), the glob may begin at any directory, so
+/// `*.pdf` matches every PDF and `Users/**/*.docx` every document below a
+/// Users directory.
+fn compile_glob(pattern: &str, case_sensitive: bool) -> Result<GlobMatcher, globset::Error> {
+    let mut pattern = pattern.replace('\\', "/");
+    while pattern.contains("//") {
+        pattern = pattern.replace("//", "/");
+    }
+    let anchored = pattern.as_bytes().get(1) == Some(&b':');
+    let pattern = if anchored {
+        pattern
+    } else {
+        format!("**/{}", pattern.trim_start_matches('/'))
+    };
+
+    GlobBuilder::new(&pattern)
+        .literal_separator(true)
+        .backslash_escape(false)
+        .case_insensitive(!case_sensitive)
+        .build()
+        .map(|glob| glob.compile_matcher())
+}
+
+/// One condition an entry is tested against
+enum Criterion {
+    Path(PathPattern),
+    Extension(Vec<String>),
+    Size { min: Option<u64>, max: Option<u64> },
+    Modified { after: Option<OffsetDateTime>, before: Option<OffsetDateTime> },
+    Created { after: Option<OffsetDateTime>, before: Option<OffsetDateTime> },
+    // Both counted from the attributes in the base record
+    Links(u64),
+    Streams(u64),
+}
+
+impl Criterion {
+    fn is_match(&self, entry: &Entry, path_lower: &str) -> bool {
+        match self {
+            Criterion::Path(pattern) => pattern.is_match(entry.path, path_lower),
+            Criterion::Extension(extensions) => {
+                let name = entry.name.to_lowercase();
+                match name.rsplit_once('.') {
+                    Some((_, ext)) => extensions.iter().any(|e| e == ext),
+                    None => false,
+                }
+            }
+            Criterion::Size { min, max } => {
+                min.is_none_or(|min| entry.size >= min) && max.is_none_or(|max| entry.size <= max)
+            }
+            Criterion::Modified { after, before } => in_range(entry.modified, *after, *before),
+            Criterion::Created { after, before } => in_range(entry.created, *after, *before),
+            Criterion::Links(min) => (entry.links)() >= *min,
+            Criterion::Streams(min) => (entry.streams)() >= *min,
+        }
+    }
+}
+
+/// What the criteria test, taken from ntfs-reader's view of a file or from
+/// a record parsed by `raw`. Link and stream counts walk the attributes
+/// again, so they are only computed when a criterion asks for them.
+struct Entry<'a> {
+    name: &'a str,
+    path: &'a str,
+    size: u64,
+    modified: Option<OffsetDateTime>,
+    created: Option<OffsetDateTime>,
+    links: &'a dyn Fn() -> u64,
+    streams: &'a dyn Fn() -> u64,
+}
+
+/// Hard links of a file, one per $FILE_NAME that isn't an 8.3 alias
+fn link_count(file: &NtfsFile) -> u64 {
+    let mut count = 0;
+    file.attributes(|attr| {
+        if attr.header.type_id == raw::ATTR_FILE_NAME && raw::file_name_link(attr.data()).is_some() {
+            count += 1;
+        }
+    });
+    count
+}
+
+/// Named $DATA attributes of a file
+fn stream_count(file: &NtfsFile) -> u64 {
+    let mut count = 0;
+    file.attributes(|attr| {
+        if attr.header.type_id == raw::ATTR_DATA && attr.header.name_length > 0 {
+            count += 1;
+        }
+    });
+    count
+}
+
+fn in_range(
+    time: Option<OffsetDateTime>,
+    after: Option<OffsetDateTime>,
+    before: Option<OffsetDateTime>,
+) -> bool {
+    let Some(time) = time else {
+        return false;
+    };
+    after.is_none_or(|after| time >= after) && before.is_none_or(|before| time < before)
+}
+
+/// Subtree a path must lie in: below `under` (the volume root when absent)
+/// and at most `max_depth` components deeper. Drive letters and `\\?\`
+/// prefixes are ignored, since every command reads a single volume, and
+/// components compare case-insensitively like NTFS does.
+#[derive(Clone, Debug, Default)]
+pub struct PathScope {
+    under: Vec<String>,
+    max_depth: Option<usize>,
+}
+
+impl PathScope {
+    pub fn new(under: Option<&str>, max_depth: Option<usize>) -> Self {
+        PathScope {
+            under: under
+                .map(|under| components(under).map(str::to_lowercase).collect())
+                .unwrap_or_default(),
+            max_depth,
+        }
+    }
+
+    /// Whether every path is in scope
+    pub fn is_empty(&self) -> bool {
+        self.under.is_empty() && self.max_depth.is_none()
+    }
+
+    /// Whether `path` is `under` itself or below it, within `max_depth`
+    pub fn contains(&self, path: &str) -> bool {
+        let mut components = components(path);
+        for directory in &self.under {
+            match components.next() {
+                Some(component) if component.to_lowercase() == *directory => {}
+                _ => return false,
+            }
+        }
+        self.max_depth.is_none_or(|max| components.count() <= max)
+    }
+}
+
+/// Components of a path below the volume root
+pub fn components(path: &str) -> impl Iterator<Item = &str> {
+    let path = path.strip_prefix("\\\\?\\").unwrap_or(path);
+    let path = match path.as_bytes() {
+        [letter, b':', ..] if letter.is_ascii_alphabetic() => &path[2..],
+        _ => path,
+    };
+    path.split(['\\', '/']).filter(|component| !component.is_empty())
+}
+
+/// Compiled form of `FilterArgs`. Exclusions and the subtree scope always
+/// win; the remaining criteria are combined with AND, or with OR under `--any`.
+pub struct FileFilter {
+    criteria: Vec<Criterion>,
+    excludes: Vec<PathPattern>,
+    scope: PathScope,
+    any: bool,
+}
+
+impl FileFilter {
+    pub fn new(args: &FilterArgs) -> Result<Self> {
+        let mode = if args.regex {
+            PatternMode::Regex
+        } else if args.glob {
+            PatternMode::Glob
+        } else {
+            PatternMode::Auto
+        };
+        let pattern = |p: &String| PathPattern::new(p, mode, args.case_sensitive);
+
+        let mut criteria: Vec<Criterion> = args
+            .filter
+            .iter()
+            .map(|f| pattern(f).map(Criterion::Path))
+            .collect::<Result<_>>()?;
+
+        if !args.ext.is_empty() {
+            let extensions = args
+                .ext
+                .iter()
+                .map(|e| e.trim().trim_start_matches('.').to_lowercase())
+                .filter(|e| !e.is_empty())
+                .collect();
+            criteria.push(Criterion::Extension(extensions));
+        }
+        if args.min_size.is_some() || args.max_size.is_some() {
+            criteria.push(Criterion::Size { min: args.min_size, max: args.max_size });
+        }
+        if args.modified_after.is_some() || args.modified_before.is_some() {
+            criteria.push(Criterion::Modified {
+                after: args.modified_after,
+                before: args.modified_before,
+            });
+        }
+        if args.created_after.is_some() || args.created_before.is_some() {
+            criteria.push(Criterion::Created {
+                after: args.created_after,
+                before: args.created_before,
+            });
+        }
+        if let Some(min) = args.min_links {
+            criteria.push(Criterion::Links(min));
+        }
+        if let Some(min) = args.min_streams {
+            criteria.push(Criterion::Streams(min));
+        }
+
+        Ok(FileFilter {
+            criteria,
+            excludes: args.exclude.iter().map(pattern).collect::<Result<_>>()?,
+            scope: PathScope::new(args.under.as_deref(), args.max_depth),
+            any: args.any,
+        })
+    }
+
+    /// Whether every entry matches
+    pub fn is_empty(&self) -> bool {
+        self.criteria.is_empty() && self.excludes.is_empty() && self.scope.is_empty()
+    }
+
+    pub fn matches(&self, info: &FileInfo, file: &NtfsFile) -> bool {
+        if self.is_empty() {
+            return true;
+        }
+
+        let path = info.path.to_string_lossy();
+        self.matches_entry(&Entry {
+            name: &info.name,
+            path: &path,
+            size: info.size,
+            modified: info.modified,
+            created: info.created,
+            links: &|| link_count(file),
+            streams: &|| stream_count(file),
+        })
+    }
+
+    /// `matches` for a file record parsed without ntfs-reader, as read from
+    /// an extracted $MFT; `path` is the one rebuilt for it
+    pub fn matches_raw(&self, path: &str, record: &[u8], parsed: &raw::ParsedRecord) -> bool {
+        if self.is_empty() {
+            return true;
+        }
+        self.matches_entry(&Entry {
+            name: parsed.name.as_deref().unwrap_or_default(),
+            path,
+            size: parsed.size,
+            modified: parsed.modified.and_then(output::from_filetime),
+            created: parsed.created.and_then(output::from_filetime),
+            links: &|| raw::file_name_links(record).len() as u64,
+            streams: &|| parsed.streams.len() as u64,
+        })
+    }
+
+    fn matches_entry(&self, entry: &Entry) -> bool {
+        if !self.scope.contains(entry.path) {
+            return false;
+        }
+        let path_lower = entry.path.to_lowercase();
+        if self.excludes.iter().any(|e| e.is_match(entry.path, &path_lower)) {
+            return false;
+        }
+        if self.criteria.is_empty() {
+            return true;
+        }
+
+        if self.any {
+            self.criteria.iter().any(|c| c.is_match(entry, &path_lower))
+        } else {
+            self.criteria.iter().all(|c| c.is_match(entry, &path_lower))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    #[derive(Parser)]
+    struct Cli {
+        #[command(flatten)]
+        filter: FilterArgs,
+    }
+
+    fn parse(args: &[&str]) -> FilterArgs {
+        Cli::try_parse_from(std::iter::once("test").chain(args.iter().copied())).unwrap().filter
+    }
+
+    fn filter(args: &[&str]) -> Result<FileFilter> {
+        FileFilter::new(&parse(args))
+    }
+
+    /// Whether a file at `path` of `size` bytes, modified at the start of
+    /// 2024, with one link and no streams, matches
+    fn matches(filter: &FileFilter, path: &str, size: u64) -> bool {
+        let modified = units::parse_timestamp("2024-01-01").ok();
+        filter.matches_entry(&Entry {
+            name: path.rsplit('\\').next().unwrap_or_default(),
+            path,
+            size,
+            modified,
+            created: None,
+            links: &|| 1,
+            streams: &|| 0,
+        })
+    }
+
+    #[test]
+    fn patterns_are_guessed_from_their_form() {
+        let pdf = filter(&["-f", "*.pdf"]).unwrap();
+        assert!(matches(&pdf, "C:\\Docs\\Report.PDF", 1));
+        assert!(!matches(&pdf, "C:\\Docs\\report.pdf.txt", 1));
+
+        let docx = filter(&["-f", "Users\\\\**\\\\*.docx"]).unwrap();
+        assert!(matches(&docx, "C:\\Users\\bob\\Documents\\a.docx", 1));
+        assert!(!matches(&docx, "C:\\Data\\a.docx", 1));
+
+        let anchored = filter(&["-f", "C:\\Users\\*"]).unwrap();
+        assert!(matches(&anchored, "C:\\Users\\bob", 1));
+        assert!(!matches(&anchored, "C:\\Users\\bob\\a.txt", 1));
+
+        let regex = filter(&["-f", "^C:\\\\Windows\\\\.*\\.dll$"]).unwrap();
+        assert!(matches(&regex, "C:\\Windows\\System32\\ntdll.DLL", 1));
+        assert!(!matches(&filter(&["-f", "Temp", "--case-sensitive"]).unwrap(), "C:\\temp\\a", 1));
+    }
+
+    #[test]
+    fn malformed_patterns_are_errors_only_in_an_explicit_mode() {
+        for args in [
+            &["--regex", "-f", "(unclosed"][..],
+            &["--regex", "-x", "a{2,1}"],
+            &["--glob", "-f", "a[b"],
+            &["--glob", "-x", "{a,b"],
+        ] {
+            assert!(filter(args).is_err(), "{:?}", args);
+        }
+
+        // Guessed ones are searched as plain text instead
+        let unclosed = filter(&["-f", "report(1"]).unwrap();
+        assert!(matches(&unclosed, "C:\\report(1).txt", 1));
+        assert!(!matches(&unclosed, "C:\\report1.txt", 1));
+        let bracket = filter(&["-f", "a[b"]).unwrap();
+        assert!(matches(&bracket, "C:\\a[b]", 1));
+    }
+
+    #[test]
+    fn criteria_combine_with_all_or_any_and_exclusions_always_win() {
+        let all = filter(&["--ext", "pdf", "--min-size", "1K"]).unwrap();
+        assert!(matches(&all, "C:\\a.pdf", 2048));
+        assert!(!matches(&all, "C:\\a.pdf", 10));
+        assert!(!matches(&all, "C:\\a.txt", 2048));
+
+        let any = filter(&["--any", "--ext", "pdf", "--min-size", "1K", "-x", "\\Temp\\"]).unwrap();
+        assert!(matches(&any, "C:\\a.pdf", 10));
+        assert!(matches(&any, "C:\\a.txt", 2048));
+        assert!(!matches(&any, "C:\\a.txt", 10));
+        assert!(!matches(&any, "C:\\Temp\\a.pdf", 2048));
+
+        let dates = filter(&["--modified-after", "2023-06-01", "--modified-before", "2024-01-01"]).unwrap();
+        assert!(!matches(&dates, "C:\\a", 1), "before is exclusive");
+        assert!(!matches(&filter(&["--created-after", "2000-01-01"]).unwrap(), "C:\\a", 1));
+
+        // Extensions given with dots, spaces or nothing at all
+        let extensions = filter(&["--ext", " .PDF, ,"]).unwrap();
+        assert!(matches(&extensions, "C:\\a.pdf", 1));
+        assert!(!matches(&extensions, "C:\\pdf", 1));
+        assert!(!matches(&filter(&["--ext", ","]).unwrap(), "C:\\a.pdf", 1));
+    }
+
+    #[test]
+    fn scopes_hold_the_subtree_down_to_a_depth() {
+        let scope = PathScope::new(Some("c:\\users\\"), Some(1));
+        assert!(scope.contains("\\\\?\\C:\\Users"));
+        assert!(scope.contains("C:\\USERS\\bob"));
+        assert!(!scope.contains("C:\\Users\\bob\\a.txt"));
+        assert!(!scope.contains("C:\\Usersbob"));
+        assert!(!scope.contains(""));
+
+        let root = PathScope::new(Some("\\"), Some(0));
+        assert!(root.contains("C:\\"));
+        assert!(!root.contains("C:\\a"));
+        for path in ["", "C:", "\\\\?\\", "\\\\", "C:a"] {
+            assert!(PathScope::new(None, None).contains(path), "{:?}", path);
+        }
+    }
+
+    #[test]
+    fn the_last_of_any_and_all_wins() {
+        assert!(!parse(&[]).any);
+        assert!(parse(&["--all", "--any"]).any);
+        // Config defaults are inserted before the options given on the command line
+        assert!(!parse(&["--any", "--all"]).any);
+    }
+}
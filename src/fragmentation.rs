@@ -0,0 +1,320 @@
+use anyhow::{bail, Result};
+use ntfs_reader::api::NtfsAttributeType;
+use ntfs_reader::file::NtfsFile;
+use serde::Serialize;
+use std::cmp::Reverse;
+use std::io::{self, Write};
+
+use crate::filter::FileFilter;
+use crate::mft;
+use crate::output::{self, ColumnType, Field, Fields, TableRow};
+use crate::raw;
+use crate::OutputFormat;
+
+#[derive(Debug, Serialize)]
+pub struct FragmentedFile {
+    pub path: String,
+    pub size: u64,
+    /// Physically contiguous pieces of the file's data; sparse runs don't count
+    pub extents: u64,
+    pub clusters: u64,
+    pub average_run_clusters: u64,
+    pub record_number: u64,
+}
+
+impl FragmentedFile {
+    /// Fields accepted by --fields, with the types of their CSV columns
+    pub const FIELDS: &'static [Field] = &[
+        ("path", ColumnType::String),
+        ("size", ColumnType::Size),
+        ("extents", ColumnType::Int),
+        ("clusters", ColumnType::Int),
+        ("average_run_clusters", ColumnType::Int),
+        ("record_number", ColumnType::Int),
+    ];
+}
+
+impl TableRow for FragmentedFile {
+    fn table_header() -> Vec<&'static str> {
+        vec!["EXTENTS", "AVG RUN", "SIZE", "PATH"]
+    }
+
+    fn table_row(&self) -> Vec<String> {
+        vec![
+            self.extents.to_string(),
+            self.average_run_clusters.to_string(),
+            output::format_size(self.size),
+            self.path.clone(),
+        ]
+    }
+
+    fn streaming_widths() -> Vec<usize> {
+        vec![7, 7, 12, 0]
+    }
+}
+
+/// Order of the fragmentation report, most fragmented first
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FragmentationSort {
+    /// Most extents first
+    Extents,
+    /// Shortest average run first
+    AverageRun,
+    /// Largest files first
+    Size,
+}
+
+impl std::str::FromStr for FragmentationSort {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "extents" => Ok(FragmentationSort::Extents),
+            "average-run" | "avg-run" => Ok(FragmentationSort::AverageRun),
+            "size" => Ok(FragmentationSort::Size),
+            _ => Err(format!("Invalid sort order: {}", s)),
+        }
+    }
+}
+
+/// Lists files whose data is split into at least `min_extents` extents,
+/// counted from the data runs of their unnamed $DATA attribute, sorted by
+/// `sort` and cut to `limit` entries. Runs kept in extension records of
+/// very large files aren't visible and don't count.
+pub fn report(
+    volume: &str,
+    filter: &FileFilter,
+    min_extents: u64,
+    sort: FragmentationSort,
+    limit: usize,
+    output: OutputFormat,
+    fields: &Fields,
+) -> Result<()> {
+    let mut files = scan(volume, filter)?;
+    files.retain(|file| file.extents >= min_extents);
+    eprintln!("Found {} files with at least {} extents", files.len(), min_extents);
+
+    match sort {
+        FragmentationSort::Extents => files.sort_by_key(|file| Reverse(file.extents)),
+        FragmentationSort::AverageRun => files.sort_by_key(|file| file.average_run_clusters),
+        FragmentationSort::Size => files.sort_by_key(|file| Reverse(file.size)),
+    }
+    files.truncate(limit);
+
+    let stdout = io::stdout();
+    output::write_report(&mut stdout.lock(), &files, output, fields, FragmentedFile::FIELDS)
+}
+
+/// Upper bounds of the extent count buckets in the volume summary; the
+/// last bucket has no bound
+const EXTENT_BUCKETS: &[u64] = &[1, 9, 99, 999];
+
+/// Number of files whose extent count falls in a range
+#[derive(Debug, Serialize)]
+pub struct ExtentBucket {
+    pub min_extents: u64,
+    /// Absent for the last, open-ended bucket
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_extents: Option<u64>,
+    pub files: u64,
+}
+
+impl TableRow for ExtentBucket {
+    fn table_header() -> Vec<&'static str> {
+        vec!["EXTENTS", "FILES"]
+    }
+
+    fn table_row(&self) -> Vec<String> {
+        let range = match self.max_extents {
+            Some(max) if max == self.min_extents => max.to_string(),
+            Some(max) => format!("{}-{}", self.min_extents, max),
+            None => format!("{}+", self.min_extents),
+        };
+        vec![range, self.files.to_string()]
+    }
+
+    fn streaming_widths() -> Vec<usize> {
+        vec![10, 0]
+    }
+}
+
+/// Fragmentation of a whole volume, as reported by `frag-report`
+#[derive(Debug, Serialize)]
+pub struct FragmentationSummary {
+    /// Files with non-resident data; resident files can't be fragmented
+    pub files: u64,
+    /// Files in more than one extent
+    pub fragmented_files: u64,
+    pub fragmented_percent: f64,
+    pub extents: u64,
+    /// Extents per file; 1.0 means no fragmentation at all
+    pub average_extents: f64,
+    pub max_extents: u64,
+    pub clusters: u64,
+    /// Clusters belonging to fragmented files
+    pub fragmented_clusters: u64,
+    pub histogram: Vec<ExtentBucket>,
+    /// The most fragmented files, most extents first
+    pub top: Vec<FragmentedFile>,
+}
+
+/// Walks every file with non-resident data and summarizes how fragmented
+/// the volume is: totals, a histogram of extent counts and the `top` files
+/// in the most extents. Table output prints the totals followed by the
+/// histogram and the top files; JSON formats print one object.
+pub fn summary(volume: &str, filter: &FileFilter, top: usize, output: OutputFormat) -> Result<()> {
+    let mut files = scan(volume, filter)?;
+
+    let fragmented: Vec<&FragmentedFile> = files.iter().filter(|file| file.extents > 1).collect();
+    let extents: u64 = files.iter().map(|file| file.extents).sum();
+    let mut lower = 1;
+    let histogram = EXTENT_BUCKETS
+        .iter()
+        .map(Some)
+        .chain([None])
+        .map(|upper| {
+            let bucket = ExtentBucket {
+                min_extents: lower,
+                max_extents: upper.copied(),
+                files: files
+                    .iter()
+                    .filter(|file| file.extents >= lower && upper.is_none_or(|max| file.extents <= *max))
+                    .count() as u64,
+            };
+            lower = upper.map_or(lower, |max| max + 1);
+            bucket
+        })
+        .collect();
+
+    let mut summary = FragmentationSummary {
+        files: files.len() as u64,
+        fragmented_files: fragmented.len() as u64,
+        fragmented_percent: percent(fragmented.len() as u64, files.len() as u64),
+        extents,
+        average_extents: if files.is_empty() { 0.0 } else { extents as f64 / files.len() as f64 },
+        max_extents: files.iter().map(|file| file.extents).max().unwrap_or(0),
+        clusters: files.iter().map(|file| file.clusters).sum(),
+        fragmented_clusters: fragmented.iter().map(|file| file.clusters).sum(),
+        histogram,
+        top: Vec::new(),
+    };
+    files.retain(|file| file.extents > 1);
+    files.sort_by_key(|file| Reverse(file.extents));
+    files.truncate(top);
+    summary.top = files;
+
+    let stdout = io::stdout();
+    let mut w = stdout.lock();
+    match output {
+        OutputFormat::Table => {
+            writeln!(w, "Files:              {}", summary.files)?;
+            writeln!(
+                w,
+                "Fragmented files:   {} ({:.1}%)",
+                summary.fragmented_files, summary.fragmented_percent
+            )?;
+            writeln!(w, "Extents:            {}", summary.extents)?;
+            writeln!(w, "Average extents:    {:.2}", summary.average_extents)?;
+            writeln!(w, "Most extents:       {}", summary.max_extents)?;
+            writeln!(
+                w,
+                "Fragmented data:    {} of {} clusters ({:.1}%)",
+                summary.fragmented_clusters,
+                summary.clusters,
+                percent(summary.fragmented_clusters, summary.clusters)
+            )?;
+            writeln!(w)?;
+            output::write_table(&mut w, &summary.histogram)?;
+            if !summary.top.is_empty() {
+                writeln!(w)?;
+                output::write_table(&mut w, &summary.top)?;
+            }
+        }
+        OutputFormat::Json | OutputFormat::Jsonl => writeln!(w, "{}", output::to_json(&summary)?)?,
+        OutputFormat::JsonPretty => writeln!(w, "{}", output::to_json_pretty(&summary)?)?,
+        _ => bail!("frag-report supports table, json, jsonl and json-pretty output"),
+    }
+    Ok(())
+}
+
+fn percent(part: u64, whole: u64) -> f64 {
+    if whole == 0 {
+        0.0
+    } else {
+        part as f64 * 100.0 / whole as f64
+    }
+}
+
+/// Every file matching `filter` whose unnamed $DATA is non-resident, with
+/// its extent count
+fn scan(volume: &str, filter: &FileFilter) -> Result<Vec<FragmentedFile>> {
+    let mft = mft::open_mft(volume)?;
+    let mut files = Vec::new();
+
+    eprintln!("Reading data runs...");
+    mft::iterate_files(&mft, |file| {
+        if file.is_directory() {
+            return;
+        }
+        let Some((extents, clusters)) = count_extents(file) else {
+            return;
+        };
+        let Some(info) = mft::parse_file_info(&mft, file) else {
+            return;
+        };
+        if !filter.matches(&info, file) {
+            return;
+        }
+
+        files.push(FragmentedFile {
+            path: output::display_path(&info.path),
+            size: info.size,
+            extents,
+            clusters,
+            average_run_clusters: clusters / extents,
+            record_number: file.number(),
+        });
+    });
+    Ok(files)
+}
+
+/// Extent and allocated cluster counts of a file's unnamed $DATA, or `None`
+/// when its data is resident or its runs can't be decoded. Runs that
+/// continue where the previous one ended are the same extent.
+fn count_extents(file: &NtfsFile) -> Option<(u64, u64)> {
+    let mut runs = Vec::new();
+    let mut non_resident = false;
+    let mut valid = true;
+
+    file.attributes(|attr| {
+        if attr.header.type_id != NtfsAttributeType::Data as u32
+            || attr.header.name_length != 0
+            || attr.header.is_non_resident == 0
+        {
+            return;
+        }
+        non_resident = true;
+        match raw::mapping_pairs(attr.data()).and_then(raw::decode_runs) {
+            Ok(decoded) => runs.extend(decoded),
+            Err(_) => valid = false,
+        }
+    });
+    if !non_resident || !valid {
+        return None;
+    }
+
+    let mut extents = 0;
+    let mut clusters = 0;
+    let mut next_lcn = None;
+    for (lcn, length) in runs {
+        let Some(lcn) = lcn else {
+            continue;
+        };
+        if next_lcn != Some(lcn) {
+            extents += 1;
+        }
+        clusters += length;
+        next_lcn = Some(lcn + length);
+    }
+    (extents > 0).then_some((extents, clusters))
+}
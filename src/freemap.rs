@@ -0,0 +1,171 @@
+use anyhow::{bail, Context, Result};
+use serde::Serialize;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use crate::mft;
+use crate::output;
+use crate::raw::{self, RawVolume};
+
+/// Height in pixels of the PNG heat strip
+const STRIP_HEIGHT: u32 = 32;
+
+/// A stretch of clusters that are all allocated or all free
+#[derive(Debug, Serialize, Clone, Copy)]
+pub struct ClusterRun {
+    pub lcn: u64,
+    pub clusters: u64,
+    pub allocated: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FreeMap {
+    pub bytes_per_cluster: u64,
+    pub total_clusters: u64,
+    pub free_clusters: u64,
+    pub free_bytes: u64,
+    pub free_runs: u64,
+    /// Longest stretch of free clusters, the largest file that fits unfragmented
+    pub largest_free_run: u64,
+}
+
+impl FreeMap {
+    fn add(&mut self, run: &ClusterRun) {
+        if !run.allocated {
+            self.free_clusters += run.clusters;
+            self.free_runs += 1;
+            self.largest_free_run = self.largest_free_run.max(run.clusters);
+        }
+    }
+}
+
+/// Allocated cluster counts of the volume split into equal slices, one per
+/// pixel column of the heat strip
+struct Buckets {
+    clusters_per_bucket: u64,
+    allocated: Vec<u64>,
+}
+
+/// Reads $Bitmap from the raw volume and prints the allocation map as JSON:
+/// the run-length encoded runs followed by the totals. Runs are written as
+/// they are decoded, so a fragmented volume doesn't have to fit in memory.
+/// With `png`, the runs are left out and a heat strip `width` pixels wide
+/// is written there instead, each column shaded by how much of its slice of
+/// the volume is allocated (black = full, white = free).
+pub fn run(volume: &str, png: Option<&Path>, width: u32) -> Result<()> {
+    let mut raw = RawVolume::open(&mft::normalize_volume_path(volume))?;
+    let runs = raw.mft_runs()?;
+    let record = raw
+        .read_file_record(&runs, raw::BITMAP_RECORD)
+        .context("Failed to read the $Bitmap record")?;
+    let attr = raw::find_attribute(&record, raw::ATTR_DATA)
+        .context("$Bitmap has no $DATA attribute")?;
+
+    let total_clusters = raw.total_clusters;
+    if total_clusters == 0 {
+        bail!("The boot sector reports an empty volume");
+    }
+    let mut buckets = png.map(|_| {
+        let clusters_per_bucket = total_clusters.div_ceil(width.max(1) as u64);
+        Buckets {
+            clusters_per_bucket,
+            allocated: vec![0; total_clusters.div_ceil(clusters_per_bucket) as usize],
+        }
+    });
+
+    let mut map = FreeMap {
+        bytes_per_cluster: raw.bytes_per_cluster,
+        total_clusters,
+        free_clusters: 0,
+        free_bytes: 0,
+        free_runs: 0,
+        largest_free_run: 0,
+    };
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    let mut first = true;
+    // Adds a finished run to the totals and, without --png, prints it
+    let mut finish = |run: ClusterRun, map: &mut FreeMap| -> Result<()> {
+        map.add(&run);
+        if png.is_none() {
+            write!(out, "{}{}", if first { "{\"runs\":[" } else { "," }, output::to_json(&run)?)?;
+            first = false;
+        }
+        Ok(())
+    };
+    let mut current: Option<ClusterRun> = None;
+    let mut lcn = 0u64;
+
+    eprintln!("Reading the allocation map of {} clusters...", total_clusters);
+    raw.read_value(attr, |chunk| {
+        for &byte in chunk {
+            for bit in 0..8 {
+                if lcn == total_clusters {
+                    return Ok(());
+                }
+                let allocated = byte & (1 << bit) != 0;
+                match current.as_mut() {
+                    Some(run) if run.allocated == allocated => run.clusters += 1,
+                    _ => {
+                        if let Some(run) = current.replace(ClusterRun { lcn, clusters: 1, allocated }) {
+                            finish(run, &mut map)?;
+                        }
+                    }
+                }
+                if allocated {
+                    if let Some(buckets) = buckets.as_mut() {
+                        buckets.allocated[(lcn / buckets.clusters_per_bucket) as usize] += 1;
+                    }
+                }
+                lcn += 1;
+            }
+        }
+        Ok(())
+    })?;
+    if lcn < total_clusters {
+        bail!("$Bitmap covers {} of {} clusters", lcn, total_clusters);
+    }
+    if let Some(run) = current {
+        finish(run, &mut map)?;
+    }
+    map.free_bytes = map.free_clusters * map.bytes_per_cluster;
+
+    if let (Some(path), Some(buckets)) = (png, buckets) {
+        write_strip(path, &buckets, total_clusters)?;
+        eprintln!("Heat strip written to {}", path.display());
+    }
+    // The totals close the object the runs were written into
+    let totals = output::to_json(&map)?;
+    match png {
+        Some(_) => writeln!(out, "{}", totals)?,
+        None => writeln!(out, "],{}", &totals[1..])?,
+    }
+    out.flush()?;
+    Ok(())
+}
+
+/// Writes one grayscale pixel column per bucket, repeated `STRIP_HEIGHT` times
+fn write_strip(path: &Path, buckets: &Buckets, total_clusters: u64) -> Result<()> {
+    let row: Vec<u8> = buckets
+        .allocated
+        .iter()
+        .enumerate()
+        .map(|(i, &allocated)| {
+            // The last bucket may cover fewer clusters than the others
+            let start = i as u64 * buckets.clusters_per_bucket;
+            let clusters = buckets.clusters_per_bucket.min(total_clusters - start);
+            255 - (allocated * 255 / clusters) as u8
+        })
+        .collect();
+
+    let file = File::create(path).context(format!("Failed to create {}", path.display()))?;
+    let mut encoder = png::Encoder::new(BufWriter::new(file), row.len() as u32, STRIP_HEIGHT);
+    encoder.set_color(png::ColorType::Grayscale);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(&row.repeat(STRIP_HEIGHT as usize))?;
+    writer.finish()?;
+    Ok(())
+}
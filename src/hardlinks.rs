@@ -0,0 +1,90 @@
+use anyhow::Result;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::io;
+
+use crate::filter::FileFilter;
+use crate::mft;
+use crate::output::{self, ColumnType, Field, Fields, TableRow};
+use crate::OutputFormat;
+
+/// A file reachable under more than one path
+#[derive(Debug, Serialize)]
+pub struct HardLinkedFile {
+    pub record_number: u64,
+    pub link_count: usize,
+    pub size: u64,
+    pub paths: Vec<String>,
+}
+
+impl HardLinkedFile {
+    /// Fields accepted by --fields, with the types of their CSV columns
+    pub const FIELDS: &'static [Field] = &[
+        ("record_number", ColumnType::Int),
+        ("link_count", ColumnType::Int),
+        ("size", ColumnType::Size),
+        ("paths", ColumnType::String),
+    ];
+}
+
+impl TableRow for HardLinkedFile {
+    fn table_header() -> Vec<&'static str> {
+        vec!["LINKS", "SIZE", "PATHS"]
+    }
+
+    fn table_row(&self) -> Vec<String> {
+        vec![
+            self.link_count.to_string(),
+            output::format_size(self.size),
+            self.paths.join(" | "),
+        ]
+    }
+
+    fn streaming_widths() -> Vec<usize> {
+        vec![5, 12, 0]
+    }
+}
+
+/// Lists the files with more than one hard link, with every path they are
+/// linked under. A file matches `filter` when its primary path does. The
+/// space of such files is counted once on the volume, so summing `size`
+/// over `paths` overstates usage.
+pub fn report(
+    volume: &str,
+    filter: &FileFilter,
+    limit: Option<usize>,
+    output: OutputFormat,
+    fields: &Fields,
+) -> Result<()> {
+    let mft = mft::open_mft(volume)?;
+    let mut parents = HashMap::new();
+    let mut files = Vec::new();
+
+    eprintln!("Looking for hard links...");
+    mft::iterate_files(&mft, |file| {
+        if file.is_directory() || limit.is_some_and(|limit| files.len() >= limit) {
+            return;
+        }
+        let paths = mft::hard_link_paths(&mft, file, &mut parents);
+        if paths.len() < 2 {
+            return;
+        }
+        let Some(info) = mft::parse_file_info(&mft, file) else {
+            return;
+        };
+        if !filter.matches(&info, file) {
+            return;
+        }
+
+        files.push(HardLinkedFile {
+            record_number: file.number(),
+            link_count: paths.len(),
+            size: info.size,
+            paths,
+        });
+    });
+    eprintln!("Found {} files with more than one link", files.len());
+
+    let stdout = io::stdout();
+    output::write_report(&mut stdout.lock(), &files, output, fields, HardLinkedFile::FIELDS)
+}
@@ -0,0 +1,256 @@
+use anyhow::{anyhow, bail, Context, Result};
+use ntfs_reader::api::NtfsAttributeType;
+use ntfs_reader::file::NtfsFile;
+use ntfs_reader::mft::Mft;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+use crate::mft;
+use crate::output;
+use crate::raw::{self, RawVolume};
+use crate::seal;
+
+/// A file listed in a manifest with the hash it is expected to have
+struct ManifestEntry {
+    path: String,
+    sha256: String,
+}
+
+/// Where the content of a manifest entry lives on the volume
+enum Target {
+    /// The file's unnamed $DATA attribute, copied out of its MFT record, or
+    /// its pieces when an $ATTRIBUTE_LIST spreads it over extension records
+    Data(Vec<Vec<u8>>),
+    Missing,
+    Unreadable(String),
+}
+
+/// Result line of `verify-hashes`, printed for every file that doesn't match
+#[derive(Serialize)]
+struct HashCheck {
+    path: String,
+    /// mismatch, missing or unreadable
+    status: &'static str,
+    expected: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    actual: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// Recomputes the SHA-256 of every file in `manifest` from its data runs on
+/// the raw volume, bypassing the file system and its caches, and prints one
+/// JSON line per file that is missing, unreadable or doesn't match. Files
+/// are hashed on `threads` workers, each with its own volume handle.
+pub fn verify_manifest(volume: &str, manifest: &Path, threads: Option<usize>) -> Result<()> {
+    let entries = read_manifest(manifest)?;
+    let mft = mft::open_mft(volume)?;
+    let volume_path = mft::normalize_volume_path(volume);
+    let mut raw = RawVolume::open(&volume_path)?;
+
+    eprintln!("Indexing paths...");
+    let index = mft::build_path_index(&mft);
+    let targets: Vec<Target> = entries
+        .iter()
+        .map(|entry| {
+            let Some(file) = index
+                .get(&mft::path_key(&entry.path))
+                .and_then(|&number| mft.get_record(number))
+            else {
+                return Target::Missing;
+            };
+            if file.is_directory() {
+                return Target::Unreadable("Path is a directory".to_string());
+            }
+            match data_attribute(&mft, &mut raw, &file) {
+                Ok(pieces) if pieces.is_empty() => {
+                    Target::Unreadable("File has no unnamed $DATA attribute".to_string())
+                }
+                Ok(pieces) => Target::Data(pieces),
+                Err(e) => Target::Unreadable(format!("{:#}", e)),
+            }
+        })
+        .collect();
+    drop(mft);
+    drop(raw);
+
+    let threads = threads
+        .unwrap_or_else(|| thread::available_parallelism().map_or(4, |n| n.get()))
+        .clamp(1, entries.len().max(1));
+    eprintln!("Hashing {} files on {} threads...", entries.len(), threads);
+
+    let next = AtomicUsize::new(0);
+    let mut hashes: Vec<(usize, Result<String>)> = thread::scope(|scope| {
+        let workers: Vec<_> = (0..threads)
+            .map(|_| {
+                scope.spawn(|| -> Result<Vec<(usize, Result<String>)>> {
+                    let mut raw = RawVolume::open(&volume_path)?;
+                    let mut hashed = Vec::new();
+                    loop {
+                        let i = next.fetch_add(1, Ordering::Relaxed);
+                        let Some(target) = targets.get(i) else {
+                            return Ok(hashed);
+                        };
+                        if let Target::Data(pieces) = target {
+                            hashed.push((i, hash_value(&mut raw, pieces)));
+                        }
+                    }
+                })
+            })
+            .collect();
+
+        workers
+            .into_iter()
+            .map(|worker| worker.join().unwrap_or_else(|_| Err(anyhow!("A hash worker panicked"))))
+            .collect::<Result<Vec<_>>>()
+            .map(|results| results.into_iter().flatten().collect())
+    })?;
+    hashes.sort_by_key(|(i, _)| *i);
+    let mut hashes = hashes.into_iter().peekable();
+
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    let mut failed = 0;
+
+    for (i, (entry, target)) in entries.iter().zip(targets).enumerate() {
+        let hashed = hashes.next_if(|(j, _)| *j == i).map(|(_, hash)| hash);
+        let (status, actual, error) = match (target, hashed) {
+            (Target::Missing, _) => ("missing", None, None),
+            (Target::Unreadable(error), _) => ("unreadable", None, Some(error)),
+            (Target::Data(_), Some(Ok(hash))) if hash.eq_ignore_ascii_case(&entry.sha256) => continue,
+            (Target::Data(_), Some(Ok(hash))) => ("mismatch", Some(hash), None),
+            (Target::Data(_), Some(Err(e))) => ("unreadable", None, Some(format!("{:#}", e))),
+            (Target::Data(_), None) => unreachable!("every readable file is hashed"),
+        };
+
+        let check = HashCheck {
+            path: entry.path.clone(),
+            status,
+            expected: entry.sha256.to_lowercase(),
+            actual,
+            error,
+        };
+        writeln!(out, "{}", output::to_json(&check)?)?;
+        failed += 1;
+    }
+    out.flush()?;
+
+    if failed > 0 {
+        bail!("{} of {} files failed verification", failed, entries.len());
+    }
+    eprintln!("All {} files match the manifest", entries.len());
+    Ok(())
+}
+
+/// Reads a CSV manifest with a header row. The `path` and `sha256` columns
+/// are used (in any order and field case); other columns are ignored, so a
+/// `list-files --output csv` export with an added hash column works as is.
+fn read_manifest(path: &Path) -> Result<Vec<ManifestEntry>> {
+    let file = File::open(path).context(format!("Failed to open manifest {}", path.display()))?;
+    let mut lines = BufReader::new(file).lines();
+
+    let header = lines.next().transpose()?.context("Manifest is empty")?;
+    let columns: Vec<String> = split_csv_line(&header)
+        .iter()
+        .map(|column| column.trim().replace('_', "").to_lowercase())
+        .collect();
+    let column = |name: &str| {
+        columns
+            .iter()
+            .position(|column| column == name)
+            .context(format!("Manifest has no '{}' column", name))
+    };
+    let path_column = column("path")?;
+    let hash_column = column("sha256")?;
+
+    let mut entries = Vec::new();
+    for (number, line) in lines.enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let fields = split_csv_line(&line);
+        let (Some(path), Some(sha256)) = (fields.get(path_column), fields.get(hash_column)) else {
+            bail!("Manifest line {} has too few columns", number + 2);
+        };
+        entries.push(ManifestEntry {
+            path: path.clone(),
+            sha256: sha256.trim().to_string(),
+        });
+    }
+    Ok(entries)
+}
+
+/// Splits one CSV line, undoing the quoting applied by `output::escape_csv`
+fn split_csv_line(line: &str) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(std::mem::take(&mut field)),
+            c => field.push(c),
+        }
+    }
+    fields.push(field);
+    fields
+}
+
+/// The unnamed $DATA attribute of a file, header included. A file too
+/// fragmented for its base record has an $ATTRIBUTE_LIST instead, and the
+/// attribute comes in pieces from the extension records it lists.
+fn data_attribute(mft: &Mft, raw: &mut RawVolume, file: &NtfsFile) -> Result<Vec<Vec<u8>>> {
+    let mut pieces = unnamed_attributes(file, NtfsAttributeType::Data as u32);
+    let Some(list) = unnamed_attributes(file, raw::ATTR_ATTRIBUTE_LIST).pop() else {
+        return Ok(pieces.into_iter().take(1).collect());
+    };
+
+    let mut value = Vec::new();
+    raw.read_value(&list, |chunk| {
+        value.extend_from_slice(chunk);
+        Ok(())
+    })
+    .context("Failed to read the $ATTRIBUTE_LIST")?;
+    for number in raw::attribute_list_records(&value, raw::ATTR_DATA) {
+        if number == file.number() {
+            continue;
+        }
+        let extension = mft
+            .get_record(number)
+            .context(format!("Extension record {} can't be read", number))?;
+        pieces.extend(unnamed_attributes(&extension, raw::ATTR_DATA));
+    }
+    Ok(pieces)
+}
+
+/// Every unnamed attribute of type `type_id` in a record, header included
+fn unnamed_attributes(file: &NtfsFile, type_id: u32) -> Vec<Vec<u8>> {
+    let mut found = Vec::new();
+    file.attributes(|attr| {
+        if attr.header.type_id == type_id && attr.header.name_length == 0 {
+            found.push(attr.data().to_vec());
+        }
+    });
+    found
+}
+
+fn hash_value(raw: &mut RawVolume, pieces: &[Vec<u8>]) -> Result<String> {
+    let mut hasher = Sha256::new();
+    raw.read_split_value(pieces, |chunk| {
+        hasher.update(chunk);
+        Ok(())
+    })?;
+    Ok(seal::encode_hex(&hasher.finalize()))
+}
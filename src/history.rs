@@ -0,0 +1,179 @@
+use anyhow::{bail, Context, Result};
+use rusqlite::types::Value;
+use rusqlite::{params, params_from_iter, Connection};
+use std::path::Path;
+use time::OffsetDateTime;
+
+use crate::cdc::CdcEncoder;
+use crate::journal::{self, EventModel, JournalEvent};
+use crate::output::{self, Fields};
+use crate::OutputFormat;
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS events (
+        usn INTEGER NOT NULL,
+        timestamp_ms INTEGER NOT NULL,
+        file_id TEXT NOT NULL,
+        parent_id TEXT NOT NULL,
+        reason INTEGER NOT NULL,
+        reason_str TEXT NOT NULL,
+        path TEXT NOT NULL,
+        kind TEXT,
+        kind_detail TEXT,
+        moved_with TEXT
+    );
+    CREATE INDEX IF NOT EXISTS events_timestamp ON events (timestamp_ms);
+";
+
+/// SQLite database the journal command stores its events in, for later
+/// `journal-query` runs. Events are written in one transaction per journal
+/// read; an interrupted run keeps everything up to its last read.
+pub struct EventDb {
+    conn: Connection,
+}
+
+impl EventDb {
+    /// Opens or creates the database and starts the first transaction
+    pub fn open(path: &Path) -> Result<Self> {
+        let conn = Connection::open(path)
+            .context(format!("Failed to open event database {}", path.display()))?;
+        conn.execute_batch(SCHEMA)?;
+        conn.execute_batch("BEGIN")?;
+        eprintln!("Storing events in {}", path.display());
+        Ok(EventDb { conn })
+    }
+
+    pub fn insert(&mut self, event: &JournalEvent) -> Result<()> {
+        let mut statement = self.conn.prepare_cached(
+            "INSERT INTO events (usn, timestamp_ms, file_id, parent_id, reason, reason_str, path,
+                                 kind, kind_detail, moved_with)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+        )?;
+        statement.execute(params![
+            event.usn,
+            event.timestamp_ms as i64,
+            event.file_id,
+            event.parent_id,
+            event.reason,
+            event.reason_str,
+            event.path,
+            event.kind,
+            event.kind_detail,
+            event.moved_with,
+        ])?;
+        Ok(())
+    }
+
+    /// Commits the events inserted so far
+    pub fn commit(&mut self) -> Result<()> {
+        self.conn.execute_batch("COMMIT; BEGIN")?;
+        Ok(())
+    }
+
+    pub fn close(self) -> Result<()> {
+        self.conn.execute_batch("COMMIT")?;
+        Ok(())
+    }
+}
+
+/// Conditions of a `journal-query` run; all given ones must hold
+pub struct QueryOptions {
+    /// SQL LIKE pattern matched against the path (case-insensitive for ASCII)
+    pub path_like: Option<String>,
+    pub since: Option<OffsetDateTime>,
+    pub until: Option<OffsetDateTime>,
+    /// Events with any of these reason bits
+    pub reason_mask: Option<u32>,
+    pub limit: Option<usize>,
+}
+
+/// Prints the stored events matching `options`, oldest first, in any of the
+/// journal command's output formats. `seq` numbers the results; `batch` is 0
+/// since reads of the original runs aren't kept.
+pub fn query(db: &Path, options: &QueryOptions, format: OutputFormat, fields: &Fields) -> Result<()> {
+    if !db.is_file() {
+        bail!("Event database {} doesn't exist", db.display());
+    }
+    let conn = Connection::open(db)
+        .context(format!("Failed to open event database {}", db.display()))?;
+
+    let mut conditions = Vec::new();
+    let mut values = Vec::new();
+    if let Some(ref path_like) = options.path_like {
+        conditions.push("path LIKE ?");
+        values.push(Value::Text(path_like.clone()));
+    }
+    if let Some(since) = options.since {
+        conditions.push("timestamp_ms >= ?");
+        values.push(Value::Integer(unix_ms(since)));
+    }
+    if let Some(until) = options.until {
+        conditions.push("timestamp_ms < ?");
+        values.push(Value::Integer(unix_ms(until)));
+    }
+    if let Some(mask) = options.reason_mask {
+        conditions.push("reason & ? != 0");
+        values.push(Value::Integer(mask as i64));
+    }
+
+    let mut sql = String::from(
+        "SELECT usn, timestamp_ms, file_id, parent_id, reason, reason_str, path,
+                kind, kind_detail, moved_with
+         FROM events",
+    );
+    if !conditions.is_empty() {
+        sql.push_str(" WHERE ");
+        sql.push_str(&conditions.join(" AND "));
+    }
+    sql.push_str(" ORDER BY timestamp_ms, usn");
+    if let Some(limit) = options.limit {
+        sql.push_str(&format!(" LIMIT {}", limit));
+    }
+
+    let mut statement = conn.prepare(&sql)?;
+    let rows = statement.query_map(params_from_iter(values), |row| {
+        Ok(JournalEvent {
+            seq: 0,
+            batch: 0,
+            usn: row.get(0)?,
+            timestamp_ms: row.get::<_, i64>(1)? as u128,
+            timestamp_filetime: None,
+            file_id: row.get(2)?,
+            parent_id: row.get(3)?,
+            reason: row.get(4)?,
+            reason_str: row.get(5)?,
+            path: row.get(6)?,
+            kind: row.get(7)?,
+            kind_detail: row.get(8)?,
+            moved_with: row.get(9)?,
+            cdc: None,
+        })
+    })?;
+
+    let mut cdc = matches!(format, OutputFormat::Cdc).then(|| CdcEncoder::new(None));
+    let mut events = Vec::new();
+    for (i, event) in rows.enumerate() {
+        let mut event = event?;
+        event.seq = i as u64 + 1;
+        event.timestamp_filetime =
+            OffsetDateTime::from_unix_timestamp_nanos(event.timestamp_ms as i128 * 1_000_000)
+                .ok()
+                .and_then(output::filetime);
+        if let Some(ref mut cdc) = cdc {
+            event.cdc = cdc.envelope(&event, fields)?;
+        }
+        events.push(event);
+    }
+    eprintln!("{} matching events", events.len());
+
+    let event_model = if events.iter().any(|event| event.kind.is_some()) {
+        EventModel::Notify
+    } else {
+        EventModel::Usn
+    };
+    journal::write_events(&mut std::io::stdout(), &events, format, event_model, fields)
+}
+
+fn unix_ms(time: OffsetDateTime) -> i64 {
+    (time.unix_timestamp_nanos() / 1_000_000) as i64
+}
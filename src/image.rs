@@ -0,0 +1,144 @@
+use anyhow::{bail, Context, Result};
+use std::fs::File;
+use std::io::{Read, SeekFrom};
+use std::path::Path;
+
+#[cfg(feature = "ewf")]
+use crate::ewf::EwfReader;
+use crate::raw::{self, VolumeSource};
+
+/// Sector sizes a GPT header is looked for with: 512-byte and 4Kn disks
+const GPT_SECTOR_SIZES: &[u64] = &[512, 4096];
+
+/// Partition entries read from a GPT at most; Windows creates 128
+const MAX_GPT_ENTRIES: u32 = 1024;
+
+/// MBR partition types of extended partitions, whose logical drives are
+/// chained through further boot records that aren't followed
+const MBR_EXTENDED: &[u8] = &[0x05, 0x0F];
+
+/// MBR partition type of the protective entry in front of a GPT
+const MBR_GPT_PROTECTIVE: u8 = 0xEE;
+
+/// Opens a disk or volume image: raw (dd) images as they are, EnCase (E01)
+/// images through their media when built with the `ewf` feature
+pub fn open(path: &Path) -> Result<Box<dyn VolumeSource>> {
+    let mut file = File::open(path).context(format!("Failed to open image {}", path.display()))?;
+    let mut signature = [0u8; 8];
+    let read = file.read(&mut signature)?;
+    if read == signature.len() && signature.starts_with(b"EVF") {
+        #[cfg(feature = "ewf")]
+        return Ok(Box::new(EwfReader::open(path)?));
+        #[cfg(not(feature = "ewf"))]
+        bail!("{} is an EnCase image; reading it needs a build with --features ewf", path.display());
+    }
+    Ok(Box::new(file))
+}
+
+/// Byte offset of the NTFS volume in an image: `offset` when given,
+/// 0 for images of a single volume, otherwise the start of the one NTFS
+/// partition in the image's GPT or MBR partition table. Partitions are
+/// recognized by their boot sector, not their type, since NTFS and exFAT
+/// share an MBR type and Windows data partitions share a GPT one.
+pub fn volume_offset(image: &mut dyn VolumeSource, path: &Path, offset: Option<u64>) -> Result<u64> {
+    if let Some(offset) = offset {
+        return Ok(offset);
+    }
+    if is_ntfs_at(image, 0) {
+        return Ok(0);
+    }
+
+    let candidates = match gpt_partitions(image)? {
+        Some(partitions) => partitions,
+        None => mbr_partitions(image)?,
+    };
+    let volumes: Vec<u64> = candidates
+        .into_iter()
+        .filter(|&offset| is_ntfs_at(image, offset))
+        .collect();
+    match volumes[..] {
+        [] => bail!(
+            "No NTFS partition found in {}; give its start with --partition-offset",
+            path.display()
+        ),
+        [offset] => {
+            eprintln!("Using the NTFS partition at byte offset {}", offset);
+            Ok(offset)
+        }
+        _ => bail!(
+            "{} holds several NTFS partitions, at byte offsets {}; choose one with --partition-offset",
+            path.display(),
+            volumes.iter().map(u64::to_string).collect::<Vec<_>>().join(", ")
+        ),
+    }
+}
+
+/// Whether an NTFS boot sector starts at `offset`
+fn is_ntfs_at(image: &mut dyn VolumeSource, offset: u64) -> bool {
+    read_at(image, offset, 512).is_ok_and(|boot| &boot[3..11] == b"NTFS    ")
+}
+
+/// Start offsets of the used entries of a GPT, or `None` if the image has
+/// no GPT header
+fn gpt_partitions(image: &mut dyn VolumeSource) -> Result<Option<Vec<u64>>> {
+    for &sector in GPT_SECTOR_SIZES {
+        let Ok(header) = read_at(image, sector, 92) else {
+            continue;
+        };
+        if &header[0..8] != b"EFI PART" {
+            continue;
+        }
+
+        let field = |offset| raw::u32_at(&header, offset).unwrap_or(0);
+        let count = field(0x50).min(MAX_GPT_ENTRIES) as usize;
+        let entry_size = field(0x54) as usize;
+        // The UEFI specification has entries of 128 bytes times a power of two
+        if !entry_size.is_power_of_two() || !(128..=4096).contains(&entry_size) {
+            bail!("Invalid GPT partition entry size {}", entry_size);
+        }
+        let entries_at = raw::u64_at(&header, 0x48)
+            .and_then(|lba| lba.checked_mul(sector))
+            .context("Invalid GPT partition entry location")?;
+        let entries = read_at(image, entries_at, count * entry_size)
+            .context("Failed to read the GPT partition entries")?;
+
+        let partitions = entries
+            .chunks_exact(entry_size)
+            // An all-zero type GUID marks an unused entry
+            .filter(|entry| entry[0..16].iter().any(|&byte| byte != 0))
+            .filter_map(|entry| raw::u64_at(entry, 0x20)?.checked_mul(sector))
+            .collect();
+        return Ok(Some(partitions));
+    }
+    Ok(None)
+}
+
+/// Start offsets of the primary partitions of an MBR
+fn mbr_partitions(image: &mut dyn VolumeSource) -> Result<Vec<u64>> {
+    let mbr = read_at(image, 0, 512).context("Failed to read the first sector of the image")?;
+    if mbr[0x1FE..0x200] != [0x55, 0xAA] {
+        bail!("The image has no partition table and doesn't start with an NTFS volume");
+    }
+
+    let mut partitions = Vec::new();
+    for entry in mbr[0x1BE..0x1FE].chunks_exact(16) {
+        let kind = entry[4];
+        let start = raw::u32_at(entry, 8).unwrap_or(0) as u64;
+        if kind == 0 || kind == MBR_GPT_PROTECTIVE || start == 0 {
+            continue;
+        }
+        if MBR_EXTENDED.contains(&kind) {
+            eprintln!("Skipping the extended partition at sector {}: logical drives aren't searched", start);
+            continue;
+        }
+        partitions.push(start * 512);
+    }
+    Ok(partitions)
+}
+
+fn read_at(image: &mut dyn VolumeSource, offset: u64, len: usize) -> Result<Vec<u8>> {
+    let mut buffer = vec![0u8; len];
+    image.seek(SeekFrom::Start(offset))?;
+    image.read_exact(&mut buffer)?;
+    Ok(buffer)
+}
@@ -1,136 +1,1615 @@
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
+use ed25519_dalek::SigningKey;
 use ntfs_reader::journal::{Journal, JournalOptions, NextUsn};
-use ntfs_reader::volume::Volume;
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use std::io::Write;
 
+use crate::capture::{CaptureHeader, CaptureReader, CapturedEvent, CaptureWriter};
+use crate::cdc::CdcEncoder;
+use crate::filter::PathScope;
+use crate::history::EventDb;
+use crate::ring::{RingSpec, RingWriter};
+use crate::seal::SealOptions;
+use crate::shadow;
+use crate::mft;
+use crate::output::{self, escape_csv, ColumnType, Field, Fields, Sink, TableRow, TeeSink, Warning};
+use crate::volume::{self, query_usn_journal};
 use crate::OutputFormat;
 
+const USN_REASON_DATA_OVERWRITE: u32 = 0x00000001;
+const USN_REASON_DATA_EXTEND: u32 = 0x00000002;
+const USN_REASON_DATA_TRUNCATION: u32 = 0x00000004;
+const USN_REASON_NAMED_DATA_OVERWRITE: u32 = 0x00000010;
+const USN_REASON_NAMED_DATA_EXTEND: u32 = 0x00000020;
+const USN_REASON_NAMED_DATA_TRUNCATION: u32 = 0x00000040;
+pub const USN_REASON_FILE_CREATE: u32 = 0x00000100;
+pub const USN_REASON_FILE_DELETE: u32 = 0x00000200;
+pub const USN_REASON_SECURITY_CHANGE: u32 = 0x00000800;
+pub const USN_REASON_RENAME_OLD_NAME: u32 = 0x00001000;
+pub const USN_REASON_RENAME_NEW_NAME: u32 = 0x00002000;
+pub const USN_REASON_BASIC_INFO_CHANGE: u32 = 0x00008000;
+pub const USN_REASON_CLOSE: u32 = 0x80000000;
+
+const DATA_CHANGE_MASK: u32 = USN_REASON_DATA_OVERWRITE
+    | USN_REASON_DATA_EXTEND
+    | USN_REASON_DATA_TRUNCATION
+    | USN_REASON_NAMED_DATA_OVERWRITE
+    | USN_REASON_NAMED_DATA_EXTEND
+    | USN_REASON_NAMED_DATA_TRUNCATION;
+
+/// Every USN_REASON_* flag, in bit order
+const REASONS: &[(&str, u32)] = &[
+    ("DATA_OVERWRITE", USN_REASON_DATA_OVERWRITE),
+    ("DATA_EXTEND", USN_REASON_DATA_EXTEND),
+    ("DATA_TRUNCATION", USN_REASON_DATA_TRUNCATION),
+    ("NAMED_DATA_OVERWRITE", USN_REASON_NAMED_DATA_OVERWRITE),
+    ("NAMED_DATA_EXTEND", USN_REASON_NAMED_DATA_EXTEND),
+    ("NAMED_DATA_TRUNCATION", USN_REASON_NAMED_DATA_TRUNCATION),
+    ("FILE_CREATE", USN_REASON_FILE_CREATE),
+    ("FILE_DELETE", USN_REASON_FILE_DELETE),
+    ("EA_CHANGE", 0x00000400),
+    ("SECURITY_CHANGE", 0x00000800),
+    ("RENAME_OLD_NAME", USN_REASON_RENAME_OLD_NAME),
+    ("RENAME_NEW_NAME", USN_REASON_RENAME_NEW_NAME),
+    ("INDEXABLE_CHANGE", 0x00004000),
+    ("BASIC_INFO_CHANGE", USN_REASON_BASIC_INFO_CHANGE),
+    ("HARD_LINK_CHANGE", 0x00010000),
+    ("COMPRESSION_CHANGE", 0x00020000),
+    ("ENCRYPTION_CHANGE", 0x00040000),
+    ("OBJECT_ID_CHANGE", 0x00080000),
+    ("REPARSE_POINT_CHANGE", 0x00100000),
+    ("STREAM_CHANGE", 0x00200000),
+    ("TRANSACTED_CHANGE", 0x00400000),
+    ("INTEGRITY_CHANGE", 0x00800000),
+    ("DESIRED_STORAGE_CLASS_CHANGE", 0x01000000),
+    ("CLOSE", USN_REASON_CLOSE),
+];
+
+/// Combines reason names into a mask. A name is either a full flag name
+/// (`USN_REASON_FILE_CREATE`, `file_create`) or a single word that selects
+/// every flag containing it (`rename` = RENAME_OLD_NAME | RENAME_NEW_NAME).
+pub fn reason_mask_from_names(names: &[String]) -> Result<u32> {
+    let mut mask = 0;
+    for raw in names {
+        let upper = raw.trim().to_uppercase();
+        let name = upper.strip_prefix("USN_REASON_").unwrap_or(&upper);
+        
+        let exact = REASONS.iter().find(|(n, _)| *n == name);
+        let bits = match exact {
+            Some((_, bit)) => *bit,
+            None => REASONS
+                .iter()
+                .filter(|(n, _)| n.split('_').any(|word| word == name))
+                .fold(0, |bits, (_, bit)| bits | bit),
+        };
+        if bits == 0 {
+            bail!("Unknown reason: {} (run `reasons` for the list)", raw);
+        }
+        mask |= bits;
+    }
+    Ok(mask)
+}
+
+/// Prints the reason table, or the mask for `names` when any are given
+pub fn print_reasons(names: &[String]) -> Result<()> {
+    if !names.is_empty() {
+        let mask = reason_mask_from_names(names)?;
+        println!("0x{:08X} ({})", mask, mask);
+        return Ok(());
+    }
+    
+    for (name, bit) in REASONS {
+        println!("0x{:08X}  {:>10}  USN_REASON_{}", bit, bit, name);
+    }
+    Ok(())
+}
+
+/// How USN reasons are presented to consumers
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EventModel {
+    /// Raw USN reason bits only
+    Usn,
+    /// Additionally map reasons to the create/modify/rename/remove model used by notify/inotify
+    Notify,
+}
+
+impl std::str::FromStr for EventModel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "usn" | "raw" => Ok(EventModel::Usn),
+            "notify" | "inotify" => Ok(EventModel::Notify),
+            _ => Err(format!("Invalid event model: {}", s)),
+        }
+    }
+}
+
+/// Maps an (accumulated) USN reason mask to a notify-style event kind and
+/// sub-kind. When several reasons are set, the most significant one wins,
+/// in the order remove > create > rename > data > metadata > close.
+fn notify_kind(reason: u32) -> (&'static str, &'static str) {
+    if reason & USN_REASON_FILE_DELETE != 0 {
+        ("remove", "any")
+    } else if reason & USN_REASON_FILE_CREATE != 0 {
+        ("create", "any")
+    } else if reason & USN_REASON_RENAME_OLD_NAME != 0 {
+        ("modify", "name-from")
+    } else if reason & USN_REASON_RENAME_NEW_NAME != 0 {
+        ("modify", "name-to")
+    } else if reason & DATA_CHANGE_MASK != 0 {
+        ("modify", "data")
+    } else if reason & !USN_REASON_CLOSE != 0 {
+        ("modify", "metadata")
+    } else if reason & USN_REASON_CLOSE != 0 {
+        ("access", "close")
+    } else {
+        ("other", "any")
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct JournalEvent {
+    /// Position of the event in this run's output, starting at 1. Gaps or
+    /// reordering on the consumer side mean events were lost or shuffled.
+    #[serde(default)]
+    pub seq: u64,
+    /// Journal read the event came from, starting at 1
+    #[serde(default)]
+    pub batch: u64,
     pub usn: i64,
     pub timestamp_ms: u128,
+    /// Raw 100ns FILETIME of the event, included with --filetime
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub timestamp_filetime: Option<u64>,
     pub file_id: String,
     pub parent_id: String,
     pub reason: u32,
     pub reason_str: String,
     pub path: String,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub kind: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub kind_detail: Option<String>,
+    /// For events synthesized by --expand-moves, the new path of the moved
+    /// directory that implied them
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub moved_with: Option<String>,
+    /// Change envelope written instead of the event by --output cdc
+    #[serde(skip)]
+    pub cdc: Option<serde_json::Value>,
 }
 
 impl JournalEvent {
-    fn from_usn_record(record: &ntfs_reader::journal::UsnRecord) -> Self {
+    /// Fields accepted by --fields, with the types of their CSV columns
+    pub const FIELDS: &'static [Field] = &[
+        ("seq", ColumnType::Int),
+        ("batch", ColumnType::Int),
+        ("usn", ColumnType::Int),
+        ("timestamp_ms", ColumnType::Int),
+        ("timestamp_filetime", ColumnType::Int),
+        ("file_id", ColumnType::String),
+        ("parent_id", ColumnType::String),
+        ("reason", ColumnType::Int),
+        ("reason_str", ColumnType::String),
+        ("path", ColumnType::String),
+        ("kind", ColumnType::String),
+        ("kind_detail", ColumnType::String),
+        ("moved_with", ColumnType::String),
+    ];
+    
+    /// Live and captured records go through the same conversion, so a
+    /// replayed capture produces exactly the events the live run would have
+    fn from_capture(record: &CapturedEvent) -> Self {
         JournalEvent {
+            seq: 0,
+            batch: 0,
             usn: record.usn,
-            timestamp_ms: record.timestamp.as_millis(),
-            file_id: format!("{:?}", record.file_id),
-            parent_id: format!("{:?}", record.parent_id),
+            timestamp_ms: record.timestamp_ns as u128 / 1_000_000,
+            timestamp_filetime: time::OffsetDateTime::from_unix_timestamp_nanos(
+                record.timestamp_ns as i128,
+            )
+            .ok()
+            .and_then(output::filetime),
+            file_id: record.file_id.clone(),
+            parent_id: record.parent_id.clone(),
             reason: record.reason,
             reason_str: Journal::get_reason_str(record.reason),
-            path: record.path.to_string_lossy().to_string(),
+            path: output::display_path(Path::new(&record.path)),
+            kind: None,
+            kind_detail: None,
+            moved_with: None,
+            cdc: None,
+        }
+    }
+    
+    fn apply_event_model(&mut self, model: EventModel) {
+        if model == EventModel::Notify {
+            let (kind, detail) = notify_kind(self.reason);
+            self.kind = Some(kind.to_string());
+            self.kind_detail = Some(detail.to_string());
+        }
+    }
+}
+
+impl TableRow for JournalEvent {
+    fn table_header() -> Vec<&'static str> {
+        vec!["TIME", "USN", "REASON", "PATH"]
+    }
+    
+    fn table_row(&self) -> Vec<String> {
+        let time = time::OffsetDateTime::from_unix_timestamp_nanos(self.timestamp_ms as i128 * 1_000_000)
+            .ok()
+            .and_then(|t| t.format(&time::format_description::well_known::Rfc3339).ok())
+            .unwrap_or_else(|| self.timestamp_ms.to_string());
+        let reason = match &self.kind {
+            Some(kind) => format!("{} ({})", self.reason_str, kind),
+            None => self.reason_str.clone(),
+        };
+        vec![time, self.usn.to_string(), reason, self.path.clone()]
+    }
+    
+    fn streaming_widths() -> Vec<usize> {
+        vec![29, 12, 40, 0]
+    }
+}
+
+/// Files the close coalescer holds events of before it emits the older half
+/// of them without waiting for their close
+const MAX_PENDING_CLOSES: usize = 100_000;
+
+/// Folds the intermediate events of a file into a single event emitted when
+/// its handle is closed, carrying every reason seen since the last close.
+/// Files kept open for a long time, like logs, would otherwise pile up
+/// until the run ends, so past `MAX_PENDING_CLOSES` the files waiting the
+/// longest are emitted early with the reasons gathered so far. Only files
+/// with a reason in `mask` are emitted, as closes are read whatever the
+/// mask.
+struct CloseCoalescer {
+    /// Latest event of each file awaiting a close, carrying every reason since
+    pending: HashMap<String, JournalEvent>,
+    /// Reasons given with --reason-mask
+    mask: u32,
+}
+
+impl CloseCoalescer {
+    fn new(mask: u32) -> Self {
+        CloseCoalescer {
+            pending: HashMap::new(),
+            mask,
+        }
+    }
+
+    /// The events ready to be emitted after `event`: itself if it closes its
+    /// file, plus any emitted early
+    fn push(&mut self, mut event: JournalEvent) -> Vec<JournalEvent> {
+        if let Some(previous) = self.pending.remove(&event.file_id) {
+            event.reason |= previous.reason;
+        }
+        
+        if event.reason & USN_REASON_CLOSE != 0 {
+            return self.emit(event).into_iter().collect();
+        }
+        self.pending.insert(event.file_id.clone(), event);
+        if self.pending.len() <= MAX_PENDING_CLOSES {
+            return Vec::new();
+        }
+        
+        let mut usns: Vec<i64> = self.pending.values().map(|event| event.usn).collect();
+        let cutoff = *usns.select_nth_unstable(MAX_PENDING_CLOSES / 2).1;
+        self.expire(cutoff)
+    }
+    
+    /// Emits the files whose last event is older than `cutoff` without
+    /// waiting for their close, in USN order
+    fn expire(&mut self, cutoff: i64) -> Vec<JournalEvent> {
+        let expired: Vec<String> = self
+            .pending
+            .iter()
+            .filter(|(_, event)| event.usn < cutoff)
+            .map(|(file_id, _)| file_id.clone())
+            .collect();
+        let expired: Vec<JournalEvent> = expired
+            .iter()
+            .filter_map(|file_id| self.pending.remove(file_id))
+            .collect();
+        let mut early: Vec<JournalEvent> = expired.into_iter().filter_map(|event| self.emit(event)).collect();
+        early.sort_by_key(|event| event.usn);
+        
+        let message = format!("{} files still open were emitted without waiting for their close", early.len());
+        Warning::new("close_overflow", message).emit();
+        early
+    }
+    
+    /// USN of the oldest event held for a file still open, which `expire`
+    /// would emit after newer events
+    fn oldest_pending(&self) -> Option<i64> {
+        self.pending.values().map(|event| event.usn).min()
+    }
+    
+    /// `event` with the names of all its reasons, unless none is in the mask
+    fn emit(&self, mut event: JournalEvent) -> Option<JournalEvent> {
+        if event.reason & self.mask == 0 {
+            return None;
+        }
+        event.reason_str = Journal::get_reason_str(event.reason);
+        Some(event)
+    }
+}
+
+static STABLE_ORDER: OnceLock<bool> = OnceLock::new();
+
+/// Emits journal events strictly in USN order (--stable-order)
+pub fn set_stable_order(enabled: bool) {
+    let _ = STABLE_ORDER.set(enabled);
+}
+
+/// Events `StableOrder` holds before it makes the close coalescer emit its
+/// oldest open files early, so a file kept open can't hold output back
+/// without bound
+const MAX_HELD_EVENTS: usize = 100_000;
+
+/// Puts events back in USN order for --stable-order. Only --on-close emits
+/// them out of order: a file emitted early carries the USN of its last
+/// event, older than the events emitted before it. Events are held until
+/// no file the coalescer still waits on is older. Events sharing a USN,
+/// like those --expand-moves derives from a rename, keep their order.
+#[derive(Default)]
+struct StableOrder {
+    /// Events by USN, then by arrival
+    held: BTreeMap<(i64, u64), JournalEvent>,
+    arrived: u64,
+}
+
+impl StableOrder {
+    /// The events that can be emitted after `events`, in USN order.
+    /// `coalescer` is the one whose open files may still be emitted with
+    /// older USNs, or `None` when nothing else can come out of order.
+    fn push(&mut self, events: Vec<JournalEvent>, coalescer: Option<&mut CloseCoalescer>) -> Vec<JournalEvent> {
+        self.hold(events);
+        let oldest = match coalescer {
+            Some(coalescer) => {
+                if self.held.len() > MAX_HELD_EVENTS {
+                    let (&(cutoff, _), _) = self.held.iter().nth(self.held.len() / 2).unwrap();
+                    let early = coalescer.expire(cutoff);
+                    self.hold(early);
+                }
+                coalescer.oldest_pending()
+            }
+            None => None,
+        };
+        
+        let held = match oldest {
+            Some(usn) => self.held.split_off(&(usn, 0)),
+            None => BTreeMap::new(),
+        };
+        std::mem::replace(&mut self.held, held).into_values().collect()
+    }
+    
+    fn hold(&mut self, events: Vec<JournalEvent>) {
+        for event in events {
+            self.held.insert((event.usn, self.arrived), event);
+            self.arrived += 1;
+        }
+    }
+}
+
+/// Tracks every path on the volume so that renaming or moving a directory
+/// can be expanded into rename events for each of its descendants, which
+/// the journal itself only records for the directory.
+struct MoveExpander {
+    /// `mft::path_key` of every path -> the file it leads to
+    paths: BTreeMap<String, IndexedFile>,
+    /// Old path of files whose RENAME_OLD_NAME record was seen, by file ID
+    renaming: HashMap<String, String>,
+}
+
+/// A file known to `MoveExpander`
+struct IndexedFile {
+    /// Path as reported by the MFT or the journal
+    path: String,
+    /// File reference in the journal's form; `Normal(<record number>)`,
+    /// without sequence number, for files indexed from the MFT
+    file_id: String,
+}
+
+impl IndexedFile {
+    fn from_event(event: &JournalEvent) -> Self {
+        IndexedFile {
+            path: event.path.clone(),
+            file_id: event.file_id.clone(),
+        }
+    }
+}
+
+impl MoveExpander {
+    fn load(volume: &str) -> Result<Self> {
+        eprintln!("Indexing paths for --expand-moves...");
+        let mut paths = BTreeMap::new();
+        mft::scan_records(volume, |record| {
+            let file_id = format!("Normal({})", record.record_number);
+            paths.insert(mft::path_key(&record.path), IndexedFile { path: record.path, file_id });
+            Ok(())
+        })?;
+        eprintln!("Indexed {} paths", paths.len());
+        
+        Ok(MoveExpander {
+            paths,
+            renaming: HashMap::new(),
+        })
+    }
+    
+    /// Updates the index from a raw event and returns the events synthesized
+    /// for the descendants of a moved directory, old name then new name
+    fn observe(&mut self, event: &JournalEvent) -> Vec<JournalEvent> {
+        let key = mft::path_key(&event.path);
+        if event.reason & USN_REASON_FILE_DELETE != 0 {
+            self.paths.remove(&key);
+        } else if event.reason & USN_REASON_FILE_CREATE != 0 {
+            self.paths.insert(key.clone(), IndexedFile::from_event(event));
+        }
+        
+        if event.reason & USN_REASON_RENAME_OLD_NAME != 0 {
+            self.renaming.insert(event.file_id.clone(), event.path.clone());
+            return Vec::new();
+        }
+        if event.reason & USN_REASON_RENAME_NEW_NAME == 0 {
+            return Vec::new();
+        }
+        
+        let Some(old_path) = self.renaming.remove(&event.file_id) else {
+            self.paths.insert(key, IndexedFile::from_event(event));
+            return Vec::new();
+        };
+        let old_key = mft::path_key(&old_path);
+        self.paths.remove(&old_key);
+        self.paths.insert(key, IndexedFile::from_event(event));
+        
+        // Keys are compared instead of paths, which the MFT and the journal
+        // may spell with different prefixes and case
+        let prefix = format!("{}\\", old_key);
+        let depth = old_key.matches('\\').count();
+        let descendants: Vec<String> = self
+            .paths
+            .range(prefix.clone()..)
+            .take_while(|(key, _)| key.starts_with(&prefix))
+            .map(|(key, _)| key.clone())
+            .collect();
+        
+        let mut synthesized = Vec::with_capacity(descendants.len() * 2);
+        for key in descendants {
+            let Some(old) = self.paths.remove(&key) else {
+                continue;
+            };
+            let below = key.matches('\\').count() - depth;
+            let new = format!("{}{}", event.path, last_components(&old.path, below));
+            let new_key = mft::path_key(&new);
+            // Parents sort before their children, so the parent is already re-indexed
+            let parent_id = new_key
+                .rsplit_once('\\')
+                .and_then(|(parent, _)| self.paths.get(parent))
+                .map(|parent| parent.file_id.clone())
+                .unwrap_or_default();
+            synthesized.push(synthesize(event, &old, &parent_id, USN_REASON_RENAME_OLD_NAME));
+            let new = IndexedFile { path: new, file_id: old.file_id };
+            synthesized.push(synthesize(event, &new, &parent_id, USN_REASON_RENAME_NEW_NAME));
+            self.paths.insert(new_key, new);
+        }
+        synthesized
+    }
+}
+
+/// The last `count` components of a path, each with its leading separator
+fn last_components(path: &str, count: usize) -> &str {
+    match count.checked_sub(1) {
+        Some(n) => path.rmatch_indices('\\').nth(n).map_or(path, |(i, _)| &path[i..]),
+        None => "",
+    }
+}
+
+/// Rename event for a descendant of the directory moved by `trigger`. The
+/// parent is the same in both events, as moving an ancestor doesn't change it.
+fn synthesize(trigger: &JournalEvent, file: &IndexedFile, parent_id: &str, reason: u32) -> JournalEvent {
+    JournalEvent {
+        seq: 0,
+        batch: 0,
+        usn: trigger.usn,
+        timestamp_ms: trigger.timestamp_ms,
+        timestamp_filetime: trigger.timestamp_filetime,
+        file_id: file.file_id.clone(),
+        parent_id: parent_id.to_string(),
+        reason,
+        reason_str: Journal::get_reason_str(reason),
+        path: file.path.clone(),
+        kind: None,
+        kind_detail: None,
+        moved_with: Some(trigger.path.clone()),
+        cdc: None,
+    }
+}
+
+/// Pacing applied when replaying historical events
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ReplaySpeed {
+    /// Emit events as fast as they are read
+    Unlimited,
+    /// Reproduce the original spacing between events, sped up by the given factor
+    Factor(f64),
+    /// Emit at most this many events per second
+    Rate(f64),
+}
+
+impl std::str::FromStr for ReplaySpeed {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim().to_lowercase();
+        let parse_positive = |value: &str| {
+            value
+                .parse::<f64>()
+                .ok()
+                .filter(|v| v.is_finite() && *v > 0.0)
+                .ok_or_else(|| format!("Invalid replay speed: {}", s))
+        };
+        
+        match s.as_str() {
+            "unlimited" | "max" => Ok(ReplaySpeed::Unlimited),
+            "realtime" | "real-time" => Ok(ReplaySpeed::Factor(1.0)),
+            _ => {
+                if let Some(factor) = s.strip_suffix('x') {
+                    Ok(ReplaySpeed::Factor(parse_positive(factor)?))
+                } else if let Some(rate) = s.strip_suffix("/s") {
+                    Ok(ReplaySpeed::Rate(parse_positive(rate)?))
+                } else {
+                    Err(format!("Invalid replay speed: {}", s))
+                }
+            }
+        }
+    }
+}
+
+/// Delays emission so replayed events arrive at the requested cadence
+struct ReplayPacer {
+    speed: ReplaySpeed,
+    started: Instant,
+    first_timestamp_ms: Option<u128>,
+    emitted: u64,
+}
+
+impl ReplayPacer {
+    fn new(speed: ReplaySpeed) -> Self {
+        ReplayPacer {
+            speed,
+            started: Instant::now(),
+            first_timestamp_ms: None,
+            emitted: 0,
+        }
+    }
+    
+    fn wait(&mut self, event: &JournalEvent) {
+        let target = match self.speed {
+            ReplaySpeed::Unlimited => return,
+            ReplaySpeed::Factor(factor) => {
+                let first = *self.first_timestamp_ms.get_or_insert(event.timestamp_ms);
+                let offset_ms = event.timestamp_ms.saturating_sub(first) as f64;
+                Duration::from_secs_f64(offset_ms / 1000.0 / factor)
+            }
+            ReplaySpeed::Rate(rate) => Duration::from_secs_f64(self.emitted as f64 / rate),
+        };
+        self.emitted += 1;
+        
+        if let Some(delay) = target.checked_sub(self.started.elapsed()) {
+            thread::sleep(delay);
+        }
+    }
+}
+
+/// Groups continuous-mode events so each write carries up to `size` of
+/// them, while no event waits longer than `max_latency` to be written
+struct Batcher {
+    size: usize,
+    max_latency: Duration,
+    events: Vec<JournalEvent>,
+    /// When the oldest buffered event arrived
+    oldest: Option<Instant>,
+}
+
+impl Batcher {
+    fn new(size: usize, max_latency: Duration) -> Self {
+        Batcher {
+            size: size.max(1),
+            max_latency,
+            events: Vec::new(),
+            oldest: None,
+        }
+    }
+    
+    /// Buffers an event; returns true once the batch is full
+    fn push(&mut self, event: JournalEvent) -> bool {
+        self.oldest.get_or_insert_with(Instant::now);
+        self.events.push(event);
+        self.events.len() >= self.size
+    }
+    
+    /// Time until the buffered events must be written, if there are any
+    fn time_left(&self) -> Option<Duration> {
+        self.oldest
+            .map(|oldest| self.max_latency.saturating_sub(oldest.elapsed()))
+    }
+    
+    fn is_due(&self) -> bool {
+        self.time_left() == Some(Duration::ZERO)
+    }
+    
+    fn take(&mut self) -> Vec<JournalEvent> {
+        self.oldest = None;
+        std::mem::take(&mut self.events)
+    }
+}
+
+/// Sampling rate expressed as "keep N out of every M events"
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SampleRate {
+    pub keep: u64,
+    pub out_of: u64,
+}
+
+impl std::str::FromStr for SampleRate {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid sample rate (expected N/M, e.g. 1/100): {}", s);
+        let (keep, out_of) = s.split_once('/').ok_or_else(invalid)?;
+        let keep: u64 = keep.trim().parse().map_err(|_| invalid())?;
+        let out_of: u64 = out_of.trim().parse().map_err(|_| invalid())?;
+        
+        if out_of == 0 || keep == 0 || keep > out_of {
+            return Err(invalid());
+        }
+        Ok(SampleRate { keep, out_of })
+    }
+}
+
+/// How events are selected when sampling
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SampleMode {
+    /// Keep or drop every event of a file together, based on a hash of its file ID
+    Frn,
+    /// Pick events independently at random
+    Random,
+}
+
+impl std::str::FromStr for SampleMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "frn" | "hash" => Ok(SampleMode::Frn),
+            "random" => Ok(SampleMode::Random),
+            _ => Err(format!("Invalid sample mode: {}", s)),
+        }
+    }
+}
+
+struct Sampler {
+    rate: SampleRate,
+    mode: SampleMode,
+    state: u64,
+}
+
+impl Sampler {
+    fn new(rate: SampleRate, mode: SampleMode) -> Self {
+        let seed = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or(0);
+        
+        Sampler {
+            rate,
+            mode,
+            // xorshift must not start from zero
+            state: seed | 1,
+        }
+    }
+    
+    fn keep(&mut self, event: &JournalEvent) -> bool {
+        let value = match self.mode {
+            SampleMode::Frn => fnv1a(event.file_id.as_bytes()),
+            SampleMode::Random => {
+                self.state ^= self.state << 13;
+                self.state ^= self.state >> 7;
+                self.state ^= self.state << 17;
+                self.state
+            }
+        };
+        value % self.rate.out_of < self.rate.keep
+    }
+}
+
+/// FNV-1a, used instead of `DefaultHasher` because its output must stay stable
+/// across runs and toolchains for FRN sampling to be deterministic
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// Lag is reported as a warning once it reaches this share of the journal's maximum size
+const LAG_WARNING_PERCENT: u64 = 80;
+
+const DEFAULT_LAG_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Serialize)]
+struct Heartbeat {
+    heartbeat: bool,
+    events: usize,
+    last_usn: Option<i64>,
+    next_usn: i64,
+    /// Unknown (null) until the first event has been processed
+    lag_bytes: Option<u64>,
+    journal_max_size: u64,
+}
+
+/// Periodically compares the last processed USN with the journal's NextUsn.
+/// The journal is a circular buffer, so a lag close to its maximum size means
+/// unread records are about to be overwritten.
+struct LagMonitor {
+    volume_path: String,
+    interval: Duration,
+    heartbeat: bool,
+    last_check: Instant,
+}
+
+impl LagMonitor {
+    fn new(volume_path: &str, heartbeat: Option<Duration>) -> Self {
+        LagMonitor {
+            volume_path: volume_path.to_string(),
+            interval: heartbeat.unwrap_or(DEFAULT_LAG_CHECK_INTERVAL),
+            heartbeat: heartbeat.is_some(),
+            last_check: Instant::now(),
         }
     }
+    
+    /// Returns false once the journal can't be queried, after which the
+    /// monitor should be dropped.
+    fn tick(&mut self, last_usn: Option<i64>, events: usize) -> Result<bool> {
+        if self.last_check.elapsed() < self.interval {
+            return Ok(true);
+        }
+        self.last_check = Instant::now();
+        
+        let data = match query_usn_journal(&self.volume_path) {
+            Ok(data) => data,
+            Err(e) => {
+                Warning::new(
+                    "lag_monitoring_disabled",
+                    format!("journal lag monitoring disabled: {:#}", e),
+                )
+                .emit();
+                return Ok(false);
+            }
+        };
+        
+        // Before the first event there is no position to measure from
+        let lag_bytes = last_usn.map(|usn| data.next_usn.saturating_sub(usn).max(0) as u64);
+        
+        if self.heartbeat {
+            let heartbeat = Heartbeat {
+                heartbeat: true,
+                events,
+                last_usn,
+                next_usn: data.next_usn,
+                lag_bytes,
+                journal_max_size: data.maximum_size,
+            };
+            eprintln!("{}", output::to_json(&heartbeat)?);
+        }
+        
+        let Some(lag_bytes) = lag_bytes else {
+            return Ok(true);
+        };
+        if data.maximum_size > 0 && lag_bytes >= data.maximum_size / 100 * LAG_WARNING_PERCENT {
+            let message = format!(
+                "journal lag is {} bytes ({}% of the {} byte journal); unread events may be overwritten",
+                lag_bytes,
+                lag_bytes * 100 / data.maximum_size,
+                data.maximum_size
+            );
+            Warning::new("journal_lag", message).emit();
+        }
+        
+        Ok(true)
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct Talker {
+    name: String,
+    events: u64,
+}
+
+#[derive(Debug, Serialize)]
+struct TopTalkersReport {
+    top_talkers: bool,
+    window_ms: u128,
+    events: u64,
+    files: Vec<Talker>,
+    directories: Vec<Talker>,
+    extensions: Vec<Talker>,
+}
+
+/// Counts emitted events per file, parent directory and extension, and
+/// periodically reports the busiest of each to stderr. Counts start over
+/// after every report, so each one covers a single window. Directories
+/// stand in for processes, which the journal doesn't record.
+struct TopTalkers {
+    limit: usize,
+    interval: Duration,
+    window_start: Instant,
+    events: u64,
+    files: HashMap<String, u64>,
+    directories: HashMap<String, u64>,
+    extensions: HashMap<String, u64>,
+}
+
+impl TopTalkers {
+    fn new(limit: usize, interval: Duration) -> Self {
+        TopTalkers {
+            limit,
+            interval,
+            window_start: Instant::now(),
+            events: 0,
+            files: HashMap::new(),
+            directories: HashMap::new(),
+            extensions: HashMap::new(),
+        }
+    }
+    
+    fn record(&mut self, event: &JournalEvent) {
+        self.events += 1;
+        *self.files.entry(event.path.clone()).or_default() += 1;
+        
+        let (directory, name) = event.path.rsplit_once('\\').unwrap_or(("", event.path.as_str()));
+        if !directory.is_empty() {
+            *self.directories.entry(directory.to_string()).or_default() += 1;
+        }
+        if let Some((_, ext)) = name.rsplit_once('.') {
+            *self.extensions.entry(ext.to_lowercase()).or_default() += 1;
+        }
+    }
+    
+    /// Reports the current window once the interval has elapsed
+    fn tick(&mut self) -> Result<()> {
+        if self.window_start.elapsed() >= self.interval {
+            self.report()?;
+        }
+        Ok(())
+    }
+    
+    fn report(&mut self) -> Result<()> {
+        let report = TopTalkersReport {
+            top_talkers: true,
+            window_ms: self.window_start.elapsed().as_millis(),
+            events: self.events,
+            files: top(&mut self.files, self.limit),
+            directories: top(&mut self.directories, self.limit),
+            extensions: top(&mut self.extensions, self.limit),
+        };
+        eprintln!("{}", output::to_json(&report)?);
+        
+        self.events = 0;
+        self.window_start = Instant::now();
+        Ok(())
+    }
+}
+
+/// Drains `counts` and returns its `limit` largest entries, busiest first
+fn top(counts: &mut HashMap<String, u64>, limit: usize) -> Vec<Talker> {
+    let mut talkers: Vec<Talker> = counts
+        .drain()
+        .map(|(name, events)| Talker { name, events })
+        .collect();
+    talkers.sort_by(|a, b| b.events.cmp(&a.events).then_with(|| a.name.cmp(&b.name)));
+    talkers.truncate(limit);
+    talkers
+}
+
+/// Runs a command every `interval` with the paths changed since its last
+/// run, so build systems and caches can invalidate what the journal says
+/// changed. The paths (both names of a rename, from its two events) are written
+/// sorted and deduplicated, one per line, to a temporary file whose path is
+/// appended to the command and set in NTFS_READER_CHANGES. The journal
+/// isn't read while the command runs; the file is deleted once it exits.
+struct ChangeHook {
+    command: String,
+    interval: Duration,
+    last_run: Instant,
+    paths: BTreeSet<String>,
+    runs: u64,
 }
 
-fn normalize_volume_path(volume: &str) -> String {
+impl ChangeHook {
+    fn new(command: String, interval: Duration) -> Self {
+        ChangeHook {
+            command,
+            interval,
+            last_run: Instant::now(),
+            paths: BTreeSet::new(),
+            runs: 0,
+        }
+    }
+    
+    fn record(&mut self, event: &JournalEvent) {
+        self.paths.insert(event.path.clone());
+    }
+    
+    /// Runs the command once the interval has elapsed, if anything changed
+    fn tick(&mut self) -> Result<()> {
+        if self.last_run.elapsed() >= self.interval {
+            self.run()?;
+        }
+        Ok(())
+    }
+    
+    /// Runs the command now with the pending paths, if there are any. A
+    /// command that can't be started or fails is reported as a warning.
+    fn run(&mut self) -> Result<()> {
+        self.last_run = Instant::now();
+        if self.paths.is_empty() {
+            return Ok(());
+        }
+        self.runs += 1;
+        let list = std::env::temp_dir().join(format!(
+            "ntfs-reader-cli-{}-changes-{}.txt",
+            std::process::id(),
+            self.runs
+        ));
+        let mut contents = String::new();
+        for path in std::mem::take(&mut self.paths) {
+            contents.push_str(&path);
+            contents.push('\n');
+        }
+        std::fs::write(&list, contents)
+            .context(format!("Failed to write changed paths to {}", list.display()))?;
+        
+        let status = shell_command(&self.command, &list)
+            .env("NTFS_READER_CHANGES", &list)
+            .status();
+        let _ = std::fs::remove_file(&list);
+        match status {
+            Ok(status) if status.success() => {}
+            Ok(status) => Warning::new(
+                "on_change_exec",
+                format!("'{}' exited with {}", self.command, status),
+            )
+            .emit(),
+            Err(e) => Warning::new(
+                "on_change_exec",
+                format!("failed to run '{}': {}", self.command, e),
+            )
+            .emit(),
+        }
+        Ok(())
+    }
+}
+
+/// `command` run by the system shell with `list` appended as its last argument
+#[cfg(windows)]
+fn shell_command(command: &str, list: &Path) -> std::process::Command {
+    use std::os::windows::process::CommandExt;
+    
+    let mut shell = std::process::Command::new("cmd.exe");
+    // /s makes cmd strip only the outermost quotes, leaving the command's intact
+    shell.raw_arg(format!("/s /c \"{} \"{}\"\"", command, list.display()));
+    shell
+}
+
+#[cfg(not(windows))]
+fn shell_command(command: &str, list: &Path) -> std::process::Command {
+    let mut shell = std::process::Command::new("sh");
+    shell.arg("-c").arg(format!("{} \"$1\"", command)).arg("sh").arg(list);
+    shell
+}
+
+pub fn normalize_volume_path(volume: &str) -> String {
+    // --shadow reads the shadow copy in place of the volume it was taken of
+    if let Some(device) = shadow::selected_device() {
+        return device;
+    }
     let volume = volume.trim();
     
     // If it's just a drive letter, convert to extended path
     if volume.len() == 2 && volume.chars().nth(1) == Some(':') {
-        return format!("\\\\?\\{}:", volume.chars().nth(0).unwrap());
+        return format!("\\\\?\\{}:", volume.chars().next().unwrap());
     }
     
     // If it's a drive letter with backslash, remove it
     if volume.len() == 3 && volume.ends_with(":\\") {
-        return format!("\\\\?\\{}:", volume.chars().nth(0).unwrap());
+        return format!("\\\\?\\{}:", volume.chars().next().unwrap());
     }
     
-    // Return as-is if already in extended format
-    volume.to_string()
+    // Device paths and names of volumes without a drive letter
+    volume::device_path(volume)
 }
 
-pub fn monitor_journal(
-    volume: &str,
-    from_start: bool,
-    from_usn: Option<i64>,
-    reason_mask: Option<u32>,
-    max_events: Option<usize>,
-    continuous: bool,
-    output: OutputFormat,
+/// Checks that the journal of `volume` can be opened
+pub fn probe(volume: &str) -> Result<()> {
+    let vol = volume::open(&normalize_volume_path(volume))?;
+    let options = JournalOptions {
+        reason_mask: 0xFFFFFFFF,
+        next_usn: NextUsn::Next,
+        max_history_size: ntfs_reader::journal::HistorySize::Limited(1000),
+    };
+    Journal::new(vol, options).context("Failed to open USN journal")?;
+    Ok(())
+}
+
+/// Number of records returned per read from a capture file
+const CAPTURE_BATCH: usize = 1000;
+
+/// Delays between attempts to reopen a lost volume, doubling up to the maximum
+const RECONNECT_MIN_DELAY: Duration = Duration::from_secs(1);
+const RECONNECT_MAX_DELAY: Duration = Duration::from_secs(60);
+
+/// Win32 errors of a volume that was dismounted, unplugged or taken offline:
+/// ERROR_NOT_READY, ERROR_DEV_NOT_EXIST, ERROR_FILE_INVALID,
+/// ERROR_DEVICE_NOT_CONNECTED and ERROR_DEVICE_REMOVED
+const VOLUME_LOST_ERRORS: &[i32] = &[21, 55, 1006, 1167, 1617];
+
+/// Whether a failed journal read means the volume went away, rather than a
+/// problem with the journal itself. Errors without an OS code are checked by
+/// querying the volume.
+fn is_volume_lost(error: &anyhow::Error, volume_path: &str) -> bool {
+    let code = error
+        .chain()
+        .filter_map(|cause| cause.downcast_ref::<std::io::Error>())
+        .find_map(|cause| cause.raw_os_error());
+    match code {
+        Some(code) => VOLUME_LOST_ERRORS.contains(&code),
+        None => query_usn_journal(volume_path).is_err(),
+    }
+}
+
+/// Waits with growing delays for a lost volume to come back, then reopens
+/// its journal just after `checkpoint`, the last USN processed. If records
+/// were purged in the meantime or the journal was recreated, the events in
+/// between are reported as lost and reading restarts at the oldest record.
+fn reconnect(
+    source: &mut EventSource,
+    volume_path: &str,
+    reason_mask: u32,
+    journal_id: Option<u64>,
+    checkpoint: Option<i64>,
+    start: NextUsn,
 ) -> Result<()> {
+    let mut delay = RECONNECT_MIN_DELAY;
+    let mut start = Some(start);
+    loop {
+        eprintln!("Reconnecting to {} in {}s...", volume_path, delay.as_secs());
+        thread::sleep(delay);
+        delay = (delay * 2).min(RECONNECT_MAX_DELAY);
+        
+        let Ok(data) = query_usn_journal(volume_path) else {
+            continue;
+        };
+        let next_usn = match checkpoint {
+            Some(usn) if journal_id.is_some_and(|id| id != data.journal_id) => {
+                let message = format!("the journal was recreated; events after USN {} are lost", usn);
+                Warning::new("events_lost", message).emit();
+                NextUsn::First
+            }
+            Some(usn) if usn < data.first_usn => {
+                let message = format!(
+                    "records from USN {} to {} were purged while the volume was away",
+                    usn, data.first_usn
+                );
+                Warning::new("events_lost", message).emit();
+                NextUsn::First
+            }
+            Some(usn) => NextUsn::Custom(usn),
+            // Nothing was read before the volume was lost: start as asked
+            None => match start.take() {
+                Some(start) => start,
+                None => NextUsn::Next,
+            },
+        };
+        let options = JournalOptions {
+            reason_mask,
+            next_usn,
+            max_history_size: ntfs_reader::journal::HistorySize::Limited(1000),
+        };
+        match source.reopen_journal(volume_path, options) {
+            Ok(()) => {
+                let message = format!("{} is available again, resuming", volume_path);
+                Warning::new("volume_reconnected", message).emit();
+                return Ok(());
+            }
+            Err(e) => eprintln!("Failed to reopen the journal: {:#}", e),
+        }
+    }
+}
+
+/// Where events are read from
+enum EventSource {
+    /// The live journal, optionally recording every record to a capture
+    Journal {
+        journal: Journal,
+        capture: Option<CaptureWriter>,
+    },
+    /// A previously recorded capture, with the filters the journal would apply
+    Capture {
+        reader: CaptureReader,
+        reason_mask: u32,
+        from_usn: Option<i64>,
+    },
+}
+
+impl EventSource {
+    /// Opens the live journal of `volume_path`, recording to `capture` if given
+    fn open_journal(
+        volume_path: &str,
+        options: JournalOptions,
+        capture: Option<&Path>,
+        encrypt_key: Option<&[u8; 32]>,
+    ) -> Result<Self> {
+        let vol = volume::open(volume_path)?;
+        
+        eprintln!("Opening USN journal...");
+        let journal = Journal::new(vol, options)
+            .context("Failed to open USN journal")?;
+        
+        let capture = match capture {
+            Some(path) => {
+                let journal_id = query_usn_journal(volume_path).ok().map(|data| data.journal_id);
+                let header = CaptureHeader::new(volume_path, journal_id);
+                eprintln!("Recording journal records to {}", path.display());
+                Some(CaptureWriter::create(path, &header, encrypt_key)?)
+            }
+            None => None,
+        };
+        
+        Ok(EventSource::Journal { journal, capture })
+    }
+    
+    /// Reopens the live journal, for instance after its volume came back,
+    /// keeping the capture being recorded
+    fn reopen_journal(&mut self, volume_path: &str, options: JournalOptions) -> Result<()> {
+        if let EventSource::Journal { journal, .. } = self {
+            *journal = Journal::new(volume::open(volume_path)?, options)
+                .context("Failed to open USN journal")?;
+        }
+        Ok(())
+    }
+    
+    fn open_capture(
+        path: &Path,
+        reason_mask: u32,
+        from_usn: Option<i64>,
+        encrypt_key: Option<&[u8; 32]>,
+    ) -> Result<Self> {
+        let reader = CaptureReader::open(path, encrypt_key)?;
+        eprintln!(
+            "Reading capture of {} taken at {}",
+            reader.header.volume, reader.header.captured_at
+        );
+        Ok(EventSource::Capture { reader, reason_mask, from_usn })
+    }
+    
+    fn is_capture(&self) -> bool {
+        matches!(self, EventSource::Capture { .. })
+    }
+    
+    /// Completes (and signs) the capture being recorded, if any
+    fn close(self, sign_key: Option<&SigningKey>) -> Result<()> {
+        match self {
+            EventSource::Journal { capture: Some(capture), .. } => capture.finish(sign_key),
+            _ => Ok(()),
+        }
+    }
+    
+    fn read(&mut self) -> Result<Vec<JournalEvent>> {
+        match self {
+            EventSource::Journal { journal, capture } => {
+                let records: Vec<CapturedEvent> = journal
+                    .read()
+                    .context("Failed to read journal events")?
+                    .iter()
+                    .map(CapturedEvent::from_usn_record)
+                    .collect();
+                if let Some(capture) = capture {
+                    for record in &records {
+                        capture.write(record)?;
+                    }
+                    capture.flush()?;
+                }
+                Ok(records.iter().map(JournalEvent::from_capture).collect())
+            }
+            EventSource::Capture { reader, reason_mask, from_usn } => {
+                let mut events = Vec::new();
+                while events.len() < CAPTURE_BATCH {
+                    let Some(record) = reader.next_event()? else {
+                        break;
+                    };
+                    if record.reason & *reason_mask == 0 || from_usn.is_some_and(|usn| record.usn < usn) {
+                        continue;
+                    }
+                    events.push(JournalEvent::from_capture(&record));
+                }
+                Ok(events)
+            }
+        }
+    }
+}
+
+/// Calls `f` with every new event of the live journal, or every event of a
+/// capture, until it returns false
+pub fn follow<F>(
+    volume: &str,
+    capture: Option<&Path>,
+    encrypt_key: Option<&[u8; 32]>,
+    mut f: F,
+) -> Result<()>
+where
+    F: FnMut(JournalEvent) -> Result<bool>,
+{
+    follow_batches(volume, capture, encrypt_key, |events| {
+        for event in events {
+            if !f(event)? {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    })
+}
+
+/// Like `follow`, but passes each read as a batch. While the live journal
+/// is idle, `f` is called with an empty batch about twice a second, so
+/// callers can act on time without waiting for the next event.
+pub fn follow_batches<F>(
+    volume: &str,
+    capture: Option<&Path>,
+    encrypt_key: Option<&[u8; 32]>,
+    mut f: F,
+) -> Result<()>
+where
+    F: FnMut(Vec<JournalEvent>) -> Result<bool>,
+{
+    let mut source = match capture {
+        Some(path) => EventSource::open_capture(path, 0xFFFFFFFF, None, encrypt_key)?,
+        None => {
+            let options = JournalOptions {
+                reason_mask: 0xFFFFFFFF,
+                next_usn: NextUsn::Next,
+                max_history_size: ntfs_reader::journal::HistorySize::Limited(1000),
+            };
+            EventSource::open_journal(&normalize_volume_path(volume), options, None, None)?
+        }
+    };
+    
+    loop {
+        let events = source.read()?;
+        
+        if events.is_empty() {
+            if source.is_capture() {
+                return Ok(());
+            }
+            if !f(events)? {
+                return Ok(());
+            }
+            thread::sleep(Duration::from_millis(500));
+            continue;
+        }
+        
+        if !f(events)? {
+            return Ok(());
+        }
+    }
+}
+
+pub struct MonitorOptions {
+    pub from_start: bool,
+    pub from_usn: Option<i64>,
+    pub reason_mask: Option<u32>,
+    pub max_events: Option<usize>,
+    pub continuous: bool,
+    pub on_close: bool,
+    pub heartbeat: Option<Duration>,
+    pub replay_speed: ReplaySpeed,
+    pub sample: Option<SampleRate>,
+    pub sample_mode: SampleMode,
+    pub event_model: EventModel,
+    pub output: OutputFormat,
+    pub tee: Vec<TeeSink>,
+    /// Fields chosen with --fields
+    pub fields: Fields,
+    /// Record every raw journal record to this capture file
+    pub capture: Option<PathBuf>,
+    /// Read events from a capture file instead of the live journal
+    pub from_capture: Option<PathBuf>,
+    /// Also store every event in this SQLite database
+    pub db: Option<PathBuf>,
+    /// Synthesize rename events for the descendants of moved directories
+    pub expand_moves: bool,
+    /// Only events for paths in this subtree (--under, --max-depth)
+    pub scope: PathScope,
+    /// Number of entries in each top talkers list, when enabled
+    pub top_talkers: Option<usize>,
+    /// How often top talkers are reported
+    pub top_interval: Duration,
+    /// Number of events written together in continuous mode, when batching
+    pub batch_size: Option<usize>,
+    /// Longest time a batched event may wait before its batch is written
+    pub batch_max_latency: Duration,
+    /// Command run with a file of the paths changed since its last run
+    pub on_change_exec: Option<String>,
+    /// How often --on-change-exec runs, when anything changed
+    pub on_change_interval: Duration,
+    /// Wait for a lost volume to come back and resume where reading stopped
+    pub reconnect: bool,
+    /// Also keep the most recent events in this ring buffer file
+    pub ring: Option<RingSpec>,
+    /// Encryption of captures (both directions) and signing of new captures
+    pub seal: SealOptions,
+}
+
+pub fn monitor_journal(volume: &str, options: MonitorOptions) -> Result<()> {
+    let MonitorOptions {
+        from_start,
+        from_usn,
+        reason_mask,
+        max_events,
+        continuous,
+        on_close,
+        heartbeat,
+        replay_speed,
+        sample,
+        sample_mode,
+        event_model,
+        output,
+        tee,
+        fields,
+        capture,
+        from_capture,
+        db,
+        expand_moves,
+        scope,
+        top_talkers,
+        top_interval,
+        batch_size,
+        batch_max_latency,
+        on_change_exec,
+        on_change_interval,
+        reconnect: reconnect_enabled,
+        ring,
+        seal,
+    } = options;
+    
+    let formats: Vec<OutputFormat> = std::iter::once(output)
+        .chain(tee.iter().map(|t| t.format))
+        .collect();
+    if formats.iter().any(|f| matches!(f, OutputFormat::JsonTree)) {
+        bail!("json-tree output is only supported by list-files");
+    }
+    if formats.iter().any(|f| matches!(f, OutputFormat::Dot)) {
+        bail!("dot output is only supported by tree");
+    }
+    let mut cdc = formats
+        .iter()
+        .any(|f| matches!(f, OutputFormat::Cdc))
+        .then(|| CdcEncoder::new(Some(volume)));
+    let mut sinks = output::open_sinks(output, &tee)?;
+    
     let volume_path = normalize_volume_path(volume);
     
-    eprintln!("Opening volume: {}", volume_path);
-    let vol = Volume::new(&volume_path)
-        .context("Failed to open volume. Make sure you're running as Administrator.")?;
+    let start = || {
+        if from_start {
+            NextUsn::First
+        } else if let Some(usn) = from_usn {
+            NextUsn::Custom(usn)
+        } else {
+            NextUsn::Next
+        }
+    };
+    
+    let user_mask = reason_mask.unwrap_or(0xFFFFFFFF);
+    let mut reason_mask = user_mask;
+    if on_close {
+        // Close records are what trigger emission, so they can't be masked
+        // out; the coalescer checks the user's mask against what the file
+        // went through instead
+        reason_mask |= USN_REASON_CLOSE;
+    }
+    
+    let mut source = match from_capture {
+        Some(ref path) => {
+            EventSource::open_capture(path, reason_mask, from_usn, seal.encrypt_key.as_ref())?
+        }
+        None => {
+            let options = JournalOptions {
+                reason_mask,
+                next_usn: start(),
+                max_history_size: ntfs_reader::journal::HistorySize::Limited(1000),
+            };
+            EventSource::open_journal(
+                &volume_path,
+                options,
+                capture.as_deref(),
+                seal.encrypt_key.as_ref(),
+            )?
+        }
+    };
+    
+    let mut coalescer = if on_close {
+        Some(CloseCoalescer::new(user_mask))
+    } else {
+        None
+    };
     
-    let next_usn = if from_start {
-        NextUsn::First
-    } else if let Some(usn) = from_usn {
-        NextUsn::Custom(usn)
+    let mut expander = if expand_moves {
+        Some(MoveExpander::load(volume)?)
     } else {
-        NextUsn::Next
+        None
     };
     
-    let options = JournalOptions {
-        reason_mask: reason_mask.unwrap_or(0xFFFFFFFF),
-        next_usn,
-        max_history_size: ntfs_reader::journal::HistorySize::Limited(1000),
+    let mut stable_order = STABLE_ORDER.get().copied().unwrap_or(false).then(StableOrder::default);
+    let mut pacer = ReplayPacer::new(replay_speed);
+    let mut sampler = sample.map(|rate| Sampler::new(rate, sample_mode));
+    
+    // Lag only means something against the live journal
+    let mut lag_monitor = if !source.is_capture() && (continuous || heartbeat.is_some()) {
+        Some(LagMonitor::new(&volume_path, heartbeat))
+    } else {
+        None
     };
     
-    eprintln!("Opening USN journal...");
-    let mut journal = Journal::new(vol, options)
-        .context("Failed to open USN journal")?;
+    let mut top_talkers = top_talkers.map(|limit| TopTalkers::new(limit, top_interval));
+    let mut batcher = batch_size.map(|size| Batcher::new(size, batch_max_latency));
+    let mut db = db.as_deref().map(EventDb::open).transpose()?;
+    let mut change_hook = on_change_exec.map(|command| ChangeHook::new(command, on_change_interval));
+    let mut ring = ring.as_ref().map(RingWriter::open).transpose()?;
+    
+    // Identifies the journal a reconnection must find again
+    let journal_id = if reconnect_enabled && !source.is_capture() {
+        query_usn_journal(&volume_path).ok().map(|data| data.journal_id)
+    } else {
+        None
+    };
     
     let mut all_events = Vec::new();
     let mut total_read = 0;
+    let mut batch = 0;
+    let mut last_usn = None;
+    // Records up to here were processed before a reconnection and are read again
+    let mut resume_after = None;
     
-    loop {
-        eprintln!("Reading journal events...");
-        let events = journal.read()
-            .context("Failed to read journal events")?;
+    'read: loop {
+        if let Some(ref mut monitor) = lag_monitor {
+            if !monitor.tick(last_usn, total_read)? {
+                lag_monitor = None;
+            }
+        }
+        if let Some(ref mut top_talkers) = top_talkers {
+            top_talkers.tick()?;
+        }
+        if let Some(ref mut change_hook) = change_hook {
+            change_hook.tick()?;
+        }
+        if let Some(ref mut batcher) = batcher {
+            if batcher.is_due() {
+                write_batch(&mut sinks, &batcher.take(), event_model, &fields)?;
+            }
+        }
         
-        if events.is_empty() {
-            if !continuous {
-                eprintln!("No more events available.");
-                break;
+        eprintln!("Reading journal events...");
+        let events = match source.read() {
+            Ok(events) => events,
+            Err(e) if !source.is_capture() && is_volume_lost(&e, &volume_path) => {
+                let message = format!("{} is no longer available: {:#}", volume_path, e);
+                Warning::new("volume_lost", message).emit();
+                if !reconnect_enabled {
+                    return Err(e);
+                }
+                reconnect(&mut source, &volume_path, reason_mask, journal_id, last_usn, start())?;
+                resume_after = last_usn;
+                continue;
             }
+            Err(e) => return Err(e),
+        };
+        
+        // Without --continuous, the first empty read ends the run: every
+        // record written before it has been read and went through the same
+        // coalescing, sampling and --max-events checks
+        let done = events.is_empty() && (!continuous || source.is_capture());
+        if done {
+            eprintln!("No more events available.");
+        } else if events.is_empty() {
             eprintln!("No new events, waiting...");
-            thread::sleep(Duration::from_millis(500));
+            // Wake up in time to write a pending batch within its latency bound
+            let idle = Duration::from_millis(500);
+            let wait = batcher
+                .as_ref()
+                .and_then(Batcher::time_left)
+                .map_or(idle, |left| left.min(idle));
+            thread::sleep(wait);
             continue;
+        } else {
+            batch += 1;
+            eprintln!("Read {} events (batch {})", events.len(), batch);
         }
         
-        eprintln!("Read {} events", events.len());
+        let mut ready = Vec::new();
+        for raw_event in events {
+            if resume_after.is_some_and(|usn| raw_event.usn <= usn) {
+                continue;
+            }
+            last_usn = Some(raw_event.usn);
+            
+            let expanded = match expander {
+                Some(ref mut expander) => expander.observe(&raw_event),
+                None => Vec::new(),
+            };
+            
+            let coalesced = match coalescer {
+                Some(ref mut coalescer) => coalescer.push(raw_event),
+                None => vec![raw_event],
+            };
+            let sampled = coalesced
+                .into_iter()
+                .filter(|event| sampler.as_mut().is_none_or(|sampler| sampler.keep(event)));
+            
+            // Synthesized events bypass coalescing and sampling: they carry
+            // no file ID and must stay complete for path-keyed consumers
+            let scoped = sampled.chain(expanded).filter(|event| scope.contains(&event.path));
+            ready.extend(scoped.map(|mut event| {
+                event.batch = batch;
+                event
+            }));
+        }
         
-        for event in events {
-            let journal_event = JournalEvent::from_usn_record(&event);
+        if let Some(ref mut stable_order) = stable_order {
+            // Once the run ends, files still open won't be emitted at all
+            ready = stable_order.push(ready, coalescer.as_mut().filter(|_| !done));
+        }
+        
+        for mut journal_event in ready {
+            journal_event.apply_event_model(event_model);
+            journal_event.seq = total_read as u64 + 1;
+            if let Some(ref mut cdc) = cdc {
+                journal_event.cdc = cdc.envelope(&journal_event, &fields)?;
+            }
+            
+            if let Some(ref mut top_talkers) = top_talkers {
+                top_talkers.record(&journal_event);
+            }
+            if let Some(ref mut db) = db {
+                db.insert(&journal_event)?;
+            }
+            if let Some(ref mut change_hook) = change_hook {
+                change_hook.record(&journal_event);
+            }
+            if let Some(ref mut ring) = ring {
+                ring.push(output::to_json(&fields.select(&journal_event))?.as_bytes())?;
+            }
             
             if continuous {
-                // Output each event immediately in continuous mode
-                match output {
-                    OutputFormat::Json => {
-                        println!("{}", serde_json::to_string(&journal_event)?);
-                    }
-                    OutputFormat::JsonPretty => {
-                        println!("{}", serde_json::to_string_pretty(&journal_event)?);
-                    }
-                    OutputFormat::Bincode => {
-                        let encoded = bincode::serialize(&journal_event)?;
-                        std::io::stdout().write_all(&encoded)?;
-                        std::io::stdout().flush()?;
-                    }
-                    OutputFormat::Msgpack => {
-                        let mut buf = Vec::new();
-                        rmp_serde::encode::write(&mut buf, &journal_event)?;
-                        std::io::stdout().write_all(&buf)?;
-                        std::io::stdout().flush()?;
+                pacer.wait(&journal_event);
+                
+                match batcher {
+                    Some(ref mut batcher) => {
+                        if batcher.push(journal_event) {
+                            write_batch(&mut sinks, &batcher.take(), event_model, &fields)?;
+                        }
                     }
-                    OutputFormat::Csv => {
-                        if total_read == 0 {
-                            output_csv_header()?;
+                    // Output each event immediately in continuous mode
+                    None => {
+                        for sink in sinks.iter_mut() {
+                            write_event(sink, &journal_event, event_model, &fields)?;
                         }
-                        output_csv_event(&journal_event)?;
                     }
                 }
             } else {
@@ -142,77 +1621,233 @@ pub fn monitor_journal(
             if let Some(max) = max_events {
                 if total_read >= max {
                     eprintln!("Reached maximum event limit: {}", max);
-                    if !continuous {
-                        output_events(&all_events, output)?;
-                    }
-                    return Ok(());
+                    break 'read;
                 }
             }
         }
         
-        if !continuous {
-            // In non-continuous mode, try one more time to get any remaining events
-            let remaining = journal.read().context("Failed to read journal events")?;
-            if remaining.is_empty() {
-                break;
-            }
-            
-            for event in &remaining {
-                let journal_event = JournalEvent::from_usn_record(event);
-                all_events.push(journal_event);
-                total_read += 1;
-                
-                if let Some(max) = max_events {
-                    if total_read >= max {
-                        break;
-                    }
-                }
-            }
+        if let Some(ref mut db) = db {
+            db.commit()?;
+        }
+        if done {
+            break;
+        }
+    }
+    
+    if let Some(db) = db {
+        db.close()?;
+    }
+    if let Some(ring) = ring {
+        ring.flush()?;
+    }
+    
+    if let Some(ref mut batcher) = batcher {
+        let rest = batcher.take();
+        if !rest.is_empty() {
+            write_batch(&mut sinks, &rest, event_model, &fields)?;
         }
     }
     
     if !continuous && !all_events.is_empty() {
-        output_events(&all_events, output)?;
+        for sink in sinks.iter_mut() {
+            let format = sink.format;
+            write_events(sink.writer(), &all_events, format, event_model, &fields)?;
+        }
+    }
+    
+    // Final (partial) window
+    if let Some(ref mut top_talkers) = top_talkers {
+        top_talkers.report()?;
+    }
+    if let Some(ref mut change_hook) = change_hook {
+        change_hook.run()?;
     }
     
+    source.close(seal.sign_key.as_ref())
+}
+
+/// Writes a single event in continuous mode, preceded by the header for
+/// formats that have one
+fn write_event(
+    sink: &mut Sink,
+    event: &JournalEvent,
+    event_model: EventModel,
+    fields: &Fields,
+) -> Result<()> {
+    let first = sink.written == 0;
+    let format = sink.format;
+    let w = sink.writer();
+    let selected = fields.select(event);
+    
+    match format {
+        OutputFormat::Json | OutputFormat::Jsonl | OutputFormat::JsonTree => {
+            writeln!(w, "{}", output::to_json(&selected)?)?;
+        }
+        OutputFormat::JsonPretty => {
+            writeln!(w, "{}", output::to_json_pretty(&selected)?)?;
+        }
+        OutputFormat::Bincode => {
+            bincode::serialize_into(&mut *w, &selected)?;
+        }
+        OutputFormat::Msgpack => {
+            rmp_serde::encode::write(&mut *w, &selected)?;
+        }
+        OutputFormat::Cdc => {
+            if let Some(ref envelope) = event.cdc {
+                writeln!(w, "{}", output::to_json(envelope)?)?;
+            }
+        }
+        OutputFormat::Dot => bail!("dot output is only supported by tree"),
+        OutputFormat::Csv => {
+            if first {
+                write_csv_header(w, event_model, fields)?;
+            }
+            write_csv_event(w, event, fields)?;
+        }
+        OutputFormat::Table if fields.is_all() => {
+            if first {
+                output::write_table_header::<JournalEvent>(w)?;
+            }
+            output::write_table_line(w, event)?;
+        }
+        OutputFormat::Table => {
+            if first {
+                output::write_selected_table_header(w, fields)?;
+            }
+            output::write_selected_table_line(w, fields, event)?;
+        }
+    }
+    w.flush()?;
+    sink.written += 1;
+    
     Ok(())
 }
 
-fn output_events(events: &[JournalEvent], output: OutputFormat) -> Result<()> {
+/// Writes a batch of continuous-mode events: as one array for JSON (one per
+/// line with jsonl), bincode and MessagePack, and as rows for CSV and tables
+fn write_batch(
+    sinks: &mut [Sink],
+    events: &[JournalEvent],
+    event_model: EventModel,
+    fields: &Fields,
+) -> Result<()> {
+    let selected: Vec<_> = events.iter().map(|event| fields.select(event)).collect();
+    
+    for sink in sinks.iter_mut() {
+        let format = sink.format;
+        if matches!(format, OutputFormat::Csv | OutputFormat::Table) {
+            for event in events {
+                write_event(sink, event, event_model, fields)?;
+            }
+            continue;
+        }
+        
+        let w = sink.writer();
+        match format {
+            OutputFormat::Json | OutputFormat::Jsonl | OutputFormat::JsonTree => {
+                writeln!(w, "{}", output::to_json(&selected)?)?;
+            }
+            OutputFormat::JsonPretty => {
+                writeln!(w, "{}", output::to_json_pretty(&selected)?)?;
+            }
+            OutputFormat::Bincode => {
+                bincode::serialize_into(&mut *w, &selected)?;
+            }
+            OutputFormat::Msgpack => {
+                rmp_serde::encode::write(&mut *w, &selected)?;
+            }
+            OutputFormat::Cdc => {
+                for envelope in events.iter().filter_map(|event| event.cdc.as_ref()) {
+                    writeln!(w, "{}", output::to_json(envelope)?)?;
+                }
+            }
+            OutputFormat::Csv | OutputFormat::Table => unreachable!("{:?} is written row by row", format),
+            OutputFormat::Dot => bail!("dot output is only supported by tree"),
+        }
+        w.flush()?;
+        sink.written += events.len();
+    }
+    Ok(())
+}
+
+pub fn write_events(
+    w: &mut dyn Write,
+    events: &[JournalEvent],
+    output: OutputFormat,
+    event_model: EventModel,
+    fields: &Fields,
+) -> Result<()> {
+    let selected: Vec<_> = events.iter().map(|event| fields.select(event)).collect();
     match output {
-        OutputFormat::Json => {
-            println!("{}", serde_json::to_string(&events)?);
+        OutputFormat::Json | OutputFormat::JsonTree => {
+            writeln!(w, "{}", output::to_json(&selected)?)?;
         }
         OutputFormat::JsonPretty => {
-            println!("{}", serde_json::to_string_pretty(&events)?);
+            writeln!(w, "{}", output::to_json_pretty(&selected)?)?;
+        }
+        OutputFormat::Jsonl => {
+            for event in &selected {
+                writeln!(w, "{}", output::to_json(event)?)?;
+            }
         }
         OutputFormat::Bincode => {
-            let encoded = bincode::serialize(&events)?;
-            std::io::stdout().write_all(&encoded)?;
+            bincode::serialize_into(&mut *w, &selected)?;
         }
         OutputFormat::Msgpack => {
-            let mut buf = Vec::new();
-            rmp_serde::encode::write(&mut buf, &events)?;
-            std::io::stdout().write_all(&buf)?;
+            rmp_serde::encode::write(&mut *w, &selected)?;
+        }
+        OutputFormat::Cdc => {
+            for envelope in events.iter().filter_map(|event| event.cdc.as_ref()) {
+                writeln!(w, "{}", output::to_json(envelope)?)?;
+            }
         }
         OutputFormat::Csv => {
-            output_csv_header()?;
+            write_csv_header(w, event_model, fields)?;
             for event in events {
-                output_csv_event(event)?;
+                write_csv_event(w, event, fields)?;
             }
         }
+        OutputFormat::Table if fields.is_all() => {
+            output::write_table(w, events)?;
+        }
+        OutputFormat::Table => {
+            output::write_selected_table(w, fields, events)?;
+        }
+        OutputFormat::Dot => bail!("dot output is only supported by tree"),
     }
+    w.flush()?;
     Ok(())
 }
 
-fn output_csv_header() -> Result<()> {
-    println!("usn,timestamp_ms,file_id,parent_id,reason,reason_str,path");
+fn write_csv_header(w: &mut dyn Write, event_model: EventModel, fields: &Fields) -> Result<()> {
+    if !fields.is_all() {
+        writeln!(w, "{}", fields.csv_header())?;
+    } else if event_model == EventModel::Notify {
+        let columns = [
+            "seq", "batch", "usn", "timestamp_ms", "file_id", "parent_id", "reason",
+            "reason_str", "path", "kind", "kind_detail",
+        ];
+        writeln!(w, "{}", Fields::new(&columns, JournalEvent::FIELDS)?.csv_header())?;
+    } else {
+        let columns = [
+            "seq", "batch", "usn", "timestamp_ms", "file_id", "parent_id", "reason",
+            "reason_str", "path",
+        ];
+        writeln!(w, "{}", Fields::new(&columns, JournalEvent::FIELDS)?.csv_header())?;
+    }
     Ok(())
 }
 
-fn output_csv_event(event: &JournalEvent) -> Result<()> {
-    println!(
-        "{},{},{},{},{},{},{}",
+fn write_csv_event(w: &mut dyn Write, event: &JournalEvent, fields: &Fields) -> Result<()> {
+    if !fields.is_all() {
+        writeln!(w, "{}", fields.csv_row(event)?)?;
+        return Ok(());
+    }
+    write!(
+        w,
+        "{},{},{},{},{},{},{},{},{}",
+        event.seq,
+        event.batch,
         event.usn,
         event.timestamp_ms,
         escape_csv(&event.file_id),
@@ -220,14 +1855,165 @@ fn output_csv_event(event: &JournalEvent) -> Result<()> {
         event.reason,
         escape_csv(&event.reason_str),
         escape_csv(&event.path)
-    );
+    )?;
+    if let (Some(kind), Some(detail)) = (&event.kind, &event.kind_detail) {
+        write!(w, ",{},{}", kind, detail)?;
+    }
+    writeln!(w)?;
     Ok(())
 }
 
-fn escape_csv(s: &str) -> String {
-    if s.contains(',') || s.contains('"') || s.contains('\n') {
-        format!("\"{}\"", s.replace('"', "\"\""))
-    } else {
-        s.to_string()
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(reason: u32, file_id: &str, path: &str) -> JournalEvent {
+        JournalEvent {
+            seq: 0,
+            batch: 0,
+            usn: 100,
+            timestamp_ms: 0,
+            timestamp_filetime: None,
+            file_id: file_id.to_string(),
+            parent_id: String::new(),
+            reason,
+            reason_str: String::new(),
+            path: path.to_string(),
+            kind: None,
+            kind_detail: None,
+            moved_with: None,
+            cdc: None,
+        }
+    }
+
+    fn indexed(paths: &[(&str, u64)]) -> MoveExpander {
+        let paths = paths
+            .iter()
+            .map(|&(path, number)| {
+                let file = IndexedFile { path: path.to_string(), file_id: format!("Normal({})", number) };
+                (mft::path_key(path), file)
+            })
+            .collect();
+        MoveExpander { paths, renaming: HashMap::new() }
+    }
+
+    #[test]
+    fn moves_expand_to_descendants_whatever_the_path_spelling() {
+        let mut expander = indexed(&[
+            ("C:\\Data", 40),
+            ("C:\\Data\\Reports", 41),
+            ("C:\\Data\\Reports\\q1.xlsx", 42),
+            ("C:\\Database", 43),
+        ]);
+        assert!(expander.observe(&event(USN_REASON_RENAME_OLD_NAME, "Normal(40)", "\\\\?\\C:\\DATA")).is_empty());
+        let synthesized = expander.observe(&event(USN_REASON_RENAME_NEW_NAME, "Normal(40)", "\\\\?\\C:\\Archive"));
+
+        let summary: Vec<(&str, &str, &str)> = synthesized
+            .iter()
+            .map(|event| (event.path.as_str(), event.file_id.as_str(), event.parent_id.as_str()))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("C:\\Data\\Reports", "Normal(41)", "Normal(40)"),
+                ("\\\\?\\C:\\Archive\\Reports", "Normal(41)", "Normal(40)"),
+                ("C:\\Data\\Reports\\q1.xlsx", "Normal(42)", "Normal(41)"),
+                ("\\\\?\\C:\\Archive\\Reports\\q1.xlsx", "Normal(42)", "Normal(41)"),
+            ]
+        );
+        assert!(synthesized.iter().all(|event| event.moved_with.as_deref() == Some("\\\\?\\C:\\Archive")));
+        assert!(expander.paths.contains_key("\\archive\\reports\\q1.xlsx"));
+        assert!(expander.paths.contains_key("\\database"));
+        assert!(!expander.paths.contains_key("\\data\\reports"));
+    }
+
+    #[test]
+    fn case_only_renames_keep_the_directory_indexed() {
+        let mut expander = indexed(&[("C:\\Data", 40), ("C:\\Data\\a.txt", 41)]);
+        expander.observe(&event(USN_REASON_RENAME_OLD_NAME, "Normal(40)", "C:\\Data"));
+        let synthesized = expander.observe(&event(USN_REASON_RENAME_NEW_NAME, "Normal(40)", "C:\\DATA"));
+        assert_eq!(synthesized.len(), 2);
+        assert_eq!(synthesized[1].path, "C:\\DATA\\a.txt");
+        assert_eq!(expander.paths["\\data"].path, "C:\\DATA");
+    }
+
+    #[test]
+    fn closes_carry_every_reason_and_open_files_are_bounded() {
+        let mut coalescer = CloseCoalescer::new(0xFFFFFFFF);
+        assert!(coalescer.push(event(0x01, "Normal(7)", "C:\\a.txt")).is_empty());
+        let closed = coalescer.push(event(0x02 | USN_REASON_CLOSE, "Normal(7)", "C:\\a.txt"));
+        assert_eq!(closed.len(), 1);
+        assert_eq!(closed[0].reason, 0x03 | USN_REASON_CLOSE);
+        assert!(coalescer.pending.is_empty());
+
+        for number in 0..=MAX_PENDING_CLOSES as i64 {
+            let mut open = event(0x01, &format!("Normal({})", number), "C:\\log.txt");
+            open.usn = number;
+            let early = coalescer.push(open);
+            if number < MAX_PENDING_CLOSES as i64 {
+                assert!(early.is_empty());
+            } else {
+                assert_eq!(early.len(), MAX_PENDING_CLOSES / 2);
+                assert!(early.windows(2).all(|pair| pair[0].usn < pair[1].usn));
+                assert_eq!(early[0].usn, 0);
+            }
+        }
+        assert_eq!(coalescer.pending.len(), MAX_PENDING_CLOSES / 2 + 1);
+    }
+
+    #[test]
+    fn stable_order_holds_events_behind_files_still_open() {
+        let at = |usn: i64, reason: u32, file_id: &str| {
+            let mut event = event(reason, file_id, "C:\\a.txt");
+            event.usn = usn;
+            event
+        };
+        let usns = |events: &[JournalEvent]| events.iter().map(|event| event.usn).collect::<Vec<_>>();
+        let mut coalescer = CloseCoalescer::new(0xFFFFFFFF);
+        let mut stable_order = StableOrder::default();
+
+        // File 8 is closed while file 7 is still open, so its close waits
+        let mut emitted = Vec::new();
+        for event in [at(10, 0x01, "Normal(7)"), at(11, 0x01, "Normal(8)"), at(12, USN_REASON_CLOSE, "Normal(8)")] {
+            let ready = coalescer.push(event);
+            emitted.extend(stable_order.push(ready, Some(&mut coalescer)));
+        }
+        assert!(emitted.is_empty());
+        let early = coalescer.expire(11);
+        emitted.extend(stable_order.push(early, Some(&mut coalescer)));
+        assert_eq!(usns(&emitted), [10, 12]);
+
+        // Events sharing a USN keep their order, and nothing is held once
+        // no file can be emitted early any more
+        let ready = vec![at(20, 0x01, "Normal(1)"), at(20, 0x01, "Normal(2)"), at(19, 0x01, "Normal(3)")];
+        let released = stable_order.push(ready, None);
+        let ids: Vec<&str> = released.iter().map(|event| event.file_id.as_str()).collect();
+        assert_eq!(ids, ["Normal(3)", "Normal(1)", "Normal(2)"]);
+        assert!(stable_order.held.is_empty());
+    }
+
+    #[test]
+    fn closes_are_emitted_only_for_files_with_a_masked_reason() {
+        let mut coalescer = CloseCoalescer::new(USN_REASON_FILE_DELETE);
+        assert!(coalescer.push(event(USN_REASON_CLOSE, "Normal(7)", "C:\\read.txt")).is_empty());
+        assert!(coalescer.push(event(0x01, "Normal(8)", "C:\\written.txt")).is_empty());
+        assert!(coalescer.push(event(USN_REASON_CLOSE, "Normal(8)", "C:\\written.txt")).is_empty());
+        assert!(coalescer.pending.is_empty());
+
+        coalescer.push(event(0x01, "Normal(9)", "C:\\deleted.txt"));
+        let closed = coalescer.push(event(USN_REASON_FILE_DELETE | USN_REASON_CLOSE, "Normal(9)", "C:\\deleted.txt"));
+        assert_eq!(closed.len(), 1);
+        assert_eq!(closed[0].reason, 0x01 | USN_REASON_FILE_DELETE | USN_REASON_CLOSE);
+
+        // Files emitted early are checked against the mask too
+        for number in 0..=MAX_PENDING_CLOSES as i64 {
+            let reason = if number == 1 { USN_REASON_FILE_DELETE } else { 0x01 };
+            let mut open = event(reason, &format!("Normal({})", number), "C:\\log.txt");
+            open.usn = number;
+            let early = coalescer.push(open);
+            if number == MAX_PENDING_CLOSES as i64 {
+                assert_eq!(early.iter().map(|event| event.usn).collect::<Vec<_>>(), [1]);
+            }
+        }
     }
 }
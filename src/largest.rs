@@ -0,0 +1,159 @@
+use anyhow::Result;
+use serde::Serialize;
+use std::cmp::{Ordering, Reverse};
+use std::collections::BinaryHeap;
+use std::io;
+
+use crate::filter::FileFilter;
+use crate::mft;
+use crate::output::{self, ColumnType, Field, Fields, TableRow};
+use crate::usage;
+use crate::OutputFormat;
+
+#[derive(Debug, Serialize)]
+pub struct LargeFile {
+    pub path: String,
+    /// Logical size of the unnamed $DATA
+    pub size: u64,
+    /// Space the file takes on disk, after compression
+    pub allocated_size: u64,
+    pub record_number: u64,
+}
+
+impl LargeFile {
+    /// Fields accepted by --fields, with the types of their CSV columns
+    pub const FIELDS: &'static [Field] = &[
+        ("path", ColumnType::String),
+        ("size", ColumnType::Size),
+        ("allocated_size", ColumnType::Int),
+        ("record_number", ColumnType::Int),
+    ];
+}
+
+impl TableRow for LargeFile {
+    fn table_header() -> Vec<&'static str> {
+        vec!["SIZE", "ON DISK", "PATH"]
+    }
+
+    fn table_row(&self) -> Vec<String> {
+        vec![
+            output::format_size(self.size),
+            output::format_size(self.allocated_size),
+            self.path.clone(),
+        ]
+    }
+
+    fn streaming_widths() -> Vec<usize> {
+        vec![12, 12, 0]
+    }
+}
+
+/// Which size `largest` ranks files by
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SizeKind {
+    /// The size applications see
+    Logical,
+    /// The space taken on disk
+    Allocated,
+}
+
+impl std::str::FromStr for SizeKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "logical" | "size" => Ok(SizeKind::Logical),
+            "allocated" | "on-disk" => Ok(SizeKind::Allocated),
+            _ => Err(format!("Invalid size kind: {}", s)),
+        }
+    }
+}
+
+/// A file kept in the heap, ordered by the size it is ranked by
+struct Candidate {
+    key: u64,
+    file: LargeFile,
+}
+
+impl Ord for Candidate {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.key
+            .cmp(&other.key)
+            .then(self.file.record_number.cmp(&other.file.record_number))
+    }
+}
+
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Candidate {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Candidate {}
+
+/// Lists the `top` largest files matching `filter`, largest first, in a
+/// single pass over the MFT. Only `top` files are held at any time, in a
+/// min-heap whose smallest entry is replaced when a larger file turns up;
+/// files too small to enter it are dismissed before their path is built.
+pub fn report(
+    volume: &str,
+    filter: &FileFilter,
+    top: usize,
+    by: SizeKind,
+    output: OutputFormat,
+    fields: &Fields,
+) -> Result<()> {
+    let mft = mft::open_mft(volume)?;
+    let mut heap: BinaryHeap<Reverse<Candidate>> = BinaryHeap::with_capacity(top + 1);
+
+    eprintln!("Looking for the {} largest files...", top);
+    mft::iterate_files(&mft, |file| {
+        if top == 0 || file.is_directory() {
+            return;
+        }
+        let (size, allocated_size) = usage::data_sizes(file);
+        let key = match by {
+            SizeKind::Logical => size,
+            SizeKind::Allocated => allocated_size,
+        };
+        if heap.len() == top && heap.peek().is_some_and(|Reverse(smallest)| key <= smallest.key) {
+            return;
+        }
+        let Some(info) = mft::parse_file_info(&mft, file) else {
+            return;
+        };
+        if !filter.matches(&info, file) {
+            return;
+        }
+
+        heap.push(Reverse(Candidate {
+            key,
+            file: LargeFile {
+                path: output::display_path(&info.path),
+                size,
+                allocated_size,
+                record_number: file.number(),
+            },
+        }));
+        if heap.len() > top {
+            heap.pop();
+        }
+    });
+
+    // Ascending order of Reverse is descending order of size
+    let files: Vec<LargeFile> = heap
+        .into_sorted_vec()
+        .into_iter()
+        .map(|Reverse(candidate)| candidate.file)
+        .collect();
+    eprintln!("Found {} files", files.len());
+
+    let stdout = io::stdout();
+    output::write_report(&mut stdout.lock(), &files, output, fields, LargeFile::FIELDS)
+}
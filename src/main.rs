@@ -1,14 +1,112 @@
-use clap::{Parser, Subcommand};
+use clap::{CommandFactory, FromArgMatches, Parser, Subcommand};
 use anyhow::Result;
+use std::path::PathBuf;
+use std::time::Duration;
 
+mod ads;
+mod attributes;
+mod audit;
+mod baseline;
+mod capacity;
+mod capture;
+mod cdc;
+mod config;
+mod content;
+mod deletion;
+mod du;
+mod elevate;
+#[cfg(feature = "ewf")]
+mod ewf;
+mod extract;
+mod filter;
+mod fragmentation;
+mod freemap;
+mod hardlinks;
+mod hashes;
+mod history;
+mod image;
+mod largest;
 mod mft;
+mod mft_file;
+mod mft_stats;
 mod journal;
+mod monitor;
+mod output;
+mod owners;
+mod query;
+mod raw;
+mod record_dump;
+mod recover;
+mod reparse;
+mod ring;
+mod seal;
+mod shadow;
+mod snapshot;
+mod timestomp;
+mod tree;
+mod units;
+mod usage;
+mod volume;
 
 #[derive(Parser)]
 #[command(name = "ntfs-reader-cli")]
 #[command(about = "Command-line interface for NTFS MFT and USN Journal reading", long_about = None)]
 #[command(version)]
 struct Cli {
+    /// Config file with per-command defaults (default: %APPDATA%\ntfs-reader-cli\config.toml)
+    #[arg(long, global = true, value_name = "FILE")]
+    #[allow(dead_code)] // Read by config::apply_defaults before parsing
+    config: Option<PathBuf>,
+
+    /// Relaunch through a UAC prompt if the command is denied access without administrator rights
+    #[arg(long, global = true)]
+    elevate: bool,
+
+    /// Set by --elevate for the elevated run: where its input and output go
+    #[arg(long = "elevated-io", global = true, hide = true, value_name = "PREFIX")]
+    elevated_io: Option<PathBuf>,
+
+    /// Show sizes as 1.4 GiB instead of bytes in table and CSV output (JSON keeps bytes)
+    #[arg(long, global = true)]
+    human_sizes: bool,
+
+    /// Like --human-sizes, but with powers of 1000 (1.5 GB)
+    #[arg(long, global = true)]
+    si: bool,
+
+    /// Emit \\?\-prefixed extended paths that can be reused directly in scripts
+    #[arg(long, global = true)]
+    extended_paths: bool,
+
+    /// Add raw 100ns FILETIME timestamps (e.g. modified_filetime) to serialized output
+    #[arg(long, global = true)]
+    filetime: bool,
+
+    /// Print warnings on stderr as JSON lines ({"warning":"encoding","message":...}) instead of text
+    #[arg(long, global = true)]
+    json_warnings: bool,
+
+    /// Abort on the first MFT record that can't be parsed instead of reporting and skipping it
+    #[arg(long, global = true)]
+    strict: bool,
+
+    /// Emit journal events strictly in USN order, holding back those --on-close would emit early
+    /// (MFT records always come in record-number order)
+    #[arg(long, global = true)]
+    stable_order: bool,
+
+    /// Add a second CSV header row with the type of each column (string, int, float, bool, datetime)
+    #[arg(long, global = true)]
+    csv_typed_header: bool,
+
+    /// Read Volume Shadow Copy N of the volume instead of its live state (see list-shadows)
+    #[arg(long, global = true, value_name = "N")]
+    shadow: Option<u32>,
+
+    /// Field naming in JSON output and CSV headers: snake, camel or pascal
+    #[arg(long, global = true, default_value = "snake", value_name = "CASE")]
+    field_case: output::FieldCase,
+
     #[command(subcommand)]
     command: Commands,
 }
@@ -17,32 +115,144 @@ struct Cli {
 enum Commands {
     /// List all files from the MFT
     ListFiles {
+        /// Volume path (e.g., \\.\C: or C:)
+        #[arg(short, long, required_unless_present_any = ["mft_file", "image"])]
+        volume: Option<String>,
+
+        /// Read an $MFT extracted from a volume instead, e.g. one copied off another machine
+        /// (paths start at \, as the drive letter isn't known)
+        #[arg(
+            long,
+            value_name = "PATH",
+            conflicts_with_all = ["volume", "estimate", "reparse", "owners", "resolve_owners"]
+        )]
+        mft_file: Option<PathBuf>,
+
+        /// Read the MFT from a disk or partition image instead, like --mft-file
+        /// (raw/dd, or EnCase E01 in builds with the ewf feature)
+        #[arg(
+            long,
+            value_name = "PATH",
+            conflicts_with_all = ["volume", "mft_file", "estimate", "reparse", "owners", "resolve_owners"]
+        )]
+        image: Option<PathBuf>,
+
+        /// Byte offset of the NTFS partition in --image (default: found from the partition table)
+        #[arg(long, value_name = "BYTES", requires = "image")]
+        partition_offset: Option<u64>,
+
+        #[command(flatten)]
+        filter: filter::FilterArgs,
+
+        /// Only show directories
+        #[arg(short, long, conflicts_with = "files_only")]
+        directories_only: bool,
+
+        /// Only show files
+        #[arg(long)]
+        files_only: bool,
+
+        /// Limit number of results
+        #[arg(short, long)]
+        limit: Option<usize>,
+
+        /// Also list deleted files whose MFT records haven't been reused, marked "deleted": true
+        #[arg(long, conflicts_with = "deleted_only")]
+        include_deleted: bool,
+
+        /// Only list deleted files whose MFT records haven't been reused
+        #[arg(long)]
+        deleted_only: bool,
+
+        /// Only scan shard i of N (e.g. 2/4); the N shards together cover every record exactly once
+        #[arg(long, value_name = "i/N")]
+        shard: Option<mft::Shard>,
+
+        /// List every path of each file, one per hard link, in a hard_links field
+        #[arg(long)]
+        all_names: bool,
+
+        /// Decode reparse points: tag and target of symlinks, junctions, mount points, cloud placeholders
+        #[arg(long)]
+        reparse: bool,
+
+        /// Add each file's owner SID, read from the volume's security descriptors ($Secure)
+        #[arg(long)]
+        owners: bool,
+
+        /// Also resolve owner SIDs to DOMAIN\account names (implies --owners)
+        #[arg(long)]
+        resolve_owners: bool,
+
+        /// Only report the approximate number of records in use, read from the MFT bitmap
+        #[arg(long)]
+        estimate: bool,
+
+        /// Output format: table, json, jsonl, json-pretty, json-tree, csv, bincode, msgpack
+        /// (default: table on a terminal, jsonl when piped)
+        #[arg(short, long, value_name = "FORMAT")]
+        output: Option<OutputFormat>,
+
+        /// Also write the output to a file in another format, as FORMAT:PATH (repeatable)
+        #[arg(long, value_name = "FORMAT:PATH")]
+        tee: Vec<output::TeeSink>,
+
+        /// Only output these fields, in this order (e.g. name,path,size,modified)
+        #[arg(long, value_name = "FIELDS", value_delimiter = ',')]
+        fields: Vec<String>,
+    },
+
+    /// List alternate data streams (named $DATA attributes), one entry per stream
+    Ads {
         /// Volume path (e.g., \\.\C: or C:)
         #[arg(short, long)]
         volume: String,
 
-        /// Filter by path pattern (case-insensitive substring match)
+        #[command(flatten)]
+        filter: filter::FilterArgs,
+
+        /// Limit number of streams
         #[arg(short, long)]
-        filter: Option<String>,
+        limit: Option<usize>,
 
-        /// Only show directories
+        /// Output format: table, json, jsonl, json-pretty, csv, bincode, msgpack
+        /// (default: table on a terminal, jsonl when piped)
+        #[arg(short, long, value_name = "FORMAT")]
+        output: Option<OutputFormat>,
+
+        /// Only output these fields, in this order (e.g. stream_path,size)
+        #[arg(long, value_name = "FIELDS", value_delimiter = ',')]
+        fields: Vec<String>,
+    },
+
+    /// List files with more than one hard link and every path they are linked under
+    Hardlinks {
+        /// Volume path (e.g., \\.\C: or C:)
         #[arg(short, long)]
-        directories_only: bool,
+        volume: String,
+
+        #[command(flatten)]
+        filter: filter::FilterArgs,
 
         /// Limit number of results
         #[arg(short, long)]
         limit: Option<usize>,
 
-        /// Output format: json, json-pretty, csv, bincode, msgpack
-        #[arg(short, long, default_value = "json", value_name = "FORMAT")]
-        output: OutputFormat,
+        /// Output format: table, json, jsonl, json-pretty, csv, bincode, msgpack
+        /// (default: table on a terminal, jsonl when piped)
+        #[arg(short, long, value_name = "FORMAT")]
+        output: Option<OutputFormat>,
+
+        /// Only output these fields, in this order (e.g. link_count,paths)
+        #[arg(long, value_name = "FIELDS", value_delimiter = ',')]
+        fields: Vec<String>,
     },
 
     /// Monitor USN journal for file system changes
     Journal {
         /// Volume path (e.g., \\?\C: or C:)
-        #[arg(short, long)]
-        volume: String,
+        #[arg(short, long, required_unless_present = "from_capture")]
+        volume: Option<String>,
 
         /// Start from beginning of journal (default: start from current position)
         #[arg(short, long)]
@@ -64,13 +274,396 @@ enum Commands {
         #[arg(short, long)]
         continuous: bool,
 
-        /// Output format: json, json-pretty, csv, bincode, msgpack
-        #[arg(short, long, default_value = "json", value_name = "FORMAT")]
-        output: OutputFormat,
+        /// Emit one event per file when its handle is closed, carrying the accumulated reasons.
+        /// With --reason-mask, only files with one of its reasons are emitted
+        #[arg(long)]
+        on_close: bool,
+
+        /// Print a JSON heartbeat with the journal lag to stderr every N seconds
+        #[arg(long, value_name = "SECONDS")]
+        heartbeat: Option<u64>,
+
+        /// Pace replayed events: unlimited, realtime, a speed-up such as 10x, or a cap such as 500/s
+        #[arg(long, default_value = "unlimited", value_name = "SPEED", requires = "continuous")]
+        replay_speed: journal::ReplaySpeed,
+
+        /// Only emit a sample of events, e.g. 1/100
+        #[arg(long, value_name = "N/M")]
+        sample: Option<journal::SampleRate>,
+
+        /// Sampling strategy: frn (deterministic per file) or random
+        #[arg(long, default_value = "frn", value_name = "MODE", requires = "sample")]
+        sample_mode: journal::SampleMode,
+
+        /// Event model: usn (raw reasons) or notify (create/modify/rename/remove kinds)
+        #[arg(long, default_value = "usn", value_name = "MODEL")]
+        event_model: journal::EventModel,
+
+        /// Output format: table, json, jsonl, json-pretty, csv, bincode, msgpack
+        /// or cdc for change envelopes (default: table on a terminal, jsonl when piped)
+        #[arg(short, long, value_name = "FORMAT")]
+        output: Option<OutputFormat>,
+
+        /// Also write the output to a file in another format, as FORMAT:PATH (repeatable)
+        #[arg(long, value_name = "FORMAT:PATH")]
+        tee: Vec<output::TeeSink>,
+
+        /// Only output these fields, in this order (e.g. usn,reason_str,path)
+        #[arg(long, value_name = "FIELDS", value_delimiter = ',')]
+        fields: Vec<String>,
+
+        /// Record every journal event, before any filtering, to a capture file for later re-analysis
+        #[arg(long, value_name = "FILE")]
+        capture: Option<PathBuf>,
+
+        /// Read events from a capture file instead of the live journal
+        #[arg(long, value_name = "FILE", conflicts_with_all = ["capture", "from_start"])]
+        from_capture: Option<PathBuf>,
+
+        /// Also store every event in this SQLite database, for journal-query
+        #[arg(long, value_name = "FILE")]
+        db: Option<PathBuf>,
+
+        /// When a directory is renamed or moved, also emit rename events for every path below it
+        #[arg(long, requires = "volume")]
+        expand_moves: bool,
+
+        /// Only report events for paths below this directory, e.g. C:\Users or Users\alice
+        #[arg(long, value_name = "DIR")]
+        under: Option<String>,
+
+        /// Only report events for paths at most N levels below --under (or the volume root)
+        #[arg(long, value_name = "N")]
+        max_depth: Option<usize>,
+
+        /// Report the N files, directories and extensions with the most events to stderr
+        #[arg(long, value_name = "N")]
+        top_talkers: Option<usize>,
+
+        /// How often --top-talkers lists are reported; counts start over each time
+        #[arg(long, default_value = "60s", value_name = "INTERVAL", value_parser = units::parse_duration, requires = "top_talkers")]
+        top_interval: Duration,
+
+        /// Write continuous-mode events in batches of up to N (arrays in JSON, bincode and msgpack)
+        #[arg(long, value_name = "N", requires = "continuous")]
+        batch_size: Option<usize>,
+
+        /// Longest time an event waits for its batch to fill before it is written
+        #[arg(long, default_value = "250ms", value_name = "INTERVAL", value_parser = units::parse_duration, requires = "batch_size")]
+        batch_max_latency: Duration,
+
+        /// Every --on-change-interval, run this command with a file listing the paths changed since
+        #[arg(long, value_name = "CMD")]
+        on_change_exec: Option<String>,
+
+        /// How often --on-change-exec runs; it is skipped when nothing changed
+        #[arg(long, default_value = "5s", value_name = "INTERVAL", value_parser = units::parse_duration, requires = "on_change_exec")]
+        on_change_interval: Duration,
+
+        /// Apply the options of [pipelines.NAME] from the config file (filters and sinks)
+        #[arg(long, value_name = "NAME")]
+        pipeline: Option<String>,
+
+        /// Also keep the most recent events in a fixed-size memory-mapped file, as ring:PATH[,size=SIZE]
+        #[arg(long, value_name = "ring:PATH[,size=SIZE]")]
+        sink: Option<ring::RingSpec>,
+
+        /// When the volume is dismounted or goes offline, keep retrying to reopen it and resume where reading stopped
+        #[arg(long, conflicts_with = "from_capture")]
+        reconnect: bool,
+
+        /// Key file used to encrypt --capture or decrypt --from-capture (AES-256-GCM)
+        #[arg(long, value_name = "FILE")]
+        encrypt_key: Option<PathBuf>,
+
+        /// Ed25519 key file used to sign the --capture file once it is complete (not with
+        /// --continuous, whose capture would stay unsigned when interrupted)
+        #[arg(long, value_name = "FILE", requires = "capture", conflicts_with = "continuous")]
+        sign_key: Option<PathBuf>,
+    },
+
+    /// Measure the journal's event rate and recommend a maximum size that covers a retention period
+    JournalCapacity {
+        /// Volume path (e.g., \\?\C: or C:)
+        #[arg(short, long)]
+        volume: String,
+
+        /// How long to sample journal activity (e.g. 60s, 10m); busier periods give safer numbers
+        #[arg(long, default_value = "60s", value_name = "INTERVAL", value_parser = units::parse_duration)]
+        window: Duration,
+
+        /// Longest gap between runs of the backup or indexer reading the journal (e.g. 24h)
+        #[arg(long, default_value = "24h", value_name = "INTERVAL", value_parser = units::parse_duration)]
+        retention: Duration,
+    },
+
+    /// Print the events kept in a ring buffer written by journal --sink, oldest first, as JSON lines
+    RingDump {
+        /// Ring buffer file
+        #[arg(long, value_name = "FILE")]
+        file: PathBuf,
+
+        /// Only print the N most recent events
+        #[arg(long, value_name = "N")]
+        last: Option<usize>,
+    },
+
+    /// Query journal events stored with journal --db
+    JournalQuery {
+        /// SQLite database written by journal --db
+        #[arg(long, value_name = "FILE")]
+        db: PathBuf,
+
+        /// Only events whose path matches this SQL LIKE pattern (% and _ wildcards)
+        #[arg(long, value_name = "PATTERN")]
+        path_like: Option<String>,
+
+        /// Only events at or after this date (YYYY-MM-DD or RFC 3339)
+        #[arg(long, value_name = "DATE", value_parser = units::parse_timestamp)]
+        since: Option<time::OffsetDateTime>,
+
+        /// Only events before this date
+        #[arg(long, value_name = "DATE", value_parser = units::parse_timestamp)]
+        until: Option<time::OffsetDateTime>,
+
+        /// Only events with any of these reason bits (bitmask of USN_REASON_* values)
+        #[arg(short, long)]
+        reason_mask: Option<u32>,
+
+        /// Limit number of results
+        #[arg(short, long)]
+        limit: Option<usize>,
+
+        /// Output format: table, json, jsonl, json-pretty, csv, bincode, msgpack
+        /// or cdc for change envelopes (default: table on a terminal, jsonl when piped)
+        #[arg(short, long, value_name = "FORMAT")]
+        output: Option<OutputFormat>,
+
+        /// Only output these fields, in this order (e.g. usn,reason_str,path)
+        #[arg(long, value_name = "FIELDS", value_delimiter = ',')]
+        fields: Vec<String>,
+    },
+
+    /// Follow the journal and emit up-to-date MFT metadata for each changed file
+    Monitor {
+        /// Volume path (e.g., \\?\C: or C:)
+        #[arg(short, long)]
+        volume: String,
+
+        /// How often the records of changed files are re-read (e.g. 500ms, 2s)
+        #[arg(long, default_value = "1s", value_name = "INTERVAL", value_parser = units::parse_duration)]
+        refresh: Duration,
+
+        /// Add the owner and a DACL summary from before and after to SECURITY_CHANGE lines
+        /// (reads $Secure and every file's security ID at startup)
+        #[arg(long)]
+        security: bool,
+
+        /// Add attribute flags and timestamps from before and after to BASIC_INFO_CHANGE lines
+        /// (reads every file's $STANDARD_INFORMATION at startup)
+        #[arg(long)]
+        basic_info: bool,
+
+        /// Only report files that grew or shrank by more than this many bytes (e.g. 100M),
+        /// adding size_delta to each line (reads every file's size at startup)
+        #[arg(long, value_name = "SIZE", value_parser = units::parse_size)]
+        min_size_delta: Option<u64>,
     },
 
     /// Get information about a specific file by MFT record number
     FileInfo {
+        /// Volume path (e.g., \\.\C: or C:)
+        #[arg(short, long, required_unless_present_any = ["mft_file", "image"])]
+        volume: Option<String>,
+
+        /// Read the record from an $MFT extracted from a volume instead
+        #[arg(
+            long,
+            value_name = "PATH",
+            conflicts_with_all = ["volume", "by_file_id", "reparse", "owners", "resolve_owners", "attributes"]
+        )]
+        mft_file: Option<PathBuf>,
+
+        /// Read the record from a disk or partition image instead (raw/dd, or E01 with the ewf feature)
+        #[arg(
+            long,
+            value_name = "PATH",
+            conflicts_with_all = [
+                "volume",
+                "mft_file",
+                "by_file_id",
+                "reparse",
+                "owners",
+                "resolve_owners",
+                "attributes",
+            ]
+        )]
+        image: Option<PathBuf>,
+
+        /// Byte offset of the NTFS partition in --image (default: found from the partition table)
+        #[arg(long, value_name = "BYTES", requires = "image")]
+        partition_offset: Option<u64>,
+
+        /// MFT record number
+        #[arg(short, long, required_unless_present = "by_file_id", conflicts_with = "by_file_id")]
+        record: Option<u64>,
+
+        /// File reference number instead of a record number, as found in journal output
+        /// (decimal, 0x-prefixed hex, Normal(...) or Extended(...)); the sequence number is checked
+        #[arg(long, value_name = "FRN")]
+        by_file_id: Option<String>,
+
+        /// Decode the reparse point: tag and target of a symlink, junction, mount point, cloud placeholder
+        #[arg(long)]
+        reparse: bool,
+
+        /// Add the file's owner SID, read from the volume's security descriptors ($Secure)
+        #[arg(long)]
+        owners: bool,
+
+        /// Also resolve owner SIDs to DOMAIN\account names (implies --owners)
+        #[arg(long)]
+        resolve_owners: bool,
+
+        /// List every attribute in the record: type, name, resident flag, sizes and flags
+        #[arg(long)]
+        attributes: bool,
+
+        /// Print the content of a small file stored inside its MFT record (resident $DATA)
+        /// as hex/ASCII instead of the record
+        #[arg(long)]
+        dump_resident: bool,
+
+        /// Write the resident content to this file instead, and show the record as usual
+        #[arg(long, value_name = "PATH", requires = "dump_resident")]
+        dump_out: Option<PathBuf>,
+
+        /// Output format: table, json, jsonl, json-pretty, csv, bincode, msgpack
+        /// (default: table on a terminal, jsonl when piped)
+        #[arg(short, long, value_name = "FORMAT")]
+        output: Option<OutputFormat>,
+
+        /// Only output these fields, in this order (e.g. name,path,size,modified)
+        #[arg(long, value_name = "FIELDS", value_delimiter = ',')]
+        fields: Vec<String>,
+    },
+
+    /// Show information about the file or directory at a path, like file-info
+    Lookup {
+        /// Volume path (e.g., \\.\C: or C:)
+        #[arg(short, long)]
+        volume: String,
+
+        /// Full path of the file or directory (e.g. C:\Windows\notepad.exe)
+        #[arg(short, long, value_name = "PATH")]
+        path: String,
+
+        /// Decode the reparse point: tag and target of a symlink, junction, mount point, cloud placeholder
+        #[arg(long)]
+        reparse: bool,
+
+        /// Add the file's owner SID, read from the volume's security descriptors ($Secure)
+        #[arg(long)]
+        owners: bool,
+
+        /// Also resolve owner SIDs to DOMAIN\account names (implies --owners)
+        #[arg(long)]
+        resolve_owners: bool,
+
+        /// List every attribute in the record: type, name, resident flag, sizes and flags
+        #[arg(long)]
+        attributes: bool,
+
+        /// Print the file's resident content as hex/ASCII instead of the record (see file-info)
+        #[arg(long)]
+        dump_resident: bool,
+
+        /// Write the resident content to this file instead, and show the record as usual
+        #[arg(long, value_name = "PATH", requires = "dump_resident")]
+        dump_out: Option<PathBuf>,
+
+        /// Output format: table, json, jsonl, json-pretty, csv, bincode, msgpack
+        /// (default: table on a terminal, jsonl when piped)
+        #[arg(short, long, value_name = "FORMAT")]
+        output: Option<OutputFormat>,
+
+        /// Only output these fields, in this order (e.g. name,path,size,modified)
+        #[arg(long, value_name = "FIELDS", value_delimiter = ',')]
+        fields: Vec<String>,
+    },
+
+    /// Resolve paths to MFT records, reading one path per line from stdin
+    Resolve {
+        /// Volume path (e.g., \\.\C: or C:)
+        #[arg(short, long)]
+        volume: String,
+
+        /// Read paths from stdin (one per line) and write one JSON result per line
+        #[arg(long, required = true)]
+        stdin: bool,
+    },
+
+    /// Resolve file reference numbers (e.g. from ETW or the journal) to current paths and metadata
+    #[command(visible_alias = "resolve-id")]
+    ResolveFrn {
+        /// Volume path (e.g., \\.\C: or C:)
+        #[arg(short, long)]
+        volume: String,
+
+        /// File reference numbers: decimal, 0x-prefixed hex, Normal(...) or Extended(...) (record numbers also work)
+        #[arg(value_name = "FRN", required_unless_present = "stdin")]
+        frns: Vec<String>,
+
+        /// Also read references from stdin, one per line
+        #[arg(long)]
+        stdin: bool,
+    },
+
+    /// Show the volume's geometry and state: sector and cluster sizes, MFT location, serial, NTFS version, dirty flag
+    VolumeInfo {
+        /// Volume path (e.g., \\.\C: or C:)
+        #[arg(short, long)]
+        volume: String,
+
+        /// Output format: table, json, jsonl, json-pretty, csv, bincode, msgpack
+        /// (default: table on a terminal, jsonl when piped)
+        #[arg(short, long, value_name = "FORMAT")]
+        output: Option<OutputFormat>,
+
+        /// Only output these fields, in this order (e.g. serial_number,ntfs_version,dirty)
+        #[arg(long, value_name = "FIELDS", value_delimiter = ',')]
+        fields: Vec<String>,
+    },
+
+    /// Summarize the MFT: record counts by state and kind, attributes per record, $MFT size and extents
+    MftStats {
+        /// Volume path (e.g., \\.\C: or C:)
+        #[arg(short, long)]
+        volume: String,
+
+        /// Output format: table, json, jsonl or json-pretty
+        /// (default: table on a terminal, jsonl when piped)
+        #[arg(short, long, value_name = "FORMAT")]
+        output: Option<OutputFormat>,
+    },
+
+    /// Report which kinds of volume access are available to the current account
+    Capabilities {
+        /// Volume path (e.g., C:)
+        #[arg(short, long)]
+        volume: String,
+    },
+
+    /// List USN_REASON_* flags, or compute a --reason-mask from their names
+    Reasons {
+        /// Reason names to combine, e.g. create,delete,rename
+        #[arg(long, value_name = "NAMES", value_delimiter = ',')]
+        mask: Vec<String>,
+    },
+
+    /// Copy a file's content by MFT record number, reading its clusters directly (works on locked files)
+    #[command(visible_alias = "cat")]
+    Extract {
         /// Volume path (e.g., \\.\C: or C:)
         #[arg(short, long)]
         volume: String,
@@ -79,19 +672,420 @@ enum Commands {
         #[arg(short, long)]
         record: u64,
 
-        /// Output format: json, json-pretty, csv, bincode, msgpack
-        #[arg(short, long, default_value = "json", value_name = "FORMAT")]
-        output: OutputFormat,
+        /// Read this alternate data stream instead of the file's main content
+        #[arg(long, value_name = "NAME")]
+        stream: Option<String>,
+
+        /// Write the content to this file instead of stdout
+        #[arg(long, value_name = "PATH")]
+        out: Option<PathBuf>,
+    },
+
+    /// Print a raw MFT record as annotated hex: header fields, fixups and attribute boundaries
+    DumpRecord {
+        /// Volume path (e.g., \\.\C: or C:)
+        #[arg(short, long)]
+        volume: String,
+
+        /// MFT record number
+        #[arg(short, long)]
+        record: u64,
+
+        /// Show the bytes as stored on disk, before the update sequence fixups are applied
+        #[arg(long)]
+        on_disk: bool,
+    },
+
+    /// Recover the content of deleted files from the MFT
+    Recover {
+        /// Volume path (e.g., \\.\C: or C:)
+        #[arg(short, long)]
+        volume: String,
+
+        /// Dump every deleted file whose data is resident in its MFT record
+        #[arg(long, required_unless_present = "record", conflicts_with = "record", requires = "out_dir")]
+        all_resident: bool,
+
+        /// Directory the recovered files are written to
+        #[arg(long, value_name = "DIR")]
+        out_dir: Option<PathBuf>,
+
+        /// Recover one deleted file by MFT record number, reading its data runs from raw clusters
+        #[arg(long, value_name = "N", requires = "out")]
+        record: Option<u64>,
+
+        /// File the content of --record is written to
+        #[arg(long, value_name = "PATH", conflicts_with = "out_dir")]
+        out: Option<PathBuf>,
+    },
+
+    /// Capture MFT snapshots (JSON Lines) once or on a schedule
+    Snapshot {
+        /// Volume path (e.g., \\.\C: or C:)
+        #[arg(short, long)]
+        volume: String,
+
+        /// Directory the snapshots are written to
+        #[arg(long, value_name = "DIR")]
+        out_dir: PathBuf,
+
+        /// Take a snapshot at this interval instead of once (e.g. 30m, 6h, 1d)
+        #[arg(long, value_name = "INTERVAL", value_parser = units::parse_interval)]
+        every: Option<Duration>,
+
+        /// Number of most recent snapshots of the volume to keep
+        #[arg(long, value_name = "COUNT")]
+        retain: Option<usize>,
+
+        /// Key file used to encrypt the snapshots (AES-256-GCM)
+        #[arg(long, value_name = "FILE")]
+        encrypt_key: Option<PathBuf>,
+
+        /// Ed25519 key file used to sign each snapshot (writes FILE.sig)
+        #[arg(long, value_name = "FILE")]
+        sign_key: Option<PathBuf>,
+    },
+
+    /// Snapshot several volumes in parallel and report what changed since their previous snapshots
+    Audit {
+        /// Volume to audit (repeatable), e.g. C:
+        #[arg(short, long = "volume", value_name = "VOLUME", required = true)]
+        volumes: Vec<String>,
+
+        /// Directory the snapshots are written to and compared from
+        #[arg(long, value_name = "DIR")]
+        out_dir: PathBuf,
+
+        /// Run an audit at this interval instead of once (e.g. 30m, 6h, 1d)
+        #[arg(long, value_name = "INTERVAL", value_parser = units::parse_interval)]
+        every: Option<Duration>,
+
+        /// Number of most recent snapshots of each volume to keep (at least 1)
+        #[arg(long, value_name = "COUNT")]
+        retain: Option<usize>,
+
+        /// Key file used to encrypt the snapshots (AES-256-GCM)
+        #[arg(long, value_name = "FILE")]
+        encrypt_key: Option<PathBuf>,
+
+        /// Ed25519 key file used to sign each snapshot (writes FILE.sig)
+        #[arg(long, value_name = "FILE")]
+        sign_key: Option<PathBuf>,
+    },
+
+    /// List the Volume Shadow Copies that --shadow can read
+    ListShadows {
+        /// Output format: table, json, jsonl, json-pretty, csv, bincode, msgpack
+        /// (default: table on a terminal, jsonl when piped)
+        #[arg(short, long, value_name = "FORMAT")]
+        output: Option<OutputFormat>,
+
+        /// Only output these fields, in this order (e.g. index,drive,created)
+        #[arg(long, value_name = "FIELDS", value_delimiter = ',')]
+        fields: Vec<String>,
+    },
+
+    /// Alert on changes under protected paths relative to a snapshot baseline
+    MonitorBaseline {
+        /// Volume path (e.g., \\?\C: or C:)
+        #[arg(short, long)]
+        volume: String,
+
+        /// Snapshot file used as the baseline
+        #[arg(long, value_name = "FILE", conflicts_with = "snapshot_dir", required_unless_present = "snapshot_dir")]
+        baseline: Option<PathBuf>,
+
+        /// Use the most recent snapshot of the volume in this directory as the baseline
+        #[arg(long, value_name = "DIR")]
+        snapshot_dir: Option<PathBuf>,
+
+        /// Protected path to watch (repeatable), e.g. C:\Windows\System32
+        #[arg(short, long = "protect", value_name = "PATH", required = true)]
+        protect: Vec<String>,
+
+        /// Check a journal capture file instead of the live journal
+        #[arg(long, value_name = "FILE")]
+        from_capture: Option<PathBuf>,
+
+        /// Key file used to decrypt an encrypted baseline or capture
+        #[arg(long, value_name = "FILE")]
+        encrypt_key: Option<PathBuf>,
+    },
+
+    /// Run a SQL query over a snapshot or Parquet file, loaded as a table named files
+    /// (needs a build with --features sql)
+    Query {
+        /// Snapshot file written by snapshot or audit, or a .parquet file
+        #[arg(long, value_name = "FILE")]
+        snapshot: PathBuf,
+
+        /// Query to run, e.g. "SELECT path, size FROM files WHERE size > 1e9 ORDER BY size DESC"
+        #[arg(long, value_name = "SQL")]
+        sql: String,
+
+        /// Key file used to decrypt an encrypted snapshot (not Parquet files)
+        #[arg(long, value_name = "FILE")]
+        encrypt_key: Option<PathBuf>,
+
+        /// Output format: table, json, jsonl, json-pretty, csv, bincode, msgpack
+        /// (default: table on a terminal, jsonl when piped)
+        #[arg(short, long, value_name = "FORMAT")]
+        output: Option<OutputFormat>,
+    },
+
+    /// Check snapshot or capture files against their .sig signatures
+    Verify {
+        /// Ed25519 public key file (signing.pub from keygen)
+        #[arg(long, value_name = "FILE")]
+        public_key: PathBuf,
+
+        /// Signed files to check
+        #[arg(required = true, value_name = "FILE")]
+        files: Vec<PathBuf>,
+    },
+
+    /// Re-hash the files listed in a manifest from raw clusters and report mismatches
+    VerifyHashes {
+        /// Volume path (e.g., \\.\C: or C:)
+        #[arg(short, long)]
+        volume: String,
+
+        /// CSV manifest with path and sha256 columns
+        #[arg(long, value_name = "FILE")]
+        manifest: PathBuf,
+
+        /// Number of files hashed in parallel (default: one per CPU)
+        #[arg(long, value_name = "N")]
+        threads: Option<usize>,
+    },
+
+    /// List the most fragmented files, from the data runs of their MFT records
+    Fragmentation {
+        /// Volume path (e.g., \\.\C: or C:)
+        #[arg(short, long)]
+        volume: String,
+
+        #[command(flatten)]
+        filter: filter::FilterArgs,
+
+        /// Only list files split into at least this many extents
+        #[arg(long, default_value_t = 2, value_name = "N")]
+        min_extents: u64,
+
+        /// Sort order: extents, average-run (shortest first) or size
+        #[arg(long, default_value = "extents", value_name = "ORDER")]
+        sort: fragmentation::FragmentationSort,
+
+        /// Limit number of results
+        #[arg(short, long, default_value_t = 100)]
+        limit: usize,
+
+        /// Output format: table, json, jsonl, json-pretty, csv, bincode, msgpack
+        /// (default: table on a terminal, jsonl when piped)
+        #[arg(short, long, value_name = "FORMAT")]
+        output: Option<OutputFormat>,
+
+        /// Only output these fields, in this order (e.g. extents,average_run_clusters,path)
+        #[arg(long, value_name = "FIELDS", value_delimiter = ',')]
+        fields: Vec<String>,
+    },
+
+    /// List the largest files by logical or allocated size, in one pass over the MFT
+    Largest {
+        /// Volume path (e.g., \\.\C: or C:)
+        #[arg(short, long)]
+        volume: String,
+
+        #[command(flatten)]
+        filter: filter::FilterArgs,
+
+        /// Number of files to list
+        #[arg(long, default_value_t = 100, value_name = "N")]
+        top: usize,
+
+        /// Rank by logical size or by allocated (on-disk) size
+        #[arg(long, default_value = "logical", value_name = "SIZE")]
+        by: largest::SizeKind,
+
+        /// Output format: table, json, jsonl, json-pretty, csv, bincode, msgpack
+        /// (default: table on a terminal, jsonl when piped)
+        #[arg(short, long, value_name = "FORMAT")]
+        output: Option<OutputFormat>,
+
+        /// Only output these fields, in this order (e.g. size,path)
+        #[arg(long, value_name = "FIELDS", value_delimiter = ',')]
+        fields: Vec<String>,
+    },
+
+    /// Summarize how fragmented a volume is and list its most fragmented files
+    FragReport {
+        /// Volume path (e.g., \\.\C: or C:)
+        #[arg(short, long)]
+        volume: String,
+
+        #[command(flatten)]
+        filter: filter::FilterArgs,
+
+        /// Number of most fragmented files to list
+        #[arg(long, default_value_t = 20, value_name = "N")]
+        top: usize,
+
+        /// Output format: table, json, jsonl, json-pretty
+        /// (default: table on a terminal, jsonl when piped)
+        #[arg(short, long, value_name = "FORMAT")]
+        output: Option<OutputFormat>,
+    },
+
+    /// Compare logical, allocated and compressed sizes per subtree
+    DiskUsage {
+        /// Volume path (e.g., \\.\C: or C:)
+        #[arg(short, long)]
+        volume: String,
+
+        #[command(flatten)]
+        filter: filter::FilterArgs,
+
+        /// Directory to report on (default: the whole volume)
+        #[arg(long, value_name = "PATH")]
+        root: Option<String>,
+
+        /// Directory levels below the root to report separately
+        #[arg(long, default_value_t = 1, value_name = "N")]
+        depth: usize,
+
+        /// Output format: table, json, jsonl, json-pretty, csv, bincode, msgpack
+        /// (default: table on a terminal, jsonl when piped)
+        #[arg(short, long, value_name = "FORMAT")]
+        output: Option<OutputFormat>,
+
+        /// Only output these fields, in this order (e.g. path,wof_savings)
+        #[arg(long, value_name = "FIELDS", value_delimiter = ',')]
+        fields: Vec<String>,
+    },
+
+    /// Count files and sizes per type detected from their first bytes, regardless of extension
+    ContentStats {
+        /// Volume path (e.g., \\.\C: or C:)
+        #[arg(short, long)]
+        volume: String,
+
+        #[command(flatten)]
+        filter: filter::FilterArgs,
+
+        /// Output format: table, json, jsonl, json-pretty, csv, bincode, msgpack
+        /// (default: table on a terminal, jsonl when piped)
+        #[arg(short, long, value_name = "FORMAT")]
+        output: Option<OutputFormat>,
+
+        /// Only output these fields, in this order (e.g. type,files,size)
+        #[arg(long, value_name = "FIELDS", value_delimiter = ',')]
+        fields: Vec<String>,
+    },
+
+    /// Flag files whose $STANDARD_INFORMATION timestamps look altered, compared with $FILE_NAME
+    Timestomp {
+        /// Volume path (e.g., \\.\C: or C:)
+        #[arg(short, long)]
+        volume: String,
+
+        #[command(flatten)]
+        filter: filter::FilterArgs,
+
+        /// Output format: table, json, jsonl, json-pretty, csv, bincode, msgpack
+        /// (default: table on a terminal, jsonl when piped)
+        #[arg(short, long, value_name = "FORMAT")]
+        output: Option<OutputFormat>,
+
+        /// Only output these fields, in this order (e.g. path,findings,si_created,fn_created)
+        #[arg(long, value_name = "FIELDS", value_delimiter = ',')]
+        fields: Vec<String>,
+    },
+
+    /// Total the size of every directory from parent references in the MFT, like du
+    Du {
+        /// Volume path (e.g., \\.\C: or C:)
+        #[arg(short, long)]
+        volume: String,
+
+        /// Directory to report on (default: the volume root)
+        #[arg(long, value_name = "PATH")]
+        path: Option<String>,
+
+        /// Directory levels below --path to list, each with the totals of everything beneath it
+        #[arg(long, default_value_t = 1, value_name = "N")]
+        depth: usize,
+
+        /// Output format: table, json, jsonl, json-pretty, csv, bincode, msgpack
+        /// (default: table on a terminal, jsonl when piped)
+        #[arg(short, long, value_name = "FORMAT")]
+        output: Option<OutputFormat>,
+
+        /// Only output these fields, in this order (e.g. size,path)
+        #[arg(long, value_name = "FIELDS", value_delimiter = ',')]
+        fields: Vec<String>,
+    },
+
+    /// Print the directory hierarchy rebuilt from parent references, with sizes and child counts
+    Tree {
+        /// Volume path (e.g., \\.\C: or C:)
+        #[arg(short, long)]
+        volume: String,
+
+        /// Directory at the top of the tree (default: the volume root)
+        #[arg(long, value_name = "PATH")]
+        path: Option<String>,
+
+        /// Levels below --path to show
+        #[arg(long, default_value_t = 2, value_name = "N")]
+        depth: usize,
+
+        /// Only show directories
+        #[arg(long)]
+        dirs_only: bool,
+
+        /// Output format: table (an indented tree), json, jsonl, json-pretty (nested), dot (Graphviz)
+        /// (default: table on a terminal, jsonl when piped)
+        #[arg(short, long, value_name = "FORMAT")]
+        output: Option<OutputFormat>,
+    },
+
+    /// Export the cluster allocation map from $Bitmap as run-length encoded JSON or a PNG heat strip
+    Freemap {
+        /// Volume path (e.g., \\.\C: or C:)
+        #[arg(short, long)]
+        volume: String,
+
+        /// Write a PNG heat strip of the allocation to FILE and print only the totals
+        #[arg(long, value_name = "FILE")]
+        png: Option<PathBuf>,
+
+        /// Width of the heat strip in pixels; each column covers an equal slice of the volume
+        #[arg(long, default_value_t = 1024, value_name = "PIXELS", requires = "png")]
+        width: u32,
+    },
+
+    /// Generate an encryption key and an Ed25519 signing key pair
+    Keygen {
+        /// Directory the key files are written to
+        #[arg(long, value_name = "DIR")]
+        out_dir: PathBuf,
     },
 }
 
 #[derive(Clone, Copy, Debug)]
 enum OutputFormat {
+    Table,
     Json,
+    Jsonl,
     JsonPretty,
+    JsonTree,
     Csv,
     Bincode,
     Msgpack,
+    /// Journal events in a Debezium-style change envelope
+    Cdc,
+    /// Graphviz digraph of the directory tree
+    Dot,
 }
 
 impl std::str::FromStr for OutputFormat {
@@ -99,28 +1093,174 @@ impl std::str::FromStr for OutputFormat {
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         match s.to_lowercase().as_str() {
+            "table" => Ok(OutputFormat::Table),
             "json" => Ok(OutputFormat::Json),
+            "jsonl" | "ndjson" => Ok(OutputFormat::Jsonl),
             "json-pretty" | "pretty" => Ok(OutputFormat::JsonPretty),
+            "json-tree" | "tree" => Ok(OutputFormat::JsonTree),
             "csv" => Ok(OutputFormat::Csv),
             "bincode" | "bin" => Ok(OutputFormat::Bincode),
             "msgpack" | "messagepack" | "mp" => Ok(OutputFormat::Msgpack),
+            "cdc" => Ok(OutputFormat::Cdc),
+            "dot" | "graphviz" => Ok(OutputFormat::Dot),
             _ => Err(format!("Invalid output format: {}", s)),
         }
     }
 }
 
 fn main() -> Result<()> {
-    let cli = Cli::parse();
+    let args = config::apply_defaults(&Cli::command(), std::env::args_os().collect())?;
+    let matches = Cli::command().get_matches_from(args);
+    let cli = Cli::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    if let Some(prefix) = &cli.elevated_io {
+        elevate::redirect(prefix)?;
+    }
+    let relaunch = match cli.elevate && !elevate::is_elevated() {
+        true => Some(elevate::Relaunch::prepare()?),
+        false => None,
+    };
+
+    if cli.si {
+        output::set_size_units(output::SizeUnits::Si);
+    } else if cli.human_sizes {
+        output::set_size_units(output::SizeUnits::Binary);
+    }
+    output::set_extended_paths(cli.extended_paths);
+    output::set_field_case(cli.field_case);
+    output::set_filetime(cli.filetime);
+    output::set_json_warnings(cli.json_warnings);
+    output::set_csv_typed_header(cli.csv_typed_header);
+    mft::set_strict(cli.strict);
+    journal::set_stable_order(cli.stable_order);
+
+    // Listing shadow copies needs administrator rights too, so it goes with the command
+    let volume = matches
+        .subcommand()
+        .and_then(|(_, command)| command.try_get_one::<String>("volume").ok().flatten())
+        .cloned();
+    let selected = match cli.shadow {
+        Some(index) => shadow::select(index, volume.as_deref()),
+        None => Ok(()),
+    };
+
+    match (selected.and_then(|()| run(cli.command)), relaunch) {
+        (Err(e), Some(relaunch))
+            if elevate::needs_elevation(&e, volume.as_deref().map(mft::normalize_volume_path).as_deref()) =>
+        {
+            eprintln!("Error: {:#}", e);
+            let code = relaunch.run()?;
+            std::process::exit(code);
+        }
+        (result, _) => result,
+    }
+}
 
-    match cli.command {
+fn run(command: Commands) -> Result<()> {
+    match command {
+        Commands::ListFiles {
+            volume: Some(volume),
+            estimate: true,
+            ..
+        } => {
+            let estimate = mft::estimate_records(&volume)?;
+            println!("{}", output::to_json_pretty(&estimate)?);
+        }
         Commands::ListFiles {
             volume,
+            mft_file,
+            image,
+            partition_offset,
             filter,
             directories_only,
+            files_only,
+            limit,
+            include_deleted,
+            deleted_only,
+            shard,
+            all_names,
+            reparse,
+            owners,
+            resolve_owners,
+            estimate: _,
+            output,
+            tee,
+            fields,
+        } => {
+            let filter = filter::FileFilter::new(&filter)?;
+            let state = if deleted_only {
+                mft::RecordState::Deleted
+            } else if include_deleted {
+                mft::RecordState::All
+            } else {
+                mft::RecordState::InUse
+            };
+            let output = output::resolve_format(output);
+            let fields = output::Fields::new(&fields, mft::FileRecord::FIELDS)?;
+            // Deleted records are recovered and extracted by their number
+            mft::set_record_numbers(state != mft::RecordState::InUse || fields.contains("record_number"));
+            let selection = mft::RecordSelection {
+                directories_only,
+                files_only,
+                limit,
+                state,
+                shard,
+            };
+            match mft_file::open_offline(mft_file.as_deref(), image.as_deref(), partition_offset)? {
+                Some(mft) => mft::list_files_offline(
+                    mft,
+                    &filter,
+                    selection,
+                    all_names,
+                    output,
+                    &tee,
+                    &fields,
+                )?,
+                None => mft::list_files(
+                    volume.as_deref().unwrap_or_default(),
+                    &filter,
+                    selection,
+                    mft::RecordDetails {
+                        hard_links: all_names,
+                        reparse,
+                        owners,
+                        resolve_owners,
+                        attributes: false,
+                    },
+                    output,
+                    &tee,
+                    &fields,
+                )?,
+            }
+        }
+        Commands::Ads {
+            volume,
+            filter,
+            limit,
+            output,
+            fields,
+        } => {
+            ads::list_streams(
+                &volume,
+                &filter::FileFilter::new(&filter)?,
+                limit,
+                output::resolve_format(output),
+                &output::Fields::new(&fields, ads::StreamEntry::FIELDS)?,
+            )?;
+        }
+        Commands::Hardlinks {
+            volume,
+            filter,
             limit,
             output,
+            fields,
         } => {
-            mft::list_files(&volume, filter.as_deref(), directories_only, limit, output)?;
+            hardlinks::report(
+                &volume,
+                &filter::FileFilter::new(&filter)?,
+                limit,
+                output::resolve_format(output),
+                &output::Fields::new(&fields, hardlinks::HardLinkedFile::FIELDS)?,
+            )?;
         }
         Commands::Journal {
             volume,
@@ -129,24 +1269,401 @@ fn main() -> Result<()> {
             reason_mask,
             max_events,
             continuous,
+            on_close,
+            heartbeat,
+            replay_speed,
+            sample,
+            sample_mode,
+            event_model,
             output,
+            tee,
+            fields,
+            capture,
+            from_capture,
+            db,
+            expand_moves,
+            under,
+            max_depth,
+            top_talkers,
+            top_interval,
+            batch_size,
+            batch_max_latency,
+            on_change_exec,
+            on_change_interval,
+            pipeline: _, // Expanded by config::apply_defaults before parsing
+            sink,
+            reconnect,
+            encrypt_key,
+            sign_key,
         } => {
             journal::monitor_journal(
-                &volume,
-                from_start,
-                from_usn,
-                reason_mask,
-                max_events,
-                continuous,
-                output,
+                volume.as_deref().unwrap_or_default(),
+                journal::MonitorOptions {
+                    from_start,
+                    from_usn,
+                    reason_mask,
+                    max_events,
+                    continuous,
+                    on_close,
+                    heartbeat: heartbeat.map(Duration::from_secs),
+                    replay_speed,
+                    sample,
+                    sample_mode,
+                    event_model,
+                    output: output::resolve_format(output),
+                    tee,
+                    fields: output::Fields::new(&fields, journal::JournalEvent::FIELDS)?,
+                    capture,
+                    from_capture,
+                    db,
+                    expand_moves,
+                    scope: filter::PathScope::new(under.as_deref(), max_depth),
+                    top_talkers,
+                    top_interval,
+                    batch_size,
+                    batch_max_latency,
+                    on_change_exec,
+                    on_change_interval,
+                    reconnect,
+                    ring: sink,
+                    seal: seal::SealOptions::load(encrypt_key.as_deref(), sign_key.as_deref())?,
+                },
             )?;
         }
+        Commands::JournalCapacity {
+            volume,
+            window,
+            retention,
+        } => {
+            capacity::report(&volume, window, retention)?;
+        }
+        Commands::RingDump { file, last } => {
+            ring::dump(&file, last)?;
+        }
+        Commands::JournalQuery {
+            db,
+            path_like,
+            since,
+            until,
+            reason_mask,
+            limit,
+            output,
+            fields,
+        } => {
+            let options = history::QueryOptions {
+                path_like,
+                since,
+                until,
+                reason_mask,
+                limit,
+            };
+            let fields = output::Fields::new(&fields, journal::JournalEvent::FIELDS)?;
+            history::query(&db, &options, output::resolve_format(output), &fields)?;
+        }
+        Commands::Monitor {
+            volume,
+            refresh,
+            security,
+            basic_info,
+            min_size_delta,
+        } => {
+            monitor::run(&volume, refresh, security, basic_info, min_size_delta)?;
+        }
         Commands::FileInfo {
             volume,
+            mft_file,
+            image,
+            partition_offset,
             record,
+            by_file_id,
+            reparse,
+            owners,
+            resolve_owners,
+            attributes,
+            dump_resident,
+            dump_out,
+            output,
+            fields,
+        } => {
+            let volume = volume.unwrap_or_default();
+            let fields = output::Fields::new(&fields, mft::FileRecord::FIELDS)?;
+            mft::set_record_numbers(fields.contains("record_number"));
+            let details = mft::RecordDetails {
+                hard_links: false,
+                reparse,
+                owners,
+                resolve_owners,
+                attributes,
+            };
+            let dump = mft::ResidentDump::from_flags(dump_resident, dump_out);
+            let output = output::resolve_format(output);
+            let offline = mft_file::open_offline(mft_file.as_deref(), image.as_deref(), partition_offset)?;
+            match (record, by_file_id) {
+                (Some(record), _) => match offline {
+                    Some(mft) => mft::file_info_offline(mft, record, dump.as_ref(), output, &fields)?,
+                    None => mft::file_info(&volume, record, details, dump.as_ref(), output, &fields)?,
+                },
+                (None, Some(frn)) => {
+                    mft::file_info_by_id(&volume, &frn, details, dump.as_ref(), output, &fields)?
+                }
+                (None, None) => unreachable!("clap requires --record or --by-file-id"),
+            }
+        }
+        Commands::Lookup {
+            volume,
+            path,
+            reparse,
+            owners,
+            resolve_owners,
+            attributes,
+            dump_resident,
+            dump_out,
+            output,
+            fields,
+        } => {
+            let fields = output::Fields::new(&fields, mft::FileRecord::FIELDS)?;
+            mft::set_record_numbers(fields.contains("record_number"));
+            let details = mft::RecordDetails {
+                hard_links: false,
+                reparse,
+                owners,
+                resolve_owners,
+                attributes,
+            };
+            let dump = mft::ResidentDump::from_flags(dump_resident, dump_out);
+            mft::lookup(&volume, &path, details, dump.as_ref(), output::resolve_format(output), &fields)?;
+        }
+        Commands::Resolve { volume, stdin: _ } => {
+            mft::resolve_stdin(&volume)?;
+        }
+        Commands::ResolveFrn { volume, frns, stdin } => {
+            mft::resolve_frns(&volume, &frns, stdin)?;
+        }
+        Commands::VolumeInfo { volume, output, fields } => {
+            volume::info(
+                &volume,
+                output::resolve_format(output),
+                &output::Fields::new(&fields, volume::VolumeInfo::FIELDS)?,
+            )?;
+        }
+        Commands::MftStats { volume, output } => {
+            mft_stats::report(&volume, output::resolve_format(output))?;
+        }
+        Commands::Capabilities { volume } => {
+            let capabilities = volume::probe(&volume);
+            println!("{}", output::to_json_pretty(&capabilities)?);
+        }
+        Commands::Reasons { mask } => {
+            journal::print_reasons(&mask)?;
+        }
+        Commands::DumpRecord { volume, record, on_disk } => {
+            record_dump::run(&volume, record, on_disk)?;
+        }
+        Commands::Extract {
+            volume,
+            record,
+            stream,
+            out,
+        } => {
+            extract::run(&volume, record, stream.as_deref(), out.as_deref())?;
+        }
+        Commands::Recover {
+            volume,
+            all_resident,
+            out_dir,
+            record,
+            out,
+        } => match recover::Recovery::from_flags(all_resident, out_dir, record, out) {
+            Some(recover::Recovery::Record(number, out)) => recover::record(&volume, number, &out)?,
+            Some(recover::Recovery::AllResident(out_dir)) => recover::all_resident(&volume, &out_dir)?,
+            None => unreachable!("clap requires --all-resident or --record with its destination"),
+        },
+        Commands::Snapshot {
+            volume,
+            out_dir,
+            every,
+            retain,
+            encrypt_key,
+            sign_key,
+        } => {
+            let seal = seal::SealOptions::load(encrypt_key.as_deref(), sign_key.as_deref())?;
+            snapshot::run(&volume, &out_dir, every, retain, &seal)?;
+        }
+        Commands::Audit {
+            volumes,
+            out_dir,
+            every,
+            retain,
+            encrypt_key,
+            sign_key,
+        } => {
+            let seal = seal::SealOptions::load(encrypt_key.as_deref(), sign_key.as_deref())?;
+            audit::run(&volumes, &out_dir, every, retain, &seal)?;
+        }
+        Commands::ListShadows { output, fields } => {
+            shadow::list_shadows(
+                output::resolve_format(output),
+                &output::Fields::new(&fields, shadow::ShadowCopy::FIELDS)?,
+            )?;
+        }
+        Commands::MonitorBaseline {
+            volume,
+            baseline,
+            snapshot_dir,
+            protect,
+            from_capture,
+            encrypt_key,
+        } => {
+            let encrypt_key = encrypt_key.as_deref().map(seal::load_key).transpose()?;
+            baseline::monitor(
+                &volume,
+                baseline.as_deref(),
+                snapshot_dir.as_deref(),
+                &protect,
+                from_capture.as_deref(),
+                encrypt_key.as_ref(),
+            )?;
+        }
+        Commands::Query {
+            snapshot,
+            sql,
+            encrypt_key,
+            output,
+        } => {
+            let encrypt_key = encrypt_key.as_deref().map(seal::load_key).transpose()?;
+            query::run(&snapshot, &sql, encrypt_key.as_ref(), output::resolve_format(output))?;
+        }
+        Commands::Verify { public_key, files } => {
+            seal::verify(&public_key, &files)?;
+        }
+        Commands::VerifyHashes {
+            volume,
+            manifest,
+            threads,
+        } => {
+            hashes::verify_manifest(&volume, &manifest, threads)?;
+        }
+        Commands::Fragmentation {
+            volume,
+            filter,
+            min_extents,
+            sort,
+            limit,
+            output,
+            fields,
+        } => {
+            fragmentation::report(
+                &volume,
+                &filter::FileFilter::new(&filter)?,
+                min_extents,
+                sort,
+                limit,
+                output::resolve_format(output),
+                &output::Fields::new(&fields, fragmentation::FragmentedFile::FIELDS)?,
+            )?;
+        }
+        Commands::Largest {
+            volume,
+            filter,
+            top,
+            by,
+            output,
+            fields,
+        } => {
+            largest::report(
+                &volume,
+                &filter::FileFilter::new(&filter)?,
+                top,
+                by,
+                output::resolve_format(output),
+                &output::Fields::new(&fields, largest::LargeFile::FIELDS)?,
+            )?;
+        }
+        Commands::FragReport {
+            volume,
+            filter,
+            top,
+            output,
+        } => {
+            fragmentation::summary(
+                &volume,
+                &filter::FileFilter::new(&filter)?,
+                top,
+                output::resolve_format(output),
+            )?;
+        }
+        Commands::ContentStats {
+            volume,
+            filter,
             output,
+            fields,
         } => {
-            mft::file_info(&volume, record, output)?;
+            content::report(
+                &volume,
+                &filter::FileFilter::new(&filter)?,
+                output::resolve_format(output),
+                &output::Fields::new(&fields, content::ContentType::FIELDS)?,
+            )?;
+        }
+        Commands::Timestomp {
+            volume,
+            filter,
+            output,
+            fields,
+        } => {
+            timestomp::report(
+                &volume,
+                &filter::FileFilter::new(&filter)?,
+                output::resolve_format(output),
+                &output::Fields::new(&fields, timestomp::TimestompedFile::FIELDS)?,
+            )?;
+        }
+        Commands::Du {
+            volume,
+            path,
+            depth,
+            output,
+            fields,
+        } => {
+            du::report(
+                &volume,
+                path.as_deref(),
+                depth,
+                output::resolve_format(output),
+                &output::Fields::new(&fields, du::DirectoryUsage::FIELDS)?,
+            )?;
+        }
+        Commands::Tree {
+            volume,
+            path,
+            depth,
+            dirs_only,
+            output,
+        } => {
+            tree::run(&volume, path.as_deref(), depth, dirs_only, output::resolve_format(output))?;
+        }
+        Commands::DiskUsage {
+            volume,
+            filter,
+            root,
+            depth,
+            output,
+            fields,
+        } => {
+            usage::report(
+                &volume,
+                &filter::FileFilter::new(&filter)?,
+                root.as_deref(),
+                depth,
+                output::resolve_format(output),
+                &output::Fields::new(&fields, usage::SubtreeUsage::FIELDS)?,
+            )?;
+        }
+        Commands::Freemap { volume, png, width } => {
+            freemap::run(&volume, png.as_deref(), width)?;
+        }
+        Commands::Keygen { out_dir } => {
+            seal::generate_keys(&out_dir)?;
         }
     }
 
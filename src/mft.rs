@@ -1,12 +1,28 @@
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 use ntfs_reader::file_info::FileInfo;
 use ntfs_reader::mft::Mft;
-use ntfs_reader::volume::Volume;
-use ntfs_reader::api::NtfsAttributeType;
+use ntfs_reader::api::{NtfsAttributeType, FIRST_NORMAL_RECORD};
 use serde::{Deserialize, Serialize};
-use regex::Regex;
-use std::io::Write;
+use std::cell::Cell;
+use std::collections::{BTreeMap, HashMap};
+use std::io::{BufRead, IsTerminal, Write};
+use std::ops::ControlFlow;
+use std::panic::{self, AssertUnwindSafe, PanicHookInfo};
+use std::path::PathBuf;
+use std::sync::{Arc, OnceLock};
 
+use crate::attributes::{self, AttributeInfo};
+use crate::deletion::{DeletionClock, DeletionEstimate};
+use crate::du::Hierarchy;
+use crate::filter::FileFilter;
+use crate::mft_file::MftFile;
+use crate::output::{self, escape_csv, ColumnType, Field, Fields, Sink, TableRow, TeeSink, Warning};
+use crate::owners::Owners;
+use crate::raw::{self, RawVolume};
+use crate::recover;
+use crate::reparse::{self, ReparsePoint};
+use crate::shadow;
+use crate::volume;
 use crate::OutputFormat;
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -20,20 +36,92 @@ pub struct FileRecord {
     pub name: String,
     pub path: String,
     pub is_directory: bool,
+    /// The record is no longer in use; its fields are what it held when the
+    /// file was deleted, and its path is rebuilt through the deleted
+    /// directories that held it (see `DeletedPaths`)
+    #[serde(skip_serializing_if = "std::ops::Not::not", default)]
+    pub deleted: bool,
+    /// When a deleted file was most likely deleted, and how sure that is
+    /// ("high", "medium" or "low"); see `DeletionClock`
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub deleted_at_estimate: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub deleted_at_confidence: Option<String>,
     pub size: u64,
     pub created: Option<String>,
     pub modified: Option<String>,
     pub accessed: Option<String>,
     #[serde(skip_serializing_if = "Vec::is_empty", default)]
     pub alternate_data_streams: Vec<AlternateDataStream>,
+    /// Every path of the file, one per hard link, included with --all-names
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    pub hard_links: Vec<String>,
+    /// Only serialized when asked for with --fields, or when listing deleted records
+    #[serde(skip_serializing_if = "hide_record_number", default)]
+    pub record_number: u64,
+    /// FILE_ATTRIBUTE_* bitmask from $STANDARD_INFORMATION
+    #[serde(default)]
+    pub file_attributes: u32,
+    /// Names of the flags set in `file_attributes`, e.g. ["hidden", "system"]
+    #[serde(default)]
+    pub file_attribute_names: Vec<String>,
+    /// Reparse tag and target of symlinks, junctions and the like, included with --reparse
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub reparse_point: Option<ReparsePoint>,
+    /// Owner SID from the file's security descriptor, included with --owners
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub owner_sid: Option<String>,
+    /// DOMAIN\account of `owner_sid`, included with --resolve-owners
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub owner_name: Option<String>,
+    /// Every attribute of the record, included with file-info --attributes
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    pub attributes: Vec<AttributeInfo>,
+    /// Raw 100ns FILETIME values, included with --filetime
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub created_filetime: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub modified_filetime: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub accessed_filetime: Option<u64>,
 }
 
 impl FileRecord {
-    fn from_file_info(info: &FileInfo, _mft: &Mft, file: &ntfs_reader::file::NtfsFile) -> Self {
+    /// Fields accepted by --fields, with the types of their CSV columns
+    pub const FIELDS: &'static [Field] = &[
+        ("name", ColumnType::String),
+        ("path", ColumnType::String),
+        ("is_directory", ColumnType::Bool),
+        ("deleted", ColumnType::Bool),
+        ("deleted_at_estimate", ColumnType::Datetime),
+        ("deleted_at_confidence", ColumnType::String),
+        ("size", ColumnType::Size),
+        ("created", ColumnType::Datetime),
+        ("modified", ColumnType::Datetime),
+        ("accessed", ColumnType::Datetime),
+        ("alternate_data_streams", ColumnType::String),
+        ("hard_links", ColumnType::String),
+        ("record_number", ColumnType::Int),
+        ("file_attributes", ColumnType::Int),
+        ("file_attribute_names", ColumnType::String),
+        ("reparse_point", ColumnType::String),
+        ("owner_sid", ColumnType::String),
+        ("owner_name", ColumnType::String),
+        ("attributes", ColumnType::String),
+        ("created_filetime", ColumnType::Int),
+        ("modified_filetime", ColumnType::Int),
+        ("accessed_filetime", ColumnType::Int),
+    ];
+    
+    pub fn from_file_info(info: &FileInfo, _mft: &Mft, file: &ntfs_reader::file::NtfsFile) -> Self {
         // Enumerate all Data attributes (including named ones = ADS)
         let mut alternate_data_streams = Vec::new();
+        let mut file_attributes = 0;
         
         file.attributes(|attr| {
+            if attr.header.type_id == raw::ATTR_STANDARD_INFORMATION {
+                file_attributes = raw::standard_information_flags(attr.data()).unwrap_or(0);
+            }
             if attr.header.type_id == NtfsAttributeType::Data as u32 {
                 // Check if this is a named attribute (ADS)
                 let name_length = attr.header.name_length as usize;
@@ -47,7 +135,14 @@ impl FileRecord {
                             .chunks_exact(2)
                             .map(|chunk| u16::from_le_bytes([chunk[0], chunk[1]]))
                             .collect();
-                        let name = String::from_utf16_lossy(&name_u16);
+                        let name = String::from_utf16(&name_u16).unwrap_or_else(|_| {
+                            let name = String::from_utf16_lossy(&name_u16);
+                            let message = format!("stream name {} isn't valid UTF-16", name);
+                            Warning::new("encoding", message)
+                                .record(file.number(), &info.path.to_string_lossy())
+                                .emit();
+                            name
+                        });
                         
                         // Get size
                         let size = if attr.header.is_non_resident == 0 {
@@ -62,215 +157,1585 @@ impl FileRecord {
             }
         });
         
+        if info.path.to_str().is_none() {
+            let message = "path isn't valid UTF-16; invalid characters were replaced with U+FFFD";
+            Warning::new("encoding", message)
+                .record(file.number(), &info.path.to_string_lossy())
+                .emit();
+        }
+        
         FileRecord {
             name: info.name.clone(),
-            path: info.path.to_string_lossy().to_string(),
+            path: output::display_path(&info.path),
             is_directory: info.is_directory,
+            deleted: !file.is_used(),
+            deleted_at_estimate: None,
+            deleted_at_confidence: None,
             size: info.size,
-            created: info.created.map(|t| format_time(t)),
-            modified: info.modified.map(|t| format_time(t)),
-            accessed: info.accessed.map(|t| format_time(t)),
+            created: info.created.map(format_time),
+            modified: info.modified.map(format_time),
+            accessed: info.accessed.map(format_time),
             alternate_data_streams,
+            hard_links: Vec::new(),
+            record_number: file.number(),
+            file_attributes,
+            file_attribute_names: attribute_names(file_attributes),
+            reparse_point: None,
+            owner_sid: None,
+            owner_name: None,
+            attributes: Vec::new(),
+            created_filetime: info.created.and_then(output::filetime),
+            modified_filetime: info.modified.and_then(output::filetime),
+            accessed_filetime: info.accessed.and_then(output::filetime),
         }
     }
+    
+    /// Builds a record from a file record read straight from the volume. The
+    /// path is supplied by the caller, since resolving it needs the whole MFT.
+    pub fn from_raw(parsed: &raw::ParsedRecord, number: u64, path: String) -> Self {
+        let created = parsed.created.and_then(output::from_filetime);
+        let modified = parsed.modified.and_then(output::from_filetime);
+        let accessed = parsed.accessed.and_then(output::from_filetime);
+        
+        FileRecord {
+            name: parsed.name.clone().unwrap_or_default(),
+            path,
+            is_directory: parsed.is_directory,
+            deleted: !parsed.in_use,
+            deleted_at_estimate: None,
+            deleted_at_confidence: None,
+            size: parsed.size,
+            created: created.map(format_time),
+            modified: modified.map(format_time),
+            accessed: accessed.map(format_time),
+            alternate_data_streams: parsed
+                .streams
+                .iter()
+                .map(|(name, size)| AlternateDataStream { name: name.clone(), size: *size })
+                .collect(),
+            hard_links: Vec::new(),
+            record_number: number,
+            file_attributes: parsed.file_attributes,
+            file_attribute_names: attribute_names(parsed.file_attributes),
+            reparse_point: None,
+            owner_sid: None,
+            owner_name: None,
+            attributes: Vec::new(),
+            created_filetime: created.and_then(output::filetime),
+            modified_filetime: modified.and_then(output::filetime),
+            accessed_filetime: accessed.and_then(output::filetime),
+        }
+    }
+}
+
+impl FileRecord {
+    pub fn set_deletion_estimate(&mut self, estimate: Option<DeletionEstimate>) {
+        self.deleted_at_estimate = estimate.map(|estimate| format_time(estimate.time));
+        self.deleted_at_confidence = estimate.map(|estimate| estimate.confidence.to_string());
+    }
+}
+
+impl TableRow for FileRecord {
+    fn table_header() -> Vec<&'static str> {
+        vec!["TYPE", "SIZE", "MODIFIED", "PATH"]
+    }
+    
+    fn table_row(&self) -> Vec<String> {
+        vec![
+            if self.is_directory { "dir" } else { "file" }.to_string(),
+            output::format_size(self.size),
+            self.modified.clone().unwrap_or_default(),
+            self.path.clone(),
+        ]
+    }
+    
+    fn streaming_widths() -> Vec<usize> {
+        vec![4, 12, 25, 0]
+    }
+}
+
+/// FILE_ATTRIBUTE_* flags and the names they are reported under
+const FILE_ATTRIBUTES: &[(&str, u32)] = &[
+    ("read_only", 0x0001),
+    ("hidden", 0x0002),
+    ("system", 0x0004),
+    ("archive", 0x0020),
+    ("device", 0x0040),
+    ("normal", 0x0080),
+    ("temporary", 0x0100),
+    ("sparse", 0x0200),
+    ("reparse_point", 0x0400),
+    ("compressed", 0x0800),
+    ("offline", 0x1000),
+    ("not_content_indexed", 0x2000),
+    ("encrypted", 0x4000),
+];
+
+pub fn attribute_names(flags: u32) -> Vec<String> {
+    FILE_ATTRIBUTES
+        .iter()
+        .filter(|(_, flag)| flags & flag != 0)
+        .map(|(name, _)| name.to_string())
+        .collect()
 }
 
-fn format_time(time: time::OffsetDateTime) -> String {
+pub fn format_time(time: time::OffsetDateTime) -> String {
     time.format(&time::format_description::well_known::Rfc3339)
         .unwrap_or_else(|_| time.to_string())
 }
 
-fn normalize_volume_path(volume: &str) -> String {
+pub fn normalize_volume_path(volume: &str) -> String {
+    // --shadow reads the shadow copy in place of the volume it was taken of
+    if let Some(device) = shadow::selected_device() {
+        return device;
+    }
     let volume = volume.trim();
     
     // If it's just a drive letter, convert to device path
     if volume.len() == 2 && volume.chars().nth(1) == Some(':') {
-        return format!("\\\\.\\{}:", volume.chars().nth(0).unwrap());
+        return format!("\\\\.\\{}:", volume.chars().next().unwrap());
     }
     
     // If it's a drive letter with backslash, remove it
     if volume.len() == 3 && volume.ends_with(":\\") {
-        return format!("\\\\.\\{}:", volume.chars().nth(0).unwrap());
+        return format!("\\\\.\\{}:", volume.chars().next().unwrap());
     }
     
-    // Return as-is if already in device format
-    volume.to_string()
+    // Device paths and names of volumes without a drive letter
+    volume::device_path(volume)
 }
 
-pub fn list_files(
-    volume: &str,
-    filter: Option<&str>,
-    directories_only: bool,
-    limit: Option<usize>,
-    output: OutputFormat,
-) -> Result<()> {
+/// Opens the volume and loads its MFT into memory
+pub fn open_mft(volume: &str) -> Result<Mft> {
     let volume_path = normalize_volume_path(volume);
     
-    eprintln!("Opening volume: {}", volume_path);
-    let vol = Volume::new(&volume_path)
-        .context("Failed to open volume. Make sure you're running as Administrator.")?;
+    let vol = volume::open(&volume_path)?;
     
     eprintln!("Loading MFT...");
-    let mft = Mft::new(vol).context("Failed to load MFT")?;
+    Mft::new(vol).context("Failed to load MFT")
+}
+
+/// Approximate record counts read from the $MFT's own $BITMAP, which is much
+/// cheaper than loading the MFT
+#[derive(Debug, Serialize)]
+pub struct RecordEstimate {
+    /// Records marked in use, including extension records of large files
+    pub records_in_use: u64,
+    /// Records the MFT currently has room for
+    pub record_slots: u64,
+    pub record_size: u64,
+}
+
+pub fn estimate_records(volume: &str) -> Result<RecordEstimate> {
+    let volume_path = normalize_volume_path(volume);
+    let mut raw = RawVolume::open(&volume_path)?;
+    
+    let record = raw.read_mft_record().context("Failed to read the $MFT record")?;
+    let bitmap = raw
+        .read_attribute(&record, raw::ATTR_BITMAP)?
+        .context("$MFT has no $BITMAP attribute")?;
+    // $MFT's $DATA is always non-resident; its data size covers every record slot
+    let record_slots = raw::find_attribute(&record, raw::ATTR_DATA)
+        .filter(|attr| attr[8] != 0)
+        .and_then(|attr| raw::u64_at(attr, 0x30))
+        .map(|size| size / raw.record_size)
+        .context("$MFT has no non-resident $DATA attribute")?;
+    
+    // Bits past the last slot are padding and don't count
+    let full_bytes = (record_slots / 8) as usize;
+    let mut records_in_use: u64 = bitmap
+        .iter()
+        .take(full_bytes)
+        .map(|b| b.count_ones() as u64)
+        .sum();
+    let remaining_bits = record_slots % 8;
+    if remaining_bits > 0 {
+        if let Some(last) = bitmap.get(full_bytes) {
+            records_in_use += (last & ((1u8 << remaining_bits) - 1)).count_ones() as u64;
+        }
+    }
+    
+    Ok(RecordEstimate {
+        records_in_use,
+        record_slots,
+        record_size: raw.record_size,
+    })
+}
+
+/// Progress of an MFT scan on stderr, shown against the estimated record
+/// count when stderr is a terminal
+struct ScanProgress {
+    expected: Option<u64>,
+    scanned: u64,
+    enabled: bool,
+}
+
+impl ScanProgress {
+    const STEP: u64 = 50_000;
+    
+    fn new(expected: Option<u64>) -> Self {
+        ScanProgress {
+            expected,
+            scanned: 0,
+            enabled: std::io::stderr().is_terminal(),
+        }
+    }
+    
+    fn tick(&mut self) {
+        self.scanned += 1;
+        if !self.enabled || !self.scanned.is_multiple_of(Self::STEP) {
+            return;
+        }
+        match self.expected {
+            Some(expected) if expected > 0 => eprint!(
+                "\rScanned {} of ~{} records ({}%)",
+                self.scanned,
+                expected,
+                (self.scanned * 100 / expected).min(100)
+            ),
+            _ => eprint!("\rScanned {} records", self.scanned),
+        }
+    }
+    
+    fn finish(&self) {
+        if self.enabled && self.scanned >= Self::STEP {
+            eprintln!();
+        }
+    }
+}
+
+/// Estimates the record count for preallocation and progress, without
+/// failing the scan when the raw read isn't possible
+fn try_estimate(volume: &str) -> Option<u64> {
+    match estimate_records(volume) {
+        Ok(estimate) => Some(estimate.records_in_use),
+        Err(e) => {
+            let message = format!("record estimate unavailable: {:#}", e);
+            Warning::new("estimate_unavailable", message).emit();
+            None
+        }
+    }
+}
+
+/// Which records a scan visits, by their in-use flag
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RecordState {
+    #[default]
+    InUse,
+    /// Records of deleted files that haven't been reused yet
+    Deleted,
+    All,
+}
+
+impl RecordState {
+    fn includes(self, file: &ntfs_reader::file::NtfsFile) -> bool {
+        self.includes_used(file.is_used())
+    }
+    
+    fn includes_used(self, in_use: bool) -> bool {
+        match self {
+            RecordState::InUse => in_use,
+            RecordState::Deleted => !in_use,
+            RecordState::All => true,
+        }
+    }
+}
+
+static STRICT: OnceLock<bool> = OnceLock::new();
+
+pub fn set_strict(enabled: bool) {
+    let _ = STRICT.set(enabled);
+}
+
+static RECORD_NUMBERS: OnceLock<bool> = OnceLock::new();
+
+/// Includes `record_number` in serialized file records
+pub fn set_record_numbers(enabled: bool) {
+    let _ = RECORD_NUMBERS.set(enabled);
+}
+
+fn hide_record_number(_: &u64) -> bool {
+    !RECORD_NUMBERS.get().copied().unwrap_or(false)
+}
+
+thread_local! {
+    /// Set while `isolate` runs, so `QuietPanics` can tell the panics it
+    /// turns into warnings from the others
+    static ISOLATING: Cell<bool> = const { Cell::new(false) };
+}
+
+type PanicHook = Box<dyn Fn(&PanicHookInfo<'_>) + Sync + Send + 'static>;
+
+/// Keeps the panics `isolate` catches from also printing the default panic
+/// message, which would land between the warnings, for as long as it lives.
+/// Any other panic goes to the previous hook, which is put back on drop.
+pub struct QuietPanics {
+    previous: Arc<PanicHook>,
+}
+
+impl QuietPanics {
+    pub fn new() -> Self {
+        let previous = Arc::new(panic::take_hook());
+        let hook = Arc::clone(&previous);
+        panic::set_hook(Box::new(move |info| {
+            if !ISOLATING.with(Cell::get) {
+                hook(info);
+            }
+        }));
+        QuietPanics { previous }
+    }
+}
+
+impl Drop for QuietPanics {
+    fn drop(&mut self) {
+        let previous = Arc::clone(&self.previous);
+        // Dropping the returned hook releases its share of `previous`
+        drop(panic::take_hook());
+        panic::set_hook(Box::new(move |info| previous(info)));
+    }
+}
+
+/// Parses record `number` with `f`, so that a malformed record that makes
+/// ntfs-reader panic costs only that record: a `malformed_record` warning is
+/// emitted and `None` returned. `f` should only parse and return what it
+/// read; the caller acts on the result outside, so a panic can't stop it
+/// halfway through writing output or a bug of ours pass for bad data. With
+/// `--strict` the panic isn't caught and ends the command.
+pub fn isolate<T>(number: u64, f: impl FnOnce() -> T) -> Option<T> {
+    if STRICT.get().copied().unwrap_or(false) {
+        return Some(f());
+    }
+    
+    // Calls may nest, like a path rebuilt while a deleted record is read
+    let outer = ISOLATING.with(|isolating| isolating.replace(true));
+    let result = panic::catch_unwind(AssertUnwindSafe(f));
+    ISOLATING.with(|isolating| isolating.set(outer));
+    
+    result
+        .map_err(|payload| {
+            let error = payload
+                .downcast_ref::<&str>()
+                .map(|s| s.to_string())
+                .or_else(|| payload.downcast_ref::<String>().cloned())
+                .unwrap_or_else(|| "unknown error".to_string());
+            let message = format!("record could not be parsed and was skipped: {}", error);
+            Warning::new("malformed_record", message).record_number(number).emit();
+        })
+        .ok()
+}
+
+/// `FileInfo::new` for a record, isolated so a malformed record is reported
+/// and skipped
+pub fn parse_file_info(mft: &Mft, file: &ntfs_reader::file::NtfsFile) -> Option<FileInfo> {
+    isolate(file.number(), || FileInfo::new(mft, file))
+}
+
+/// Like `Mft::iterate_files`, with reading each record isolated so a
+/// malformed one is reported and skipped instead of ending the walk.
+/// Callbacks get the names and times of a record with `parse_file_info`.
+pub fn iterate_files<F>(mft: &Mft, mut f: F)
+where
+    F: FnMut(&ntfs_reader::file::NtfsFile),
+{
+    iterate_files_until(mft, |file| {
+        f(file);
+        ControlFlow::Continue(())
+    })
+}
+
+/// Like `iterate_files`, but `f` can end the walk early, so callers that
+/// only need part of the MFT don't pay for all of it
+fn iterate_files_until<F>(mft: &Mft, f: F)
+where
+    F: FnMut(&ntfs_reader::file::NtfsFile) -> ControlFlow<()>,
+{
+    iterate_records_until(mft, RecordState::InUse, f)
+}
+
+/// `iterate_files_until` over the records in the given state
+fn iterate_records_until<F>(mft: &Mft, state: RecordState, mut f: F)
+where
+    F: FnMut(&ntfs_reader::file::NtfsFile) -> ControlFlow<()>,
+{
+    let _quiet = QuietPanics::new();
+    for number in FIRST_NORMAL_RECORD..mft.max_record {
+        let Some(file) = isolate(number, || mft.get_record(number)).flatten() else {
+            continue;
+        };
+        if state.includes(&file) && f(&file).is_break() {
+            return;
+        }
+    }
+}
+
+/// Calls `f` with the record of every file on the volume, stopping at the
+/// first error it returns
+pub fn scan_records<F>(volume: &str, mut f: F) -> Result<()>
+where
+    F: FnMut(FileRecord) -> Result<()>,
+{
+    let expected = try_estimate(volume);
+    let mft = open_mft(volume)?;
     
     eprintln!("Iterating files...");
-    let mut records = Vec::new();
+    let mut progress = ScanProgress::new(expected);
+    let mut result = Ok(());
+    iterate_files_until(&mft, |file| {
+        progress.tick();
+        let Some(info) = parse_file_info(&mft, file) else {
+            return ControlFlow::Continue(());
+        };
+        result = f(FileRecord::from_file_info(&info, &mft, file));
+        if result.is_err() {
+            ControlFlow::Break(())
+        } else {
+            ControlFlow::Continue(())
+        }
+    });
+    progress.finish();
     
-    // Compile regex if filter looks like a pattern or regex
-    let filter_regex = filter.and_then(|f| {
-        // Convert glob patterns like *.pdf to regex
-        let pattern = if f.contains('*') || f.contains('?') {
-            let regex_pattern = f
-                .replace('\\', "\\\\")
-                .replace('.', "\\.")
-                .replace('*', ".*")
-                .replace('?', ".")
-                .to_lowercase();
-            Some(regex_pattern)
-        } else if f.starts_with('^') || f.contains('[') || f.contains('(') {
-            // Looks like regex
-            Some(f.to_lowercase())
+    result
+}
+
+/// Which records a listing includes, besides those its filter rejects
+#[derive(Clone, Copy, Debug)]
+pub struct RecordSelection {
+    /// Only directories (--directories-only)
+    pub directories_only: bool,
+    /// Only files (--files-only)
+    pub files_only: bool,
+    /// At most this many records (--limit)
+    pub limit: Option<usize>,
+    /// In-use records, deleted ones or both
+    pub state: RecordState,
+    /// Only this process's share of the records (--shard)
+    pub shard: Option<Shard>,
+}
+
+/// Optional record fields that take extra work per record to fill in
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RecordDetails {
+    /// Every hard link path (--all-names)
+    pub hard_links: bool,
+    /// Decoded $REPARSE_POINT (--reparse)
+    pub reparse: bool,
+    /// Owner SID from $Secure (--owners)
+    pub owners: bool,
+    /// Owner account name too (--resolve-owners)
+    pub resolve_owners: bool,
+    /// Every attribute header of the record (--attributes)
+    pub attributes: bool,
+}
+
+/// Lookups shared by every record of a scan
+struct DetailLookups {
+    /// Paths of parent directories, for hard links
+    parents: HashMap<u64, PathBuf>,
+    owners: Option<Owners>,
+}
+
+impl RecordDetails {
+    /// Loads what the chosen details need before the first record
+    fn lookups(&self, volume: &str) -> Result<DetailLookups> {
+        let owners = if self.owners || self.resolve_owners {
+            Some(Owners::load(volume, self.resolve_owners)?)
         } else {
-            // Simple substring search
             None
         };
+        Ok(DetailLookups { parents: HashMap::new(), owners })
+    }
+
+    fn fill(
+        &self,
+        record: &mut FileRecord,
+        mft: &Mft,
+        file: &ntfs_reader::file::NtfsFile,
+        lookups: &mut DetailLookups,
+    ) {
+        if self.hard_links {
+            record.hard_links = hard_link_paths(mft, file, &mut lookups.parents);
+        }
+        if self.reparse {
+            record.reparse_point = reparse::read(file);
+        }
+        if self.attributes {
+            record.attributes = attributes::list(file);
+        }
+        if let Some(owner) = lookups.owners.as_ref().and_then(|owners| owners.of(file)) {
+            record.owner_sid = Some(owner.sid.clone());
+            record.owner_name = owner.name.clone();
+        }
+    }
+}
+
+/// One of N disjoint parts of the MFT record space, given as `i/N` with i
+/// from 1 to N. Records are dealt out by number modulo N, which doesn't
+/// depend on the size of the MFT, so processes reading the same volume or
+/// image agree on the split even if the MFT grows in between, and together
+/// the shards cover every record exactly once.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Shard {
+    pub index: u64,
+    pub count: u64,
+}
+
+impl Shard {
+    pub fn includes(self, record_number: u64) -> bool {
+        record_number % self.count == self.index - 1
+    }
+}
+
+impl std::str::FromStr for Shard {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid shard (expected i/N, e.g. 1/4): {}", s);
+        let (index, count) = s.split_once('/').ok_or_else(invalid)?;
+        let index: u64 = index.trim().parse().map_err(|_| invalid())?;
+        let count: u64 = count.trim().parse().map_err(|_| invalid())?;
+        if count == 0 || index == 0 || index > count {
+            return Err(format!("Shard index must be between 1 and {}: {}", count, s));
+        }
+        Ok(Shard { index, count })
+    }
+}
+
+/// Directory that stands in for the part of a deleted file's path that
+/// can't be rebuilt, as in other forensic tools
+pub const ORPHAN_DIRECTORY: &str = "$OrphanFiles";
+
+/// Longest chain of parent directories followed, against reference loops
+pub const MAX_PATH_DEPTH: usize = 256;
+
+/// Rebuilds the paths deleted records had. The parent reference of each
+/// $FILE_NAME is followed through directories that were deleted too, as
+/// long as their records survive, up to the first directory still in use.
+/// Where the chain breaks (a parent record that was reused for a file or
+/// holds no name), the rest is put under `$OrphanFiles`, so
+/// `C:\$OrphanFiles\bob\Documents\report.docx` still shows what is known.
+pub struct DeletedPaths {
+    root: PathBuf,
+    /// Rebuilt paths of the directories met so far
+    directories: HashMap<u64, PathBuf>,
+}
+
+impl DeletedPaths {
+    pub fn new(mft: &Mft) -> Self {
+        let root = mft
+            .get_record(crate::du::ROOT_RECORD)
+            .and_then(|file| parse_file_info(mft, &file))
+            .map(|info| info.path)
+            .unwrap_or_default();
+        DeletedPaths { root, directories: HashMap::new() }
+    }
+    
+    /// The path the file had before it was deleted, as far as it can be told
+    pub fn path(&mut self, mft: &Mft, file: &ntfs_reader::file::NtfsFile) -> PathBuf {
+        match first_link(file) {
+            Some((parent, name)) => self.directory(mft, parent, 0).join(name),
+            None => self.root.join(ORPHAN_DIRECTORY).join(format!("record-{}", file.number())),
+        }
+    }
+    
+    fn directory(&mut self, mft: &Mft, number: u64, depth: usize) -> PathBuf {
+        if number == crate::du::ROOT_RECORD {
+            return self.root.clone();
+        }
+        if let Some(path) = self.directories.get(&number) {
+            return path.clone();
+        }
         
-        pattern.and_then(|p| Regex::new(&p).ok())
+        let orphan = self.root.join(ORPHAN_DIRECTORY);
+        let path = match mft.get_record(number) {
+            Some(file) if depth < MAX_PATH_DEPTH && file.is_directory() => {
+                if file.is_used() {
+                    parse_file_info(mft, &file).map_or(orphan, |info| info.path)
+                } else {
+                    match first_link(&file) {
+                        Some((parent, name)) => self.directory(mft, parent, depth + 1).join(name),
+                        None => orphan,
+                    }
+                }
+            }
+            _ => orphan,
+        };
+        self.directories.insert(number, path.clone());
+        path
+    }
+}
+
+/// Parent record number and name of a record's first (non-DOS) $FILE_NAME
+pub fn first_link(file: &ntfs_reader::file::NtfsFile) -> Option<(u64, String)> {
+    let mut link = None;
+    file.attributes(|attr| {
+        if link.is_none() && attr.header.type_id == raw::ATTR_FILE_NAME {
+            link = raw::file_name_link(attr.data());
+        }
     });
+    link
+}
+
+/// The volume opened for raw reads, with the runs locating its file records
+fn open_raw_mft(volume: &str) -> Result<(RawVolume, raw::MftRuns)> {
+    let mut raw = RawVolume::open(&normalize_volume_path(volume))?;
+    let runs = raw.mft_runs()?;
+    Ok((raw, runs))
+}
+
+/// File reference, with sequence number, of the parent directory of the
+/// first name of a file
+fn first_parent_reference(file: &ntfs_reader::file::NtfsFile) -> Option<u64> {
+    let mut parent = None;
+    file.attributes(|attr| {
+        if parent.is_none() && attr.header.type_id == raw::ATTR_FILE_NAME {
+            parent = raw::file_name_parent(attr.data());
+        }
+    });
+    parent
+}
+
+pub fn list_files(
+    volume: &str,
+    filter: &FileFilter,
+    selection: RecordSelection,
+    details: RecordDetails,
+    output: OutputFormat,
+    tee: &[TeeSink],
+    fields: &Fields,
+) -> Result<()> {
+    let RecordSelection { directories_only, files_only, limit, state, shard } = selection;
+    let expected = try_estimate(volume).map(|expected| match shard {
+        Some(shard) => expected.div_ceil(shard.count),
+        None => expected,
+    });
+    let mft = open_mft(volume)?;
+    let mut lookups = details.lookups(volume)?;
     
-    let filter_simple = if filter_regex.is_none() {
-        filter.map(|f| f.to_lowercase())
-    } else {
-        None
+    let mut sinks = open_record_sinks(output, tee, fields)?;
+    let buffered = is_buffered(&sinks);
+    let capacity = match (buffered, limit, filter.is_empty() && !directories_only && !files_only) {
+        (false, _, _) => 0,
+        (true, Some(limit), _) => limit,
+        // Only unfiltered scans keep (almost) every record
+        (true, None, true) => expected.unwrap_or(0) as usize,
+        (true, None, false) => 0,
     };
-
-    mft.iterate_files(|file| {
-        let info = FileInfo::new(&mft, file);
+    let mut records = Vec::with_capacity(capacity);
+    let mut matched = 0;
+    let mut result = Ok(());
+    
+    eprintln!("Iterating files...");
+    // The estimate only counts records in use
+    let mut progress = ScanProgress::new(expected.filter(|_| state == RecordState::InUse));
+    let mut deleted_paths = (state != RecordState::InUse).then(|| DeletedPaths::new(&mft));
+    let mut deletion_clock = (state != RecordState::InUse).then(|| DeletionClock::load(volume));
+    // ntfs-reader doesn't expose record sequence numbers, which tell a
+    // deletion event of the record and a parent directory apart from later
+    // reuses of the same record; they are read from the volume as needed
+    let mut sequences = match deletion_clock {
+        Some(_) => open_raw_mft(volume)
+            .map_err(|e| {
+                let message = format!("deletion times are estimated without sequence numbers: {:#}", e);
+                Warning::new("raw_read_failed", message).emit();
+            })
+            .ok(),
+        None => None,
+    };
+    iterate_records_until(&mft, state, |file| {
+        if limit.is_some_and(|limit| matched >= limit) {
+            return ControlFlow::Break(());
+        }
+        if shard.is_some_and(|shard| !shard.includes(file.number())) {
+            return ControlFlow::Continue(());
+        }
+        progress.tick();
+        let Some(mut info) = parse_file_info(&mft, file) else {
+            return ControlFlow::Continue(());
+        };
+        if let (Some(deleted_paths), false) = (deleted_paths.as_mut(), file.is_used()) {
+            let Some(path) = isolate(file.number(), || deleted_paths.path(&mft, file)) else {
+                return ControlFlow::Continue(());
+            };
+            info.path = path;
+        }
         
         // Apply filters
         if directories_only && !info.is_directory {
-            return;
+            return ControlFlow::Continue(());
         }
-        
-        // Apply filter (regex or simple substring)
-        if let Some(ref regex) = filter_regex {
-            let path_lower = info.path.to_string_lossy().to_lowercase();
-            if !regex.is_match(&path_lower) {
-                return;
-            }
-        } else if let Some(ref filter_str) = filter_simple {
-            let path_lower = info.path.to_string_lossy().to_lowercase();
-            if !path_lower.contains(filter_str) {
-                return;
-            }
+        if files_only && info.is_directory {
+            return ControlFlow::Continue(());
+        }
+        if !filter.matches(&info, file) {
+            return ControlFlow::Continue(());
         }
         
-        records.push(FileRecord::from_file_info(&info, &mft, file));
+        matched += 1;
+        let mut record = FileRecord::from_file_info(&info, &mft, file);
+        details.fill(&mut record, &mft, file, &mut lookups);
+        if let (Some(clock), false) = (deletion_clock.as_mut(), file.is_used()) {
+            let mut read = |number| {
+                let (raw, runs) = sequences.as_mut()?;
+                let record = raw.read_file_record(runs, number).ok()?;
+                Some(raw::parse_file_record(&record))
+            };
+            let sequence = read(file.number()).map(|parsed| parsed.sequence);
+            let last_own = info.created.max(info.modified);
+            let estimate = clock.estimate(file.number(), sequence, last_own, first_parent_reference(file), read);
+            record.set_deletion_estimate(estimate);
+        }
+        result = stream_to_sinks(&mut sinks, &record, fields);
+        if buffered {
+            records.push(record);
+        }
         
-        if let Some(lim) = limit {
-            if records.len() >= lim {
-                return;
-            }
+        if result.is_err() {
+            ControlFlow::Break(())
+        } else {
+            ControlFlow::Continue(())
         }
     });
+    progress.finish();
+    result?;
+    
+    close_record_sinks(&mut sinks, &records, fields)
+}
 
-    output_records(&records, output)?;
+/// `list_files` over an $MFT read without its volume, from an extracted
+/// copy or a disk image. Records are parsed by `raw`, so paths start at the
+/// root rather than a drive letter and attributes in extension records
+/// aren't followed.
+pub fn list_files_offline(
+    mut mft: MftFile,
+    filter: &FileFilter,
+    selection: RecordSelection,
+    hard_links: bool,
+    output: OutputFormat,
+    tee: &[TeeSink],
+    fields: &Fields,
+) -> Result<()> {
+    let RecordSelection { directories_only, files_only, limit, state, shard } = selection;
+    let mut sinks = open_record_sinks(output, tee, fields)?;
+    let buffered = is_buffered(&sinks);
+    let mut records = Vec::new();
+    let mut matched = 0;
+    // Without the volume there is no journal to date deletions by
+    let mut deletion_clock = DeletionClock::without_journal();
     
-    Ok(())
+    eprintln!("Iterating files...");
+    let mut progress = ScanProgress::new(Some(mft.max_record()));
+    for number in FIRST_NORMAL_RECORD..mft.max_record() {
+        if limit.is_some_and(|limit| matched >= limit) {
+            break;
+        }
+        if shard.is_some_and(|shard| !shard.includes(number)) {
+            continue;
+        }
+        progress.tick();
+        let Some(raw_record) = mft.record(number) else {
+            continue;
+        };
+        let parsed = raw::parse_file_record(&raw_record);
+        // Extension records only hold overflow attributes of their base record
+        if parsed.base_record != 0 || !state.includes_used(parsed.in_use) {
+            continue;
+        }
+        
+        if directories_only && !parsed.is_directory {
+            continue;
+        }
+        if files_only && parsed.is_directory {
+            continue;
+        }
+        let file_path = mft.path(number, &raw_record);
+        if !filter.matches_raw(&file_path, &raw_record, &parsed) {
+            continue;
+        }
+        
+        matched += 1;
+        let mut record = FileRecord::from_raw(&parsed, number, file_path);
+        if hard_links {
+            record.hard_links = mft.hard_links(&raw_record);
+        }
+        if !parsed.in_use {
+            let last_own = parsed.created.max(parsed.modified).and_then(output::from_filetime);
+            let parent = raw::first_file_name_parent(&raw_record);
+            let estimate = deletion_clock.estimate(number, Some(parsed.sequence), last_own, parent, |parent| {
+                mft.record(parent).map(|record| raw::parse_file_record(&record))
+            });
+            record.set_deletion_estimate(estimate);
+        }
+        stream_to_sinks(&mut sinks, &record, fields)?;
+        if buffered {
+            records.push(record);
+        }
+    }
+    progress.finish();
+    
+    close_record_sinks(&mut sinks, &records, fields)
 }
 
-pub fn file_info(volume: &str, record_number: u64, output: OutputFormat) -> Result<()> {
-    let volume_path = normalize_volume_path(volume);
+/// What `file-info --dump-resident` does with the content of a file whose
+/// data is resident in its MFT record
+pub enum ResidentDump {
+    /// Hex/ASCII listing on stdout, in place of the record
+    Hex,
+    /// Raw bytes written to this file; the record is shown as usual
+    File(PathBuf),
+}
+
+impl ResidentDump {
+    /// The dump asked for by --dump-resident and --dump-out, if any
+    pub fn from_flags(dump_resident: bool, dump_out: Option<PathBuf>) -> Option<Self> {
+        dump_resident.then(|| dump_out.map_or(Self::Hex, Self::File))
+    }
+    
+    /// Dumps the resident content of record `record_number`, returning
+    /// whether the dump took the place of the record
+    fn write(&self, record_number: u64, data: Option<Vec<u8>>) -> Result<bool> {
+        let data = data.context(format!(
+            "Record {} has no resident $DATA: its content is stored in clusters (use extract)",
+            record_number
+        ))?;
+        match self {
+            ResidentDump::Hex => {
+                output::write_hex_dump(&mut std::io::stdout().lock(), &data)?;
+                Ok(true)
+            }
+            ResidentDump::File(path) => {
+                std::fs::write(path, &data).context(format!("Failed to write {}", path.display()))?;
+                eprintln!("Wrote {} bytes to {}", data.len(), path.display());
+                Ok(false)
+            }
+        }
+    }
+}
+
+pub fn file_info(
+    volume: &str,
+    record_number: u64,
+    details: RecordDetails,
+    dump: Option<&ResidentDump>,
+    output: OutputFormat,
+    fields: &Fields,
+) -> Result<()> {
+    let mft = open_mft(volume)?;
+    print_record(&mft, volume, record_number, details, dump, output, fields)
+}
+
+/// `file-info` for record `record_number` of an $MFT read without its
+/// volume, parsed the way `list_files_offline` parses it
+pub fn file_info_offline(
+    mut mft: MftFile,
+    record_number: u64,
+    dump: Option<&ResidentDump>,
+    output: OutputFormat,
+    fields: &Fields,
+) -> Result<()> {
+    let raw_record = mft
+        .record(record_number)
+        .context(format!("Record {} not found or invalid", record_number))?;
     
-    eprintln!("Opening volume: {}", volume_path);
-    let vol = Volume::new(&volume_path)
-        .context("Failed to open volume. Make sure you're running as Administrator.")?;
+    if let Some(dump) = dump {
+        let data = raw::find_attribute(&raw_record, raw::ATTR_DATA)
+            .and_then(raw::resident_value)
+            .map(<[u8]>::to_vec);
+        if dump.write(record_number, data)? {
+            return Ok(());
+        }
+    }
     
-    eprintln!("Loading MFT...");
-    let mft = Mft::new(vol).context("Failed to load MFT")?;
+    let parsed = raw::parse_file_record(&raw_record);
+    let record = FileRecord::from_raw(&parsed, record_number, mft.path(record_number, &raw_record));
+    write_record(&mut std::io::stdout(), &record, output, fields)
+}
+
+/// `file-info` for the file or directory at `path`. The path is resolved
+/// through the directory hierarchy rebuilt from parent references, matching
+/// each component case-insensitively; a file with several hard links is
+/// found under its first name.
+pub fn lookup(
+    volume: &str,
+    path: &str,
+    details: RecordDetails,
+    dump: Option<&ResidentDump>,
+    output: OutputFormat,
+    fields: &Fields,
+) -> Result<()> {
+    let mft = open_mft(volume)?;
+    
+    eprintln!("Resolving {}...", path);
+    let hierarchy = Hierarchy::load(&mft, true);
+    let record_number = hierarchy
+        .find_entry(path)
+        .context(format!("Path not found: {}", path))?;
+    eprintln!("{} is record {}", path, record_number);
     
+    print_record(&mft, volume, record_number, details, dump, output, fields)
+}
+
+fn print_record(
+    mft: &Mft,
+    volume: &str,
+    record_number: u64,
+    details: RecordDetails,
+    dump: Option<&ResidentDump>,
+    output: OutputFormat,
+    fields: &Fields,
+) -> Result<()> {
     let file = mft
         .get_record(record_number)
         .context(format!("Record {} not found or invalid", record_number))?;
     
-    let info = FileInfo::new(&mft, &file);
-    let record = FileRecord::from_file_info(&info, &mft, &file);
+    if let Some(dump) = dump {
+        if dump.write(record_number, recover::resident_data(&file))? {
+            return Ok(());
+        }
+    }
+    
+    let info = parse_file_info(mft, &file).context(format!("Record {} could not be parsed", record_number))?;
+    let mut record = FileRecord::from_file_info(&info, mft, &file);
+    details.fill(&mut record, mft, &file, &mut details.lookups(volume)?);
     
+    let mut stdout = std::io::stdout();
+    write_record(&mut stdout, &record, output, fields)?;
+    // The attribute list doesn't fit the record's table row
+    if matches!(output, OutputFormat::Table) && fields.is_all() && !record.attributes.is_empty() {
+        writeln!(stdout)?;
+        output::write_table(&mut stdout, &record.attributes)?;
+    }
+    Ok(())
+}
+
+/// Result line of `resolve`: the record, or a marker for paths that don't
+/// exist or lead to a record that can't be parsed
+#[derive(Serialize)]
+#[serde(untagged)]
+enum Resolved {
+    Found(Box<FileRecord>),
+    Malformed {
+        path: String,
+        found: bool,
+        record_number: u64,
+        reason: &'static str,
+    },
+    NotFound { path: String, found: bool },
+}
+
+/// Reads one path per line from stdin and writes the matching record (or a
+/// not-found marker) per line as JSON, loading the MFT only once
+pub fn resolve_stdin(volume: &str) -> Result<()> {
+    let mft = open_mft(volume)?;
+    
+    eprintln!("Indexing paths...");
+    let index = build_path_index(&mft);
+    eprintln!("Indexed {} paths", index.len());
+    
+    let stdout = std::io::stdout();
+    let mut out = stdout.lock();
+    
+    for line in std::io::stdin().lock().lines() {
+        let line = line?;
+        // Only strip line endings: NTFS names may legally end in spaces
+        let path = line.trim_end_matches(['\r', '\n']);
+        if path.is_empty() {
+            continue;
+        }
+        
+        let file = index
+            .get(&path_key(path))
+            .and_then(|&number| mft.get_record(number));
+        let resolved = match file {
+            Some(file) => match parse_file_info(&mft, &file) {
+                Some(info) => Resolved::Found(Box::new(FileRecord::from_file_info(&info, &mft, &file))),
+                None => Resolved::Malformed {
+                    path: path.to_string(),
+                    found: false,
+                    record_number: file.number(),
+                    reason: MALFORMED_REASON,
+                },
+            },
+            None => Resolved::NotFound {
+                path: path.to_string(),
+                found: false,
+            },
+        };
+        
+        writeln!(out, "{}", output::to_json(&resolved)?)?;
+        // Callers typically write a path and wait for its answer
+        out.flush()?;
+    }
+    
+    Ok(())
+}
+
+/// `reason` of result lines for records `parse_file_info` skipped
+const MALFORMED_REASON: &str = "record could not be parsed";
+
+/// Result line of `resolve-frn`: the record the reference points to, or why
+/// it no longer points to a file
+#[derive(Serialize)]
+#[serde(untagged)]
+enum ResolvedFrn {
+    Found {
+        frn: String,
+        #[serde(flatten)]
+        record: Box<FileRecord>,
+    },
+    Malformed {
+        frn: String,
+        found: bool,
+        record_number: u64,
+        reason: &'static str,
+    },
+    NotFound {
+        frn: String,
+        found: bool,
+        reason: String,
+    },
+}
+
+/// Resolves file reference numbers (as logged by ETW, the journal or other
+/// tools) to the current path and metadata of their file, one JSON line per
+/// reference. `frns` are resolved first, then one reference per stdin line
+/// if `stdin` is set. A reference whose sequence number doesn't match the
+/// record's current one belonged to a file that has since been deleted.
+pub fn resolve_frns(volume: &str, frns: &[String], stdin: bool) -> Result<()> {
+    let mft = open_mft(volume)?;
+    let mut raw = RawVolume::open(&normalize_volume_path(volume))?;
+    let runs = raw.mft_runs()?;
+    
+    let stdout = std::io::stdout();
+    let mut out = stdout.lock();
+    let mut resolve = |frn: &str| -> Result<()> {
+        let resolved = match record_for_frn(&mut raw, &runs, frn) {
+            Err(reason) => not_found(frn, &reason),
+            Ok(number) => match mft.get_record(number) {
+                None => not_found(frn, "record not found"),
+                Some(file) => match parse_file_info(&mft, &file) {
+                    Some(info) => ResolvedFrn::Found {
+                        frn: frn.to_string(),
+                        record: Box::new(FileRecord::from_file_info(&info, &mft, &file)),
+                    },
+                    None => ResolvedFrn::Malformed {
+                        frn: frn.to_string(),
+                        found: false,
+                        record_number: number,
+                        reason: MALFORMED_REASON,
+                    },
+                },
+            },
+        };
+        writeln!(out, "{}", output::to_json(&resolved)?)?;
+        out.flush()?;
+        Ok(())
+    };
+    
+    for frn in frns {
+        resolve(frn)?;
+    }
+    if stdin {
+        for line in std::io::stdin().lock().lines() {
+            let line = line?;
+            let frn = line.trim();
+            if !frn.is_empty() {
+                resolve(frn)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn not_found(frn: &str, reason: &str) -> ResolvedFrn {
+    ResolvedFrn::NotFound {
+        frn: frn.to_string(),
+        found: false,
+        reason: reason.to_string(),
+    }
+}
+
+/// Record number and, when present, sequence number of a file reference
+/// given in decimal, as 0x-prefixed hex or in the journal's `Normal(...)`
+/// form. A value below 2^48 is a bare record number. 128-bit file IDs, as
+/// reported by USN_RECORD_V3 (`Extended(...)`), are accepted when their
+/// upper half is zero, which is always the case on NTFS.
+pub fn parse_frn(frn: &str) -> Option<(u64, Option<u16>)> {
+    let value = if let Some(extended) = frn.strip_prefix("Extended(") {
+        // Identifier bytes, least significant first
+        let bytes = extended.split_once('[')?.1.split_once(']')?.0;
+        bytes
+            .split(',')
+            .map(|byte| byte.trim().parse::<u8>().ok())
+            .collect::<Option<Vec<u8>>>()?
+            .iter()
+            .rev()
+            .fold(0u128, |value, &byte| value << 8 | byte as u128)
+    } else {
+        let frn = frn
+            .strip_prefix("Normal(")
+            .and_then(|frn| frn.strip_suffix(')'))
+            .unwrap_or(frn);
+        match frn.strip_prefix("0x").or_else(|| frn.strip_prefix("0X")) {
+            Some(hex) => u128::from_str_radix(hex, 16).ok()?,
+            None => frn.parse().ok()?,
+        }
+    };
+    u64::try_from(value).ok().map(split_frn)
+}
+
+/// Record number and sequence number of the file reference of a journal
+/// record, as `parse_frn` gives them for its text form
+pub fn usn_record_frn(file_id: &ntfs_reader::journal::FileId) -> Option<(u64, Option<u16>)> {
+    match file_id {
+        ntfs_reader::journal::FileId::Normal(value) => Some(split_frn(*value)),
+        // The 128-bit ID is only exposed through its Debug form
+        extended => parse_frn(&format!("{:?}", extended)),
+    }
+}
+
+fn split_frn(value: u64) -> (u64, Option<u16>) {
+    let sequence = (value >> 48) as u16;
+    (value & 0xFFFF_FFFF_FFFF, (sequence != 0).then_some(sequence))
+}
+
+/// Record number a file reference points to, checking that the record is
+/// in use and, when the reference has one, that its sequence number still
+/// matches; otherwise why it no longer points to a file
+fn record_for_frn(raw: &mut RawVolume, runs: &raw::MftRuns, frn: &str) -> Result<u64, String> {
+    let (number, sequence) = parse_frn(frn).ok_or("not a file reference number")?;
+    let record = raw.read_file_record(runs, number).map_err(|e| format!("{:#}", e))?;
+    let parsed = raw::parse_file_record(&record);
+    if !parsed.in_use {
+        return Err("record not in use (file deleted)".to_string());
+    }
+    match sequence {
+        Some(sequence) if sequence != parsed.sequence => Err(format!(
+            "record reused (sequence {}, reference has {})",
+            parsed.sequence, sequence
+        )),
+        _ => Ok(number),
+    }
+}
+
+/// `file-info` for the file a reference number (from the journal, ETW or
+/// another tool) points to, failing if the file has since been deleted
+pub fn file_info_by_id(
+    volume: &str,
+    frn: &str,
+    details: RecordDetails,
+    dump: Option<&ResidentDump>,
+    output: OutputFormat,
+    fields: &Fields,
+) -> Result<()> {
+    let mut raw = RawVolume::open(&normalize_volume_path(volume))?;
+    let runs = raw.mft_runs()?;
+    let record_number = match record_for_frn(&mut raw, &runs, frn) {
+        Ok(number) => number,
+        Err(reason) => bail!("File ID {} doesn't resolve: {}", frn, reason),
+    };
+    
+    let mft = open_mft(volume)?;
+    print_record(&mft, volume, record_number, details, dump, output, fields)
+}
+
+/// Paths of every hard link of a file, built from its $FILE_NAME attributes.
+/// Parent directory paths are cached in `parents` across calls.
+pub fn hard_link_paths(
+    mft: &Mft,
+    file: &ntfs_reader::file::NtfsFile,
+    parents: &mut HashMap<u64, PathBuf>,
+) -> Vec<String> {
+    let mut links = Vec::new();
+    file.attributes(|attr| {
+        if attr.header.type_id == raw::ATTR_FILE_NAME {
+            if let Some(link) = raw::file_name_link(attr.data()) {
+                links.push(link);
+            }
+        }
+    });
+    
+    let mut paths: Vec<String> = links
+        .into_iter()
+        .filter_map(|(parent, name)| {
+            let parent_path = match parents.get(&parent) {
+                Some(path) => path.clone(),
+                None => {
+                    let path = parse_file_info(mft, &mft.get_record(parent)?)?.path;
+                    parents.insert(parent, path.clone());
+                    path
+                }
+            };
+            Some(output::display_path(&parent_path.join(name)))
+        })
+        .collect();
+    paths.dedup();
+    paths
+}
+
+/// Maps the lookup key of every path on the volume to its record number
+pub fn build_path_index(mft: &Mft) -> HashMap<String, u64> {
+    let mut index = HashMap::new();
+    iterate_files(mft, |file| {
+        let Some(info) = parse_file_info(mft, file) else {
+            return;
+        };
+        index.insert(path_key(&info.path.to_string_lossy()), file.number());
+    });
+    index
+}
+
+/// Case-insensitive, volume-relative lookup key: `\\?\C:\Windows\` and
+/// `c:\windows` both map to `\windows`
+pub fn path_key(path: &str) -> String {
+    let path = path
+        .strip_prefix("\\\\?\\")
+        .or_else(|| path.strip_prefix("\\\\.\\"))
+        .unwrap_or(path);
+    let path = match path.as_bytes() {
+        [letter, b':', ..] if letter.is_ascii_alphabetic() => &path[2..],
+        _ => path,
+    };
+    
+    let mut key = path.trim_end_matches('\\').to_lowercase();
+    if !key.starts_with('\\') {
+        key.insert(0, '\\');
+    }
+    key
+}
+
+fn write_record(w: &mut dyn Write, record: &FileRecord, output: OutputFormat, fields: &Fields) -> Result<()> {
+    let selected = fields.select(record);
     match output {
-        OutputFormat::Json => {
-            println!("{}", serde_json::to_string(&record)?);
+        OutputFormat::Json | OutputFormat::Jsonl | OutputFormat::JsonTree => {
+            writeln!(w, "{}", output::to_json(&selected)?)?;
         }
         OutputFormat::JsonPretty => {
-            println!("{}", serde_json::to_string_pretty(&record)?);
+            writeln!(w, "{}", output::to_json_pretty(&selected)?)?;
         }
         OutputFormat::Bincode => {
-            let encoded = bincode::serialize(&record)?;
-            std::io::stdout().write_all(&encoded)?;
+            bincode::serialize_into(&mut *w, &selected)?;
         }
         OutputFormat::Msgpack => {
-            let mut buf = Vec::new();
-            rmp_serde::encode::write(&mut buf, &record)?;
-            std::io::stdout().write_all(&buf)?;
+            rmp_serde::encode::write(&mut *w, &selected)?;
         }
         OutputFormat::Csv => {
-            output_csv_header()?;
-            output_csv_record(&record)?;
+            write_csv_header(w, fields)?;
+            write_csv_record(w, record, fields)?;
+        }
+        OutputFormat::Cdc => bail!("cdc output is only supported by journal and journal-query"),
+        OutputFormat::Dot => bail!("dot output is only supported by tree"),
+        OutputFormat::Table if fields.is_all() => {
+            output::write_table(w, std::slice::from_ref(record))?;
+        }
+        OutputFormat::Table => {
+            output::write_selected_table(w, fields, std::slice::from_ref(record))?;
         }
     }
+    w.flush()?;
     
     Ok(())
 }
 
-fn output_records(records: &[FileRecord], output: OutputFormat) -> Result<()> {
+fn write_records(
+    w: &mut dyn Write,
+    records: &[FileRecord],
+    output: OutputFormat,
+    fields: &Fields,
+) -> Result<()> {
+    let selected: Vec<_> = records.iter().map(|record| fields.select(record)).collect();
     match output {
         OutputFormat::Json => {
-            println!("{}", serde_json::to_string(&records)?);
+            writeln!(w, "{}", output::to_json(&selected)?)?;
         }
         OutputFormat::JsonPretty => {
-            println!("{}", serde_json::to_string_pretty(&records)?);
+            writeln!(w, "{}", output::to_json_pretty(&selected)?)?;
+        }
+        OutputFormat::Jsonl => {
+            for record in &selected {
+                writeln!(w, "{}", output::to_json(record)?)?;
+            }
+        }
+        OutputFormat::JsonTree => {
+            writeln!(w, "{}", output::to_json(&build_tree(records, fields))?)?;
         }
         OutputFormat::Bincode => {
-            let encoded = bincode::serialize(&records)?;
-            std::io::stdout().write_all(&encoded)?;
+            bincode::serialize_into(&mut *w, &selected)?;
         }
         OutputFormat::Msgpack => {
-            let mut buf = Vec::new();
-            rmp_serde::encode::write(&mut buf, &records)?;
-            std::io::stdout().write_all(&buf)?;
+            rmp_serde::encode::write(&mut *w, &selected)?;
         }
         OutputFormat::Csv => {
-            output_csv_header()?;
+            write_csv_header(w, fields)?;
             for record in records {
-                output_csv_record(record)?;
+                write_csv_record(w, record, fields)?;
+            }
+        }
+        OutputFormat::Cdc => bail!("cdc output is only supported by journal and journal-query"),
+        OutputFormat::Dot => bail!("dot output is only supported by tree"),
+        OutputFormat::Table if fields.is_all() => {
+            output::write_table(w, records)?;
+        }
+        OutputFormat::Table => {
+            output::write_selected_table(w, fields, records)?;
+        }
+    }
+    w.flush()?;
+    Ok(())
+}
+
+/// The sinks of a record listing, with the streamed ones begun
+fn open_record_sinks(output: OutputFormat, tee: &[TeeSink], fields: &Fields) -> Result<Vec<Sink>> {
+    let mut sinks = output::open_sinks(output, tee)?;
+    for sink in sinks.iter_mut() {
+        if is_streamable(sink.format) {
+            begin_stream(sink, fields)?;
+        }
+    }
+    Ok(sinks)
+}
+
+/// Formats that need every record up front (table widths, tree nesting,
+/// length-prefixed arrays) are buffered; the others are written as the
+/// scan goes, so memory stays flat however large the volume is
+fn is_buffered(sinks: &[Sink]) -> bool {
+    sinks.iter().any(|sink| !is_streamable(sink.format))
+}
+
+fn stream_to_sinks(sinks: &mut [Sink], record: &FileRecord, fields: &Fields) -> Result<()> {
+    sinks
+        .iter_mut()
+        .filter(|sink| is_streamable(sink.format))
+        .try_for_each(|sink| stream_record(sink, record, fields))
+}
+
+/// Ends the streamed sinks and writes the buffered records to the others
+fn close_record_sinks(sinks: &mut [Sink], records: &[FileRecord], fields: &Fields) -> Result<()> {
+    for sink in sinks.iter_mut() {
+        if is_streamable(sink.format) {
+            end_stream(sink)?;
+        } else {
+            let format = sink.format;
+            write_records(sink.writer(), records, format, fields)?;
+        }
+    }
+    Ok(())
+}
+
+/// Whether a format can be written one record at a time
+fn is_streamable(format: OutputFormat) -> bool {
+    matches!(
+        format,
+        OutputFormat::Json | OutputFormat::JsonPretty | OutputFormat::Jsonl | OutputFormat::Csv
+    )
+}
+
+fn begin_stream(sink: &mut Sink, fields: &Fields) -> Result<()> {
+    let format = sink.format;
+    let w = sink.writer();
+    match format {
+        OutputFormat::Json | OutputFormat::JsonPretty => write!(w, "[")?,
+        OutputFormat::Csv => write_csv_header(w, fields)?,
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Writes one record of a streamed result. JSON arrays are produced
+/// element by element, matching what serializing the whole array would give.
+fn stream_record(sink: &mut Sink, record: &FileRecord, fields: &Fields) -> Result<()> {
+    let first = sink.written == 0;
+    let format = sink.format;
+    let w = sink.writer();
+    let selected = fields.select(record);
+    
+    match format {
+        OutputFormat::Json => {
+            if !first {
+                write!(w, ",")?;
             }
+            write!(w, "{}", output::to_json(&selected)?)?;
         }
+        OutputFormat::JsonPretty => {
+            let pretty = output::to_json_pretty(&selected)?.replace('\n', "\n  ");
+            write!(w, "{}\n  {}", if first { "" } else { "," }, pretty)?;
+        }
+        OutputFormat::Jsonl => {
+            writeln!(w, "{}", output::to_json(&selected)?)?;
+        }
+        OutputFormat::Csv => {
+            write_csv_record(w, record, fields)?;
+        }
+        _ => unreachable!("{:?} output is not streamed", format),
     }
+    sink.written += 1;
     Ok(())
 }
 
-fn output_csv_header() -> Result<()> {
-    println!("name,path,is_directory,size,created,modified,accessed");
+fn end_stream(sink: &mut Sink) -> Result<()> {
+    let empty = sink.written == 0;
+    let format = sink.format;
+    let w = sink.writer();
+    match format {
+        OutputFormat::Json => writeln!(w, "]")?,
+        OutputFormat::JsonPretty if empty => writeln!(w, "]")?,
+        OutputFormat::JsonPretty => writeln!(w, "\n]")?,
+        _ => {}
+    }
+    w.flush()?;
     Ok(())
 }
 
-fn output_csv_record(record: &FileRecord) -> Result<()> {
-    println!(
-        "{},{},{},{},{},{},{}",
+/// A node of the nested json-tree output. Directories that were not part of
+/// the result themselves (e.g. parents of filtered files) only carry a name.
+#[derive(Serialize)]
+struct TreeNode<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+    #[serde(flatten)]
+    record: Option<output::Selected<'a, FileRecord>>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    children: Vec<TreeNode<'a>>,
+}
+
+#[derive(Default)]
+struct TreeBuilder<'a> {
+    record: Option<&'a FileRecord>,
+    children: BTreeMap<String, TreeBuilder<'a>>,
+}
+
+impl<'a> TreeBuilder<'a> {
+    fn into_node(self, name: String, fields: &'a Fields) -> TreeNode<'a> {
+        TreeNode {
+            name: if self.record.is_none() { Some(name) } else { None },
+            record: self.record.map(|record| fields.select(record)),
+            children: self
+                .children
+                .into_iter()
+                .map(|(name, child)| child.into_node(name, fields))
+                .collect(),
+        }
+    }
+}
+
+/// Nests records under their parent directories based on their paths
+fn build_tree<'a>(records: &'a [FileRecord], fields: &'a Fields) -> Vec<TreeNode<'a>> {
+    let mut root = TreeBuilder::default();
+    
+    for record in records {
+        let mut node = &mut root;
+        for component in record.path.split('\\').filter(|c| !c.is_empty()) {
+            node = node.children.entry(component.to_string()).or_default();
+        }
+        node.record = Some(record);
+    }
+    
+    root.children
+        .into_iter()
+        .map(|(name, child)| child.into_node(name, fields))
+        .collect()
+}
+
+/// Columns of CSV output without --fields. `record_number` is included
+/// when it would be serialized.
+fn csv_columns() -> Vec<&'static str> {
+    let mut columns = vec!["name", "path", "is_directory", "size", "created", "modified", "accessed", "deleted"];
+    if !hide_record_number(&0) {
+        columns.push("record_number");
+    }
+    columns.extend(["file_attributes", "file_attribute_names"]);
+    columns
+}
+
+fn write_csv_header(w: &mut dyn Write, fields: &Fields) -> Result<()> {
+    if !fields.is_all() {
+        writeln!(w, "{}", fields.csv_header())?;
+        return Ok(());
+    }
+    writeln!(w, "{}", Fields::new(&csv_columns(), FileRecord::FIELDS)?.csv_header())?;
+    Ok(())
+}
+
+fn write_csv_record(w: &mut dyn Write, record: &FileRecord, fields: &Fields) -> Result<()> {
+    if !fields.is_all() {
+        writeln!(w, "{}", fields.csv_row(record)?)?;
+        return Ok(());
+    }
+    let mut cells = vec![
         escape_csv(&record.name),
         escape_csv(&record.path),
-        record.is_directory,
-        record.size,
-        record.created.as_deref().unwrap_or(""),
-        record.modified.as_deref().unwrap_or(""),
-        record.accessed.as_deref().unwrap_or("")
-    );
+        record.is_directory.to_string(),
+        output::format_size(record.size),
+        record.created.clone().unwrap_or_default(),
+        record.modified.clone().unwrap_or_default(),
+        record.accessed.clone().unwrap_or_default(),
+        record.deleted.to_string(),
+    ];
+    if !hide_record_number(&record.record_number) {
+        cells.push(record.record_number.to_string());
+    }
+    cells.push(record.file_attributes.to_string());
+    // One cell, so the names are separated by semicolons rather than commas
+    cells.push(escape_csv(&record.file_attribute_names.join(";")));
+    writeln!(w, "{}", cells.join(","))?;
     Ok(())
 }
 
-fn escape_csv(s: &str) -> String {
-    if s.contains(',') || s.contains('"') || s.contains('\n') {
-        format!("\"{}\"", s.replace('"', "\"\""))
-    } else {
-        s.to_string()
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_csv_rows_carry_deletion_and_attributes() {
+        let record: FileRecord = serde_json::from_value(serde_json::json!({
+            "name": "a, b.txt",
+            "path": "C:\\a, b.txt",
+            "is_directory": false,
+            "deleted": true,
+            "size": 10,
+            "created": "2024-01-15T10:30:00Z",
+            "modified": null,
+            "accessed": null,
+            "file_attributes": 34,
+            "file_attribute_names": ["hidden", "archive"],
+        }))
+        .unwrap();
+        let mut csv = Vec::new();
+        write_csv_header(&mut csv, &Fields::default()).unwrap();
+        write_csv_record(&mut csv, &record, &Fields::default()).unwrap();
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "name,path,is_directory,size,created,modified,accessed,deleted,file_attributes,file_attribute_names\n\
+             \"a, b.txt\",\"C:\\a, b.txt\",false,10,2024-01-15T10:30:00Z,,,true,34,hidden;archive\n"
+        );
+    }
+
+    #[test]
+    fn isolate_turns_parse_panics_into_skipped_records() {
+        let _quiet = QuietPanics::new();
+        assert_eq!(isolate(30, || 5), Some(5));
+        assert_eq!(isolate(31, || -> u8 { panic!("attribute runs past the record") }), None);
+        // Only the parse is skipped; the next record reads as usual
+        assert_eq!(isolate(32, || "next"), Some("next"));
+
+        // A nested call leaves the outer one isolated
+        let outer = isolate(33, || {
+            assert_eq!(isolate(34, || 1), Some(1));
+            assert!(ISOLATING.with(Cell::get));
+            2
+        });
+        assert_eq!(outer, Some(2));
+        assert!(!ISOLATING.with(Cell::get));
+    }
+
+    #[test]
+    fn file_references_parse_in_every_form() {
+        let frn = (5u64 << 48) | 0x2A;
+        assert_eq!(parse_frn(&format!("Normal({})", frn)), Some((0x2A, Some(5))));
+        assert_eq!(parse_frn("0x2A"), Some((0x2A, None)));
+        let extended = "Extended(FILE_ID_128 { Identifier: [42, 0, 0, 0, 0, 0, 5, 0, 0, 0, 0, 0, 0, 0, 0, 0] })";
+        assert_eq!(parse_frn(extended), Some((0x2A, Some(5))));
+        assert_eq!(parse_frn("Extended([0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0])"), None);
+        assert_eq!(parse_frn("Normal(x)"), None);
+        assert_eq!(usn_record_frn(&ntfs_reader::journal::FileId::Normal(frn)), Some((0x2A, Some(5))));
     }
 }
@@ -0,0 +1,154 @@
+use anyhow::{bail, Context, Result};
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::du::ROOT_RECORD;
+use crate::image;
+use crate::mft::{MAX_PATH_DEPTH, ORPHAN_DIRECTORY};
+use crate::output::Warning;
+use crate::raw::{self, RawVolume};
+
+/// The MFT given by --mft-file or --image, if either was, for commands that
+/// can run without a volume
+pub fn open_offline(
+    mft_file: Option<&Path>,
+    image: Option<&Path>,
+    partition_offset: Option<u64>,
+) -> Result<Option<MftFile>> {
+    match (mft_file, image) {
+        (Some(path), _) => MftFile::open(path).map(Some),
+        (None, Some(path)) => MftFile::from_image(path, partition_offset).map(Some),
+        (None, None) => Ok(None),
+    }
+}
+
+/// An $MFT copied off a volume (by FTK Imager, RawCopy, `extract` and the
+/// like) or read out of a disk image, examined without the volume it
+/// came from. Records are parsed by `raw`, since ntfs-reader needs the
+/// volume the MFT belongs to, as a device or file starting with it.
+pub struct MftFile {
+    data: Vec<u8>,
+    record_size: usize,
+    sector_size: usize,
+    /// Rebuilt paths of the directories met so far, "" for the root
+    directories: HashMap<u64, String>,
+}
+
+impl MftFile {
+    /// Loads an extracted $MFT. With no boot sector to read them from, the
+    /// record size is taken from record 0 ($MFT's own record) and the sector
+    /// size from the number of sectors its update sequence array covers.
+    pub fn open(path: &Path) -> Result<Self> {
+        let data = std::fs::read(path).context(format!("Failed to read {}", path.display()))?;
+        if data.len() < 0x30 || &data[0..4] != b"FILE" {
+            bail!("{} doesn't start with a file record: not an extracted $MFT", path.display());
+        }
+
+        let record_size = raw::u32_at(&data, 0x1C).unwrap_or(0) as usize;
+        if !record_size.is_power_of_two() || !(256..=65536).contains(&record_size) || record_size > data.len() {
+            bail!("Invalid record size {} in the first record of {}", record_size, path.display());
+        }
+        // The array holds the update sequence number, then one entry per sector
+        let sectors = (raw::u16_at(&data, 0x06).unwrap_or(0) as usize).saturating_sub(1);
+        if sectors == 0 || !record_size.is_multiple_of(sectors) {
+            bail!("Invalid update sequence array in the first record of {}", path.display());
+        }
+
+        Ok(Self::new(data, record_size, record_size / sectors))
+    }
+
+    /// Reads the $MFT of the NTFS volume in a disk or partition image,
+    /// found as `image::volume_offset` describes
+    pub fn from_image(path: &Path, partition_offset: Option<u64>) -> Result<Self> {
+        let mut source = image::open(path)?;
+        let offset = image::volume_offset(&mut *source, path, partition_offset)?;
+        let mut volume = RawVolume::from_source(source, offset, &path.to_string_lossy())?;
+        let record = volume.read_mft_record().context("Failed to read the $MFT record")?;
+
+        eprintln!("Loading MFT...");
+        let data = volume
+            .read_attribute(&record, raw::ATTR_DATA)
+            .context("Failed to read $MFT")?
+            .context("$MFT has no $DATA attribute")?;
+        Ok(Self::new(data, volume.record_size as usize, volume.bytes_per_sector as usize))
+    }
+
+    fn new(data: Vec<u8>, record_size: usize, sector_size: usize) -> Self {
+        MftFile {
+            data,
+            record_size,
+            sector_size,
+            directories: HashMap::new(),
+        }
+    }
+
+    /// Record slots the MFT holds
+    pub fn max_record(&self) -> u64 {
+        (self.data.len() / self.record_size) as u64
+    }
+
+    /// File record `number` with its fixups applied, or `None` for slots
+    /// that never held a record. Torn records are skipped with a warning.
+    pub fn record(&self, number: u64) -> Option<Vec<u8>> {
+        let start = usize::try_from(number).ok()?.checked_mul(self.record_size)?;
+        let mut record = self.data.get(start..start + self.record_size)?.to_vec();
+        if &record[0..4] != b"FILE" {
+            return None;
+        }
+        match raw::apply_fixups(&mut record, self.sector_size) {
+            Ok(()) => Some(record),
+            Err(e) => {
+                let message = format!("record could not be parsed and was skipped: {:#}", e);
+                Warning::new("malformed_record", message).record_number(number).emit();
+                None
+            }
+        }
+    }
+
+    /// Path of a file record, rebuilt from the parent reference of its first
+    /// name. Parents are followed whether in use or not, so deleted files
+    /// get the path they had; where the chain breaks, the rest goes under
+    /// `$OrphanFiles` as with `DeletedPaths`. The drive letter isn't known,
+    /// so paths start at the root: `\Windows\notepad.exe`.
+    pub fn path(&mut self, number: u64, record: &[u8]) -> String {
+        if number == ROOT_RECORD {
+            return "\\".to_string();
+        }
+        match raw::file_name_links(record).into_iter().next() {
+            Some((parent, name)) => format!("{}\\{}", self.directory(parent, 0), name),
+            None => format!("\\{}\\record-{}", ORPHAN_DIRECTORY, number),
+        }
+    }
+
+    /// Paths of every hard link of a file record
+    pub fn hard_links(&mut self, record: &[u8]) -> Vec<String> {
+        let mut paths: Vec<String> = raw::file_name_links(record)
+            .into_iter()
+            .map(|(parent, name)| format!("{}\\{}", self.directory(parent, 0), name))
+            .collect();
+        paths.dedup();
+        paths
+    }
+
+    fn directory(&mut self, number: u64, depth: usize) -> String {
+        if number == ROOT_RECORD {
+            return String::new();
+        }
+        if let Some(path) = self.directories.get(&number) {
+            return path.clone();
+        }
+
+        let orphan = format!("\\{}", ORPHAN_DIRECTORY);
+        let path = match self.record(number) {
+            Some(record) if depth < MAX_PATH_DEPTH && raw::parse_file_record(&record).is_directory => {
+                match raw::file_name_links(&record).into_iter().next() {
+                    Some((parent, name)) => format!("{}\\{}", self.directory(parent, depth + 1), name),
+                    None => orphan,
+                }
+            }
+            _ => orphan,
+        };
+        self.directories.insert(number, path.clone());
+        path
+    }
+}
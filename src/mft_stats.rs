@@ -0,0 +1,151 @@
+use anyhow::{bail, Result};
+use ntfs_reader::file::NtfsFile;
+use serde::Serialize;
+use std::io::{self, Write};
+
+use crate::mft;
+use crate::output;
+use crate::raw::{self, RawVolume};
+use crate::OutputFormat;
+
+/// Scale and shape of a volume's MFT, as reported by `mft-stats`
+#[derive(Debug, Default, Serialize)]
+pub struct MftStats {
+    pub record_size: u64,
+    /// Record slots the MFT has room for
+    pub records: u64,
+    pub records_in_use: u64,
+    /// Slots never used or freed by deleted files
+    pub records_free: u64,
+    /// Freed slots still holding a deleted file's record
+    pub records_deleted: u64,
+    pub directories: u64,
+    pub files: u64,
+    /// In-use records holding overflow attributes of another record
+    pub extension_records: u64,
+    /// Files whose data is stored inside their MFT record
+    pub resident_files: u64,
+    /// Attributes per in-use record
+    pub average_attributes: f64,
+    pub max_attributes: u64,
+    /// Record with the most attributes
+    pub max_attributes_record: u64,
+    /// Bytes allocated to $MFT
+    pub mft_size: u64,
+    /// Contiguous pieces $MFT is stored in; 1 means not fragmented
+    pub mft_extents: u64,
+}
+
+/// Counts every record slot of the MFT by state and kind, the attributes
+/// they hold and how $MFT itself is laid out on the volume. Table output
+/// prints one line per figure; JSON formats print one object.
+pub fn report(volume: &str, output: OutputFormat) -> Result<()> {
+    let mut raw = RawVolume::open(&mft::normalize_volume_path(volume))?;
+    let runs = raw.mft_runs()?;
+    let mft = mft::open_mft(volume)?;
+
+    let mut stats = MftStats {
+        record_size: raw.record_size,
+        records: mft.max_record,
+        mft_size: runs.clusters() * raw.bytes_per_cluster,
+        mft_extents: runs.extents() as u64,
+        ..Default::default()
+    };
+    let mut attributes = 0u64;
+
+    eprintln!("Counting records...");
+    let _quiet = mft::QuietPanics::new();
+    for number in 0..mft.max_record {
+        let Some(file) = mft::isolate(number, || mft.get_record(number)).flatten() else {
+            continue;
+        };
+        if !file.is_used() {
+            stats.records_deleted += 1;
+            continue;
+        }
+        stats.records_in_use += 1;
+
+        let Some(record) = mft::isolate(number, || inspect(&file)) else {
+            continue;
+        };
+        attributes += record.attributes;
+        if record.attributes > stats.max_attributes {
+            stats.max_attributes = record.attributes;
+            stats.max_attributes_record = number;
+        }
+        if !record.has_standard_information {
+            stats.extension_records += 1;
+        } else if file.is_directory() {
+            stats.directories += 1;
+        } else {
+            stats.files += 1;
+            if record.resident_data {
+                stats.resident_files += 1;
+            }
+        }
+    }
+    stats.records_free = stats.records - stats.records_in_use;
+    if stats.records_in_use > 0 {
+        stats.average_attributes =
+            (attributes as f64 * 100.0 / stats.records_in_use as f64).round() / 100.0;
+    }
+
+    let stdout = io::stdout();
+    let mut w = stdout.lock();
+    match output {
+        OutputFormat::Table => {
+            writeln!(w, "Record size:        {}", stats.record_size)?;
+            writeln!(w, "Records:            {}", stats.records)?;
+            writeln!(w, "In use:             {}", stats.records_in_use)?;
+            writeln!(
+                w,
+                "Free:               {} ({} still hold deleted files)",
+                stats.records_free, stats.records_deleted
+            )?;
+            writeln!(w, "Directories:        {}", stats.directories)?;
+            writeln!(w, "Files:              {}", stats.files)?;
+            writeln!(w, "Resident files:     {}", stats.resident_files)?;
+            writeln!(w, "Extension records:  {}", stats.extension_records)?;
+            writeln!(w, "Average attributes: {:.2}", stats.average_attributes)?;
+            writeln!(
+                w,
+                "Most attributes:    {} (record {})",
+                stats.max_attributes, stats.max_attributes_record
+            )?;
+            writeln!(w, "MFT size:           {}", output::format_size(stats.mft_size))?;
+            writeln!(w, "MFT extents:        {}", stats.mft_extents)?;
+        }
+        OutputFormat::Json | OutputFormat::Jsonl => writeln!(w, "{}", output::to_json(&stats)?)?,
+        OutputFormat::JsonPretty => writeln!(w, "{}", output::to_json_pretty(&stats)?)?,
+        _ => bail!("mft-stats supports table, json, jsonl and json-pretty output"),
+    }
+    Ok(())
+}
+
+/// What `mft-stats` needs from one in-use record
+struct RecordShape {
+    attributes: u64,
+    /// Base records always have one; extension records never do
+    has_standard_information: bool,
+    /// The unnamed $DATA attribute is resident
+    resident_data: bool,
+}
+
+fn inspect(file: &NtfsFile) -> RecordShape {
+    let mut shape = RecordShape {
+        attributes: 0,
+        has_standard_information: false,
+        resident_data: false,
+    };
+    file.attributes(|attr| {
+        shape.attributes += 1;
+        match attr.header.type_id {
+            raw::ATTR_STANDARD_INFORMATION => shape.has_standard_information = true,
+            raw::ATTR_DATA if attr.header.name_length == 0 => {
+                shape.resident_data = attr.header.is_non_resident == 0;
+            }
+            _ => {}
+        }
+    });
+    shape
+}
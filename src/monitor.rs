@@ -0,0 +1,503 @@
+use anyhow::Result;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::time::{Duration, Instant};
+
+use crate::journal::{
+    self, JournalEvent, USN_REASON_BASIC_INFO_CHANGE, USN_REASON_FILE_CREATE, USN_REASON_FILE_DELETE,
+    USN_REASON_SECURITY_CHANGE,
+};
+use crate::mft::{self, FileRecord};
+use crate::output::{self, Warning};
+use crate::owners::{self, DaclSummary, Owners};
+use crate::raw::{self, RawVolume, StandardInformation};
+
+#[derive(Debug, Serialize)]
+pub struct MonitorChange {
+    /// updated or deleted
+    pub change: &'static str,
+    pub usn: i64,
+    pub timestamp_ms: u128,
+    pub file_id: String,
+    /// Every reason seen for the file since its last change line
+    pub reason: u32,
+    pub reason_str: String,
+    /// Path of a deleted file, which has no record left to describe it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    /// Owner and DACL before and after a SECURITY_CHANGE, with --security
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub security: Option<SecurityChange>,
+    /// Attribute flags and timestamps before and after a BASIC_INFO_CHANGE,
+    /// with --basic-info
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub basic_info: Option<BasicInfoChange>,
+    /// Bytes the file grew (positive) or shrank (negative) by since it was
+    /// last seen, with --min-size-delta
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size_delta: Option<i64>,
+    #[serde(flatten)]
+    pub record: Option<FileRecord>,
+}
+
+/// A file's security descriptor, as found in $Secure
+#[derive(Debug, Serialize)]
+pub struct SecurityState {
+    pub security_id: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub owner_sid: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dacl: Option<DaclSummary>,
+}
+
+/// The security descriptor of a file before and after its permissions or
+/// owner changed
+#[derive(Debug, Serialize)]
+pub struct SecurityChange {
+    /// Unknown for files created after monitoring started
+    pub before: Option<SecurityState>,
+    pub after: Option<SecurityState>,
+}
+
+/// A file's attribute flags and $STANDARD_INFORMATION timestamps
+#[derive(Debug, Serialize)]
+pub struct BasicInfoState {
+    pub file_attributes: u32,
+    pub file_attribute_names: Vec<String>,
+    pub created: Option<String>,
+    pub modified: Option<String>,
+    pub mft_modified: Option<String>,
+    pub accessed: Option<String>,
+}
+
+impl BasicInfoState {
+    fn new(info: &StandardInformation) -> Self {
+        let format = |filetime| output::from_filetime(filetime).map(mft::format_time);
+        BasicInfoState {
+            file_attributes: info.file_attributes,
+            file_attribute_names: mft::attribute_names(info.file_attributes),
+            created: format(info.created),
+            modified: format(info.modified),
+            mft_modified: format(info.mft_modified),
+            accessed: format(info.accessed),
+        }
+    }
+}
+
+/// The attribute flags and timestamps of a file before and after a
+/// BASIC_INFO_CHANGE
+#[derive(Debug, Serialize)]
+pub struct BasicInfoChange {
+    /// Unknown for files created after monitoring started
+    pub before: Option<BasicInfoState>,
+    pub after: Option<BasicInfoState>,
+    /// Fields that differ between the two, e.g. ["file_attributes", "modified"]
+    pub changed: Vec<&'static str>,
+}
+
+/// Journal events of one file received since the last refresh
+struct PendingChange {
+    sequence: u16,
+    usn: i64,
+    timestamp_ms: u128,
+    file_id: String,
+    reason: u32,
+    path: String,
+    /// Refreshes that found the record on disk older than the events
+    stale_reads: u32,
+}
+
+/// Refreshes a record may be read again for when it is older than its
+/// latest event, before it is reported as it is
+const MAX_STALE_READS: u32 = 3;
+
+/// What re-reading a changed file's record gave
+enum Refresh {
+    Line(Box<MonitorChange>),
+    /// The record on disk doesn't show the events yet; try again later
+    Stale(PendingChange),
+    Unreadable,
+}
+
+/// Tails the journal and, every `refresh`, re-reads the MFT records of the
+/// files that changed straight from the volume. One JSON line is written per
+/// changed file with its current metadata, so consumers get up-to-date
+/// records instead of raw reasons. Several events for a file within one
+/// refresh interval produce a single line.
+/// With `security`, SECURITY_CHANGE lines also carry the file's owner and
+/// DACL summary before and after the change, and with `basic_info`,
+/// BASIC_INFO_CHANGE lines carry attribute flags and timestamps before and
+/// after. With `min_size_delta`, only files whose size changed by more than
+/// that many bytes are reported.
+pub fn run(
+    volume: &str,
+    refresh: Duration,
+    security: bool,
+    basic_info: bool,
+    min_size_delta: Option<u64>,
+) -> Result<()> {
+    let mut raw = RawVolume::open(&mft::normalize_volume_path(volume))?;
+    let runs = raw.mft_runs()?;
+    let mut security = security.then(|| SecurityTracker::load(volume)).transpose()?;
+    let mut basic_info = basic_info.then(|| BasicInfoTracker::load(volume)).transpose()?;
+    let mut sizes = min_size_delta.map(|min| SizeTracker::load(volume, min)).transpose()?;
+
+    let mut pending: HashMap<u64, PendingChange> = HashMap::new();
+    let mut last_refresh = Instant::now();
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+
+    eprintln!("Monitoring changes, refreshing records every {:?}", refresh);
+    journal::follow_batches(volume, None, None, |events| {
+        for event in events {
+            track(&mut pending, event);
+        }
+
+        if last_refresh.elapsed() >= refresh {
+            last_refresh = Instant::now();
+            let mut changes: Vec<(u64, PendingChange)> = pending.drain().collect();
+            changes.sort_by_key(|(_, change)| change.usn);
+
+            for (number, change) in changes {
+                let trackers = (security.as_mut(), basic_info.as_mut());
+                let mut line = match refresh_record(&mut raw, &runs, number, change, trackers) {
+                    Refresh::Line(line) => *line,
+                    Refresh::Stale(change) => {
+                        pending.insert(number, change);
+                        continue;
+                    }
+                    Refresh::Unreadable => continue,
+                };
+                if let Some(sizes) = sizes.as_mut() {
+                    match sizes.observe(number, &line) {
+                        Some(delta) => line.size_delta = Some(delta),
+                        None => continue,
+                    }
+                }
+                writeln!(out, "{}", output::to_json(&line)?)?;
+            }
+            out.flush()?;
+        }
+        Ok(true)
+    })
+}
+
+fn track(pending: &mut HashMap<u64, PendingChange>, event: JournalEvent) {
+    let Some((number, sequence)) = mft::parse_frn(&event.file_id) else {
+        return;
+    };
+    let sequence = sequence.unwrap_or(0);
+
+    let change = pending.entry(number).or_insert_with(|| PendingChange {
+        sequence,
+        usn: event.usn,
+        timestamp_ms: event.timestamp_ms,
+        file_id: String::new(),
+        reason: 0,
+        path: String::new(),
+        stale_reads: 0,
+    });
+    // A reused record is another file; only its latest incarnation matters
+    if change.sequence != sequence {
+        change.reason = 0;
+        change.sequence = sequence;
+    }
+    change.usn = event.usn;
+    change.timestamp_ms = event.timestamp_ms;
+    change.file_id = event.file_id;
+    change.reason |= event.reason;
+    change.path = event.path;
+}
+
+/// Reads the current state of a changed file. A record that is no longer in
+/// use, or now belongs to another file, means the file was deleted.
+///
+/// The journal can be ahead of the volume: NTFS writes changed records back
+/// lazily, and raw reads don't go through its cache. A record whose
+/// $STANDARD_INFORMATION hasn't caught up with the latest event's USN, or
+/// that looks deleted without a FILE_DELETE event, is read again at the next
+/// refresh, up to `MAX_STALE_READS` times; after that it is reported as it
+/// is on disk.
+fn refresh_record(
+    raw: &mut RawVolume,
+    runs: &raw::MftRuns,
+    number: u64,
+    mut change: PendingChange,
+    (security, basic_info): (Option<&mut SecurityTracker>, Option<&mut BasicInfoTracker>),
+) -> Refresh {
+    let (record, parsed) = match raw.read_file_record(runs, number) {
+        Ok(record) => {
+            let parsed = raw::parse_file_record(&record);
+            (record, parsed)
+        }
+        Err(e) => {
+            Warning::new("unreadable_record", format!("{:#}", e))
+                .record(number, &change.path)
+                .emit();
+            return Refresh::Unreadable;
+        }
+    };
+
+    let delete_event = change.reason & USN_REASON_FILE_DELETE != 0;
+    let gone = !parsed.in_use || parsed.sequence != change.sequence;
+    let behind = !gone && record_usn(&record).is_some_and(|usn| (usn as i64) < change.usn);
+    if !delete_event && (gone || behind) && change.stale_reads < MAX_STALE_READS {
+        change.stale_reads += 1;
+        return Refresh::Stale(change);
+    }
+
+    let deleted = delete_event || gone;
+    let security = security.and_then(|tracker| {
+        let current = (!deleted).then(|| security_id(&record)).flatten();
+        tracker.observe(number, current, change.reason)
+    });
+    let basic_info = basic_info.and_then(|tracker| {
+        let current = (!deleted).then(|| standard_information(&record)).flatten();
+        tracker.observe(number, current, change.reason)
+    });
+    let (path, record) = if deleted {
+        (Some(change.path), None)
+    } else {
+        (None, Some(FileRecord::from_raw(&parsed, number, change.path)))
+    };
+
+    Refresh::Line(Box::new(MonitorChange {
+        change: if deleted { "deleted" } else { "updated" },
+        usn: change.usn,
+        timestamp_ms: change.timestamp_ms,
+        file_id: change.file_id,
+        reason: change.reason,
+        reason_str: ntfs_reader::journal::Journal::get_reason_str(change.reason),
+        path,
+        security,
+        basic_info,
+        size_delta: None,
+        record,
+    }))
+}
+
+/// Sizes of the files on the volume, kept current as changes come in, so
+/// changes that leave a file's size alone (timestamps, attributes, renames,
+/// rewrites in place) can be told from growth and shrinkage
+struct SizeTracker {
+    /// Size of every file as last seen, by record number
+    sizes: HashMap<u64, u64>,
+    min_delta: u64,
+}
+
+impl SizeTracker {
+    fn load(volume: &str, min_delta: u64) -> Result<Self> {
+        let mft = mft::open_mft(volume)?;
+
+        eprintln!("Indexing file sizes for --min-size-delta...");
+        let mut sizes = HashMap::new();
+        mft::iterate_files(&mft, |file| {
+            if !file.is_directory() {
+                // Sized like the records refreshed from the volume, so an
+                // unchanged file doesn't show a delta
+                let mut size = raw::FileSize::default();
+                file.attributes(|attr| size.add(attr.data()));
+                sizes.insert(file.number(), size.size());
+            }
+        });
+        eprintln!("Indexed {} files", sizes.len());
+
+        Ok(SizeTracker { sizes, min_delta })
+    }
+
+    /// Records the size a changed file has now and returns how much it
+    /// changed by, or `None` when that isn't more than the threshold. New
+    /// files grew from nothing and deleted ones shrank to nothing;
+    /// directories have no size to follow.
+    fn observe(&mut self, number: u64, change: &MonitorChange) -> Option<i64> {
+        let current = change
+            .record
+            .as_ref()
+            .filter(|record| !record.is_directory)
+            .map(|record| record.size);
+        let previous = match current {
+            Some(size) => self.sizes.insert(number, size),
+            None => self.sizes.remove(&number),
+        };
+        // A reused record is another file, which starts from nothing
+        let previous = previous.filter(|_| change.reason & USN_REASON_FILE_CREATE == 0);
+
+        let delta = current.unwrap_or(0) as i64 - previous.unwrap_or(0) as i64;
+        (delta.unsigned_abs() > self.min_delta).then_some(delta)
+    }
+}
+
+/// Security IDs of the files on the volume and the descriptors they select,
+/// kept current as changes come in so a SECURITY_CHANGE can be reported
+/// with the descriptor the file had before it
+struct SecurityTracker {
+    volume: String,
+    owners: Owners,
+    /// Security ID of every file as last seen, by record number
+    ids: HashMap<u64, u32>,
+}
+
+impl SecurityTracker {
+    fn load(volume: &str) -> Result<Self> {
+        let owners = Owners::load(volume, false)?;
+        let mft = mft::open_mft(volume)?;
+
+        eprintln!("Indexing security IDs for --security...");
+        let mut ids = HashMap::new();
+        mft::iterate_files(&mft, |file| {
+            if let Some(id) = owners::security_id(file) {
+                ids.insert(file.number(), id);
+            }
+        });
+        eprintln!("Indexed {} files", ids.len());
+
+        Ok(SecurityTracker { volume: volume.to_string(), owners, ids })
+    }
+
+    /// Records the security ID a file has now (`None` once it is deleted)
+    /// and, when `reason` includes SECURITY_CHANGE, returns its descriptor
+    /// before and after. A new file has no descriptor before.
+    fn observe(&mut self, number: u64, current: Option<u32>, reason: u32) -> Option<SecurityChange> {
+        let previous = match current {
+            Some(id) => self.ids.insert(number, id),
+            None => self.ids.remove(&number),
+        };
+        if reason & USN_REASON_SECURITY_CHANGE == 0 {
+            return None;
+        }
+        let previous = previous.filter(|_| reason & USN_REASON_FILE_CREATE == 0);
+
+        // A descriptor no file had before is appended to $Secure; read it again
+        if current.is_some_and(|id| !self.owners.contains(id)) {
+            match Owners::load(&self.volume, false) {
+                Ok(owners) => self.owners = owners,
+                Err(e) => {
+                    let message = format!("failed to reload $Secure: {:#}", e);
+                    Warning::new("owners", message).record_number(number).emit();
+                }
+            }
+        }
+        Some(SecurityChange {
+            before: previous.map(|id| self.state(id)),
+            after: current.map(|id| self.state(id)),
+        })
+    }
+
+    fn state(&self, security_id: u32) -> SecurityState {
+        SecurityState {
+            security_id,
+            owner_sid: self.owners.by_id(security_id).map(|owner| owner.sid.clone()),
+            dacl: self.owners.dacl(security_id).cloned(),
+        }
+    }
+}
+
+/// Attribute flags and timestamps of the files on the volume, kept current
+/// as changes come in so a BASIC_INFO_CHANGE can be reported with what the
+/// file had before it
+struct BasicInfoTracker {
+    /// $STANDARD_INFORMATION of every file as last seen, by record number
+    infos: HashMap<u64, StandardInformation>,
+}
+
+impl BasicInfoTracker {
+    fn load(volume: &str) -> Result<Self> {
+        let mft = mft::open_mft(volume)?;
+
+        eprintln!("Indexing attributes and timestamps for --basic-info...");
+        let mut infos = HashMap::new();
+        mft::iterate_files(&mft, |file| {
+            file.attributes(|attr| {
+                if attr.header.type_id == raw::ATTR_STANDARD_INFORMATION {
+                    if let Some(info) = raw::standard_information(attr.data()) {
+                        infos.insert(file.number(), info);
+                    }
+                }
+            });
+        });
+        eprintln!("Indexed {} files", infos.len());
+
+        Ok(BasicInfoTracker { infos })
+    }
+
+    /// Records the attributes and timestamps a file has now (`None` once it
+    /// is deleted) and, when `reason` includes BASIC_INFO_CHANGE, returns
+    /// them before and after along with the fields that differ. A new file
+    /// has nothing before.
+    fn observe(
+        &mut self,
+        number: u64,
+        current: Option<StandardInformation>,
+        reason: u32,
+    ) -> Option<BasicInfoChange> {
+        let previous = match current {
+            Some(info) => self.infos.insert(number, info),
+            None => self.infos.remove(&number),
+        };
+        if reason & USN_REASON_BASIC_INFO_CHANGE == 0 {
+            return None;
+        }
+        let previous = previous.filter(|_| reason & USN_REASON_FILE_CREATE == 0);
+
+        let changed = match (&previous, &current) {
+            (Some(before), Some(after)) => [
+                ("file_attributes", before.file_attributes != after.file_attributes),
+                ("created", before.created != after.created),
+                ("modified", before.modified != after.modified),
+                ("mft_modified", before.mft_modified != after.mft_modified),
+                ("accessed", before.accessed != after.accessed),
+            ]
+            .into_iter()
+            .filter_map(|(field, differs)| differs.then_some(field))
+            .collect(),
+            _ => Vec::new(),
+        };
+        Some(BasicInfoChange {
+            before: previous.as_ref().map(BasicInfoState::new),
+            after: current.as_ref().map(BasicInfoState::new),
+            changed,
+        })
+    }
+}
+
+/// The attribute flags and timestamps in the $STANDARD_INFORMATION of a raw
+/// file record
+fn standard_information(record: &[u8]) -> Option<StandardInformation> {
+    raw::find_attribute(record, raw::ATTR_STANDARD_INFORMATION).and_then(raw::standard_information)
+}
+
+/// The security ID in the $STANDARD_INFORMATION of a raw file record
+fn security_id(record: &[u8]) -> Option<u32> {
+    raw::find_attribute(record, raw::ATTR_STANDARD_INFORMATION)
+        .and_then(raw::standard_information_security_id)
+}
+
+/// The USN of the last journal record written for the file, from the
+/// $STANDARD_INFORMATION of a raw file record
+fn record_usn(record: &[u8]) -> Option<u64> {
+    raw::find_attribute(record, raw::ATTR_STANDARD_INFORMATION).and_then(raw::standard_information_usn)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn basic_info_changes_list_the_fields_that_differ() {
+        let mut tracker = BasicInfoTracker { infos: HashMap::new() };
+        let before = StandardInformation { file_attributes: 0x20, modified: 1, ..Default::default() };
+        tracker.infos.insert(7, before);
+
+        let after = StandardInformation { file_attributes: 0x21, ..before };
+        assert!(tracker.observe(7, Some(after), USN_REASON_SECURITY_CHANGE).is_none());
+        let after = StandardInformation { modified: 2, ..after };
+        let change = tracker.observe(7, Some(after), USN_REASON_BASIC_INFO_CHANGE).unwrap();
+        assert_eq!(change.changed, ["modified"]);
+        assert_eq!(change.after.unwrap().file_attribute_names, ["read_only", "archive"]);
+
+        let created = USN_REASON_FILE_CREATE | USN_REASON_BASIC_INFO_CHANGE;
+        let change = tracker.observe(7, Some(before), created).unwrap();
+        assert!(change.before.is_none() && change.changed.is_empty());
+    }
+}
@@ -0,0 +1,768 @@
+use anyhow::{bail, Context, Result};
+use std::fs::File;
+use std::io::{self, BufWriter, IsTerminal, Write};
+use std::path::PathBuf;
+use serde::ser::{Error as _, SerializeMap, Serializer};
+use serde::Serialize;
+use serde_json::Value;
+use std::sync::OnceLock;
+
+use crate::OutputFormat;
+
+/// Records that can be rendered as a human-readable table
+pub trait TableRow {
+    fn table_header() -> Vec<&'static str>;
+    fn table_row(&self) -> Vec<String>;
+
+    /// Minimum column widths used when rows are printed one at a time and
+    /// can't be measured up front
+    fn streaming_widths() -> Vec<usize>;
+}
+
+/// How byte counts are rendered in human-oriented formats (table, CSV).
+/// Serialized formats such as JSON always carry raw bytes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SizeUnits {
+    Bytes,
+    /// Powers of 1024 (KiB, MiB, ...)
+    Binary,
+    /// Powers of 1000 (kB, MB, ...)
+    Si,
+}
+
+static SIZE_UNITS: OnceLock<SizeUnits> = OnceLock::new();
+
+pub fn set_size_units(units: SizeUnits) {
+    let _ = SIZE_UNITS.set(units);
+}
+
+pub fn format_size(bytes: u64) -> String {
+    let (base, units) = match SIZE_UNITS.get().copied().unwrap_or(SizeUnits::Bytes) {
+        SizeUnits::Bytes => return bytes.to_string(),
+        SizeUnits::Binary => (1024.0, ["B", "KiB", "MiB", "GiB", "TiB", "PiB", "EiB"]),
+        SizeUnits::Si => (1000.0, ["B", "kB", "MB", "GB", "TB", "PB", "EB"]),
+    };
+    
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= base && unit < units.len() - 1 {
+        value /= base;
+        unit += 1;
+    }
+    
+    if unit == 0 {
+        format!("{} {}", bytes, units[0])
+    } else {
+        format!("{:.1} {}", value, units[unit])
+    }
+}
+
+static EXTENDED_PATHS: OnceLock<bool> = OnceLock::new();
+
+pub fn set_extended_paths(enabled: bool) {
+    let _ = EXTENDED_PATHS.set(enabled);
+}
+
+/// Renders a path for output. With `--extended-paths`, drive paths get the
+/// `\\?\` prefix so they can be reused verbatim in scripts even when they
+/// exceed MAX_PATH, end in dots or spaces, or contain device names like CON.
+pub fn display_path(path: &std::path::Path) -> String {
+    let path = path.to_string_lossy();
+    if !EXTENDED_PATHS.get().copied().unwrap_or(false) {
+        return path.into_owned();
+    }
+    
+    match path.as_bytes() {
+        [letter, b':', b'\\', ..] if letter.is_ascii_alphabetic() => format!("\\\\?\\{}", path),
+        _ => path.into_owned(),
+    }
+}
+
+static FILETIME: OnceLock<bool> = OnceLock::new();
+
+pub fn set_filetime(enabled: bool) {
+    let _ = FILETIME.set(enabled);
+}
+
+/// Offset between the FILETIME epoch (1601-01-01) and the Unix epoch, in 100ns ticks
+const FILETIME_UNIX_EPOCH: i128 = 116_444_736_000_000_000;
+
+/// Raw FILETIME value (100ns ticks since 1601) of a timestamp, when
+/// `--filetime` is enabled
+pub fn filetime(time: time::OffsetDateTime) -> Option<u64> {
+    if !FILETIME.get().copied().unwrap_or(false) {
+        return None;
+    }
+    u64::try_from(time.unix_timestamp_nanos() / 100 + FILETIME_UNIX_EPOCH).ok()
+}
+
+/// Timestamp of a raw FILETIME value; zero means unset
+pub fn from_filetime(filetime: u64) -> Option<time::OffsetDateTime> {
+    if filetime == 0 {
+        return None;
+    }
+    let nanos = (filetime as i128 - FILETIME_UNIX_EPOCH) * 100;
+    time::OffsetDateTime::from_unix_timestamp_nanos(nanos).ok()
+}
+
+static JSON_WARNINGS: OnceLock<bool> = OnceLock::new();
+
+pub fn set_json_warnings(enabled: bool) {
+    let _ = JSON_WARNINGS.set(enabled);
+}
+
+/// A non-fatal problem, reported on stderr apart from the data on stdout
+#[derive(Debug, Serialize)]
+pub struct Warning {
+    /// Stable category for consumers to group by, e.g. `encoding` or `journal_lag`
+    pub warning: &'static str,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub record_number: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+}
+
+impl Warning {
+    pub fn new(warning: &'static str, message: impl Into<String>) -> Self {
+        Warning {
+            warning,
+            message: message.into(),
+            record_number: None,
+            path: None,
+        }
+    }
+
+    pub fn record(mut self, record_number: u64, path: &str) -> Self {
+        self.record_number = Some(record_number);
+        self.path = Some(path.to_string());
+        self
+    }
+
+    /// Attaches a record number when there is no path to go with it
+    pub fn record_number(mut self, record_number: u64) -> Self {
+        self.record_number = Some(record_number);
+        self
+    }
+
+    /// Prints the warning as a `Warning: ...` line, or as a JSON line with
+    /// `--json-warnings`
+    pub fn emit(&self) {
+        if JSON_WARNINGS.get().copied().unwrap_or(false) {
+            if let Ok(json) = to_json(self) {
+                eprintln!("{}", json);
+                return;
+            }
+        }
+        match (&self.path, self.record_number) {
+            (Some(path), Some(number)) => {
+                eprintln!("Warning: {} (record {}, {})", self.message, number, path)
+            }
+            (None, Some(number)) => eprintln!("Warning: {} (record {})", self.message, number),
+            _ => eprintln!("Warning: {}", self.message),
+        }
+    }
+}
+
+/// Naming convention for field names in JSON output and CSV headers.
+/// Bincode and MessagePack are written positionally and carry no names.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FieldCase {
+    Snake,
+    Camel,
+    Pascal,
+}
+
+impl std::str::FromStr for FieldCase {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "snake" | "snake_case" => Ok(FieldCase::Snake),
+            "camel" | "camelcase" => Ok(FieldCase::Camel),
+            "pascal" | "pascalcase" => Ok(FieldCase::Pascal),
+            _ => Err(format!("Invalid field case: {}", s)),
+        }
+    }
+}
+
+static FIELD_CASE: OnceLock<FieldCase> = OnceLock::new();
+
+pub fn set_field_case(case: FieldCase) {
+    let _ = FIELD_CASE.set(case);
+}
+
+fn field_case() -> FieldCase {
+    FIELD_CASE.get().copied().unwrap_or(FieldCase::Snake)
+}
+
+/// Converts a snake_case field name to the selected case
+pub fn field_name(name: &str) -> String {
+    let case = field_case();
+    if case == FieldCase::Snake {
+        return name.to_string();
+    }
+    
+    let mut renamed = String::with_capacity(name.len());
+    let mut upper = case == FieldCase::Pascal;
+    for c in name.chars() {
+        if c == '_' {
+            upper = true;
+        } else if upper {
+            renamed.push(c.to_ascii_uppercase());
+            upper = false;
+        } else {
+            renamed.push(c);
+        }
+    }
+    renamed
+}
+
+fn rename_keys(value: serde_json::Value) -> serde_json::Value {
+    match value {
+        serde_json::Value::Object(map) => map
+            .into_iter()
+            .map(|(key, value)| (field_name(&key), rename_keys(value)))
+            .collect(),
+        serde_json::Value::Array(items) => items.into_iter().map(rename_keys).collect(),
+        other => other,
+    }
+}
+
+/// Serializes to JSON with field names in the selected case
+pub fn to_json<T: Serialize + ?Sized>(value: &T) -> serde_json::Result<String> {
+    if field_case() == FieldCase::Snake {
+        return serde_json::to_string(value);
+    }
+    serde_json::to_string(&rename_keys(serde_json::to_value(value)?))
+}
+
+/// Like `to_json`, pretty-printed
+pub fn to_json_pretty<T: Serialize + ?Sized>(value: &T) -> serde_json::Result<String> {
+    if field_case() == FieldCase::Snake {
+        return serde_json::to_string_pretty(value);
+    }
+    serde_json::to_string_pretty(&rename_keys(serde_json::to_value(value)?))
+}
+
+static CSV_TYPED_HEADER: OnceLock<bool> = OnceLock::new();
+
+pub fn set_csv_typed_header(enabled: bool) {
+    let _ = CSV_TYPED_HEADER.set(enabled);
+}
+
+/// Type of a CSV column, given in the second header row with
+/// `--csv-typed-header`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ColumnType {
+    Int,
+    Float,
+    Bool,
+    /// RFC 3339
+    Datetime,
+    String,
+    /// A `size` column: bytes, or text when --human-sizes or --si renders
+    /// it with units
+    Size,
+}
+
+impl ColumnType {
+    fn name(self) -> &'static str {
+        let sizes_in_bytes = SIZE_UNITS.get().copied().unwrap_or(SizeUnits::Bytes) == SizeUnits::Bytes;
+        match self {
+            ColumnType::Int => "int",
+            ColumnType::Size if sizes_in_bytes => "int",
+            ColumnType::Float => "float",
+            ColumnType::Bool => "bool",
+            ColumnType::Datetime => "datetime",
+            ColumnType::String | ColumnType::Size => "string",
+        }
+    }
+}
+
+/// A field of a record type, as listed in its `FIELDS`: the name accepted
+/// by --fields and the type of its CSV column
+pub type Field = (&'static str, ColumnType);
+
+/// CSV header line with column names in the selected case. With
+/// `--csv-typed-header`, a second line gives the type of each column.
+fn csv_header(names: &[&str], types: &[ColumnType]) -> String {
+    let header = names.iter().map(|c| field_name(c)).collect::<Vec<_>>().join(",");
+    if !CSV_TYPED_HEADER.get().copied().unwrap_or(false) {
+        return header;
+    }
+
+    let types: Vec<&str> = types.iter().map(|t| t.name()).collect();
+    format!("{}\n{}", header, types.join(","))
+}
+
+pub fn escape_csv(s: &str) -> String {
+    // Quote leading/trailing spaces too, which are legal at the end of NTFS names
+    if s.contains([',', '"', '\n', '\r']) || s.starts_with(' ') || s.ends_with(' ') {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+/// Fields chosen with `--fields`, in the order given. No fields means the
+/// whole record in its usual layout.
+#[derive(Clone, Debug, Default)]
+pub struct Fields {
+    names: Vec<&'static str>,
+    types: Vec<ColumnType>,
+}
+
+impl Fields {
+    /// Resolves requested names against the fields of a record type. Names
+    /// may be given in snake case or in the selected field case.
+    pub fn new<S: AsRef<str>>(requested: &[S], available: &[Field]) -> Result<Self> {
+        let (names, types) = requested
+            .iter()
+            .map(|name| {
+                let name = name.as_ref().trim();
+                available
+                    .iter()
+                    .copied()
+                    .find(|(field, _)| *field == name || field_name(field) == name)
+                    .context(format!(
+                        "Unknown field '{}' (available: {})",
+                        name,
+                        available.iter().map(|(field, _)| *field).collect::<Vec<_>>().join(", ")
+                    ))
+            })
+            .collect::<Result<Vec<_>>>()?
+            .into_iter()
+            .unzip();
+        Ok(Fields { names, types })
+    }
+
+    /// Every field of a record type, in its usual order
+    fn all_of(available: &[Field]) -> Self {
+        let (names, types) = available.iter().copied().unzip();
+        Fields { names, types }
+    }
+
+    pub fn is_all(&self) -> bool {
+        self.names.is_empty()
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.names.contains(&name)
+    }
+
+    /// Wraps a record so that serializing it only writes the chosen fields
+    pub fn select<'a, T>(&'a self, record: &'a T) -> Selected<'a, T> {
+        Selected { record, fields: self }
+    }
+
+    /// The chosen fields of a record, in order; missing ones are null
+    pub fn values<T: Serialize>(&self, record: &T) -> serde_json::Result<Vec<Value>> {
+        let mut value = serde_json::to_value(record)?;
+        Ok(self
+            .names
+            .iter()
+            .map(|name| value.get_mut(*name).map(Value::take).unwrap_or(Value::Null))
+            .collect())
+    }
+
+    /// Text of each chosen field for tables and CSV; sizes follow
+    /// --human-sizes like in the full layouts
+    pub fn cells<T: Serialize>(&self, record: &T) -> serde_json::Result<Vec<String>> {
+        Ok(self
+            .names
+            .iter()
+            .zip(self.values(record)?)
+            .map(|(name, value)| match value {
+                Value::Null => String::new(),
+                Value::String(s) => s,
+                Value::Number(n) if *name == "size" => n.as_u64().map(format_size).unwrap_or_default(),
+                other => other.to_string(),
+            })
+            .collect())
+    }
+
+    pub fn csv_header(&self) -> String {
+        csv_header(&self.names, &self.types)
+    }
+
+    pub fn csv_row<T: Serialize>(&self, record: &T) -> serde_json::Result<String> {
+        let cells: Vec<String> = self.cells(record)?.iter().map(|c| escape_csv(c)).collect();
+        Ok(cells.join(","))
+    }
+
+    fn table_header(&self) -> Vec<String> {
+        self.names.iter().map(|name| name.to_uppercase()).collect()
+    }
+
+    /// Column widths for tables printed one row at a time
+    fn streaming_widths(&self) -> Vec<usize> {
+        self.names.iter().map(|name| name.len().max(12)).collect()
+    }
+}
+
+/// A record seen through `Fields`. With all fields it serializes exactly like
+/// the record; otherwise as a map of the chosen fields. Nulls are written as
+/// `None`, so bincode output stays decodable field by field.
+pub struct Selected<'a, T> {
+    record: &'a T,
+    fields: &'a Fields,
+}
+
+impl<T: Serialize> Serialize for Selected<'_, T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        if self.fields.is_all() {
+            return self.record.serialize(serializer);
+        }
+
+        let values = self.fields.values(self.record).map_err(S::Error::custom)?;
+        let mut map = serializer.serialize_map(Some(values.len()))?;
+        for (name, value) in self.fields.names.iter().zip(&values) {
+            let value = if value.is_null() { None } else { Some(value) };
+            map.serialize_entry(name, &value)?;
+        }
+        map.end()
+    }
+}
+
+/// Picks the output format when none was given: a table for interactive
+/// terminals and JSON Lines when stdout is piped or redirected
+pub fn resolve_format(output: Option<OutputFormat>) -> OutputFormat {
+    output.unwrap_or_else(|| {
+        if std::io::stdout().is_terminal() {
+            OutputFormat::Table
+        } else {
+            OutputFormat::Jsonl
+        }
+    })
+}
+
+/// Extra destination written from the same scan or monitoring pass,
+/// given on the command line as FORMAT:PATH (e.g. `csv:files.csv`)
+#[derive(Clone, Debug)]
+pub struct TeeSink {
+    pub format: OutputFormat,
+    pub path: PathBuf,
+}
+
+impl std::str::FromStr for TeeSink {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (format, path) = s
+            .split_once(':')
+            .ok_or_else(|| format!("Invalid sink (expected FORMAT:PATH): {}", s))?;
+        if path.is_empty() {
+            return Err(format!("Invalid sink (missing path): {}", s));
+        }
+
+        Ok(TeeSink {
+            format: format.parse()?,
+            path: PathBuf::from(path),
+        })
+    }
+}
+
+/// An open output destination along with the format written to it
+pub struct Sink {
+    pub format: OutputFormat,
+    /// Number of records written so far, used to emit headers once
+    pub written: usize,
+    writer: Box<dyn Write>,
+}
+
+impl Sink {
+    pub fn writer(&mut self) -> &mut dyn Write {
+        &mut self.writer
+    }
+}
+
+/// Opens stdout with the main format followed by every tee file
+pub fn open_sinks(format: OutputFormat, tee: &[TeeSink]) -> Result<Vec<Sink>> {
+    let mut sinks = vec![Sink {
+        format,
+        written: 0,
+        // Writers flush explicitly wherever output must appear promptly
+        writer: Box::new(BufWriter::new(io::stdout())),
+    }];
+
+    for sink in tee {
+        let file = File::create(&sink.path)
+            .context(format!("Failed to create output file {}", sink.path.display()))?;
+        sinks.push(Sink {
+            format: sink.format,
+            written: 0,
+            writer: Box::new(BufWriter::new(file)),
+        });
+    }
+
+    Ok(sinks)
+}
+
+/// Writes a complete result set of a report command in any output format.
+/// `available` is the full field list of `T`, used for CSV columns when no
+/// fields were chosen; json-tree is written as plain JSON.
+pub fn write_report<T: Serialize + TableRow>(
+    w: &mut dyn Write,
+    rows: &[T],
+    format: OutputFormat,
+    fields: &Fields,
+    available: &[Field],
+) -> Result<()> {
+    let selected: Vec<_> = rows.iter().map(|row| fields.select(row)).collect();
+    match format {
+        OutputFormat::Json | OutputFormat::JsonTree => {
+            writeln!(w, "{}", to_json(&selected)?)?;
+        }
+        OutputFormat::JsonPretty => {
+            writeln!(w, "{}", to_json_pretty(&selected)?)?;
+        }
+        OutputFormat::Jsonl => {
+            for row in &selected {
+                writeln!(w, "{}", to_json(row)?)?;
+            }
+        }
+        OutputFormat::Bincode => {
+            bincode::serialize_into(&mut *w, &selected)?;
+        }
+        OutputFormat::Msgpack => {
+            rmp_serde::encode::write(&mut *w, &selected)?;
+        }
+        OutputFormat::Csv => {
+            let columns = if fields.is_all() {
+                Fields::all_of(available)
+            } else {
+                fields.clone()
+            };
+            writeln!(w, "{}", columns.csv_header())?;
+            for row in rows {
+                writeln!(w, "{}", columns.csv_row(row)?)?;
+            }
+        }
+        OutputFormat::Cdc => bail!("cdc output is only supported by journal and journal-query"),
+        OutputFormat::Dot => bail!("dot output is only supported by tree"),
+        OutputFormat::Table if fields.is_all() => {
+            write_table(w, rows)?;
+        }
+        OutputFormat::Table => {
+            write_selected_table(w, fields, rows)?;
+        }
+    }
+    w.flush()?;
+    Ok(())
+}
+
+/// Writes rows whose columns are only known at run time, such as the result
+/// of a SQL query, in any output format that doesn't need a record type.
+/// CSV column types are taken from the values of each column.
+pub fn write_columns(w: &mut dyn Write, columns: &[String], rows: &[Vec<Value>], format: OutputFormat) -> Result<()> {
+    let records: Vec<_> = rows.iter().map(|values| ColumnRow { columns, values }).collect();
+    match format {
+        OutputFormat::Json | OutputFormat::JsonTree => {
+            writeln!(w, "{}", to_json(&records)?)?;
+        }
+        OutputFormat::JsonPretty => {
+            writeln!(w, "{}", to_json_pretty(&records)?)?;
+        }
+        OutputFormat::Jsonl => {
+            for record in &records {
+                writeln!(w, "{}", to_json(record)?)?;
+            }
+        }
+        OutputFormat::Bincode => {
+            bincode::serialize_into(&mut *w, &records)?;
+        }
+        OutputFormat::Msgpack => {
+            rmp_serde::encode::write(&mut *w, &records)?;
+        }
+        OutputFormat::Csv => {
+            let names: Vec<&str> = columns.iter().map(String::as_str).collect();
+            let types: Vec<ColumnType> = (0..columns.len())
+                .map(|i| value_type(rows.iter().map(|row| &row[i])))
+                .collect();
+            writeln!(w, "{}", csv_header(&names, &types))?;
+            for row in rows {
+                let cells: Vec<String> = row.iter().map(|value| escape_csv(&cell(value))).collect();
+                writeln!(w, "{}", cells.join(","))?;
+            }
+        }
+        OutputFormat::Cdc => bail!("cdc output is only supported by journal and journal-query"),
+        OutputFormat::Dot => bail!("dot output is only supported by tree"),
+        OutputFormat::Table => {
+            let header: Vec<String> = columns.iter().map(|name| name.to_uppercase()).collect();
+            let cells: Vec<Vec<String>> = rows.iter().map(|row| row.iter().map(cell).collect()).collect();
+            write_rows(w, &header, &cells)?;
+        }
+    }
+    w.flush()?;
+    Ok(())
+}
+
+/// A row of `write_columns`, serialized as a map of its columns. Nulls are
+/// written as `None`, like in `Selected`.
+struct ColumnRow<'a> {
+    columns: &'a [String],
+    values: &'a [Value],
+}
+
+impl Serialize for ColumnRow<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(self.columns.len()))?;
+        for (name, value) in self.columns.iter().zip(self.values) {
+            let value = if value.is_null() { None } else { Some(value) };
+            map.serialize_entry(name, &value)?;
+        }
+        map.end()
+    }
+}
+
+/// Text of a value in tables and CSV
+fn cell(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+/// Column type of a set of values, ignoring nulls; mixed values are strings
+fn value_type<'a>(values: impl Iterator<Item = &'a Value>) -> ColumnType {
+    let mut column = None;
+    for value in values {
+        let kind = match value {
+            Value::Null => continue,
+            Value::Bool(_) => ColumnType::Bool,
+            Value::Number(n) if n.is_f64() => ColumnType::Float,
+            Value::Number(_) => ColumnType::Int,
+            _ => ColumnType::String,
+        };
+        column = match (column, kind) {
+            (None, kind) => Some(kind),
+            (Some(ColumnType::Int), ColumnType::Float) | (Some(ColumnType::Float), ColumnType::Int) => {
+                Some(ColumnType::Float)
+            }
+            (Some(column), kind) if column == kind => Some(column),
+            _ => Some(ColumnType::String),
+        };
+    }
+    column.unwrap_or(ColumnType::String)
+}
+
+pub fn write_table<T: TableRow>(w: &mut dyn Write, rows: &[T]) -> io::Result<()> {
+    let cells: Vec<Vec<String>> = rows.iter().map(|row| row.table_row()).collect();
+    write_rows(w, &T::table_header(), &cells)
+}
+
+/// Table of the chosen fields of each record
+pub fn write_selected_table<T: Serialize>(w: &mut dyn Write, fields: &Fields, rows: &[T]) -> Result<()> {
+    let cells = rows.iter().map(|row| fields.cells(row)).collect::<serde_json::Result<Vec<_>>>()?;
+    write_rows(w, &fields.table_header(), &cells)?;
+    Ok(())
+}
+
+pub fn write_selected_table_header(w: &mut dyn Write, fields: &Fields) -> io::Result<()> {
+    write_line(w, &fields.table_header(), &fields.streaming_widths())
+}
+
+pub fn write_selected_table_line<T: Serialize>(w: &mut dyn Write, fields: &Fields, row: &T) -> Result<()> {
+    write_line(w, &fields.cells(row)?, &fields.streaming_widths())?;
+    Ok(())
+}
+
+fn write_rows<S: AsRef<str>>(w: &mut dyn Write, header: &[S], cells: &[Vec<String>]) -> io::Result<()> {
+    let mut widths: Vec<usize> = header.iter().map(|h| h.as_ref().len()).collect();
+    for row in cells {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    write_line(w, header, &widths)?;
+    for row in cells {
+        write_line(w, row, &widths)?;
+    }
+    Ok(())
+}
+
+pub fn write_table_header<T: TableRow>(w: &mut dyn Write) -> io::Result<()> {
+    write_line(w, &T::table_header(), &T::streaming_widths())
+}
+
+pub fn write_table_line<T: TableRow>(w: &mut dyn Write, row: &T) -> io::Result<()> {
+    write_line(w, &row.table_row(), &T::streaming_widths())
+}
+
+fn write_line<S: AsRef<str>>(w: &mut dyn Write, cells: &[S], widths: &[usize]) -> io::Result<()> {
+    let last = cells.len().saturating_sub(1);
+    let line: Vec<String> = cells
+        .iter()
+        .enumerate()
+        .map(|(i, cell)| {
+            let cell = cell.as_ref();
+            let width = widths.get(i).copied().unwrap_or(0);
+            // Right-align numbers and sizes; don't pad the last column to avoid trailing spaces
+            if i == last {
+                cell.to_string()
+            } else if cell.starts_with(|c: char| c.is_ascii_digit())
+                && cell.chars().all(|c| c.is_ascii_alphanumeric() || c == '.' || c == ' ')
+            {
+                format!("{:>width$}", cell)
+            } else {
+                format!("{:<width$}", cell)
+            }
+        })
+        .collect();
+    writeln!(w, "{}", line.join("  "))
+}
+
+/// Writes `bytes` as a hex/ASCII listing, 16 bytes per line: the offset, the
+/// bytes in hex and their printable ASCII characters (others shown as `.`)
+pub fn write_hex_dump(w: &mut dyn Write, bytes: &[u8]) -> io::Result<()> {
+    write_hex_lines(w, bytes, 0)?;
+    writeln!(w, "{:08x}", bytes.len())
+}
+
+/// The lines of a hex/ASCII listing of `bytes`, with offsets counted from `base`
+pub fn write_hex_lines(w: &mut dyn Write, bytes: &[u8], base: usize) -> io::Result<()> {
+    for (line, chunk) in bytes.chunks(16).enumerate() {
+        let hex: Vec<String> = chunk.iter().map(|b| format!("{:02x}", b)).collect();
+        let (left, right) = hex.split_at(hex.len().min(8));
+        let ascii: String = chunk
+            .iter()
+            .map(|&b| if b.is_ascii_graphic() || b == b' ' { b as char } else { '.' })
+            .collect();
+        writeln!(
+            w,
+            "{:08x}  {:<23}  {:<23}  |{}|",
+            base + line * 16,
+            left.join(" "),
+            right.join(" "),
+            ascii
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fields_carry_the_column_types_of_their_record() {
+        let fields = Fields::new(&["dirty", "total_clusters", "size"], crate::volume::VolumeInfo::FIELDS).unwrap();
+        assert_eq!(fields.types, [ColumnType::Bool, ColumnType::Int, ColumnType::Size]);
+        let fields = Fields::new(&[" si_created "], crate::timestomp::TimestompedFile::FIELDS).unwrap();
+        assert_eq!(fields.types, [ColumnType::Datetime]);
+        assert!(Fields::new(&["bogus"], crate::mft::FileRecord::FIELDS).is_err());
+    }
+
+    #[test]
+    fn column_types_follow_the_values_of_a_column() {
+        use serde_json::json;
+        let column_type = |values: &[Value]| value_type(values.iter());
+        assert_eq!(column_type(&[json!(1), Value::Null, json!(2)]), ColumnType::Int);
+        assert_eq!(column_type(&[json!(1), json!(2.5)]), ColumnType::Float);
+        assert_eq!(column_type(&[json!(1), json!("a")]), ColumnType::String);
+        assert_eq!(column_type(&[Value::Null]), ColumnType::String);
+    }
+}
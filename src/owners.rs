@@ -0,0 +1,282 @@
+use anyhow::{Context, Result};
+use ntfs_reader::file::NtfsFile;
+use serde::Serialize;
+use std::collections::HashMap;
+
+use crate::mft;
+use crate::output::Warning;
+use crate::raw::{self, RawVolume};
+
+/// Record number of $Secure, which holds the volume's security descriptors
+const SECURE_RECORD: u64 = 9;
+
+/// $SDS is written in blocks of this size, each followed by a mirror copy
+const SDS_BLOCK: usize = 256 * 1024;
+
+/// Size of the header in front of each descriptor in $SDS: hash, security
+/// ID, offset of the entry and its length
+const SDS_ENTRY_HEADER: usize = 20;
+
+/// Owner of a security descriptor
+#[derive(Debug, Clone)]
+pub struct Owner {
+    /// SID in its string form, e.g. S-1-5-32-544
+    pub sid: String,
+    /// DOMAIN\account, when resolved and the SID is known to this machine
+    pub name: Option<String>,
+}
+
+/// Security descriptor control flags
+const SE_DACL_PRESENT: u16 = 0x0004;
+const SE_DACL_PROTECTED: u16 = 0x1000;
+
+/// ACE types and flags counted in a DACL summary
+const ACCESS_ALLOWED_ACE: u8 = 0x00;
+const ACCESS_DENIED_ACE: u8 = 0x01;
+const INHERITED_ACE: u8 = 0x10;
+
+/// The shape of a descriptor's DACL, enough to tell what kind of change a
+/// permission change was without decoding every access mask
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct DaclSummary {
+    /// No DACL at all grants everyone full access
+    pub present: bool,
+    /// Inheritance from the parent directory is blocked
+    pub protected: bool,
+    pub aces: u16,
+    pub allow: u16,
+    pub deny: u16,
+    /// ACEs inherited from a parent rather than set on the file itself
+    pub inherited: u16,
+}
+
+/// Owners of every security descriptor in $Secure, by security ID
+pub struct Owners {
+    owners: HashMap<u32, Owner>,
+    dacls: HashMap<u32, DaclSummary>,
+}
+
+impl Owners {
+    /// Reads the $SDS stream of $Secure from the raw volume. With `resolve`,
+    /// each distinct SID is also looked up as an account name once.
+    pub fn load(volume: &str, resolve: bool) -> Result<Self> {
+        let mut raw = RawVolume::open(&mft::normalize_volume_path(volume))?;
+        let runs = raw.mft_runs()?;
+        let record = raw
+            .read_file_record(&runs, SECURE_RECORD)
+            .context("Failed to read the $Secure record")?;
+        let attr = raw::find_named_attribute(&record, raw::ATTR_DATA, "$SDS")
+            .context("$Secure has no $SDS stream")?;
+
+        eprintln!("Reading security descriptors...");
+        let mut sids = HashMap::new();
+        let mut dacls = HashMap::new();
+        let mut pending = Vec::new();
+        let mut block = 0;
+        raw.read_value(attr, |chunk| {
+            pending.extend_from_slice(chunk);
+            while pending.len() >= SDS_BLOCK {
+                // Odd blocks mirror the block before them
+                if block % 2 == 0 {
+                    read_block(&pending[..SDS_BLOCK], &mut sids, &mut dacls);
+                }
+                pending.drain(..SDS_BLOCK);
+                block += 1;
+            }
+            Ok(())
+        })?;
+        if block % 2 == 0 {
+            read_block(&pending, &mut sids, &mut dacls);
+        }
+
+        if resolve && !cfg!(windows) {
+            let message = "account names can only be resolved on Windows; only SIDs are reported";
+            Warning::new("owners", message).emit();
+        }
+        let mut names: HashMap<Vec<u8>, Option<String>> = HashMap::new();
+        let owners = sids
+            .into_iter()
+            .map(|(security_id, sid)| {
+                let name = if resolve {
+                    names.entry(sid.clone()).or_insert_with(|| imp::account_name(&sid)).clone()
+                } else {
+                    None
+                };
+                (security_id, Owner { sid: sid_string(&sid), name })
+            })
+            .collect::<HashMap<_, _>>();
+        eprintln!("Read {} security descriptors", owners.len());
+
+        Ok(Owners { owners, dacls })
+    }
+
+    /// Owner of the file, found through the security ID in its
+    /// $STANDARD_INFORMATION
+    pub fn of(&self, file: &NtfsFile) -> Option<&Owner> {
+        self.owners.get(&security_id(file)?)
+    }
+
+    /// Owner of the descriptor with this security ID
+    pub fn by_id(&self, security_id: u32) -> Option<&Owner> {
+        self.owners.get(&security_id)
+    }
+
+    /// DACL summary of the descriptor with this security ID
+    pub fn dacl(&self, security_id: u32) -> Option<&DaclSummary> {
+        self.dacls.get(&security_id)
+    }
+
+    /// Whether the descriptor with this security ID was on the volume when
+    /// $SDS was read; descriptors added since need a reload
+    pub fn contains(&self, security_id: u32) -> bool {
+        self.owners.contains_key(&security_id) || self.dacls.contains_key(&security_id)
+    }
+}
+
+/// The security ID in a file's $STANDARD_INFORMATION, which selects its
+/// descriptor in $Secure
+pub fn security_id(file: &NtfsFile) -> Option<u32> {
+    let mut security_id = None;
+    file.attributes(|attr| {
+        if attr.header.type_id == raw::ATTR_STANDARD_INFORMATION {
+            security_id = raw::standard_information_security_id(attr.data());
+        }
+    });
+    security_id
+}
+
+/// Adds the owner SID and DACL summary of every descriptor in one $SDS
+/// block. Entries are 16-byte aligned and never cross a block; an entry
+/// with a zero length ends the block's used part.
+fn read_block(block: &[u8], sids: &mut HashMap<u32, Vec<u8>>, dacls: &mut HashMap<u32, DaclSummary>) {
+    let mut offset = 0;
+    while offset + SDS_ENTRY_HEADER <= block.len() {
+        let (Some(security_id), Some(length)) = (raw::u32_at(block, offset + 4), raw::u32_at(block, offset + 16))
+        else {
+            break;
+        };
+        let length = length as usize;
+        if length < SDS_ENTRY_HEADER || offset + length > block.len() {
+            break;
+        }
+        let descriptor = &block[offset + SDS_ENTRY_HEADER..offset + length];
+        if let Some(sid) = owner_sid(descriptor) {
+            sids.insert(security_id, sid.to_vec());
+        }
+        if let Some(dacl) = dacl_summary(descriptor) {
+            dacls.insert(security_id, dacl);
+        }
+        offset += length.next_multiple_of(16);
+    }
+}
+
+/// The owner SID of a self-relative security descriptor
+fn owner_sid(descriptor: &[u8]) -> Option<&[u8]> {
+    if descriptor.len() < 20 {
+        return None;
+    }
+    let offset = raw::u32_at(descriptor, 4)? as usize;
+    let sid = descriptor.get(offset..)?.get(..8)?;
+    let length = 8 + sid[1] as usize * 4;
+    if offset == 0 || sid[0] != 1 {
+        return None;
+    }
+    descriptor.get(offset..offset + length)
+}
+
+/// Counts the ACEs of a self-relative security descriptor's DACL
+fn dacl_summary(descriptor: &[u8]) -> Option<DaclSummary> {
+    if descriptor.len() < 20 {
+        return None;
+    }
+    let control = raw::u16_at(descriptor, 2)?;
+    let mut summary = DaclSummary {
+        present: control & SE_DACL_PRESENT != 0,
+        protected: control & SE_DACL_PROTECTED != 0,
+        ..Default::default()
+    };
+    let offset = raw::u32_at(descriptor, 16)? as usize;
+    if !summary.present || offset == 0 {
+        summary.present = false;
+        return Some(summary);
+    }
+
+    let acl = descriptor.get(offset..)?;
+    let acl = acl.get(..(raw::u16_at(acl.get(..8)?, 2)? as usize).min(acl.len()))?;
+    let count = raw::u16_at(acl, 4)?;
+    let mut ace = 8;
+    for _ in 0..count {
+        let Some(header) = acl.get(ace..ace + 4) else {
+            break;
+        };
+        let size = raw::u16_at(header, 2).unwrap_or(0) as usize;
+        if size < 4 {
+            break;
+        }
+        summary.aces += 1;
+        match header[0] {
+            ACCESS_ALLOWED_ACE => summary.allow += 1,
+            ACCESS_DENIED_ACE => summary.deny += 1,
+            _ => {}
+        }
+        if header[1] & INHERITED_ACE != 0 {
+            summary.inherited += 1;
+        }
+        ace += size;
+    }
+    Some(summary)
+}
+
+/// S-1-<authority>-<subauthority>..., the authority in decimal
+fn sid_string(sid: &[u8]) -> String {
+    let authority = sid[2..8].iter().fold(0u64, |acc, &byte| acc << 8 | byte as u64);
+    let mut string = format!("S-{}-{}", sid[0], authority);
+    for sub in sid[8..].chunks_exact(4) {
+        string.push_str(&format!("-{}", u32::from_le_bytes([sub[0], sub[1], sub[2], sub[3]])));
+    }
+    string
+}
+
+#[cfg(windows)]
+mod imp {
+    use std::ptr;
+    use windows_sys::Win32::Security::{LookupAccountSidW, SID_NAME_USE};
+
+    /// DOMAIN\account of a binary SID, or `None` if this machine can't map it
+    pub fn account_name(sid: &[u8]) -> Option<String> {
+        let mut sid = sid.to_vec();
+        let mut name = [0u16; 256];
+        let mut domain = [0u16; 256];
+        let mut name_len = name.len() as u32;
+        let mut domain_len = domain.len() as u32;
+        let mut name_use: SID_NAME_USE = 0;
+
+        // SAFETY: the SID buffer holds a complete SID, and the lengths passed
+        // match the buffers they describe
+        let found = unsafe {
+            LookupAccountSidW(
+                ptr::null(),
+                sid.as_mut_ptr().cast(),
+                name.as_mut_ptr(),
+                &mut name_len,
+                domain.as_mut_ptr(),
+                &mut domain_len,
+                &mut name_use,
+            )
+        };
+        if found == 0 {
+            return None;
+        }
+
+        let name = String::from_utf16_lossy(&name[..name_len as usize]);
+        let domain = String::from_utf16_lossy(&domain[..domain_len as usize]);
+        Some(if domain.is_empty() { name } else { format!("{}\\{}", domain, name) })
+    }
+}
+
+#[cfg(not(windows))]
+mod imp {
+    pub fn account_name(_sid: &[u8]) -> Option<String> {
+        None
+    }
+}
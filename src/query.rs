@@ -0,0 +1,259 @@
+use anyhow::Result;
+use serde_json::Value;
+use std::path::Path;
+
+use crate::output;
+use crate::OutputFormat;
+
+/// Result of a query: column names and one row of values per result row
+#[cfg_attr(not(feature = "sql"), allow(dead_code))]
+struct ResultSet {
+    columns: Vec<String>,
+    rows: Vec<Vec<Value>>,
+}
+
+/// Loads a snapshot, or a Parquet file, as a table named `files` and prints
+/// the result of `sql` over it. The file itself is never modified.
+pub fn run(path: &Path, sql: &str, encrypt_key: Option<&[u8; 32]>, format: OutputFormat) -> Result<()> {
+    let result = imp::query(path, sql, encrypt_key)?;
+    eprintln!("{} rows", result.rows.len());
+    output::write_columns(&mut std::io::stdout().lock(), &result.columns, &result.rows, format)
+}
+
+#[cfg(feature = "sql")]
+mod imp {
+    use super::ResultSet;
+    use anyhow::{bail, Context, Result};
+    use datafusion::arrow::array::{Array, ArrayRef, AsArray, BooleanArray, RecordBatch, StringArray, UInt32Array, UInt64Array};
+    use datafusion::arrow::compute::cast;
+    use datafusion::arrow::datatypes::{DataType, Field, Float64Type, Int64Type, Schema, UInt64Type};
+    use datafusion::arrow::util::display::{ArrayFormatter, FormatOptions};
+    use datafusion::execution::context::SQLOptions;
+    use datafusion::prelude::{ParquetReadOptions, SessionContext};
+    use serde_json::Value;
+    use std::path::Path;
+    use std::sync::Arc;
+
+    use crate::mft::FileRecord;
+    use crate::snapshot;
+
+    pub fn query(path: &Path, sql: &str, encrypt_key: Option<&[u8; 32]>) -> Result<ResultSet> {
+        runtime()?.block_on(async {
+            let ctx = SessionContext::new();
+            if is_parquet(path) {
+                if encrypt_key.is_some() {
+                    bail!("--encrypt-key only applies to snapshots, not Parquet files");
+                }
+                let location = path.to_str().context("The Parquet file path isn't valid UTF-8")?;
+                ctx.register_parquet("files", location, ParquetReadOptions::default())
+                    .await
+                    .context(format!("Failed to read {}", path.display()))?;
+            } else {
+                let records = snapshot::load_snapshot(path, encrypt_key)?;
+                eprintln!("Loaded {} records from {}", records.len(), path.display());
+                ctx.register_batch("files", files_batch(&records)?)?;
+            }
+            execute(&ctx, sql).await
+        })
+    }
+
+    pub(super) fn runtime() -> Result<tokio::runtime::Runtime> {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .context("Failed to start the query engine")
+    }
+
+    fn is_parquet(path: &Path) -> bool {
+        path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("parquet"))
+    }
+
+    /// The `files` table of a snapshot. Timestamps are RFC 3339 text, which
+    /// compares in time order, e.g. `modified >= '2024-01-01'`.
+    pub(super) fn files_batch(records: &[FileRecord]) -> Result<RecordBatch> {
+        let schema = Schema::new(vec![
+            Field::new("name", DataType::Utf8, false),
+            Field::new("path", DataType::Utf8, false),
+            Field::new("is_directory", DataType::Boolean, false),
+            Field::new("deleted", DataType::Boolean, false),
+            Field::new("size", DataType::UInt64, false),
+            Field::new("created", DataType::Utf8, true),
+            Field::new("modified", DataType::Utf8, true),
+            Field::new("accessed", DataType::Utf8, true),
+            Field::new("file_attributes", DataType::UInt32, false),
+            Field::new("stream_count", DataType::UInt64, false),
+        ]);
+        let text = |value: fn(&FileRecord) -> Option<&str>| -> ArrayRef {
+            Arc::new(records.iter().map(value).collect::<StringArray>())
+        };
+        let columns: Vec<ArrayRef> = vec![
+            text(|record| Some(&record.name)),
+            text(|record| Some(&record.path)),
+            Arc::new(records.iter().map(|record| Some(record.is_directory)).collect::<BooleanArray>()),
+            Arc::new(records.iter().map(|record| Some(record.deleted)).collect::<BooleanArray>()),
+            Arc::new(UInt64Array::from_iter_values(records.iter().map(|record| record.size))),
+            text(|record| record.created.as_deref()),
+            text(|record| record.modified.as_deref()),
+            text(|record| record.accessed.as_deref()),
+            Arc::new(UInt32Array::from_iter_values(records.iter().map(|record| record.file_attributes))),
+            Arc::new(UInt64Array::from_iter_values(
+                records.iter().map(|record| record.alternate_data_streams.len() as u64),
+            )),
+        ];
+        Ok(RecordBatch::try_new(Arc::new(schema), columns)?)
+    }
+
+    /// Runs one read-only statement; anything after it, like a second
+    /// statement, is refused rather than ignored
+    pub(super) async fn execute(ctx: &SessionContext, sql: &str) -> Result<ResultSet> {
+        let options = SQLOptions::new()
+            .with_allow_ddl(false)
+            .with_allow_dml(false)
+            .with_allow_statements(false);
+        let frame = ctx.sql_with_options(sql, options).await.context("Invalid SQL query")?;
+        let columns = frame.schema().fields().iter().map(|field| field.name().clone()).collect();
+
+        let mut rows = Vec::new();
+        for batch in frame.collect().await.context("Failed to run the SQL query")? {
+            let values = batch.columns().iter().map(json_values).collect::<Result<Vec<_>>>()?;
+            rows.extend((0..batch.num_rows()).map(|i| values.iter().map(|column| column[i].clone()).collect()));
+        }
+        Ok(ResultSet { columns, rows })
+    }
+
+    /// JSON form of the values of a column: numbers and booleans as they
+    /// are, anything else (text, dates, decimals) as its text
+    pub(super) fn json_values(column: &ArrayRef) -> Result<Vec<Value>> {
+        let values = match column.data_type() {
+            DataType::Boolean => column.as_boolean().iter().map(|value| value.map_or(Value::Null, Value::from)).collect(),
+            DataType::UInt64 => column
+                .as_primitive::<UInt64Type>()
+                .iter()
+                .map(|value| value.map_or(Value::Null, Value::from))
+                .collect(),
+            data_type if data_type.is_integer() => cast(column, &DataType::Int64)?
+                .as_primitive::<Int64Type>()
+                .iter()
+                .map(|value| value.map_or(Value::Null, Value::from))
+                .collect(),
+            data_type if data_type.is_floating() => cast(column, &DataType::Float64)?
+                .as_primitive::<Float64Type>()
+                .iter()
+                .map(|value| value.and_then(serde_json::Number::from_f64).map_or(Value::Null, Value::Number))
+                .collect(),
+            _ => {
+                let formatter = ArrayFormatter::try_new(column.as_ref(), &FormatOptions::default())?;
+                (0..column.len())
+                    .map(|i| match column.is_null(i) {
+                        true => Value::Null,
+                        false => Value::String(formatter.value(i).to_string()),
+                    })
+                    .collect()
+            }
+        };
+        Ok(values)
+    }
+}
+
+#[cfg(not(feature = "sql"))]
+mod imp {
+    use super::ResultSet;
+    use anyhow::{bail, Result};
+    use std::path::Path;
+
+    pub fn query(_path: &Path, _sql: &str, _encrypt_key: Option<&[u8; 32]>) -> Result<ResultSet> {
+        bail!("query needs a build with --features sql")
+    }
+}
+
+#[cfg(all(test, feature = "sql"))]
+mod tests {
+    use super::imp::*;
+    use super::*;
+    use crate::mft::FileRecord;
+    use datafusion::arrow::array::{ArrayRef, Date32Array, Float32Array, Int32Array, StringArray};
+    use datafusion::prelude::SessionContext;
+    use serde_json::json;
+    use std::sync::Arc;
+
+    fn record(path: &str, size: u64, modified: &str) -> FileRecord {
+        serde_json::from_value(json!({
+            "name": path.rsplit('\\').next().unwrap(),
+            "path": path,
+            "is_directory": false,
+            "size": size,
+            "created": null,
+            "modified": modified,
+            "accessed": null,
+        }))
+        .unwrap()
+    }
+
+    fn query(sql: &str) -> Result<ResultSet> {
+        let records = [
+            record("C:\\big.iso", 4_000_000_000, "2024-03-01T00:00:00Z"),
+            record("C:\\notes.txt", 120, "2023-12-31T23:59:59Z"),
+            record("C:\\video.mkv", 2_000_000_000, "2024-01-15T10:00:00Z"),
+        ];
+        let ctx = SessionContext::new();
+        ctx.register_batch("files", files_batch(&records)?)?;
+        runtime()?.block_on(execute(&ctx, sql))
+    }
+
+    #[test]
+    fn snapshots_load_as_a_files_table() {
+        let batch = files_batch(&[record("C:\\a.txt", 5, "2024-01-01T00:00:00Z")]).unwrap();
+        let names: Vec<&str> = batch.schema_ref().fields().iter().map(|field| field.name().as_str()).collect();
+        assert_eq!(
+            names,
+            ["name", "path", "is_directory", "deleted", "size", "created", "modified", "accessed", "file_attributes", "stream_count"]
+        );
+        let values: Vec<Value> = batch.columns().iter().flat_map(|column| json_values(column).unwrap()).collect();
+        assert_eq!(
+            values,
+            [json!("a.txt"), json!("C:\\a.txt"), json!(false), json!(false), json!(5), json!(null), json!("2024-01-01T00:00:00Z"), json!(null), json!(0), json!(0)]
+        );
+    }
+
+    #[test]
+    fn values_keep_their_json_type() {
+        let integers: ArrayRef = Arc::new(Int32Array::from(vec![Some(-3), None]));
+        assert_eq!(json_values(&integers).unwrap(), [json!(-3), json!(null)]);
+        let floats: ArrayRef = Arc::new(Float32Array::from(vec![1.5, f32::NAN]));
+        assert_eq!(json_values(&floats).unwrap(), [json!(1.5), json!(null)]);
+        let text: ArrayRef = Arc::new(StringArray::from(vec![Some("a,b"), None]));
+        assert_eq!(json_values(&text).unwrap(), [json!("a,b"), json!(null)]);
+        let dates: ArrayRef = Arc::new(Date32Array::from(vec![19_723]));
+        assert_eq!(json_values(&dates).unwrap(), [json!("2024-01-01")]);
+    }
+
+    #[test]
+    fn queries_run_over_the_loaded_snapshot() {
+        let result = query("SELECT path, size FROM files WHERE size > 1e9 ORDER BY size DESC").unwrap();
+        assert_eq!(result.columns, ["path", "size"]);
+        assert_eq!(
+            result.rows,
+            [
+                vec![json!("C:\\big.iso"), json!(4_000_000_000u64)],
+                vec![json!("C:\\video.mkv"), json!(2_000_000_000u64)],
+            ]
+        );
+
+        let result = query("SELECT count(*) AS n FROM files WHERE NOT deleted AND modified >= '2024-01-01'").unwrap();
+        assert_eq!(result.rows, [vec![json!(2)]]);
+    }
+
+    #[test]
+    fn invalid_writing_and_trailing_statements_are_refused() {
+        for sql in [
+            "SELECT nope FROM files",
+            "SELEKT * FROM files",
+            "SELECT path FROM files; SELECT name FROM files",
+            "SELECT path FROM files; DROP TABLE files",
+            "CREATE TABLE copy AS SELECT * FROM files",
+            "INSERT INTO files SELECT * FROM files",
+        ] {
+            assert!(query(sql).is_err(), "{}", sql);
+        }
+    }
+}
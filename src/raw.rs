@@ -0,0 +1,1194 @@
+use anyhow::{bail, Context, Result};
+use std::io::{Read, Seek, SeekFrom};
+
+use crate::volume;
+
+/// Attribute type codes used by the raw reader
+pub const ATTR_STANDARD_INFORMATION: u32 = 0x10;
+pub const ATTR_ATTRIBUTE_LIST: u32 = 0x20;
+pub const ATTR_FILE_NAME: u32 = 0x30;
+pub const ATTR_DATA: u32 = 0x80;
+pub const ATTR_BITMAP: u32 = 0xB0;
+pub const ATTR_REPARSE_POINT: u32 = 0xC0;
+const ATTR_END: u32 = 0xFFFF_FFFF;
+
+/// Attribute flags whose data can't be read cluster by cluster
+const ATTR_FLAG_COMPRESSED: u16 = 0x0001;
+const ATTR_FLAG_ENCRYPTED: u16 = 0x4000;
+
+/// Largest piece of a non-resident value read at once
+const CHUNK_SIZE: u64 = 1024 * 1024;
+
+/// Record number of $MFT itself
+const MFT_RECORD: u64 = 0;
+
+/// Record number of $Bitmap, the cluster allocation map of the volume
+pub const BITMAP_RECORD: u64 = 6;
+
+/// Bytes of a file record header up to its first attribute offset and flags
+const RECORD_HEADER_SIZE: usize = 0x30;
+
+/// File record header flags
+const RECORD_IN_USE: u16 = 0x0001;
+const RECORD_IS_DIRECTORY: u16 = 0x0002;
+
+/// $FILE_NAME namespace of 8.3 short names
+const NAMESPACE_DOS: u8 = 2;
+
+/// Anything a volume can be read from: a device, an image or evidence media
+pub trait VolumeSource: Read + Seek {}
+
+impl<T: Read + Seek> VolumeSource for T {}
+
+/// Direct, read-only access to an NTFS volume device for the few structures
+/// ntfs-reader doesn't expose. Reads go through sector-aligned buffers, as
+/// required for volume handles.
+pub struct RawVolume {
+    source: Box<dyn VolumeSource>,
+    /// Where the volume starts in the file: 0 for devices and volume images,
+    /// the partition's offset in whole-disk images
+    offset: u64,
+    pub bytes_per_sector: u64,
+    pub bytes_per_cluster: u64,
+    pub total_clusters: u64,
+    pub mft_lcn: u64,
+    /// First cluster of $MFTMirr, the copy of the first MFT records
+    pub mft_mirror_lcn: u64,
+    pub record_size: u64,
+    /// Size of the index records of directories
+    pub index_record_size: u64,
+    pub serial_number: u64,
+}
+
+impl RawVolume {
+    /// Opens a volume given as a device path (`\\.\C:`) and parses its boot sector
+    pub fn open(volume_path: &str) -> Result<Self> {
+        Self::open_at(volume_path, 0)
+    }
+
+    /// Opens the volume starting `offset` bytes into a device or image file
+    pub fn open_at(volume_path: &str, offset: u64) -> Result<Self> {
+        let file = volume::open_device(volume_path)
+            .context(format!("Failed to open volume {} for raw reads", volume_path))?;
+        Self::from_source(Box::new(file), offset, volume_path)
+    }
+
+    /// Reads the volume starting `offset` bytes into `source`; `name` is
+    /// what errors call it
+    pub fn from_source(mut source: Box<dyn VolumeSource>, offset: u64, name: &str) -> Result<Self> {
+        let mut boot = [0u8; 512];
+        source.seek(SeekFrom::Start(offset))?;
+        source.read_exact(&mut boot).context("Failed to read the boot sector")?;
+        if &boot[3..11] != b"NTFS    " {
+            bail!("{} is not an NTFS volume", name);
+        }
+
+        let field = |offset| u64_at(&boot, offset).unwrap_or(0);
+        let bytes_per_sector = u16_at(&boot, 0x0B).unwrap_or(0) as u64;
+        if !bytes_per_sector.is_power_of_two() || !(256..=4096).contains(&bytes_per_sector) {
+            bail!("Invalid NTFS boot sector on {} (sector size {})", name, bytes_per_sector);
+        }
+        let Some(sectors_per_cluster) = sectors_per_cluster(boot[0x0D]) else {
+            bail!("Invalid NTFS boot sector on {} (sectors per cluster field 0x{:02x})", name, boot[0x0D]);
+        };
+        let bytes_per_cluster = bytes_per_sector
+            .checked_mul(sectors_per_cluster)
+            .filter(|&size| size <= MAX_CLUSTER_SIZE)
+            .context(format!("Invalid NTFS boot sector on {} (cluster too large)", name))?;
+        // The boot sector is the only source of these sizes, so one that is
+        // out of range means the volume can't be read, not that records
+        // have a strange size
+        let record_size = structure_size(boot[0x40], bytes_per_cluster)
+            .context(format!("Invalid file record size in the boot sector of {}", name))?;
+        let index_record_size = structure_size(boot[0x44], bytes_per_cluster)
+            .context(format!("Invalid index record size in the boot sector of {}", name))?;
+
+        Ok(RawVolume {
+            source,
+            offset,
+            bytes_per_sector,
+            bytes_per_cluster,
+            total_clusters: field(0x28) / sectors_per_cluster,
+            mft_lcn: field(0x30),
+            mft_mirror_lcn: field(0x38),
+            record_size,
+            index_record_size,
+            serial_number: field(0x48),
+        })
+    }
+
+    /// Reads `len` bytes at `offset`, aligning the underlying read to sectors
+    pub fn read_at(&mut self, offset: u64, len: usize) -> Result<Vec<u8>> {
+        let sector = self.bytes_per_sector;
+        let start = offset / sector * sector;
+        let end = offset
+            .checked_add(len as u64)
+            .and_then(|end| end.div_ceil(sector).checked_mul(sector))
+            .context(format!("Read of {} bytes at offset {} is past any volume", len, offset))?;
+
+        let mut buffer = vec![0u8; (end - start) as usize];
+        let position = self.offset.checked_add(start).context("Read past the end of the image")?;
+        self.source.seek(SeekFrom::Start(position))?;
+        self.source
+            .read_exact(&mut buffer)
+            .context(format!("Failed to read {} bytes at offset {}", len, offset))?;
+
+        let skip = (offset - start) as usize;
+        buffer.drain(..skip);
+        buffer.truncate(len);
+        Ok(buffer)
+    }
+
+    /// Reads the $MFT's own file record, which is always at the start of the MFT
+    pub fn read_mft_record(&mut self) -> Result<Vec<u8>> {
+        let offset = self.cluster_offset(self.mft_lcn, MFT_RECORD * self.record_size)?;
+        let mut record = self.read_at(offset, self.record_size as usize)?;
+        apply_fixups(&mut record, self.bytes_per_sector as usize)?;
+        Ok(record)
+    }
+
+    /// Reads the data runs of $MFT, which locate every other file record
+    pub fn mft_runs(&mut self) -> Result<MftRuns> {
+        let record = self.read_mft_record().context("Failed to read the $MFT record")?;
+        let attr = find_attribute(&record, ATTR_DATA)
+            .filter(|attr| attr[8] != 0)
+            .context("$MFT has no non-resident $DATA attribute")?;
+        Ok(MftRuns(decode_runs(mapping_pairs(attr)?)?))
+    }
+
+    /// Byte offset of `within` bytes into cluster `lcn`, which garbage run
+    /// lists can put past what a u64 holds
+    fn cluster_offset(&self, lcn: u64, within: u64) -> Result<u64> {
+        lcn.checked_mul(self.bytes_per_cluster)
+            .and_then(|offset| offset.checked_add(within))
+            .context(format!("Cluster {} is past the end of any volume", lcn))
+    }
+
+    /// Reads file record `number` straight from the volume, so it reflects
+    /// changes made after the MFT was loaded
+    pub fn read_file_record(&mut self, runs: &MftRuns, number: u64) -> Result<Vec<u8>> {
+        let mut record = self.read_file_record_on_disk(runs, number)?;
+        apply_fixups(&mut record, self.bytes_per_sector as usize)?;
+        Ok(record)
+    }
+
+    /// Reads file record `number` as stored on disk, before its fixups are
+    /// applied and without checking its signature
+    pub fn read_file_record_on_disk(&mut self, runs: &MftRuns, number: u64) -> Result<Vec<u8>> {
+        let mut offset = number
+            .checked_mul(self.record_size)
+            .context(format!("File record {} is past the end of $MFT", number))?;
+        for &(lcn, clusters) in &runs.0 {
+            let run_size = clusters.saturating_mul(self.bytes_per_cluster);
+            if offset >= run_size {
+                offset -= run_size;
+                continue;
+            }
+            let lcn = lcn.context(format!("File record {} lies in a sparse part of $MFT", number))?;
+            if offset + self.record_size > run_size {
+                bail!("File record {} spans two $MFT runs", number);
+            }
+            let offset = self.cluster_offset(lcn, offset)?;
+            return self.read_at(offset, self.record_size as usize);
+        }
+        bail!("File record {} is past the end of $MFT", number)
+    }
+
+    /// Returns the content of an unnamed attribute of the given type in a
+    /// file record, following data runs for non-resident attributes
+    pub fn read_attribute(&mut self, record: &[u8], type_id: u32) -> Result<Option<Vec<u8>>> {
+        let Some(attr) = find_attribute(record, type_id) else {
+            return Ok(None);
+        };
+
+        let mut data = Vec::new();
+        self.read_value(attr, |chunk| {
+            data.extend_from_slice(chunk);
+            Ok(())
+        })?;
+        Ok(Some(data))
+    }
+
+    /// Passes the value of an attribute to `f` in order, in pieces of at most
+    /// `CHUNK_SIZE` bytes, so large files can be processed without holding
+    /// them in memory. Sparse runs and bytes past the initialized size read
+    /// as zeros, like they do through the file system.
+    pub fn read_value<F>(&mut self, attr: &[u8], mut f: F) -> Result<()>
+    where
+        F: FnMut(&[u8]) -> Result<()>,
+    {
+        if attr.get(8) == Some(&0) {
+            let value = resident_value(attr).context("Resident value extends past its attribute")?;
+            return f(value);
+        }
+
+        let header = NonResidentHeader::parse(attr)?;
+        let runs = decode_runs(header.runs)?;
+        self.read_runs(&runs, header.data_size, header.initialized_size, f)
+    }
+
+    /// Like `read_value`, for a value an $ATTRIBUTE_LIST splits over several
+    /// attributes in extension records. Each piece holds the runs from the
+    /// starting VCN in its header; the one starting at 0 has the sizes.
+    pub fn read_split_value<F>(&mut self, pieces: &[Vec<u8>], f: F) -> Result<()>
+    where
+        F: FnMut(&[u8]) -> Result<()>,
+    {
+        if let [attr] = pieces {
+            return self.read_value(attr, f);
+        }
+
+        let mut pieces = pieces
+            .iter()
+            .map(|attr| {
+                if attr.get(8) != Some(&1) {
+                    bail!("Resident attribute among the pieces of a split value");
+                }
+                Ok((u64_at(attr, 0x10).context("Truncated non-resident attribute header")?, attr))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        pieces.sort_by_key(|(vcn, _)| *vcn);
+        let Some((0, first)) = pieces.first() else {
+            bail!("The piece holding the start of the value is missing");
+        };
+        let header = NonResidentHeader::parse(first)?;
+
+        let mut runs = Vec::new();
+        let mut vcn = 0u64;
+        for (start, attr) in &pieces {
+            if *start != vcn {
+                bail!("Data runs jump from cluster {} to {} between pieces", vcn, start);
+            }
+            for (lcn, clusters) in decode_runs(mapping_pairs(attr)?)? {
+                vcn = vcn.saturating_add(clusters);
+                runs.push((lcn, clusters));
+            }
+        }
+        self.read_runs(&runs, header.data_size, header.initialized_size, f)
+    }
+
+    /// Passes the `data_size` bytes `runs` hold to `f`, as `read_value` does
+    fn read_runs<F>(
+        &mut self,
+        runs: &[(Option<u64>, u64)],
+        data_size: u64,
+        initialized_size: u64,
+        mut f: F,
+    ) -> Result<()>
+    where
+        F: FnMut(&[u8]) -> Result<()>,
+    {
+        let mut position = 0;
+        for &(lcn, clusters) in runs {
+            let run_size = clusters.saturating_mul(self.bytes_per_cluster);
+            let mut run_offset = 0;
+            while run_offset < run_size && position < data_size {
+                let len = (run_size - run_offset).min(CHUNK_SIZE).min(data_size - position);
+                let mut chunk = match lcn {
+                    Some(lcn) if position < initialized_size => {
+                        let offset = self.cluster_offset(lcn, run_offset)?;
+                        self.read_at(offset, len as usize)?
+                    }
+                    // Sparse run, or entirely past the initialized size
+                    _ => vec![0u8; len as usize],
+                };
+                if position + len > initialized_size && position < initialized_size {
+                    chunk[(initialized_size - position) as usize..].fill(0);
+                }
+                f(&chunk)?;
+                run_offset += len;
+                position += len;
+            }
+            if position >= data_size {
+                break;
+            }
+        }
+
+        if position < data_size {
+            bail!(
+                "Data runs stop at {} of {} bytes (the rest is in an extension record)",
+                position,
+                data_size
+            );
+        }
+        Ok(())
+    }
+
+    /// Up to `len` bytes from the start of an attribute's value, taken from
+    /// its first run only, for looking at content without reading it all
+    pub fn read_head(&mut self, attr: &[u8], len: usize) -> Result<Vec<u8>> {
+        if attr.get(8) == Some(&0) {
+            let value = resident_value(attr).context("Resident value extends past its attribute")?;
+            return Ok(value[..value.len().min(len)].to_vec());
+        }
+
+        let header = NonResidentHeader::parse(attr)?;
+        let (data_size, initialized_size) = (header.data_size, header.initialized_size);
+        let Some(&(lcn, clusters)) = decode_runs(header.runs)?.first() else {
+            return Ok(Vec::new());
+        };
+        let len = (len as u64)
+            .min(data_size)
+            .min(clusters.saturating_mul(self.bytes_per_cluster)) as usize;
+        let mut head = match lcn {
+            Some(lcn) if initialized_size > 0 => self.read_at(self.cluster_offset(lcn, 0)?, len)?,
+            _ => vec![0u8; len],
+        };
+        if (len as u64) > initialized_size {
+            head[initialized_size as usize..].fill(0);
+        }
+        Ok(head)
+    }
+
+    /// Up to `len` bytes of an attribute's value starting `offset` bytes in,
+    /// reading only the runs that hold them
+    pub fn read_range(&mut self, attr: &[u8], offset: u64, len: u64) -> Result<Vec<u8>> {
+        if attr.get(8) == Some(&0) {
+            let value = resident_value(attr).context("Resident value extends past its attribute")?;
+            let start = offset.min(value.len() as u64) as usize;
+            let end = offset.saturating_add(len).min(value.len() as u64) as usize;
+            return Ok(value[start..end].to_vec());
+        }
+
+        let header = NonResidentHeader::parse(attr)?;
+        let end = offset.saturating_add(len).min(header.data_size);
+        let mut range = vec![0u8; end.saturating_sub(offset) as usize];
+        let mut position = 0u64;
+        for (lcn, clusters) in decode_runs(header.runs)? {
+            if position >= end {
+                break;
+            }
+            let run_end = position.saturating_add(clusters.saturating_mul(self.bytes_per_cluster));
+            let start = position.max(offset);
+            // Sparse runs and bytes past the initialized size stay zero
+            let stop = run_end.min(end).min(header.initialized_size);
+            if let Some(lcn) = lcn.filter(|_| start < stop) {
+                let data = self.read_at(self.cluster_offset(lcn, start - position)?, (stop - start) as usize)?;
+                range[(start - offset) as usize..(stop - offset) as usize].copy_from_slice(&data);
+            }
+            position = run_end;
+        }
+
+        if position < end {
+            bail!(
+                "Data runs stop at {} of {} bytes (the rest is in an extension record)",
+                position,
+                header.data_size
+            );
+        }
+        Ok(range)
+    }
+}
+
+/// Largest cluster size NTFS supports
+const MAX_CLUSTER_SIZE: u64 = 2 * 1024 * 1024;
+
+/// Sectors per cluster from its boot sector field: up to 0x80 the count
+/// itself, above it 2^(256-n) for the clusters of 64 KiB and more that
+/// don't fit a byte. `None` for no sectors or counts that aren't a power
+/// of two.
+fn sectors_per_cluster(field: u8) -> Option<u64> {
+    let sectors = match field {
+        0..=0x80 => field as u64,
+        // 2^(256-n) for n of 0x81 and up overflows for n <= 0xC0
+        _ => 1u64.checked_shl(256 - field as u32)?,
+    };
+    sectors.is_power_of_two().then_some(sectors)
+}
+
+/// Size of a file or index record from its boot sector field: positive
+/// counts clusters per record, negative means the record is 2^-n bytes.
+/// `None` for sizes no NTFS volume uses, outside 256 bytes to 64 KiB or
+/// not a power of two.
+fn structure_size(field: u8, bytes_per_cluster: u64) -> Option<u64> {
+    let clusters = field as i8;
+    let size = match clusters {
+        0 => return None,
+        1.. => (clusters as u64).checked_mul(bytes_per_cluster)?,
+        // 2^-n with n of at most 128 still overflows for n >= 64
+        _ => 1u64.checked_shl(-(clusters as i32) as u32)?,
+    };
+    (size.is_power_of_two() && (256..=65536).contains(&size)).then_some(size)
+}
+
+/// The fields of a non-resident attribute header the reader uses
+struct NonResidentHeader<'a> {
+    data_size: u64,
+    initialized_size: u64,
+    /// The attribute's mapping pairs array
+    runs: &'a [u8],
+}
+
+impl<'a> NonResidentHeader<'a> {
+    /// Reads the header of a non-resident attribute, refusing compressed and
+    /// encrypted ones and headers cut short by a damaged record
+    fn parse(attr: &'a [u8]) -> Result<Self> {
+        let flags = u16_at(attr, 0x0C).context("Truncated attribute header")?;
+        if flags & (ATTR_FLAG_COMPRESSED | ATTR_FLAG_ENCRYPTED) != 0 {
+            bail!("Compressed and encrypted data can't be read from raw clusters");
+        }
+        Ok(NonResidentHeader {
+            data_size: u64_at(attr, 0x30).context("Truncated non-resident attribute header")?,
+            initialized_size: u64_at(attr, 0x38).context("Truncated non-resident attribute header")?,
+            runs: mapping_pairs(attr)?,
+        })
+    }
+}
+
+/// The mapping pairs array of a non-resident attribute, which runs from the
+/// offset in its header to the end of the attribute
+pub fn mapping_pairs(attr: &[u8]) -> Result<&[u8]> {
+    u16_at(attr, 0x20)
+        .and_then(|offset| attr.get(offset as usize..))
+        .context("Data runs start past the end of their attribute")
+}
+
+/// Records holding unnamed attributes of type `type_id` according to the
+/// value of an $ATTRIBUTE_LIST, in order and without repeats
+pub fn attribute_list_records(list: &[u8], type_id: u32) -> Vec<u64> {
+    let mut records = Vec::new();
+    let mut offset = 0;
+    while let (Some(entry_type), Some(length)) = (u32_at(list, offset), u16_at(list, offset + 4)) {
+        // Shorter than an entry without a name means the list is damaged
+        if length < 0x1A {
+            break;
+        }
+        if entry_type == type_id && list.get(offset + 6) == Some(&0) {
+            if let Some(reference) = u64_at(list, offset + 0x10) {
+                let number = reference & 0xFFFF_FFFF_FFFF;
+                if !records.contains(&number) {
+                    records.push(number);
+                }
+            }
+        }
+        offset += length as usize;
+    }
+    records
+}
+
+/// Data runs of $MFT, which may be fragmented
+pub struct MftRuns(Vec<(Option<u64>, u64)>);
+
+impl MftRuns {
+    /// Number of contiguous pieces $MFT is stored in
+    pub fn extents(&self) -> usize {
+        self.0.iter().filter(|(lcn, _)| lcn.is_some()).count()
+    }
+
+    /// Clusters allocated to $MFT
+    pub fn clusters(&self) -> u64 {
+        self.0.iter().map(|(_, clusters)| clusters).sum()
+    }
+}
+
+/// The metadata of a file record, parsed without ntfs-reader. Timestamps
+/// are raw FILETIME values taken from $STANDARD_INFORMATION.
+#[derive(Debug, Default)]
+pub struct ParsedRecord {
+    pub in_use: bool,
+    pub is_directory: bool,
+    /// Incremented each time the record is reused for another file
+    pub sequence: u16,
+    /// Record this one holds overflow attributes of; 0 for base records
+    pub base_record: u64,
+    pub name: Option<String>,
+    pub created: Option<u64>,
+    pub modified: Option<u64>,
+    pub accessed: Option<u64>,
+    /// FILE_ATTRIBUTE_* flags from $STANDARD_INFORMATION
+    pub file_attributes: u32,
+    /// Size of the unnamed $DATA attribute
+    pub size: u64,
+    /// Named $DATA attributes (alternate data streams) and their sizes
+    pub streams: Vec<(String, u64)>,
+}
+
+/// Parses a file record whose fixups have been applied. Attributes held in
+/// extension records (through an $ATTRIBUTE_LIST) aren't followed; the
+/// size then falls back to the one stored in $FILE_NAME.
+pub fn parse_file_record(record: &[u8]) -> ParsedRecord {
+    if record.len() < RECORD_HEADER_SIZE {
+        return ParsedRecord::default();
+    }
+    let flags = u16_at(record, 0x16).unwrap_or(0);
+    let mut parsed = ParsedRecord {
+        in_use: flags & RECORD_IN_USE != 0,
+        is_directory: flags & RECORD_IS_DIRECTORY != 0,
+        sequence: u16_at(record, 0x10).unwrap_or(0),
+        base_record: u64_at(record, 0x20).unwrap_or(0) & 0xFFFF_FFFF_FFFF,
+        ..Default::default()
+    };
+    let mut size = FileSize::default();
+
+    for attr in attributes(record) {
+        size.add(attr);
+        match attribute_type(attr) {
+            ATTR_STANDARD_INFORMATION => {
+                if let Some(value) = resident_value(attr).filter(|v| v.len() >= 0x20) {
+                    parsed.created = u64_at(value, 0x00);
+                    parsed.modified = u64_at(value, 0x08);
+                    parsed.accessed = u64_at(value, 0x18);
+                }
+                parsed.file_attributes = standard_information_flags(attr).unwrap_or(0);
+            }
+            ATTR_FILE_NAME => {
+                let Some(value) = resident_value(attr).filter(|v| v.len() >= 0x42) else {
+                    continue;
+                };
+                // Prefer the long name when the file also has an 8.3 one
+                if parsed.name.is_some() && value[0x41] == NAMESPACE_DOS {
+                    continue;
+                }
+                let length = value[0x40] as usize;
+                if let Some(name) = value.get(0x42..0x42 + length * 2) {
+                    parsed.name = Some(utf16_lossy(name));
+                }
+            }
+            ATTR_DATA if attr[9] != 0 => {
+                if let Some(name) = attribute_name(attr) {
+                    parsed.streams.push((name, value_size(attr)));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    parsed.size = if parsed.is_directory { 0 } else { size.size() };
+    parsed
+}
+
+/// A file's size worked out from the attributes of its base record, one at
+/// a time: the size of the unnamed $DATA, or when that lives in an
+/// extension record, the size stored in $FILE_NAME (of the long name, if
+/// the file also has an 8.3 one). `parse_file_record` sizes files this way,
+/// and so can anything holding the same attributes from elsewhere.
+#[derive(Default)]
+pub struct FileSize {
+    data: Option<u64>,
+    name: Option<u64>,
+}
+
+impl FileSize {
+    pub fn add(&mut self, attr: &[u8]) {
+        match attribute_type(attr) {
+            ATTR_DATA if attr.get(9) == Some(&0) => self.data = Some(value_size(attr)),
+            ATTR_FILE_NAME => {
+                let Some(value) = resident_value(attr).filter(|v| v.len() >= 0x42) else {
+                    return;
+                };
+                if self.name.is_some() && value[0x41] == NAMESPACE_DOS {
+                    return;
+                }
+                let length = value[0x40] as usize;
+                if value.len() >= 0x42 + length * 2 {
+                    self.name = Some(u64_at(value, 0x30).unwrap_or(0));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    pub fn size(&self) -> u64 {
+        self.data.or(self.name).unwrap_or(0)
+    }
+}
+
+/// Parent directory record number and name of a $FILE_NAME attribute, or
+/// `None` for the 8.3 short names that merely alias a long name
+pub fn file_name_link(attr: &[u8]) -> Option<(u64, String)> {
+    let value = resident_value(attr).filter(|v| v.len() >= 0x42)?;
+    if value[0x41] == NAMESPACE_DOS {
+        return None;
+    }
+    let length = value[0x40] as usize;
+    let name = value.get(0x42..0x42 + length * 2)?;
+    Some((u64_at(value, 0x00)? & 0xFFFF_FFFF_FFFF, utf16_lossy(name)))
+}
+
+/// File reference of the parent directory of a $FILE_NAME attribute, with
+/// its sequence number, or `None` for 8.3 short names as in `file_name_link`
+pub fn file_name_parent(attr: &[u8]) -> Option<u64> {
+    let value = resident_value(attr).filter(|v| v.len() >= 0x42)?;
+    (value[0x41] != NAMESPACE_DOS).then(|| u64_at(value, 0x00)).flatten()
+}
+
+/// `file_name_parent` of the first name of a file record
+pub fn first_file_name_parent(record: &[u8]) -> Option<u64> {
+    attributes(record)
+        .filter(|attr| attribute_type(attr) == ATTR_FILE_NAME)
+        .find_map(file_name_parent)
+}
+
+/// `file_name_link` of every $FILE_NAME in a file record, one per hard link
+pub fn file_name_links(record: &[u8]) -> Vec<(u64, String)> {
+    attributes(record)
+        .filter(|attr| attribute_type(attr) == ATTR_FILE_NAME)
+        .filter_map(file_name_link)
+        .collect()
+}
+
+/// Timestamps (FILETIMEs) and FILE_ATTRIBUTE_* flags of a resident
+/// $STANDARD_INFORMATION attribute
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct StandardInformation {
+    pub created: u64,
+    pub modified: u64,
+    /// When the MFT record last changed
+    pub mft_modified: u64,
+    pub accessed: u64,
+    pub file_attributes: u32,
+}
+
+pub fn standard_information(attr: &[u8]) -> Option<StandardInformation> {
+    let value = resident_value(attr)?;
+    Some(StandardInformation {
+        created: u64_at(value, 0x00)?,
+        modified: u64_at(value, 0x08)?,
+        mft_modified: u64_at(value, 0x10)?,
+        accessed: u64_at(value, 0x18)?,
+        file_attributes: u32_at(value, 0x20)?,
+    })
+}
+
+/// FILE_ATTRIBUTE_* flags stored in a resident $STANDARD_INFORMATION attribute
+pub fn standard_information_flags(attr: &[u8]) -> Option<u32> {
+    resident_value(attr).and_then(|value| u32_at(value, 0x20))
+}
+
+/// Security ID of a resident $STANDARD_INFORMATION attribute, the key of the
+/// file's security descriptor in $Secure. Volumes older than NTFS 3.0 have
+/// a short $STANDARD_INFORMATION without one.
+pub fn standard_information_security_id(attr: &[u8]) -> Option<u32> {
+    resident_value(attr).and_then(|value| u32_at(value, 0x34))
+}
+
+/// USN of the last journal record written for the file, kept in a resident
+/// $STANDARD_INFORMATION attribute from NTFS 3.0 on
+pub fn standard_information_usn(attr: &[u8]) -> Option<u64> {
+    resident_value(attr).and_then(|value| u64_at(value, 0x40))
+}
+
+/// Restores the last two bytes of every sector of a multi-sector record from
+/// the update sequence array, checking them against the sequence number
+pub fn apply_fixups(record: &mut [u8], sector_size: usize) -> Result<()> {
+    if record.get(0..4) != Some(b"FILE") || record.len() < RECORD_HEADER_SIZE {
+        bail!("Invalid file record signature");
+    }
+    let usa_offset = u16_at(record, 0x04).unwrap_or(0) as usize;
+    let usa_count = u16_at(record, 0x06).unwrap_or(0) as usize;
+    if usa_count == 0 || sector_size < 2 || usa_offset + usa_count * 2 > record.len() {
+        bail!("Invalid update sequence array");
+    }
+
+    let sequence = [record[usa_offset], record[usa_offset + 1]];
+    for i in 1..usa_count {
+        let end = i * sector_size;
+        if end > record.len() {
+            break;
+        }
+        if record[end - 2..end] != sequence {
+            bail!("Torn file record (update sequence mismatch in sector {})", i);
+        }
+        let fixup = usa_offset + i * 2;
+        record[end - 2] = record[fixup];
+        record[end - 1] = record[fixup + 1];
+    }
+    Ok(())
+}
+
+/// Finds the first unnamed attribute of the given type in a file record
+pub fn find_attribute(record: &[u8], type_id: u32) -> Option<&[u8]> {
+    attributes(record).find(|attr| attribute_type(attr) == type_id && attr[9] == 0)
+}
+
+/// Finds the attribute of the given type and name in a file record
+pub fn find_named_attribute<'a>(record: &'a [u8], type_id: u32, name: &str) -> Option<&'a [u8]> {
+    attributes(record).find(|attr| {
+        attribute_type(attr) == type_id && attribute_name(attr).is_some_and(|n| n == name)
+    })
+}
+
+/// Type code of an attribute yielded by `attributes`, which are at least a
+/// resident header long
+fn attribute_type(attr: &[u8]) -> u32 {
+    u32_at(attr, 0).unwrap_or(ATTR_END)
+}
+
+/// Name of a named attribute; `None` for unnamed ones and names that run
+/// past the attribute
+fn attribute_name(attr: &[u8]) -> Option<String> {
+    let length = *attr.get(9)? as usize;
+    let offset = u16_at(attr, 0x0A)? as usize;
+    (length > 0).then(|| attr.get(offset..offset + length * 2)).flatten().map(utf16_lossy)
+}
+
+/// Every attribute of a file record, in order, up to the end marker
+fn attributes(record: &[u8]) -> impl Iterator<Item = &[u8]> {
+    let mut offset = u16_at(record, 0x14).unwrap_or(u16::MAX) as usize;
+    std::iter::from_fn(move || {
+        let attr_type = u32_at(record, offset)?;
+        let length = u32_at(record, offset + 4)? as usize;
+        // Shorter than a resident attribute header means the record is damaged
+        if attr_type == ATTR_END || length < 0x18 || offset + length > record.len() {
+            return None;
+        }
+        let attr = &record[offset..offset + length];
+        offset += length;
+        Some(attr)
+    })
+}
+
+/// The value of a resident attribute
+pub fn resident_value(attr: &[u8]) -> Option<&[u8]> {
+    if *attr.get(8)? != 0 {
+        return None;
+    }
+    let length = u32_at(attr, 0x10)? as usize;
+    let offset = u16_at(attr, 0x14)? as usize;
+    attr.get(offset..offset.checked_add(length)?)
+}
+
+/// Size of an attribute's value, resident or not
+fn value_size(attr: &[u8]) -> u64 {
+    let size = if attr[8] == 0 {
+        u32_at(attr, 0x10).map(u64::from)
+    } else {
+        u64_at(attr, 0x30)
+    };
+    size.unwrap_or(0)
+}
+
+/// Decodes little-endian UTF-16, replacing invalid code units with U+FFFD
+pub fn utf16_lossy(bytes: &[u8]) -> String {
+    let units: Vec<u16> = bytes
+        .chunks_exact(2)
+        .map(|chunk| u16::from_le_bytes([chunk[0], chunk[1]]))
+        .collect();
+    String::from_utf16_lossy(&units)
+}
+
+/// Decodes a mapping pairs array into (LCN, cluster count) runs; sparse
+/// runs have no LCN
+pub fn decode_runs(mut runs: &[u8]) -> Result<Vec<(Option<u64>, u64)>> {
+    let mut decoded = Vec::new();
+    let mut lcn: i64 = 0;
+
+    while let Some(&header) = runs.first() {
+        if header == 0 {
+            break;
+        }
+        let length_size = (header & 0x0F) as usize;
+        let offset_size = (header >> 4) as usize;
+        if runs.len() < 1 + length_size + offset_size || length_size > 8 || offset_size > 8 {
+            bail!("Truncated data run");
+        }
+
+        let clusters = le_uint(&runs[1..1 + length_size]);
+        if offset_size == 0 {
+            decoded.push((None, clusters));
+        } else {
+            lcn = lcn
+                .checked_add(le_int(&runs[1 + length_size..1 + length_size + offset_size]))
+                .context("Data run points past the end of any volume")?;
+            if lcn < 0 {
+                bail!("Data run points before the start of the volume");
+            }
+            decoded.push((Some(lcn as u64), clusters));
+        }
+        runs = &runs[1 + length_size + offset_size..];
+    }
+    Ok(decoded)
+}
+
+fn le_uint(bytes: &[u8]) -> u64 {
+    bytes.iter().rev().fold(0, |value, &b| (value << 8) | b as u64)
+}
+
+/// Little-endian signed integer of 1 to 8 bytes
+fn le_int(bytes: &[u8]) -> i64 {
+    let value = le_uint(bytes);
+    let shift = 64 - bytes.len() * 8;
+    ((value << shift) as i64) >> shift
+}
+
+/// Little-endian integers read from on-disk structures, `None` when `data`
+/// ends before them
+pub fn u16_at(data: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(bytes_at(data, offset)?))
+}
+
+pub fn u32_at(data: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(bytes_at(data, offset)?))
+}
+
+pub fn u64_at(data: &[u8], offset: usize) -> Option<u64> {
+    Some(u64::from_le_bytes(bytes_at(data, offset)?))
+}
+
+fn bytes_at<const N: usize>(data: &[u8], offset: usize) -> Option<[u8; N]> {
+    data.get(offset..offset.checked_add(N)?)?.try_into().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    const SECTOR: usize = 512;
+
+    /// A 1 KiB in-use file record holding `attributes`, with its update
+    /// sequence array filled in as if it had been written to disk
+    fn record(attributes: &[Vec<u8>]) -> Vec<u8> {
+        let mut record = vec![0u8; 1024];
+        record[0..4].copy_from_slice(b"FILE");
+        record[0x04..0x06].copy_from_slice(&0x30u16.to_le_bytes());
+        record[0x06..0x08].copy_from_slice(&3u16.to_le_bytes());
+        record[0x10..0x12].copy_from_slice(&7u16.to_le_bytes());
+        record[0x14..0x16].copy_from_slice(&0x38u16.to_le_bytes());
+        record[0x16..0x18].copy_from_slice(&RECORD_IN_USE.to_le_bytes());
+        let mut offset = 0x38;
+        for attr in attributes {
+            record[offset..offset + attr.len()].copy_from_slice(attr);
+            offset += attr.len();
+        }
+        record[offset..offset + 4].copy_from_slice(&ATTR_END.to_le_bytes());
+
+        // Move the last two bytes of each sector into the array
+        record[0x30..0x32].copy_from_slice(&[0xAB, 0xCD]);
+        for i in 1..3 {
+            let end = i * SECTOR;
+            let (original, fixup) = (record[end - 2..end].to_vec(), 0x30 + i * 2);
+            record[fixup..fixup + 2].copy_from_slice(&original);
+            record[end - 2..end].copy_from_slice(&[0xAB, 0xCD]);
+        }
+        record
+    }
+
+    fn resident(type_id: u32, value: &[u8]) -> Vec<u8> {
+        let length = (0x18 + value.len()).next_multiple_of(8);
+        let mut attr = vec![0u8; length];
+        attr[0..4].copy_from_slice(&type_id.to_le_bytes());
+        attr[4..8].copy_from_slice(&(length as u32).to_le_bytes());
+        attr[0x10..0x14].copy_from_slice(&(value.len() as u32).to_le_bytes());
+        attr[0x14..0x16].copy_from_slice(&0x18u16.to_le_bytes());
+        attr[0x18..0x18 + value.len()].copy_from_slice(value);
+        attr
+    }
+
+    fn file_name(parent: u64, name: &str) -> Vec<u8> {
+        let units: Vec<u8> = name.encode_utf16().flat_map(u16::to_le_bytes).collect();
+        let mut value = vec![0u8; 0x42];
+        value[0..8].copy_from_slice(&parent.to_le_bytes());
+        value[0x30..0x38].copy_from_slice(&1234u64.to_le_bytes());
+        value[0x40] = name.encode_utf16().count() as u8;
+        value.extend_from_slice(&units);
+        resident(ATTR_FILE_NAME, &value)
+    }
+
+    fn boot_sector(sectors_per_cluster: u8, clusters_per_record: u8) -> Vec<u8> {
+        let mut boot = vec![0u8; 512];
+        boot[3..11].copy_from_slice(b"NTFS    ");
+        boot[0x0B..0x0D].copy_from_slice(&512u16.to_le_bytes());
+        boot[0x0D] = sectors_per_cluster;
+        boot[0x28..0x30].copy_from_slice(&80_000u64.to_le_bytes());
+        boot[0x30..0x38].copy_from_slice(&4u64.to_le_bytes());
+        boot[0x40] = clusters_per_record;
+        boot[0x44] = 1;
+        boot
+    }
+
+    fn open(boot: Vec<u8>) -> Result<RawVolume> {
+        RawVolume::from_source(Box::new(Cursor::new(boot)), 0, "test")
+    }
+
+    #[test]
+    fn integer_reads_stop_at_the_end_of_the_data() {
+        let data = [1, 2, 3, 4, 5, 6, 7, 8];
+        assert_eq!(u16_at(&data, 6), Some(0x0807));
+        assert_eq!(u16_at(&data, 7), None);
+        assert_eq!(u32_at(&data, 5), None);
+        assert_eq!(u64_at(&data, 0), Some(0x0807_0605_0403_0201));
+        assert_eq!(u64_at(&data, 1), None);
+        assert_eq!(u32_at(&data, usize::MAX), None);
+        assert_eq!(u16_at(&[], 0), None);
+    }
+
+    #[test]
+    fn structure_size_rejects_sizes_no_volume_uses() {
+        assert_eq!(structure_size(0xF6, 4096), Some(1024));
+        assert_eq!(structure_size(0x01, 4096), Some(4096));
+        assert_eq!(structure_size(0x00, 4096), None);
+        // 2^64 and beyond used to overflow the shift
+        assert_eq!(structure_size(0xC0, 4096), None);
+        assert_eq!(structure_size(0x80, 4096), None);
+        // 2^7 bytes is smaller than any record, 127 clusters larger
+        assert_eq!(structure_size(0xF9, 4096), None);
+        assert_eq!(structure_size(0x7F, 4096), None);
+        assert_eq!(structure_size(0x03, 512), None);
+    }
+
+    #[test]
+    fn large_clusters_are_decoded_from_negative_counts() {
+        assert_eq!(sectors_per_cluster(8), Some(8));
+        assert_eq!(sectors_per_cluster(0x80), Some(128));
+        assert_eq!(sectors_per_cluster(0xFF), Some(2));
+        // 2 MiB clusters with 512-byte sectors
+        assert_eq!(sectors_per_cluster(0xF4), Some(4096));
+        assert_eq!(sectors_per_cluster(0x81), None);
+        assert_eq!(sectors_per_cluster(0xC0), None);
+        assert_eq!(sectors_per_cluster(0), None);
+        assert_eq!(sectors_per_cluster(6), None);
+
+        // Index records larger than a cluster are given in bytes as well
+        let mut boot = boot_sector(0xF7, 0xF6);
+        boot[0x44] = 0xF4;
+        let volume = open(boot).unwrap();
+        assert_eq!(volume.bytes_per_cluster, 256 * 1024);
+        assert_eq!(volume.total_clusters, 80_000 / 512);
+        assert!(open(boot_sector(0xF3, 0xF6)).is_err());
+    }
+
+    #[test]
+    fn boot_sectors_with_invalid_record_sizes_are_refused() {
+        let volume = open(boot_sector(8, 0xF6)).unwrap();
+        assert_eq!(volume.bytes_per_cluster, 4096);
+        assert_eq!(volume.record_size, 1024);
+        assert_eq!(volume.total_clusters, 10_000);
+
+        for field in [0x00, 0x80, 0xC0, 0xEF] {
+            assert!(open(boot_sector(8, field)).is_err(), "record size field 0x{:02x}", field);
+        }
+        assert!(open(boot_sector(0, 0xF6)).is_err());
+
+        let mut boot = boot_sector(8, 0xF6);
+        boot[0x0B..0x0D].copy_from_slice(&0u16.to_le_bytes());
+        assert!(open(boot).is_err());
+        assert!(open(b"NTFS".to_vec()).is_err());
+    }
+
+    #[test]
+    fn fixups_restore_sector_ends() {
+        let mut record = record(&[]);
+        assert_eq!(&record[SECTOR - 2..SECTOR], &[0xAB, 0xCD]);
+        apply_fixups(&mut record, SECTOR).unwrap();
+        assert_eq!(&record[SECTOR - 2..SECTOR], &[0, 0]);
+    }
+
+    #[test]
+    fn fixups_reject_truncated_and_torn_records() {
+        assert!(apply_fixups(&mut [], SECTOR).is_err());
+        assert!(apply_fixups(&mut [b'F'], SECTOR).is_err());
+        assert!(apply_fixups(&mut b"FILE".to_vec(), SECTOR).is_err());
+        assert!(apply_fixups(&mut record(&[]), 0).is_err());
+        assert!(apply_fixups(&mut record(&[])[..SECTOR].to_vec(), SECTOR).is_ok());
+
+        let mut torn = record(&[]);
+        torn[2 * SECTOR - 1] ^= 0xFF;
+        assert!(apply_fixups(&mut torn, SECTOR).is_err());
+
+        let mut array_past_end = record(&[]);
+        array_past_end[0x04..0x06].copy_from_slice(&0x3FEu16.to_le_bytes());
+        assert!(apply_fixups(&mut array_past_end, SECTOR).is_err());
+    }
+
+    #[test]
+    fn records_are_parsed() {
+        let mut record = record(&[file_name(5 | 3 << 48, "notepad.exe"), resident(ATTR_DATA, b"hello")]);
+        apply_fixups(&mut record, SECTOR).unwrap();
+
+        let parsed = parse_file_record(&record);
+        assert!(parsed.in_use);
+        assert_eq!(parsed.sequence, 7);
+        assert_eq!(parsed.name.as_deref(), Some("notepad.exe"));
+        assert_eq!(parsed.size, 5);
+        assert_eq!(file_name_links(&record), vec![(5, "notepad.exe".to_string())]);
+        let data = find_attribute(&record, ATTR_DATA).unwrap();
+        assert_eq!(resident_value(data), Some(&b"hello"[..]));
+    }
+
+    #[test]
+    fn sizes_fall_back_to_the_file_name() {
+        let name = file_name(5, "big.iso");
+        let mut record = record(std::slice::from_ref(&name));
+        apply_fixups(&mut record, SECTOR).unwrap();
+        assert_eq!(parse_file_record(&record).size, 1234);
+
+        // Sized from the attributes alone, as monitor's index does
+        let mut size = FileSize::default();
+        // A truncated attribute is skipped
+        for attr in [name, resident(ATTR_DATA, b"hello"), vec![0x80, 0, 0]] {
+            size.add(&attr);
+        }
+        assert_eq!(size.size(), 5);
+    }
+
+    #[test]
+    fn garbage_records_parse_without_panicking() {
+        let mut damaged = record(&[file_name(5, "a.txt")]);
+        apply_fixups(&mut damaged, SECTOR).unwrap();
+        // Attribute length running past the record
+        damaged[0x3C..0x40].copy_from_slice(&0xFFFF_FFF0u32.to_le_bytes());
+
+        let mut first_attribute_past_end = record(&[]);
+        first_attribute_past_end[0x14..0x16].copy_from_slice(&0xFFF0u16.to_le_bytes());
+
+        let mut value_past_end = record(&[resident(ATTR_FILE_NAME, &[0u8; 0x42])]);
+        value_past_end[0x38 + 0x14..0x38 + 0x16].copy_from_slice(&0x3F0u16.to_le_bytes());
+
+        let noise: Vec<u8> = (0..1024u32).map(|i| (i.wrapping_mul(2_654_435_761) >> 13) as u8).collect();
+        let inputs = [
+            Vec::new(),
+            b"FILE".to_vec(),
+            record(&[])[..0x2F].to_vec(),
+            damaged,
+            first_attribute_past_end,
+            value_past_end,
+            noise,
+        ];
+        for input in &inputs {
+            let parsed = parse_file_record(input);
+            assert_eq!(parsed.name, None);
+            assert!(file_name_links(input).is_empty());
+            assert!(find_attribute(input, ATTR_DATA).is_none());
+            assert!(find_named_attribute(input, ATTR_DATA, "stream").is_none());
+        }
+        assert_eq!(resident_value(&[]), None);
+        assert_eq!(standard_information_security_id(&[0u8; 0x18]), None);
+        assert_eq!(standard_information_usn(&[0u8; 0x18]), None);
+    }
+
+    #[test]
+    fn data_runs_are_decoded_and_checked() {
+        // 0x18 clusters at 0x5634, then 0x10 sparse clusters, then 4 clusters
+        // 0x34 before the previous run
+        let runs = [0x21, 0x18, 0x34, 0x56, 0x01, 0x10, 0x11, 0x04, 0xCC, 0x00];
+        assert_eq!(
+            decode_runs(&runs).unwrap(),
+            vec![(Some(0x5634), 0x18), (None, 0x10), (Some(0x5634 - 0x34), 4)]
+        );
+        assert!(decode_runs(&[]).unwrap().is_empty());
+        assert!(decode_runs(&[0x21, 0x18]).is_err());
+        assert!(decode_runs(&[0x91, 0x01, 0, 0, 0, 0, 0, 0, 0, 0, 0]).is_err());
+        assert!(decode_runs(&[0x11, 0x01, 0xFF]).is_err());
+        let far = [0x81, 0x01, 0, 0, 0, 0, 0, 0, 0, 0x7F];
+        assert!(decode_runs(&[&far[..], &far[..], &[0]].concat()).is_err());
+    }
+
+    #[test]
+    fn truncated_non_resident_headers_are_errors() {
+        let mut volume = open(boot_sector(8, 0xF6)).unwrap();
+        let mut attr = vec![0u8; 0x20];
+        attr[8] = 1;
+        assert!(NonResidentHeader::parse(&attr).is_err());
+        assert!(volume.read_value(&attr, |_| Ok(())).is_err());
+        assert!(volume.read_head(&attr, 16).is_err());
+        assert!(volume.read_value(&[], |_| Ok(())).is_err());
+
+        let mut runs_past_end = vec![0u8; 0x48];
+        runs_past_end[8] = 1;
+        runs_past_end[0x20..0x22].copy_from_slice(&0x100u16.to_le_bytes());
+        assert!(mapping_pairs(&runs_past_end).is_err());
+        assert!(volume.read_value(&runs_past_end, |_| Ok(())).is_err());
+        assert!(volume.read_range(&runs_past_end, 0, 16).is_err());
+    }
+
+    #[test]
+    fn ranges_read_only_the_runs_they_cover() {
+        // 512-byte clusters: the boot sector, then clusters of 0x11, 0x22, 0x33
+        let mut image = boot_sector(1, 0xF6);
+        for fill in [0x11, 0x22, 0x33] {
+            image.extend_from_slice(&[fill; 512]);
+        }
+        let mut volume = open(image).unwrap();
+
+        // Cluster 3, a sparse cluster, then cluster 1; the last 100 bytes
+        // are past the initialized size
+        let runs = [0x11, 0x01, 0x03, 0x01, 0x01, 0x11, 0x01, 0xFE, 0x00];
+        let mut attr = vec![0u8; 0x50];
+        attr[8] = 1;
+        attr[0x20..0x22].copy_from_slice(&0x40u16.to_le_bytes());
+        attr[0x30..0x38].copy_from_slice(&1536u64.to_le_bytes());
+        attr[0x38..0x40].copy_from_slice(&1436u64.to_le_bytes());
+        attr[0x40..0x40 + runs.len()].copy_from_slice(&runs);
+
+        let range = volume.read_range(&attr, 500, 2000).unwrap();
+        let expected = [vec![0x33; 12], vec![0; 512], vec![0x11; 412], vec![0; 100]].concat();
+        assert_eq!(range, expected);
+        assert!(volume.read_range(&attr, 2000, 10).unwrap().is_empty());
+
+        // Runs that stop before the value's size
+        attr[0x30..0x38].copy_from_slice(&4096u64.to_le_bytes());
+        assert!(volume.read_range(&attr, 1000, 2000).is_err());
+
+        let resident = resident(ATTR_DATA, b"hello");
+        assert_eq!(volume.read_range(&resident, 1, 3).unwrap(), b"ell");
+        assert_eq!(volume.read_range(&resident, 3, 100).unwrap(), b"lo");
+        assert!(volume.read_range(&resident, u64::MAX, u64::MAX).unwrap().is_empty());
+    }
+
+    fn list_entry(type_id: u32, name_length: u8, reference: u64) -> Vec<u8> {
+        let mut entry = vec![0u8; 0x20];
+        entry[0..4].copy_from_slice(&type_id.to_le_bytes());
+        entry[4..6].copy_from_slice(&0x20u16.to_le_bytes());
+        entry[6] = name_length;
+        entry[0x10..0x18].copy_from_slice(&reference.to_le_bytes());
+        entry
+    }
+
+    #[test]
+    fn attribute_lists_name_the_records_holding_an_attribute() {
+        let list = [
+            list_entry(ATTR_STANDARD_INFORMATION, 0, 40),
+            list_entry(ATTR_DATA, 0, 41 | 2 << 48),
+            list_entry(ATTR_DATA, 3, 42),
+            list_entry(ATTR_DATA, 0, 43),
+            list_entry(ATTR_DATA, 0, 41),
+        ]
+        .concat();
+        assert_eq!(attribute_list_records(&list, ATTR_DATA), vec![41, 43]);
+        assert_eq!(attribute_list_records(&list[..0x50], ATTR_DATA), vec![41]);
+
+        // A zero entry length would loop forever
+        let mut looping = list_entry(ATTR_DATA, 0, 41);
+        looping[4..6].copy_from_slice(&0u16.to_le_bytes());
+        assert!(attribute_list_records(&looping, ATTR_DATA).is_empty());
+        assert!(attribute_list_records(&[0xFF; 5], ATTR_DATA).is_empty());
+    }
+
+    #[test]
+    fn split_values_are_read_in_vcn_order() {
+        let mut image = boot_sector(1, 0xF6);
+        for fill in [0x11, 0x22] {
+            image.extend_from_slice(&[fill; 512]);
+        }
+        let mut volume = open(image).unwrap();
+
+        let piece = |vcn: u64, runs: &[u8]| {
+            let mut attr = vec![0u8; 0x48];
+            attr[8] = 1;
+            attr[0x10..0x18].copy_from_slice(&vcn.to_le_bytes());
+            attr[0x20..0x22].copy_from_slice(&0x40u16.to_le_bytes());
+            attr[0x30..0x38].copy_from_slice(&1024u64.to_le_bytes());
+            attr[0x38..0x40].copy_from_slice(&1024u64.to_le_bytes());
+            attr[0x40..0x40 + runs.len()].copy_from_slice(runs);
+            attr
+        };
+        let first = piece(0, &[0x11, 0x01, 0x02, 0x00]);
+        let second = piece(1, &[0x11, 0x01, 0x01, 0x00]);
+
+        let mut value = Vec::new();
+        volume
+            .read_split_value(&[second.clone(), first.clone()], |chunk| {
+                value.extend_from_slice(chunk);
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(value, [vec![0x22; 512], vec![0x11; 512]].concat());
+
+        let read = |volume: &mut RawVolume, pieces: &[Vec<u8>]| volume.read_split_value(pieces, |_| Ok(()));
+        assert!(read(&mut volume, &[]).is_err());
+        assert!(read(&mut volume, std::slice::from_ref(&second)).is_err());
+        assert!(read(&mut volume, &[first.clone(), piece(2, &[0x11, 0x01, 0x01, 0x00])]).is_err());
+        assert!(read(&mut volume, &[first, resident(ATTR_DATA, b"hello")]).is_err());
+    }
+}
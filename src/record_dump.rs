@@ -0,0 +1,296 @@
+use anyhow::{Context, Result};
+use std::io::{self, Write};
+
+use crate::attributes;
+use crate::mft;
+use crate::output;
+use crate::raw::{self, RawVolume};
+
+/// End of the attribute list in a file record
+const ATTR_END: u32 = 0xFFFF_FFFF;
+
+/// File record header flags and the names they are shown under
+const RECORD_FLAGS: &[(&str, u16)] = &[
+    ("in_use", 0x0001),
+    ("directory", 0x0002),
+    ("extension", 0x0004),
+    ("view_index", 0x0008),
+];
+
+/// Prints file record `number` read straight from the volume: the decoded
+/// header, the update sequence array with the bytes each fixup replaces, the
+/// attribute headers and a hex listing split at attribute boundaries. The
+/// record is dumped whatever its state, so damaged and deleted records can be
+/// inspected too; `on_disk` lists the bytes before the fixups are applied.
+pub fn run(volume: &str, number: u64, on_disk: bool) -> Result<()> {
+    let mut raw = RawVolume::open(&mft::normalize_volume_path(volume))?;
+    let runs = raw.mft_runs()?;
+    let mut record = raw
+        .read_file_record_on_disk(&runs, number)
+        .context(format!("Failed to read file record {}", number))?;
+    let sector_size = raw.bytes_per_sector as usize;
+
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    writeln!(out, "File record {} ({} bytes, {}-byte sectors)", number, record.len(), sector_size)?;
+
+    writeln!(out)?;
+    writeln!(out, "Header")?;
+    write_header(&mut out, &record)?;
+
+    writeln!(out)?;
+    writeln!(out, "Update sequence")?;
+    let fixed = write_fixups(&mut out, &mut record, sector_size, !on_disk)?;
+
+    writeln!(out)?;
+    writeln!(out, "Attributes")?;
+    let regions = write_attributes(&mut out, &record)?;
+
+    writeln!(out)?;
+    writeln!(
+        out,
+        "Hex ({})",
+        if on_disk || !fixed { "as stored on disk" } else { "fixups applied" }
+    )?;
+    let mut start = 0;
+    for (end, label) in regions {
+        writeln!(out, "-- {} --", label)?;
+        output::write_hex_lines(&mut out, &record[start..end], start)?;
+        start = end;
+    }
+    if start < record.len() {
+        writeln!(out, "-- unused --")?;
+        output::write_hex_lines(&mut out, &record[start..], start)?;
+    }
+    Ok(())
+}
+
+fn write_header(out: &mut dyn Write, record: &[u8]) -> io::Result<()> {
+    // Records are read whole, so only a truncated test input lacks a field
+    let u16_at = |offset| raw::u16_at(record, offset).unwrap_or(0);
+    let u32_at = |offset| raw::u32_at(record, offset).unwrap_or(0);
+    let u64_at = |offset| raw::u64_at(record, offset).unwrap_or(0);
+    let signature = String::from_utf8_lossy(&record[..record.len().min(4)]).into_owned();
+    let flags = u16_at(0x16);
+    let flag_names: Vec<&str> = RECORD_FLAGS
+        .iter()
+        .filter(|(_, flag)| flags & flag != 0)
+        .map(|(name, _)| *name)
+        .collect();
+    let base = u64_at(0x20);
+
+    let mut fields = vec![
+        (0x00, "signature", format!("{:?}", signature)),
+        (0x04, "update_sequence_offset", format!("0x{:x}", u16_at(0x04))),
+        (0x06, "update_sequence_count", u16_at(0x06).to_string()),
+        (0x08, "log_sequence_number", u64_at(0x08).to_string()),
+        (0x10, "sequence_number", u16_at(0x10).to_string()),
+        (0x12, "hard_link_count", u16_at(0x12).to_string()),
+        (0x14, "first_attribute_offset", format!("0x{:x}", u16_at(0x14))),
+        (0x16, "flags", format!("0x{:04x} ({})", flags, flag_names.join(", "))),
+        (0x18, "used_size", u32_at(0x18).to_string()),
+        (0x1C, "allocated_size", u32_at(0x1C).to_string()),
+        (
+            0x20,
+            "base_record",
+            format!("{} (record {})", base, base & 0x0000_FFFF_FFFF_FFFF),
+        ),
+        (0x28, "next_attribute_id", u16_at(0x28).to_string()),
+    ];
+    // NTFS 3.1 records store their own number after the header; older ones
+    // start the update sequence array there
+    if u16_at(0x04) >= 0x30 {
+        fields.push((0x2C, "record_number", u32_at(0x2C).to_string()));
+    }
+
+    for (offset, name, value) in fields {
+        writeln!(out, "  0x{:02x}  {:<24}{}", offset, name, value)?;
+    }
+    if signature != "FILE" {
+        writeln!(out, "  signature is not FILE: the record is unused, damaged or marked BAAD")?;
+    }
+    Ok(())
+}
+
+/// Lists the update sequence array and, when `apply` is set, restores the
+/// last two bytes of each sector. Returns whether the fixups were applied;
+/// they aren't when the array is invalid.
+fn write_fixups(out: &mut dyn Write, record: &mut [u8], sector_size: usize, apply: bool) -> io::Result<bool> {
+    let usa_offset = raw::u16_at(record, 0x04).unwrap_or(0) as usize;
+    let usa_count = raw::u16_at(record, 0x06).unwrap_or(0) as usize;
+    if usa_count == 0 || sector_size < 2 || usa_offset + usa_count * 2 > record.len() {
+        writeln!(out, "  invalid update sequence array")?;
+        return Ok(false);
+    }
+
+    let sequence = [record[usa_offset], record[usa_offset + 1]];
+    writeln!(out, "  0x{:03x}  update sequence number  {:02x} {:02x}", usa_offset, sequence[0], sequence[1])?;
+    for i in 1..usa_count {
+        let end = i * sector_size;
+        if end > record.len() {
+            break;
+        }
+        let fixup = usa_offset + i * 2;
+        let stored = [record[end - 2], record[end - 1]];
+        let original = [record[fixup], record[fixup + 1]];
+        writeln!(
+            out,
+            "  0x{:03x}  sector {:<3} stored {:02x} {:02x}, original {:02x} {:02x}{}",
+            end - 2,
+            i,
+            stored[0],
+            stored[1],
+            original[0],
+            original[1],
+            if stored == sequence { "" } else { "  MISMATCH (torn write)" }
+        )?;
+        if apply {
+            record[end - 2..end].copy_from_slice(&original);
+        }
+    }
+    Ok(apply)
+}
+
+/// Lists the attribute headers and returns where each region of the record
+/// ends, with its label: the header, then every attribute and the end marker
+fn write_attributes(out: &mut dyn Write, record: &[u8]) -> io::Result<Vec<(usize, String)>> {
+    let mut offset = raw::u16_at(record, 0x14).map_or(usize::MAX, usize::from);
+    if offset > record.len() {
+        writeln!(out, "  first attribute offset is past the end of the record")?;
+        return Ok(Vec::new());
+    }
+    let mut regions = vec![(offset, "header and update sequence array".to_string())];
+
+    loop {
+        if offset + 4 > record.len() {
+            writeln!(out, "  0x{:03x}  record ends without an end marker", offset)?;
+            break;
+        }
+        let type_id = raw::u32_at(record, offset).unwrap_or(ATTR_END);
+        if type_id == ATTR_END {
+            writeln!(out, "  0x{:03x}  end marker", offset)?;
+            regions.push((offset + 4, "end marker".to_string()));
+            break;
+        }
+        let length = raw::u32_at(record, offset + 4).unwrap_or(0) as usize;
+        if length < 0x18 || offset + length > record.len() {
+            writeln!(
+                out,
+                "  0x{:03x}  {} with invalid length {}: stopping",
+                offset,
+                attributes::type_name(type_id),
+                length
+            )?;
+            break;
+        }
+
+        let attr = &record[offset..offset + length];
+        let name_length = attr[9] as usize;
+        let name_offset = raw::u16_at(attr, 0x0A).unwrap_or(0) as usize;
+        let name = attr
+            .get(name_offset..name_offset + name_length * 2)
+            .filter(|_| name_length > 0)
+            .map(|name| format!(" \"{}\"", raw::utf16_lossy(name)))
+            .unwrap_or_default();
+        let label = format!("{}{} at 0x{:03x}", attributes::type_name(type_id), name, offset);
+        writeln!(
+            out,
+            "  0x{:03x}  {:<40} length {:<5} id {:<3} {}",
+            offset,
+            format!("{}{}", attributes::type_name(type_id), name),
+            length,
+            raw::u16_at(attr, 0x0E).unwrap_or(0),
+            if attr[8] == 0 { "resident" } else { "non-resident" }
+        )?;
+
+        offset += length;
+        regions.push((offset, label));
+    }
+    Ok(regions)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A 1024-byte record with two 512-byte sectors, one resident
+    /// $STANDARD_INFORMATION and the end marker
+    fn record() -> Vec<u8> {
+        let mut record = vec![0u8; 1024];
+        record[..4].copy_from_slice(b"FILE");
+        record[0x04..0x06].copy_from_slice(&0x30u16.to_le_bytes());
+        record[0x06..0x08].copy_from_slice(&3u16.to_le_bytes());
+        record[0x14..0x16].copy_from_slice(&0x38u16.to_le_bytes());
+        record[0x16..0x18].copy_from_slice(&1u16.to_le_bytes());
+        // Update sequence number 0xAB 0x01, with original bytes 11 22 and 33 44
+        record[0x30..0x36].copy_from_slice(&[0xAB, 0x01, 0x11, 0x22, 0x33, 0x44]);
+        for end in [512, 1024] {
+            record[end - 2..end].copy_from_slice(&[0xAB, 0x01]);
+        }
+        record[0x38..0x3C].copy_from_slice(&0x10u32.to_le_bytes());
+        record[0x3C..0x40].copy_from_slice(&0x60u32.to_le_bytes());
+        record[0x98..0x9C].copy_from_slice(&ATTR_END.to_le_bytes());
+        record
+    }
+
+    fn dump(record: &mut [u8]) -> (String, bool, Vec<(usize, String)>) {
+        let mut out = Vec::new();
+        write_header(&mut out, record).unwrap();
+        let fixed = write_fixups(&mut out, record, 512, true).unwrap();
+        let regions = write_attributes(&mut out, record).unwrap();
+        (String::from_utf8(out).unwrap(), fixed, regions)
+    }
+
+    #[test]
+    fn records_are_split_at_their_attributes() {
+        let mut record = record();
+        let (text, fixed, regions) = dump(&mut record);
+        assert!(fixed);
+        assert_eq!(&record[510..512], &[0x11, 0x22]);
+        assert_eq!(&record[1022..1024], &[0x33, 0x44]);
+        assert!(text.contains("(in_use)"));
+        assert!(!text.contains("MISMATCH"));
+        let ends: Vec<usize> = regions.iter().map(|(end, _)| *end).collect();
+        assert_eq!(ends, [0x38, 0x98, 0x9C]);
+    }
+
+    #[test]
+    fn damaged_records_are_described_instead_of_read_past() {
+        // A torn write: the second sector doesn't end with the sequence number
+        let mut torn = record();
+        torn[1023] = 0;
+        assert!(dump(&mut torn).0.contains("MISMATCH (torn write)"));
+
+        // An update sequence array running past the record
+        let mut usa = record();
+        usa[0x06..0x08].copy_from_slice(&u16::MAX.to_le_bytes());
+        let (text, fixed, _) = dump(&mut usa);
+        assert!(!fixed && text.contains("invalid update sequence array"));
+
+        // Attributes with a length that is too short, or past the record
+        for length in [0, 0x17, 0x10000] {
+            let mut record = record();
+            record[0x3C..0x40].copy_from_slice(&(length as u32).to_le_bytes());
+            let (text, _, regions) = dump(&mut record);
+            assert!(text.contains("invalid length"), "{}", length);
+            assert_eq!(regions.len(), 1);
+        }
+
+        // No end marker, and a first attribute past the end
+        let mut unterminated = record();
+        unterminated[0x98..0x9C].copy_from_slice(&0x30u32.to_le_bytes());
+        unterminated[0x9C..0xA0].copy_from_slice(&(1024u32 - 0x98).to_le_bytes());
+        assert!(dump(&mut unterminated).0.contains("record ends without an end marker"));
+        let mut past = record();
+        past[0x14..0x16].copy_from_slice(&0x800u16.to_le_bytes());
+        assert!(dump(&mut past).0.contains("past the end of the record"));
+
+        // Records cut short, down to nothing, still dump what they have
+        for len in [0, 3, 0x20, 0x40] {
+            let mut short = record()[..len].to_vec();
+            let (text, fixed, _) = dump(&mut short);
+            assert!(text.contains("signature"), "{}", len);
+            assert_eq!(fixed, len >= 0x36, "{}", len);
+        }
+    }
+}
@@ -0,0 +1,306 @@
+use anyhow::{bail, Context, Result};
+use ntfs_reader::api::{NtfsAttributeType, FIRST_NORMAL_RECORD};
+use ntfs_reader::file::NtfsFile;
+use ntfs_reader::mft::Mft;
+use serde::Serialize;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use crate::mft::{self, DeletedPaths};
+use crate::output;
+use crate::raw::{self, RawVolume};
+
+/// What `recover` was asked to do
+pub enum Recovery {
+    /// Every deleted file with resident data (--all-resident), into a directory
+    AllResident(PathBuf),
+    /// One deleted record (--record), into a file
+    Record(u64, PathBuf),
+}
+
+impl Recovery {
+    /// The recovery asked for by --all-resident/--out-dir or --record/--out,
+    /// if one of them came with its destination
+    pub fn from_flags(
+        all_resident: bool,
+        out_dir: Option<PathBuf>,
+        record: Option<u64>,
+        out: Option<PathBuf>,
+    ) -> Option<Self> {
+        match (record, out, all_resident, out_dir) {
+            (Some(number), Some(out), _, _) => Some(Self::Record(number, out)),
+            (None, _, true, Some(out_dir)) => Some(Self::AllResident(out_dir)),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct RecoveredFile {
+    pub record_number: u64,
+    pub name: String,
+    /// Path the file had before it was deleted, as far as it can be rebuilt
+    pub original_path: String,
+    pub size: u64,
+    pub recovered_to: String,
+}
+
+/// Clusters of a deleted file that $Bitmap now marks as allocated
+#[derive(Debug, Serialize)]
+pub struct ClusterRange {
+    pub lcn: u64,
+    pub clusters: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RecoveredRecord {
+    pub record_number: u64,
+    pub name: String,
+    pub size: u64,
+    pub recovered_to: String,
+    /// Clusters the file's data runs point to; 0 for resident data
+    pub clusters: u64,
+    /// Clusters since reused by other files, written as zeros
+    pub reallocated_clusters: u64,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub reallocated: Vec<ClusterRange>,
+}
+
+/// Recovers deleted record `number` by reading its unnamed $DATA from raw
+/// clusters into `out`. Clusters that $Bitmap marks as allocated again
+/// belong to another file by now; they are written as zeros and reported,
+/// so the content may be partial. Prints one JSON line describing the result.
+pub fn record(volume: &str, number: u64, out: &Path) -> Result<()> {
+    let mut raw = RawVolume::open(&mft::normalize_volume_path(volume))?;
+    let runs = raw.mft_runs()?;
+    let record = raw
+        .read_file_record(&runs, number)
+        .context(format!("Failed to read file record {}", number))?;
+    let parsed = raw::parse_file_record(&record);
+    if parsed.in_use {
+        bail!("Record {} is in use; only deleted files can be recovered", number);
+    }
+    if parsed.is_directory {
+        bail!("Record {} is a directory", number);
+    }
+    let attr = raw::find_attribute(&record, raw::ATTR_DATA)
+        .context(format!("Record {} has no $DATA attribute", number))?;
+
+    // Reallocated clusters with the position (VCN) they have in the file
+    let mut reallocated: Vec<(u64, ClusterRange)> = Vec::new();
+    let mut clusters = 0;
+    if attr[8] != 0 {
+        let bitmap_record = raw
+            .read_file_record(&runs, raw::BITMAP_RECORD)
+            .context("Failed to read the $Bitmap record")?;
+        let bitmap_attr = raw::find_attribute(&bitmap_record, raw::ATTR_DATA)
+            .context("$Bitmap has no $DATA attribute")?;
+
+        let mut vcn = 0u64;
+        for (lcn, length) in raw::decode_runs(raw::mapping_pairs(attr)?)? {
+            let Some(lcn) = lcn else {
+                vcn = vcn.saturating_add(length);
+                continue;
+            };
+            clusters += length;
+            // Only the $Bitmap bytes covering this run, a piece at a time;
+            // clusters past the end of $Bitmap don't exist and count as free
+            let mut done = 0;
+            while done < length {
+                let first = lcn.saturating_add(done);
+                let count = (length - done).min(BITMAP_PIECE_CLUSTERS - first % 8);
+                let bitmap = raw.read_range(bitmap_attr, first / 8, (first % 8 + count).div_ceil(8))?;
+                for (start, run) in allocated_ranges(&bitmap, first % 8, count) {
+                    let cluster = first + start;
+                    let position = vcn + done + start;
+                    match reallocated.last_mut() {
+                        Some((vcn, range))
+                            if range.lcn + range.clusters == cluster && *vcn + range.clusters == position =>
+                        {
+                            range.clusters += run
+                        }
+                        _ => reallocated.push((position, ClusterRange { lcn: cluster, clusters: run })),
+                    }
+                }
+                done += count;
+            }
+            vcn = vcn.saturating_add(length);
+        }
+    }
+
+    let file = File::create(out).context(format!("Failed to create {}", out.display()))?;
+    let mut writer = BufWriter::new(file);
+    raw.read_value(attr, |chunk| Ok(writer.write_all(chunk)?))?;
+    let mut file = writer.into_inner().map_err(|e| e.into_error())?;
+
+    // Whatever reallocated clusters hold now is another file's data
+    for (vcn, range) in &reallocated {
+        let start = vcn * raw.bytes_per_cluster;
+        if start >= parsed.size {
+            continue;
+        }
+        let length = (range.clusters * raw.bytes_per_cluster).min(parsed.size - start);
+        file.seek(SeekFrom::Start(start))?;
+        io::copy(&mut io::repeat(0).take(length), &mut file)?;
+    }
+    file.flush()?;
+
+    let recovered = RecoveredRecord {
+        record_number: number,
+        name: parsed.name.clone().unwrap_or_default(),
+        size: parsed.size,
+        recovered_to: out.display().to_string(),
+        clusters,
+        reallocated_clusters: reallocated.iter().map(|(_, range)| range.clusters).sum(),
+        reallocated: reallocated.into_iter().map(|(_, range)| range).collect(),
+    };
+    if recovered.reallocated_clusters > 0 {
+        eprintln!(
+            "{} of {} clusters were reused by other files; the content is partial",
+            recovered.reallocated_clusters, clusters
+        );
+    }
+    println!("{}", output::to_json(&recovered)?);
+    Ok(())
+}
+
+/// Clusters whose allocation is checked per read of $Bitmap
+const BITMAP_PIECE_CLUSTERS: u64 = 8 * 1024 * 1024;
+
+/// Ranges of allocated clusters, as (offset, count), among the `count`
+/// clusters whose bits start at bit `first_bit` of `bitmap`. Bytes with no
+/// bit set are skipped whole; missing bytes count as free.
+fn allocated_ranges(bitmap: &[u8], first_bit: u64, count: u64) -> Vec<(u64, u64)> {
+    let mut ranges: Vec<(u64, u64)> = Vec::new();
+    let mut i = 0;
+    while i < count {
+        let bit = first_bit + i;
+        let byte = bitmap.get((bit / 8) as usize).copied().unwrap_or(0);
+        if byte == 0 {
+            i += 8 - bit % 8;
+            continue;
+        }
+        if byte & (1 << (bit % 8)) != 0 {
+            match ranges.last_mut() {
+                Some((start, length)) if *start + *length == i => *length += 1,
+                _ => ranges.push((i, 1)),
+            }
+        }
+        i += 1;
+    }
+    ranges
+}
+
+/// Walks every deleted record of the volume and writes the content of those
+/// whose unnamed $DATA is resident (stored inside the record itself, which
+/// is the case for files up to roughly 700 bytes) into `out_dir`. Files are
+/// named `<record number>_<original name>`; one JSON line is printed per
+/// recovered file.
+pub fn all_resident(volume: &str, out_dir: &Path) -> Result<()> {
+    fs::create_dir_all(out_dir)
+        .context(format!("Failed to create output directory {}", out_dir.display()))?;
+    let mft = mft::open_mft(volume)?;
+
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    let mut deleted = 0u64;
+    let mut recovered = 0u64;
+    let mut deleted_paths = DeletedPaths::new(&mft);
+
+    eprintln!("Scanning deleted records...");
+    // Deleted records are the likeliest to be damaged, so reading them is
+    // isolated like in every other scan
+    let _quiet = mft::QuietPanics::new();
+    for number in FIRST_NORMAL_RECORD..mft.max_record {
+        let Some(file) = mft::isolate(number, || mft.get_record(number)).flatten() else {
+            continue;
+        };
+        if file.is_used() || file.is_directory() {
+            continue;
+        }
+        deleted += 1;
+
+        let resident = mft::isolate(number, || resident_file(&mft, &mut deleted_paths, &file));
+        let Some(Some(resident)) = resident else {
+            continue;
+        };
+        let target = out_dir.join(format!("{}_{}", number, sanitize_name(&resident.name)));
+        fs::write(&target, &resident.data).context(format!("Failed to write {}", target.display()))?;
+        recovered += 1;
+
+        let entry = RecoveredFile {
+            record_number: number,
+            name: resident.name,
+            original_path: output::display_path(&resident.original_path),
+            size: resident.data.len() as u64,
+            recovered_to: target.display().to_string(),
+        };
+        writeln!(out, "{}", output::to_json(&entry)?)?;
+    }
+    out.flush()?;
+
+    eprintln!(
+        "Recovered {} of {} deleted files (the others are non-resident)",
+        recovered, deleted
+    );
+    Ok(())
+}
+
+/// A deleted file whose content is resident in its record
+struct ResidentFile {
+    name: String,
+    original_path: PathBuf,
+    data: Vec<u8>,
+}
+
+/// The name, former path and content of a deleted file, or `None` when its
+/// data isn't resident
+fn resident_file(mft: &Mft, deleted_paths: &mut DeletedPaths, file: &NtfsFile) -> Option<ResidentFile> {
+    let data = resident_data(file)?;
+    Some(ResidentFile {
+        name: mft::parse_file_info(mft, file)?.name,
+        original_path: deleted_paths.path(mft, file),
+        data,
+    })
+}
+
+/// The value of the unnamed, resident $DATA attribute of a record
+pub fn resident_data(file: &NtfsFile) -> Option<Vec<u8>> {
+    let mut value = None;
+    file.attributes(|attr| {
+        if value.is_some()
+            || attr.header.type_id != NtfsAttributeType::Data as u32
+            || attr.header.name_length != 0
+            || attr.header.is_non_resident != 0
+        {
+            return;
+        }
+        let Some(header) = attr.resident_header() else {
+            return;
+        };
+        let offset = header.value_offset as usize;
+        value = attr
+            .data()
+            .get(offset..offset + header.value_length as usize)
+            .map(|v| v.to_vec());
+    });
+    value
+}
+
+/// Replaces characters Windows doesn't allow in file names
+fn sanitize_name(name: &str) -> String {
+    let name: String = name
+        .chars()
+        .map(|c| match c {
+            '<' | '>' | ':' | '"' | '/' | '\\' | '|' | '?' | '*' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect();
+    if name.is_empty() {
+        "unnamed".to_string()
+    } else {
+        name
+    }
+}
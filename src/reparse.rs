@@ -0,0 +1,186 @@
+use ntfs_reader::file::NtfsFile;
+use serde::{Deserialize, Serialize};
+
+use crate::raw;
+
+const IO_REPARSE_TAG_MOUNT_POINT: u32 = 0xA000_0003;
+const IO_REPARSE_TAG_SYMLINK: u32 = 0xA000_000C;
+const IO_REPARSE_TAG_DEDUP: u32 = 0x8000_0013;
+const IO_REPARSE_TAG_WOF: u32 = 0x8000_0017;
+const IO_REPARSE_TAG_APPEXECLINK: u32 = 0x8000_001B;
+const IO_REPARSE_TAG_LX_SYMLINK: u32 = 0xA000_001D;
+/// Cloud files placeholders (OneDrive and other sync engines) use
+/// 0x9000001A with a provider-specific value in bits 12-15
+const IO_REPARSE_TAG_CLOUD: u32 = 0x9000_001A;
+const IO_REPARSE_TAG_CLOUD_MASK: u32 = 0xFFFF_0FFF;
+
+/// Symlink flag for targets relative to the link's directory
+const SYMLINK_FLAG_RELATIVE: u32 = 0x0000_0001;
+
+/// Decoded $REPARSE_POINT attribute of a record
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReparsePoint {
+    /// Raw IO_REPARSE_TAG_* value
+    pub tag: u32,
+    /// symlink, junction, mount_point, cloud, wsl_symlink, app_exec_link,
+    /// dedup, wof or unknown
+    pub kind: String,
+    /// Where the reparse point leads, for the kinds that have a target
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub target: Option<String>,
+    /// Whether a symlink target is relative to the link's directory
+    #[serde(skip_serializing_if = "std::ops::Not::not", default)]
+    pub relative: bool,
+}
+
+/// The reparse point of a file, when it has a resident $REPARSE_POINT
+pub fn read(file: &NtfsFile) -> Option<ReparsePoint> {
+    let mut reparse = None;
+    file.attributes(|attr| {
+        if reparse.is_none() && attr.header.type_id == raw::ATTR_REPARSE_POINT {
+            reparse = raw::resident_value(attr.data()).and_then(decode);
+        }
+    });
+    reparse
+}
+
+/// Decodes a REPARSE_DATA_BUFFER: tag, data length, reserved, then data
+fn decode(value: &[u8]) -> Option<ReparsePoint> {
+    if value.len() < 8 {
+        return None;
+    }
+    let tag = raw::u32_at(value, 0)?;
+    let length = raw::u16_at(value, 4)? as usize;
+    let data = value.get(8..8 + length).unwrap_or(&value[8..]);
+
+    let mut reparse = ReparsePoint {
+        tag,
+        kind: "unknown".to_string(),
+        target: None,
+        relative: false,
+    };
+    let kind = match tag {
+        IO_REPARSE_TAG_MOUNT_POINT => {
+            reparse.target = substitute_name(data, 8);
+            // Volume mount points target a volume GUID path instead of a directory
+            let volume = reparse.target.as_deref().is_some_and(|t| t.starts_with("\\??\\Volume{"));
+            if volume { "mount_point" } else { "junction" }
+        }
+        IO_REPARSE_TAG_SYMLINK => {
+            reparse.target = substitute_name(data, 12);
+            reparse.relative = raw::u32_at(data, 8).is_some_and(|flags| flags & SYMLINK_FLAG_RELATIVE != 0);
+            "symlink"
+        }
+        IO_REPARSE_TAG_LX_SYMLINK => {
+            // Version, then the UTF-8 target
+            reparse.target = data.get(4..).map(|t| String::from_utf8_lossy(t).into_owned());
+            "wsl_symlink"
+        }
+        IO_REPARSE_TAG_APPEXECLINK => {
+            // Version, then NUL-terminated package ID, app ID and target executable
+            let strings = data.get(4..).map(utf16_strings).unwrap_or_default();
+            reparse.target = strings.into_iter().nth(2);
+            "app_exec_link"
+        }
+        IO_REPARSE_TAG_DEDUP => "dedup",
+        IO_REPARSE_TAG_WOF => "wof",
+        _ if tag & IO_REPARSE_TAG_CLOUD_MASK == IO_REPARSE_TAG_CLOUD => "cloud",
+        _ => "unknown",
+    };
+    reparse.kind = kind.to_string();
+    Some(reparse)
+}
+
+/// The substitute name of a mount point or symlink buffer, whose path
+/// buffer starts at `buffer` and is addressed by the offset and length
+/// that open the data. Falls back to the print name when that is empty.
+fn substitute_name(data: &[u8], buffer: usize) -> Option<String> {
+    let name = |offset_at: usize| -> Option<String> {
+        let offset = raw::u16_at(data, offset_at)? as usize;
+        let length = raw::u16_at(data, offset_at + 2)? as usize;
+        let bytes = data.get(buffer + offset..buffer + offset + length)?;
+        Some(raw::utf16_lossy(bytes)).filter(|name| !name.is_empty())
+    };
+    name(0).or_else(|| name(4))
+}
+
+fn utf16_strings(bytes: &[u8]) -> Vec<String> {
+    raw::utf16_lossy(bytes).split('\0').map(str::to_string).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utf16(s: &str) -> Vec<u8> {
+        s.encode_utf16().flat_map(u16::to_le_bytes).collect()
+    }
+
+    /// A REPARSE_DATA_BUFFER of `tag` holding `data`
+    fn buffer(tag: u32, data: &[u8]) -> Vec<u8> {
+        let mut value = tag.to_le_bytes().to_vec();
+        value.extend_from_slice(&(data.len() as u16).to_le_bytes());
+        value.extend_from_slice(&[0, 0]);
+        value.extend_from_slice(data);
+        value
+    }
+
+    /// Data of a mount point (no flags) or symlink (with flags) buffer
+    fn link_data(substitute: &str, print: &str, flags: Option<u32>) -> Vec<u8> {
+        let (substitute, print) = (utf16(substitute), utf16(print));
+        let mut data = Vec::new();
+        for value in [0, substitute.len(), substitute.len(), print.len()] {
+            data.extend_from_slice(&(value as u16).to_le_bytes());
+        }
+        if let Some(flags) = flags {
+            data.extend_from_slice(&flags.to_le_bytes());
+        }
+        data.extend_from_slice(&substitute);
+        data.extend_from_slice(&print);
+        data
+    }
+
+    #[test]
+    fn links_decode_with_their_targets() {
+        let junction = decode(&buffer(IO_REPARSE_TAG_MOUNT_POINT, &link_data("\\??\\D:\\data", "D:\\data", None))).unwrap();
+        assert_eq!((junction.kind.as_str(), junction.target.as_deref()), ("junction", Some("\\??\\D:\\data")));
+
+        let volume = link_data("\\??\\Volume{1234}\\", "", None);
+        assert_eq!(decode(&buffer(IO_REPARSE_TAG_MOUNT_POINT, &volume)).unwrap().kind, "mount_point");
+
+        let symlink = decode(&buffer(IO_REPARSE_TAG_SYMLINK, &link_data("", "..\\lib", Some(1)))).unwrap();
+        assert_eq!((symlink.kind.as_str(), symlink.target.as_deref()), ("symlink", Some("..\\lib")));
+        assert!(symlink.relative);
+
+        let mut app = 3u32.to_le_bytes().to_vec();
+        app.extend(utf16("Package\0App\0C:\\app.exe\0"));
+        let app = decode(&buffer(IO_REPARSE_TAG_APPEXECLINK, &app)).unwrap();
+        assert_eq!(app.target.as_deref(), Some("C:\\app.exe"));
+
+        assert_eq!(decode(&buffer(0x9000_301A, &[])).unwrap().kind, "cloud");
+        assert_eq!(decode(&buffer(0x1234, &[])).unwrap().kind, "unknown");
+    }
+
+    #[test]
+    fn malformed_buffers_decode_without_targets() {
+        assert!(decode(&[]).is_none());
+        assert!(decode(&IO_REPARSE_TAG_SYMLINK.to_le_bytes()).is_none());
+
+        // Name offsets and lengths pointing past the data
+        let mut data = link_data("C:\\target", "", Some(0));
+        data[0..2].copy_from_slice(&0xFFF0u16.to_le_bytes());
+        let symlink = decode(&buffer(IO_REPARSE_TAG_SYMLINK, &data)).unwrap();
+        assert_eq!((symlink.kind.as_str(), symlink.target), ("symlink", None));
+
+        // A data length larger than the buffer, and data too short for the names
+        let mut value = buffer(IO_REPARSE_TAG_MOUNT_POINT, &[4, 0]);
+        value[4..6].copy_from_slice(&u16::MAX.to_le_bytes());
+        let junction = decode(&value).unwrap();
+        assert_eq!(junction.target, None);
+
+        // A name cut off by the end of the data, and a WSL symlink with no room for its version
+        let cut = decode(&buffer(IO_REPARSE_TAG_MOUNT_POINT, &link_data("ab", "", None)[..9])).unwrap();
+        assert_eq!(cut.target, None);
+        assert_eq!(decode(&buffer(IO_REPARSE_TAG_LX_SYMLINK, &[2, 0])).unwrap().target, None);
+    }
+}
@@ -0,0 +1,342 @@
+use anyhow::{bail, Context, Result};
+use memmap2::MmapMut;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{fence, Ordering};
+
+use crate::output::Warning;
+use crate::units;
+
+/// Signature at the start of every ring file
+const MAGIC: &[u8; 8] = b"USNRING\0";
+const VERSION: u32 = 1;
+
+/// Size of the header in front of the data area. Offsets in it are
+/// absolute byte counts since the ring was created; their position in the
+/// data area is the offset modulo the capacity.
+const HEADER_LEN: usize = 64;
+const CAPACITY_AT: usize = 16;
+/// End of the newest record
+const HEAD_AT: usize = 24;
+/// Start of the oldest record still retained
+const TAIL_AT: usize = 32;
+/// Records between tail and head
+const RECORDS_AT: usize = 40;
+/// Records ever written, including those since overwritten
+const WRITTEN_AT: usize = 48;
+
+/// Each record is its length as a little-endian u32 followed by the event
+const LENGTH_PREFIX: usize = 4;
+
+/// Ring buffer destination given as `ring:PATH[,size=SIZE]`
+#[derive(Clone, Debug)]
+pub struct RingSpec {
+    pub path: PathBuf,
+    /// Bytes available to records, header excluded
+    pub size: u64,
+}
+
+impl RingSpec {
+    const DEFAULT_SIZE: u64 = 64 * 1024 * 1024;
+}
+
+impl std::str::FromStr for RingSpec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let spec = s
+            .strip_prefix("ring:")
+            .ok_or_else(|| format!("Invalid sink (expected ring:PATH[,size=SIZE]): {}", s))?;
+        let (path, size) = match spec.rsplit_once(",size=") {
+            Some((path, size)) => (path, units::parse_size(size)?),
+            None => (spec, Self::DEFAULT_SIZE),
+        };
+        if path.is_empty() {
+            return Err(format!("Invalid sink (missing path): {}", s));
+        }
+        if size < 4096 {
+            return Err(format!("Ring buffer size must be at least 4K: {}", s));
+        }
+        Ok(RingSpec { path: PathBuf::from(path), size })
+    }
+}
+
+/// Fixed-size, memory-mapped file that keeps the most recent records.
+/// Writing a record costs the same whatever the ring holds: the oldest
+/// records are dropped from the tail until the new one fits.
+pub struct RingWriter {
+    map: MmapMut,
+    capacity: u64,
+    head: u64,
+    tail: u64,
+    records: u64,
+    written: u64,
+}
+
+impl RingWriter {
+    /// Opens the ring at `spec.path`, continuing an existing ring of the
+    /// same size so history survives restarts, or creates a new one
+    pub fn open(spec: &RingSpec) -> Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&spec.path)
+            .context(format!("Failed to open ring buffer {}", spec.path.display()))?;
+        let length = HEADER_LEN as u64 + spec.size;
+        let existing = file.metadata()?.len();
+        if existing != length {
+            if existing > 0 {
+                let message = format!(
+                    "{} has a different size and was reset to {} bytes",
+                    spec.path.display(),
+                    length
+                );
+                Warning::new("ring_reset", message).emit();
+            }
+            file.set_len(0)?;
+            file.set_len(length)?;
+        }
+
+        // SAFETY: the file stays open and at this length for the lifetime of
+        // the map; other processes may only read it
+        let mut map = unsafe { MmapMut::map_mut(&file) }
+            .context(format!("Failed to map ring buffer {}", spec.path.display()))?;
+
+        let matches = &map[..8] == MAGIC && u64_at(&map, CAPACITY_AT) == spec.size;
+        // A header left inconsistent by a crash or another program would
+        // have the writer drop records past the head or overwrite live ones
+        let valid = matches && header_is_consistent(&map);
+        if matches && !valid {
+            let message = format!("{} has an inconsistent header and was reset", spec.path.display());
+            Warning::new("ring_reset", message).emit();
+        }
+        if !valid {
+            map[..HEADER_LEN].fill(0);
+            map[..8].copy_from_slice(MAGIC);
+            map[8..12].copy_from_slice(&VERSION.to_le_bytes());
+            map[CAPACITY_AT..CAPACITY_AT + 8].copy_from_slice(&spec.size.to_le_bytes());
+        }
+
+        Ok(RingWriter {
+            capacity: spec.size,
+            head: u64_at(&map, HEAD_AT),
+            tail: u64_at(&map, TAIL_AT),
+            records: u64_at(&map, RECORDS_AT),
+            written: u64_at(&map, WRITTEN_AT),
+            map,
+        })
+    }
+
+    /// Appends one record, dropping the oldest ones it needs room from
+    pub fn push(&mut self, record: &[u8]) -> Result<()> {
+        let length = (LENGTH_PREFIX + record.len()) as u64;
+        if length > self.capacity {
+            let message = format!("event of {} bytes doesn't fit the ring buffer", record.len());
+            Warning::new("ring_overflow", message).emit();
+            return Ok(());
+        }
+
+        while self.head + length - self.tail > self.capacity {
+            let mut prefix = [0u8; LENGTH_PREFIX];
+            self.read_wrapping(self.tail, &mut prefix);
+            self.tail += (LENGTH_PREFIX + u32::from_le_bytes(prefix) as usize) as u64;
+            self.records -= 1;
+        }
+        // Readers drop whatever lies before the tail, so it moves first
+        self.store(TAIL_AT, self.tail);
+        fence(Ordering::Release);
+
+        self.write_wrapping(self.head, &(record.len() as u32).to_le_bytes());
+        self.write_wrapping(self.head + LENGTH_PREFIX as u64, record);
+        fence(Ordering::Release);
+
+        self.head += length;
+        self.records += 1;
+        self.written += 1;
+        self.store(RECORDS_AT, self.records);
+        self.store(WRITTEN_AT, self.written);
+        self.store(HEAD_AT, self.head);
+        Ok(())
+    }
+
+    /// Writes the mapped pages back to the file
+    pub fn flush(&self) -> Result<()> {
+        self.map.flush().context("Failed to flush ring buffer")
+    }
+
+    fn store(&mut self, at: usize, value: u64) {
+        self.map[at..at + 8].copy_from_slice(&value.to_le_bytes());
+    }
+
+    fn write_wrapping(&mut self, offset: u64, bytes: &[u8]) {
+        let data = &mut self.map[HEADER_LEN..];
+        let start = (offset % self.capacity) as usize;
+        let first = bytes.len().min(data.len() - start);
+        data[start..start + first].copy_from_slice(&bytes[..first]);
+        data[..bytes.len() - first].copy_from_slice(&bytes[first..]);
+    }
+
+    fn read_wrapping(&self, offset: u64, bytes: &mut [u8]) {
+        read_wrapping(&self.map[HEADER_LEN..], offset, bytes);
+    }
+}
+
+fn read_wrapping(data: &[u8], offset: u64, bytes: &mut [u8]) {
+    let start = (offset % data.len() as u64) as usize;
+    let first = bytes.len().min(data.len() - start);
+    bytes[..first].copy_from_slice(&data[start..start + first]);
+    let rest = bytes.len() - first;
+    bytes[first..].copy_from_slice(&data[..rest]);
+}
+
+/// Whether the offsets and counts in a ring header agree with each other and
+/// with the records between tail and head
+fn header_is_consistent(bytes: &[u8]) -> bool {
+    let capacity = u64_at(bytes, CAPACITY_AT);
+    let (head, tail) = (u64_at(bytes, HEAD_AT), u64_at(bytes, TAIL_AT));
+    let (records, written) = (u64_at(bytes, RECORDS_AT), u64_at(bytes, WRITTEN_AT));
+    if tail > head || head - tail > capacity || records > written {
+        return false;
+    }
+
+    let data = &bytes[HEADER_LEN..];
+    let mut offset = tail;
+    let mut count = 0;
+    while offset < head {
+        let mut prefix = [0u8; LENGTH_PREFIX];
+        read_wrapping(data, offset, &mut prefix);
+        offset += (LENGTH_PREFIX + u32::from_le_bytes(prefix) as usize) as u64;
+        count += 1;
+    }
+    offset == head && count == records
+}
+
+fn u64_at(data: &[u8], at: usize) -> u64 {
+    u64::from_le_bytes(data[at..at + 8].try_into().unwrap())
+}
+
+/// Prints the records of a ring file, oldest first, one per line. The ring
+/// may be written while it is read: records the writer overwrote in the
+/// meantime, found by reading the tail again, are left out.
+pub fn dump(path: &Path, last: Option<usize>) -> Result<()> {
+    let bytes = fs::read(path).context(format!("Failed to read ring buffer {}", path.display()))?;
+    if bytes.len() < HEADER_LEN || &bytes[..8] != MAGIC {
+        bail!("{} is not a ring buffer file", path.display());
+    }
+    let capacity = u64_at(&bytes, CAPACITY_AT);
+    if bytes.len() as u64 != HEADER_LEN as u64 + capacity {
+        bail!("{} is truncated", path.display());
+    }
+    let data = &bytes[HEADER_LEN..];
+    let head = u64_at(&bytes, HEAD_AT);
+    let tail = u64_at(&bytes, TAIL_AT);
+    if tail > head || head - tail > capacity {
+        bail!("{} is corrupt: tail {} and head {} don't fit the ring", path.display(), tail, head);
+    }
+
+    let mut offsets = Vec::new();
+    let mut offset = tail;
+    while offset < head {
+        let mut prefix = [0u8; LENGTH_PREFIX];
+        read_wrapping(data, offset, &mut prefix);
+        let length = (LENGTH_PREFIX + u32::from_le_bytes(prefix) as usize) as u64;
+        if length > capacity {
+            bail!("{} is corrupt: record at offset {} is too long", path.display(), offset);
+        }
+        offsets.push(offset);
+        offset += length;
+    }
+
+    let mut header = [0u8; HEADER_LEN];
+    File::open(path)?.read_exact(&mut header)?;
+    let tail = u64_at(&header, TAIL_AT);
+    offsets.retain(|&offset| offset >= tail);
+    if let Some(last) = last {
+        offsets.drain(..offsets.len().saturating_sub(last));
+    }
+
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    for offset in offsets {
+        let mut prefix = [0u8; LENGTH_PREFIX];
+        read_wrapping(data, offset, &mut prefix);
+        let mut record = vec![0u8; u32::from_le_bytes(prefix) as usize];
+        read_wrapping(data, offset + LENGTH_PREFIX as u64, &mut record);
+        out.write_all(&record)?;
+        writeln!(out)?;
+    }
+    out.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_ring(name: &str) -> RingSpec {
+        let path = std::env::temp_dir().join(format!("ring-test-{}-{}", std::process::id(), name));
+        let _ = fs::remove_file(&path);
+        RingSpec { path, size: 4096 }
+    }
+
+    fn set(path: &Path, at: usize, value: u64) {
+        let mut bytes = fs::read(path).unwrap();
+        bytes[at..at + 8].copy_from_slice(&value.to_le_bytes());
+        fs::write(path, bytes).unwrap();
+    }
+
+    #[test]
+    fn specs_need_a_path_and_a_usable_size() {
+        let spec: RingSpec = "ring:C:\\events.ring,size=1M".parse().unwrap();
+        assert_eq!((spec.path, spec.size), (PathBuf::from("C:\\events.ring"), 1024 * 1024));
+        assert_eq!("ring:events.ring".parse::<RingSpec>().unwrap().size, RingSpec::DEFAULT_SIZE);
+        for spec in ["events.ring", "ring:", "ring:,size=1M", "ring:a,size=1K", "ring:a,size=lots"] {
+            assert!(spec.parse::<RingSpec>().is_err(), "{}", spec);
+        }
+    }
+
+    #[test]
+    fn rings_wrap_and_survive_reopening() {
+        let spec = temp_ring("wrap");
+        let mut ring = RingWriter::open(&spec).unwrap();
+        for i in 0..100u8 {
+            ring.push(&[i; 100]).unwrap();
+        }
+        ring.flush().unwrap();
+        drop(ring);
+
+        let ring = RingWriter::open(&spec).unwrap();
+        assert_eq!((ring.records, ring.written), (39, 100));
+        assert!(ring.head - ring.tail <= ring.capacity);
+        let mut record = [0u8; 100];
+        ring.read_wrapping(ring.tail + LENGTH_PREFIX as u64, &mut record);
+        assert_eq!(record, [61; 100]);
+        drop(ring);
+        fs::remove_file(&spec.path).unwrap();
+    }
+
+    #[test]
+    fn inconsistent_headers_reset_the_ring() {
+        let spec = temp_ring("corrupt");
+
+        // Tail past the head, head past the capacity, a count that doesn't
+        // match the records and a head inside a record
+        for (at, value) in [(TAIL_AT, 100), (HEAD_AT, 5000), (RECORDS_AT, 3), (HEAD_AT, 12)] {
+            let mut ring = RingWriter::open(&spec).unwrap();
+            ring.push(b"first").unwrap();
+            ring.push(b"second").unwrap();
+            drop(ring);
+            set(&spec.path, at, value);
+
+            let ring = RingWriter::open(&spec).unwrap();
+            assert_eq!((ring.head, ring.tail, ring.records, ring.written), (0, 0, 0, 0));
+        }
+
+        set(&spec.path, TAIL_AT, 100);
+        assert!(dump(&spec.path, None).is_err());
+        fs::remove_file(&spec.path).unwrap();
+    }
+}
@@ -0,0 +1,489 @@
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use aes_gcm::{Aes256Gcm, Nonce};
+use anyhow::{bail, Context, Result};
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use serde::Serialize;
+use sha2::{Digest, Sha512};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+
+use crate::output;
+
+/// Signature at the start of encrypted files
+const MAGIC: &[u8; 8] = b"NTFSENC1";
+/// Plaintext bytes per encrypted chunk
+const CHUNK_SIZE: usize = 64 * 1024;
+/// Extension of detached signature files
+const SIGNATURE_EXTENSION: &str = "sig";
+
+/// Keys used when writing snapshots and captures
+#[derive(Default)]
+pub struct SealOptions {
+    pub encrypt_key: Option<[u8; 32]>,
+    pub sign_key: Option<SigningKey>,
+}
+
+impl SealOptions {
+    pub fn load(encrypt_key: Option<&Path>, sign_key: Option<&Path>) -> Result<Self> {
+        Ok(SealOptions {
+            encrypt_key: encrypt_key.map(load_key).transpose()?,
+            sign_key: sign_key
+                .map(|path| load_key(path).map(|key| SigningKey::from_bytes(&key)))
+                .transpose()?,
+        })
+    }
+}
+
+/// Reads a 32-byte key stored either raw or as 64 hex characters
+pub fn load_key(path: &Path) -> Result<[u8; 32]> {
+    let data = fs::read(path).context(format!("Failed to read key file {}", path.display()))?;
+    let key = match std::str::from_utf8(&data).map(str::trim) {
+        Ok(text) if text.len() == 64 => decode_hex(text),
+        _ => data.clone().try_into().ok(),
+    };
+    key.context(format!(
+        "Invalid key file {} (expected 32 raw bytes or 64 hex characters)",
+        path.display()
+    ))
+}
+
+/// Writes a new encryption key and Ed25519 key pair into `dir` as hex files
+pub fn generate_keys(dir: &Path) -> Result<()> {
+    fs::create_dir_all(dir).context(format!("Failed to create {}", dir.display()))?;
+
+    let encryption = Aes256Gcm::generate_key(OsRng);
+    let signing = SigningKey::generate(&mut OsRng);
+
+    for (name, key) in [
+        ("encryption.key", encryption.to_vec()),
+        ("signing.key", signing.to_bytes().to_vec()),
+        ("signing.pub", signing.verifying_key().to_bytes().to_vec()),
+    ] {
+        let path = dir.join(name);
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)
+            .context(format!("Failed to create {} (refusing to overwrite keys)", path.display()))?;
+        writeln!(file, "{}", encode_hex(&key))?;
+        eprintln!("Wrote {}", path.display());
+    }
+    Ok(())
+}
+
+/// A file writer that optionally encrypts. Encrypted output is a sequence of
+/// AES-256-GCM chunks; the last one is flagged so truncation is detected.
+pub enum SealedWriter<W: Write> {
+    Plain(W),
+    Encrypted(Box<EncryptWriter<W>>),
+}
+
+impl<W: Write> SealedWriter<W> {
+    pub fn new(inner: W, key: Option<&[u8; 32]>) -> io::Result<Self> {
+        match key {
+            Some(key) => Ok(SealedWriter::Encrypted(Box::new(EncryptWriter::new(inner, key)?))),
+            None => Ok(SealedWriter::Plain(inner)),
+        }
+    }
+
+    /// Completes the file; required for encrypted output to be readable
+    pub fn finish(self) -> io::Result<()> {
+        match self {
+            SealedWriter::Plain(mut w) => w.flush(),
+            SealedWriter::Encrypted(w) => w.finish(),
+        }
+    }
+}
+
+impl<W: Write> Write for SealedWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            SealedWriter::Plain(w) => w.write(buf),
+            SealedWriter::Encrypted(w) => w.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            SealedWriter::Plain(w) => w.flush(),
+            SealedWriter::Encrypted(w) => w.flush(),
+        }
+    }
+}
+
+/// Nonces are an 8-byte random prefix chosen per file followed by the
+/// big-endian chunk counter, so they never repeat within a file
+pub struct EncryptWriter<W: Write> {
+    inner: W,
+    cipher: Aes256Gcm,
+    prefix: [u8; 8],
+    counter: u32,
+    buffer: Vec<u8>,
+}
+
+impl<W: Write> EncryptWriter<W> {
+    fn new(mut inner: W, key: &[u8; 32]) -> io::Result<Self> {
+        let mut prefix = [0u8; 8];
+        prefix.copy_from_slice(&Aes256Gcm::generate_nonce(&mut OsRng)[..8]);
+        inner.write_all(MAGIC)?;
+        inner.write_all(&prefix)?;
+
+        Ok(EncryptWriter {
+            inner,
+            cipher: Aes256Gcm::new(key.into()),
+            prefix,
+            counter: 0,
+            buffer: Vec::with_capacity(CHUNK_SIZE),
+        })
+    }
+
+    /// Chunk layout: u32 LE ciphertext length, a last-chunk flag byte (also
+    /// authenticated as associated data), then the ciphertext
+    fn write_chunk(&mut self, last: bool) -> io::Result<()> {
+        let nonce = chunk_nonce(&self.prefix, self.counter);
+        let flag = [last as u8];
+        let ciphertext = self
+            .cipher
+            .encrypt(&nonce, Payload { msg: &self.buffer, aad: &flag })
+            .map_err(|_| io::Error::other("Encryption failed"))?;
+        self.counter = self
+            .counter
+            .checked_add(1)
+            .ok_or_else(|| io::Error::other("Encrypted file too large"))?;
+
+        self.inner.write_all(&(ciphertext.len() as u32).to_le_bytes())?;
+        self.inner.write_all(&flag)?;
+        self.inner.write_all(&ciphertext)?;
+        self.buffer.clear();
+        Ok(())
+    }
+
+    fn finish(mut self) -> io::Result<()> {
+        self.write_chunk(true)?;
+        self.inner.flush()
+    }
+}
+
+impl<W: Write> Write for EncryptWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = buf.len().min(CHUNK_SIZE - self.buffer.len());
+        self.buffer.extend_from_slice(&buf[..n]);
+        if self.buffer.len() == CHUNK_SIZE {
+            self.write_chunk(false)?;
+        }
+        Ok(n)
+    }
+
+    /// Seals whatever is buffered as a chunk, so flushed data survives an
+    /// interrupted run
+    fn flush(&mut self) -> io::Result<()> {
+        if !self.buffer.is_empty() {
+            self.write_chunk(false)?;
+        }
+        self.inner.flush()
+    }
+}
+
+fn chunk_nonce(prefix: &[u8; 8], counter: u32) -> Nonce<aes_gcm::aead::consts::U12> {
+    let mut nonce = [0u8; 12];
+    nonce[..8].copy_from_slice(prefix);
+    nonce[8..].copy_from_slice(&counter.to_be_bytes());
+    nonce.into()
+}
+
+/// Opens a file for reading, decrypting it transparently when it was
+/// written encrypted
+pub fn open_reader(path: &Path, key: Option<&[u8; 32]>) -> Result<Box<dyn Read>> {
+    let mut file = File::open(path).context(format!("Failed to open {}", path.display()))?;
+    let mut magic = [0u8; 8];
+    let read = read_full(&mut file, &mut magic)?;
+
+    if read == MAGIC.len() && &magic == MAGIC {
+        let Some(key) = key else {
+            bail!("{} is encrypted; pass --encrypt-key", path.display());
+        };
+        let mut prefix = [0u8; 8];
+        file.read_exact(&mut prefix).context("Truncated encryption header")?;
+        return Ok(Box::new(DecryptReader {
+            inner: BufReader::new(file),
+            cipher: Aes256Gcm::new(key.into()),
+            prefix,
+            counter: 0,
+            plaintext: Vec::new(),
+            position: 0,
+            done: false,
+        }));
+    }
+
+    // Not encrypted: hand back the bytes consumed while sniffing
+    Ok(Box::new(io::Cursor::new(magic[..read].to_vec()).chain(BufReader::new(file))))
+}
+
+struct DecryptReader<R: Read> {
+    inner: R,
+    cipher: Aes256Gcm,
+    prefix: [u8; 8],
+    counter: u32,
+    plaintext: Vec<u8>,
+    position: usize,
+    done: bool,
+}
+
+impl<R: Read> DecryptReader<R> {
+    /// Decrypts the next chunk. Running out of data before the last chunk
+    /// means the file was cut short or tampered with, which unlike the end
+    /// of a plain file can be told apart from a complete one, so it is
+    /// reported as `InvalidData` rather than `UnexpectedEof`.
+    fn next_chunk(&mut self) -> io::Result<()> {
+        let truncated = |e: io::Error| match e.kind() {
+            io::ErrorKind::UnexpectedEof => {
+                io::Error::new(io::ErrorKind::InvalidData, "Encrypted file is truncated")
+            }
+            _ => e,
+        };
+        let mut header = [0u8; 5];
+        self.inner.read_exact(&mut header).map_err(truncated)?;
+        let length = u32::from_le_bytes([header[0], header[1], header[2], header[3]]) as usize;
+        if length > CHUNK_SIZE + 16 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Corrupt encrypted chunk"));
+        }
+        let flag = [header[4]];
+
+        let mut ciphertext = vec![0u8; length];
+        self.inner.read_exact(&mut ciphertext).map_err(truncated)?;
+        let nonce = chunk_nonce(&self.prefix, self.counter);
+        self.plaintext = self
+            .cipher
+            .decrypt(&nonce, Payload { msg: &ciphertext, aad: &flag })
+            .map_err(|_| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "Decryption failed: wrong key or tampered file",
+                )
+            })?;
+        self.position = 0;
+        self.counter += 1;
+        self.done = flag[0] != 0;
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for DecryptReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.position == self.plaintext.len() {
+            if self.done {
+                return Ok(0);
+            }
+            self.next_chunk()?;
+        }
+        let n = buf.len().min(self.plaintext.len() - self.position);
+        buf[..n].copy_from_slice(&self.plaintext[self.position..self.position + n]);
+        self.position += n;
+        Ok(n)
+    }
+}
+
+fn read_full(r: &mut impl Read, buf: &mut [u8]) -> io::Result<usize> {
+    let mut read = 0;
+    while read < buf.len() {
+        match r.read(&mut buf[read..])? {
+            0 => break,
+            n => read += n,
+        }
+    }
+    Ok(read)
+}
+
+pub fn signature_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".");
+    name.push(SIGNATURE_EXTENSION);
+    PathBuf::from(name)
+}
+
+/// SHA-512 of the file's bytes, as written (i.e. after encryption)
+fn digest_file(path: &Path) -> Result<[u8; 64]> {
+    let mut file = File::open(path).context(format!("Failed to open {}", path.display()))?;
+    let mut hasher = Sha512::new();
+    io::copy(&mut file, &mut hasher)?;
+    Ok(hasher.finalize().into())
+}
+
+/// Signs the SHA-512 digest of a file with Ed25519 and writes the hex
+/// signature next to it as `<file>.sig`
+pub fn sign_file(path: &Path, key: &SigningKey) -> Result<PathBuf> {
+    let signature = key.sign(&digest_file(path)?);
+    let sig_path = signature_path(path);
+    fs::write(&sig_path, format!("{}\n", encode_hex(&signature.to_bytes())))
+        .context(format!("Failed to write {}", sig_path.display()))?;
+    Ok(sig_path)
+}
+
+/// Checks a file against its `<file>.sig` signature
+pub fn verify_file(path: &Path, public_key: &[u8; 32]) -> Result<()> {
+    let key = VerifyingKey::from_bytes(public_key).context("Invalid public key")?;
+    let sig_path = signature_path(path);
+    let text = fs::read_to_string(&sig_path)
+        .context(format!("Failed to read signature {}", sig_path.display()))?;
+    let bytes: [u8; 64] = decode_hex(text.trim())
+        .context(format!("Invalid signature file {}", sig_path.display()))?;
+
+    if key.verify(&digest_file(path)?, &Signature::from_bytes(&bytes)).is_err() {
+        bail!("Signature does not match {}", path.display());
+    }
+    Ok(())
+}
+
+#[derive(Serialize)]
+struct Verification {
+    path: String,
+    valid: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// Verifies each file and prints one JSON line per file; fails if any
+/// signature is missing or invalid
+pub fn verify(public_key: &Path, files: &[PathBuf]) -> Result<()> {
+    let public_key = load_key(public_key)?;
+    let mut failed = 0;
+
+    for file in files {
+        let result = verify_file(file, &public_key);
+        let line = Verification {
+            path: file.display().to_string(),
+            valid: result.is_ok(),
+            error: result.err().map(|e| format!("{:#}", e)),
+        };
+        println!("{}", output::to_json(&line)?);
+        if !line.valid {
+            failed += 1;
+        }
+    }
+
+    if failed > 0 {
+        bail!("{} of {} files failed verification", failed, files.len());
+    }
+    Ok(())
+}
+
+pub fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn decode_hex<const N: usize>(text: &str) -> Option<[u8; N]> {
+    if text.len() != N * 2 || !text.is_ascii() {
+        return None;
+    }
+    let mut bytes = [0u8; N];
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&text[i * 2..i * 2 + 2], 16).ok()?;
+    }
+    Some(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: [u8; 32] = [7; 32];
+
+    fn temp_file(name: &str, data: &[u8]) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("ntfs-reader-cli-{}-{}", std::process::id(), name));
+        fs::write(&path, data).unwrap();
+        path
+    }
+
+    fn encrypt(data: &[u8]) -> Vec<u8> {
+        let mut sealed = Vec::new();
+        let mut writer = SealedWriter::new(&mut sealed, Some(&KEY)).unwrap();
+        writer.write_all(data).unwrap();
+        writer.finish().unwrap();
+        sealed
+    }
+
+    fn read(path: &Path, key: Option<&[u8; 32]>) -> Result<Vec<u8>> {
+        let mut data = Vec::new();
+        open_reader(path, key)?.read_to_end(&mut data)?;
+        Ok(data)
+    }
+
+    #[test]
+    fn encrypted_files_read_back() {
+        let data: Vec<u8> = (0..3 * CHUNK_SIZE as u32 / 2).map(|i| i as u8).collect();
+        let path = temp_file("roundtrip", &encrypt(&data));
+        assert_eq!(read(&path, Some(&KEY)).unwrap(), data);
+        assert!(read(&path, None).is_err());
+        assert!(read(&path, Some(&[8; 32])).is_err());
+        fs::remove_file(path).unwrap();
+
+        let path = temp_file("plain", b"NTFS");
+        assert_eq!(read(&path, None).unwrap(), b"NTFS");
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn truncated_and_tampered_files_are_refused() {
+        let sealed = encrypt(&vec![1u8; CHUNK_SIZE + 10]);
+        for (name, damaged) in [
+            ("no-last-chunk", sealed[..sealed.len() - 30].to_vec()),
+            ("header-only", sealed[..MAGIC.len() + 8].to_vec()),
+        ] {
+            let path = temp_file(name, &damaged);
+            let error = read(&path, Some(&KEY)).unwrap_err();
+            assert!(
+                error.downcast_ref::<io::Error>().is_none_or(|e| e.kind() != io::ErrorKind::UnexpectedEof),
+                "{}: {:#}",
+                name,
+                error
+            );
+            fs::remove_file(path).unwrap();
+        }
+
+        let path = temp_file("short-prefix", &sealed[..MAGIC.len() + 3]);
+        assert!(open_reader(&path, Some(&KEY)).is_err());
+        fs::remove_file(path).unwrap();
+
+        let mut tampered = sealed.clone();
+        let last = tampered.len() - 1;
+        tampered[last] ^= 1;
+        let path = temp_file("tampered", &tampered);
+        assert!(read(&path, Some(&KEY)).is_err());
+        fs::remove_file(path).unwrap();
+
+        // A chunk length no writer produces
+        let mut oversized = sealed[..MAGIC.len() + 8].to_vec();
+        oversized.extend_from_slice(&u32::MAX.to_le_bytes());
+        oversized.push(1);
+        let path = temp_file("oversized", &oversized);
+        assert!(read(&path, Some(&KEY)).is_err());
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn signatures_cover_the_file_as_stored() {
+        let key = SigningKey::from_bytes(&KEY);
+        let public_key = key.verifying_key().to_bytes();
+        let path = temp_file("signed", b"evidence");
+        let signature = sign_file(&path, &key).unwrap();
+        verify_file(&path, &public_key).unwrap();
+
+        fs::write(&path, b"evidencE").unwrap();
+        assert!(verify_file(&path, &public_key).is_err());
+        fs::write(&signature, "not hex\n").unwrap();
+        assert!(verify_file(&path, &public_key).is_err());
+        fs::remove_file(signature).unwrap();
+        assert!(verify_file(&path, &public_key).is_err());
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn hex_decoding_checks_length_and_digits() {
+        assert_eq!(decode_hex::<2>("0aff"), Some([0x0A, 0xFF]));
+        assert_eq!(decode_hex::<2>(&encode_hex(&[1, 2])), Some([1, 2]));
+        assert_eq!(decode_hex::<2>("0af"), None);
+        assert_eq!(decode_hex::<2>("0afg"), None);
+        assert_eq!(decode_hex::<2>("éé"), None);
+    }
+}
@@ -0,0 +1,171 @@
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::process::Command;
+use std::sync::OnceLock;
+
+use crate::mft;
+use crate::output::{self, ColumnType, Field, Fields, TableRow};
+use crate::OutputFormat;
+
+/// Device name shadow copies are created under, followed by their index
+const SHADOW_DEVICE_PREFIX: &str = "HarddiskVolumeShadowCopy";
+
+/// Lists the shadow copies through WMI with the drive letter of the volume
+/// each one was taken of, as JSON. WMI is the only enumeration that doesn't
+/// need the VSS COM API; vssadmin's output is localized.
+const LIST_SCRIPT: &str = "$letters = @{}; \
+    Get-CimInstance Win32_Volume | ForEach-Object { $letters[$_.DeviceID] = $_.DriveLetter }; \
+    $copies = @(Get-CimInstance Win32_ShadowCopy | ForEach-Object { [pscustomobject]@{ \
+        id = $_.ID; device = $_.DeviceObject; volume_name = $_.VolumeName; \
+        drive = $letters[$_.VolumeName]; created = $_.InstallDate.ToUniversalTime().ToString('o') } }); \
+    ConvertTo-Json -InputObject $copies -Compress";
+
+/// Shadow copy chosen with --shadow, which every --volume is read through
+static SHADOW: OnceLock<ShadowCopy> = OnceLock::new();
+
+/// A Volume Shadow Copy: a read-only, point-in-time image of a volume
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ShadowCopy {
+    /// Number in the device name, as given to --shadow
+    #[serde(default)]
+    pub index: u32,
+    /// Shadow copy ID, as vssadmin shows it
+    pub id: String,
+    /// Device path the copy is read through, e.g. \\?\GLOBALROOT\Device\HarddiskVolumeShadowCopy3
+    pub device: String,
+    /// Volume GUID path of the volume the copy was taken of
+    pub volume_name: String,
+    /// Drive letter of that volume, if it has one
+    pub drive: Option<String>,
+    pub created: Option<String>,
+}
+
+impl ShadowCopy {
+    /// Fields accepted by --fields, with the types of their CSV columns
+    pub const FIELDS: &'static [Field] = &[
+        ("index", ColumnType::Int),
+        ("id", ColumnType::String),
+        ("device", ColumnType::String),
+        ("volume_name", ColumnType::String),
+        ("drive", ColumnType::String),
+        ("created", ColumnType::Datetime),
+    ];
+}
+
+impl TableRow for ShadowCopy {
+    fn table_header() -> Vec<&'static str> {
+        vec!["INDEX", "DRIVE", "CREATED", "DEVICE"]
+    }
+
+    fn table_row(&self) -> Vec<String> {
+        vec![
+            self.index.to_string(),
+            self.drive.clone().unwrap_or_default(),
+            self.created.clone().unwrap_or_default(),
+            self.device.clone(),
+        ]
+    }
+
+    fn streaming_widths() -> Vec<usize> {
+        vec![5, 5, 25, 0]
+    }
+}
+
+/// Shadow copies on this machine, oldest first
+pub fn list() -> Result<Vec<ShadowCopy>> {
+    if !cfg!(windows) {
+        bail!("Volume shadow copies are only supported on Windows");
+    }
+    let listed = Command::new("powershell.exe")
+        .args(["-NoProfile", "-NonInteractive", "-Command", LIST_SCRIPT])
+        .output()
+        .context("Failed to run powershell.exe to list shadow copies")?;
+    if !listed.status.success() {
+        bail!(
+            "Listing shadow copies failed (run as Administrator): {}",
+            String::from_utf8_lossy(&listed.stderr).trim()
+        );
+    }
+
+    let mut copies: Vec<ShadowCopy> =
+        serde_json::from_slice(&listed.stdout).context("Unexpected shadow copy listing from WMI")?;
+    for copy in &mut copies {
+        copy.index = shadow_index(&copy.device).unwrap_or_default();
+        copy.created = copy.created.as_deref().map(|created| {
+            time::OffsetDateTime::parse(created, &time::format_description::well_known::Rfc3339)
+                .map_or_else(|_| created.to_string(), mft::format_time)
+        });
+    }
+    copies.sort_by_key(|copy| copy.index);
+    Ok(copies)
+}
+
+pub fn list_shadows(output: OutputFormat, fields: &Fields) -> Result<()> {
+    let copies = list()?;
+    if copies.is_empty() {
+        eprintln!("No shadow copies found");
+    }
+    let stdout = std::io::stdout();
+    output::write_report(&mut stdout.lock(), &copies, output, fields, ShadowCopy::FIELDS)
+}
+
+/// Chooses the shadow copy `index` for the rest of the run, after checking
+/// that it was taken of `volume`, the volume the command was given
+pub fn select(index: u32, volume: Option<&str>) -> Result<()> {
+    let Some(volume) = volume else {
+        bail!("--shadow needs a command that reads a single --volume");
+    };
+    let copies = list()?;
+    let Some(copy) = copies.into_iter().find(|copy| copy.index == index) else {
+        bail!("No shadow copy {} (run list-shadows to see the available ones)", index);
+    };
+
+    if let (Some(drive), Some(letter)) = (&copy.drive, drive_letter(volume)) {
+        if !drive.eq_ignore_ascii_case(&letter) {
+            bail!("Shadow copy {} was taken of {}, not {}", index, drive, volume);
+        }
+    }
+    eprintln!(
+        "Reading shadow copy {} of {} taken {}",
+        index,
+        copy.drive.as_deref().unwrap_or(&copy.volume_name),
+        copy.created.as_deref().unwrap_or("at an unknown time")
+    );
+    let _ = SHADOW.set(copy);
+    Ok(())
+}
+
+/// Device path of the shadow copy chosen with --shadow, if one was
+pub fn selected_device() -> Option<String> {
+    SHADOW.get().map(|copy| copy.device.clone())
+}
+
+/// `\\?\GLOBALROOT` path of a shadow copy given by device name
+/// (`HarddiskVolumeShadowCopy3` or `\Device\HarddiskVolumeShadowCopy3`),
+/// which has no `\\.\` link, or `None` for other volumes
+pub fn device_path(volume: &str) -> Option<String> {
+    let name = volume.strip_prefix("\\Device\\").unwrap_or(volume);
+    shadow_index(name)
+        .filter(|_| !name.contains('\\'))
+        .map(|_| format!("\\\\?\\GLOBALROOT\\Device\\{}", name))
+}
+
+/// Index of a shadow copy from its device name or path
+fn shadow_index(device: &str) -> Option<u32> {
+    let name = device.rsplit('\\').next()?;
+    let prefix = name.get(..SHADOW_DEVICE_PREFIX.len())?;
+    if !prefix.eq_ignore_ascii_case(SHADOW_DEVICE_PREFIX) {
+        return None;
+    }
+    name[SHADOW_DEVICE_PREFIX.len()..].parse().ok()
+}
+
+/// Drive letter of a volume given as C:, C:\, \\.\C: or \\?\C:
+fn drive_letter(volume: &str) -> Option<String> {
+    let volume = volume.trim().trim_end_matches('\\');
+    let volume = volume
+        .strip_prefix("\\\\.\\")
+        .or_else(|| volume.strip_prefix("\\\\?\\"))
+        .unwrap_or(volume);
+    (volume.len() == 2 && volume.ends_with(':')).then(|| volume.to_uppercase())
+}
@@ -0,0 +1,198 @@
+use anyhow::{Context, Result};
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::mft::{self, FileRecord};
+use crate::output::Warning;
+use crate::seal::{self, SealOptions, SealedWriter};
+
+const SNAPSHOT_PREFIX: &str = "snapshot-";
+const SNAPSHOT_EXTENSION: &str = ".jsonl";
+
+/// Captures MFT snapshots into `out_dir`, either once or every `every`,
+/// keeping only the `retain` most recent snapshots of the volume.
+pub fn run(
+    volume: &str,
+    out_dir: &Path,
+    every: Option<Duration>,
+    retain: Option<usize>,
+    seal: &SealOptions,
+) -> Result<()> {
+    fs::create_dir_all(out_dir)
+        .context(format!("Failed to create snapshot directory {}", out_dir.display()))?;
+
+    loop {
+        let started = Instant::now();
+
+        match take_snapshot(volume, out_dir, seal) {
+            Ok(path) => eprintln!("Snapshot written: {}", path.display()),
+            // A failed run shouldn't stop the schedule; the next one may succeed
+            Err(e) if every.is_some() => {
+                Warning::new("snapshot_failed", format!("snapshot failed: {:#}", e)).emit()
+            }
+            Err(e) => return Err(e),
+        }
+
+        if let Some(retain) = retain {
+            prune(volume, out_dir, retain)?;
+        }
+
+        let Some(interval) = every else {
+            return Ok(());
+        };
+        let wait = interval.saturating_sub(started.elapsed());
+        eprintln!("Next snapshot in {}s", wait.as_secs());
+        thread::sleep(wait);
+    }
+}
+
+/// Writes one snapshot as JSON Lines of `FileRecord`s and returns its path.
+/// The file is written under a temporary name and renamed once complete, so
+/// readers never see a partial snapshot; a failed snapshot leaves nothing
+/// behind. Encryption applies to the whole file; the signature is written
+/// next to it once it is complete.
+pub fn take_snapshot(volume: &str, out_dir: &Path, seal: &SealOptions) -> Result<PathBuf> {
+    let (path, partial, file) = create_partial(volume, out_dir)?;
+    let mut count = 0usize;
+
+    let write = || -> Result<()> {
+        let mut writer = SealedWriter::new(BufWriter::new(file), seal.encrypt_key.as_ref())?;
+        mft::scan_records(volume, |record| {
+            serde_json::to_writer(&mut writer, &record)?;
+            writer.write_all(b"\n")?;
+            count += 1;
+            Ok(())
+        })?;
+        writer.finish()?;
+        fs::rename(&partial, &path).context(format!("Failed to finalize {}", path.display()))
+    };
+    if let Err(e) = write() {
+        let _ = fs::remove_file(&partial);
+        return Err(e);
+    }
+
+    eprintln!("Captured {} records", count);
+    if let Some(key) = &seal.sign_key {
+        let signature = seal::sign_file(&path, key)?;
+        eprintln!("Snapshot signed: {}", signature.display());
+    }
+    Ok(path)
+}
+
+/// Creates the temporary file of a new snapshot, returning the snapshot's
+/// path, the temporary one and the open file. Names carry the time to the
+/// second, so a snapshot of the same volume already taken (or being
+/// written) that second makes this wait for the next one; the names keep
+/// sorting in the order the snapshots were taken.
+fn create_partial(volume: &str, out_dir: &Path) -> Result<(PathBuf, PathBuf, File)> {
+    loop {
+        let now = time::OffsetDateTime::now_utc();
+        let name = format!(
+            "{}{}-{:04}{:02}{:02}T{:02}{:02}{:02}Z{}",
+            SNAPSHOT_PREFIX,
+            volume_tag(volume),
+            now.year(),
+            now.month() as u8,
+            now.day(),
+            now.hour(),
+            now.minute(),
+            now.second(),
+            SNAPSHOT_EXTENSION
+        );
+        let path = out_dir.join(&name);
+        let partial = out_dir.join(format!("{}.partial", name));
+
+        if !path.exists() {
+            match File::options().write(true).create_new(true).open(&partial) {
+                Ok(file) => return Ok((path, partial, file)),
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {}
+                Err(e) => {
+                    return Err(e).context(format!("Failed to create {}", partial.display()));
+                }
+            }
+        }
+        thread::sleep(Duration::from_nanos(1_000_000_000 - now.nanosecond() as u64));
+    }
+}
+
+/// Returns the snapshots of `volume` in `dir`, oldest first
+pub fn list_snapshots(volume: &str, dir: &Path) -> Result<Vec<PathBuf>> {
+    let prefix = format!("{}{}-", SNAPSHOT_PREFIX, volume_tag(volume));
+    let mut snapshots: Vec<PathBuf> = fs::read_dir(dir)
+        .context(format!("Failed to read snapshot directory {}", dir.display()))?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.file_name()
+                .and_then(|n| n.to_str())
+                .map(|n| n.starts_with(&prefix) && n.ends_with(SNAPSHOT_EXTENSION))
+                .unwrap_or(false)
+        })
+        .collect();
+
+    // Timestamps in the names sort chronologically
+    snapshots.sort();
+    Ok(snapshots)
+}
+
+/// Reads a snapshot, decrypting it first if it is encrypted
+pub fn load_snapshot(path: &Path, encrypt_key: Option<&[u8; 32]>) -> Result<Vec<FileRecord>> {
+    let reader = seal::open_reader(path, encrypt_key)?;
+    let mut records = Vec::new();
+
+    for (index, line) in BufReader::new(reader).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let record = serde_json::from_str(&line)
+            .context(format!("Invalid record on line {} of {}", index + 1, path.display()))?;
+        records.push(record);
+    }
+
+    Ok(records)
+}
+
+/// Removes all but the `retain` most recent snapshots of `volume`, with
+/// their signatures
+pub fn prune(volume: &str, dir: &Path, retain: usize) -> Result<()> {
+    let snapshots = list_snapshots(volume, dir)?;
+    let excess = snapshots.len().saturating_sub(retain);
+
+    for old in &snapshots[..excess] {
+        eprintln!("Removing old snapshot: {}", old.display());
+        fs::remove_file(old).context(format!("Failed to remove {}", old.display()))?;
+        let signature = seal::signature_path(old);
+        if signature.exists() {
+            fs::remove_file(&signature)
+                .context(format!("Failed to remove {}", signature.display()))?;
+        }
+    }
+    Ok(())
+}
+
+/// File-name friendly identifier of a volume, e.g. `C` for `\\.\C:`
+fn volume_tag(volume: &str) -> String {
+    let volume = volume.trim().trim_start_matches(['\\', '.', '?']);
+    volume.chars().filter(|c| c.is_ascii_alphanumeric()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn snapshots_taken_within_a_second_get_their_own_names() {
+        let dir = std::env::temp_dir().join(format!("snapshot-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        let (first, first_partial, _) = create_partial("C:", &dir).unwrap();
+        let (second, second_partial, _) = create_partial("C:", &dir).unwrap();
+        assert!(first < second, "{} then {}", first.display(), second.display());
+        assert!(first_partial.exists() && second_partial.exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,177 @@
+use anyhow::Result;
+use ntfs_reader::file::NtfsFile;
+use serde::Serialize;
+use std::io;
+
+use crate::filter::FileFilter;
+use crate::mft;
+use crate::output::{self, ColumnType, Field, Fields, TableRow};
+use crate::raw;
+use crate::OutputFormat;
+
+/// $FILE_NAME namespace of 8.3 short names
+const NAMESPACE_DOS: u8 = 2;
+
+/// 100ns ticks in a second
+const TICKS_PER_SECOND: u64 = 10_000_000;
+
+/// A file whose $STANDARD_INFORMATION timestamps look altered, with both
+/// timestamp sets so the finding can be checked
+#[derive(Debug, Serialize)]
+pub struct TimestompedFile {
+    pub path: String,
+    pub record_number: u64,
+    /// What looks wrong: fn_created_after_si, fn_modified_after_si,
+    /// fn_mft_modified_after_si, si_zero_subseconds
+    pub findings: Vec<String>,
+    pub si_created: Option<String>,
+    pub si_modified: Option<String>,
+    pub si_mft_modified: Option<String>,
+    pub si_accessed: Option<String>,
+    pub fn_created: Option<String>,
+    pub fn_modified: Option<String>,
+    pub fn_mft_modified: Option<String>,
+    pub fn_accessed: Option<String>,
+}
+
+impl TimestompedFile {
+    /// Fields accepted by --fields, with the types of their CSV columns
+    pub const FIELDS: &'static [Field] = &[
+        ("path", ColumnType::String),
+        ("record_number", ColumnType::Int),
+        ("findings", ColumnType::String),
+        ("si_created", ColumnType::Datetime),
+        ("si_modified", ColumnType::Datetime),
+        ("si_mft_modified", ColumnType::Datetime),
+        ("si_accessed", ColumnType::Datetime),
+        ("fn_created", ColumnType::Datetime),
+        ("fn_modified", ColumnType::Datetime),
+        ("fn_mft_modified", ColumnType::Datetime),
+        ("fn_accessed", ColumnType::Datetime),
+    ];
+}
+
+impl TableRow for TimestompedFile {
+    fn table_header() -> Vec<&'static str> {
+        vec!["SI CREATED", "FN CREATED", "FINDINGS", "PATH"]
+    }
+
+    fn table_row(&self) -> Vec<String> {
+        vec![
+            self.si_created.clone().unwrap_or_default(),
+            self.fn_created.clone().unwrap_or_default(),
+            self.findings.join(","),
+            self.path.clone(),
+        ]
+    }
+
+    fn streaming_widths() -> Vec<usize> {
+        vec![25, 25, 40, 0]
+    }
+}
+
+/// Created, modified, MFT modified and accessed, as raw FILETIME values.
+/// $STANDARD_INFORMATION and $FILE_NAME store them in the same order.
+type Timestamps = [u64; 4];
+
+/// Compares the $STANDARD_INFORMATION timestamps of every file matching
+/// `filter` with those of its $FILE_NAME and reports the files where they
+/// disagree the way timestamp-altering tools leave them. Such tools go
+/// through SetFileTime, which only writes $STANDARD_INFORMATION, so a
+/// $FILE_NAME time later than its $STANDARD_INFORMATION counterpart means
+/// the latter was moved back. Whole-second times are reported too, as tools
+/// that take times from the command line rarely set the fraction; files
+/// copied from FAT or extracted from archives can show them legitimately.
+pub fn report(volume: &str, filter: &FileFilter, output: OutputFormat, fields: &Fields) -> Result<()> {
+    let mft = mft::open_mft(volume)?;
+    let mut rows = Vec::new();
+
+    eprintln!("Comparing timestamps...");
+    mft::iterate_files(&mft, |file| {
+        let Some(info) = mft::parse_file_info(&mft, file) else {
+            return;
+        };
+        if !filter.matches(&info, file) {
+            return;
+        }
+        let Some((si, file_name)) = timestamps(file) else {
+            return;
+        };
+        let findings = findings(&si, &file_name);
+        if findings.is_empty() {
+            return;
+        }
+
+        let [si_created, si_modified, si_mft_modified, si_accessed] = si.map(format_filetime);
+        let [fn_created, fn_modified, fn_mft_modified, fn_accessed] = file_name.map(format_filetime);
+        rows.push(TimestompedFile {
+            path: output::display_path(&info.path),
+            record_number: file.number(),
+            findings,
+            si_created,
+            si_modified,
+            si_mft_modified,
+            si_accessed,
+            fn_created,
+            fn_modified,
+            fn_mft_modified,
+            fn_accessed,
+        });
+    });
+    eprintln!("{} files with suspicious timestamps", rows.len());
+
+    let stdout = io::stdout();
+    output::write_report(&mut stdout.lock(), &rows, output, fields, TimestompedFile::FIELDS)
+}
+
+/// The $STANDARD_INFORMATION and $FILE_NAME timestamps of a record. The
+/// long name's $FILE_NAME is preferred over the 8.3 one.
+fn timestamps(file: &NtfsFile) -> Option<(Timestamps, Timestamps)> {
+    let mut si = None;
+    let mut file_name: Option<(Timestamps, bool)> = None;
+    file.attributes(|attr| {
+        let Some(value) = raw::resident_value(attr.data()) else {
+            return;
+        };
+        match attr.header.type_id {
+            raw::ATTR_STANDARD_INFORMATION if value.len() >= 0x20 => {
+                si = Some(read_timestamps(value, 0x00));
+            }
+            raw::ATTR_FILE_NAME if value.len() >= 0x42 => {
+                let dos = value[0x41] == NAMESPACE_DOS;
+                let replace = match file_name {
+                    None => true,
+                    Some((_, was_dos)) => was_dos && !dos,
+                };
+                if replace {
+                    file_name = Some((read_timestamps(value, 0x08), dos));
+                }
+            }
+            _ => {}
+        }
+    });
+    Some((si?, file_name?.0))
+}
+
+fn read_timestamps(value: &[u8], offset: usize) -> Timestamps {
+    std::array::from_fn(|i| raw::u64_at(value, offset + i * 8).unwrap_or(0))
+}
+
+/// Signs that the $STANDARD_INFORMATION times were set after the fact.
+/// Accessed times are left out: NTFS updates them lazily, if at all.
+fn findings(si: &Timestamps, file_name: &Timestamps) -> Vec<String> {
+    let mut findings = Vec::new();
+    for (i, name) in ["created", "modified", "mft_modified"].iter().enumerate() {
+        if si[i] != 0 && file_name[i] > si[i] {
+            findings.push(format!("fn_{}_after_si", name));
+        }
+    }
+    if si[..2].iter().any(|&time| time != 0 && time % TICKS_PER_SECOND == 0) {
+        findings.push("si_zero_subseconds".to_string());
+    }
+    findings
+}
+
+fn format_filetime(filetime: u64) -> Option<String> {
+    output::from_filetime(filetime).map(mft::format_time)
+}
@@ -0,0 +1,219 @@
+use anyhow::{bail, Result};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+use crate::du::{self, Hierarchy, Totals};
+use crate::mft;
+use crate::output;
+use crate::OutputFormat;
+
+/// One entry of the nested JSON tree
+#[derive(Debug, Serialize)]
+pub struct TreeNode {
+    pub name: String,
+    pub is_directory: bool,
+    /// For directories, the total of everything below them
+    pub size: u64,
+    /// Entries directly inside a directory
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub child_count: Option<u64>,
+    /// Files anywhere below a directory
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub files: Option<u64>,
+    pub record_number: u64,
+    /// Entries of a directory within the requested depth
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub children: Vec<TreeNode>,
+}
+
+/// Prints the directory tree under `path` (the volume root when not given)
+/// down to `depth` levels, as an indented tree or a nested JSON structure.
+/// Directories come first, then files, each sorted by name; `dirs_only`
+/// leaves files out.
+pub fn run(
+    volume: &str,
+    path: Option<&str>,
+    depth: usize,
+    dirs_only: bool,
+    output: OutputFormat,
+) -> Result<()> {
+    let mft = mft::open_mft(volume)?;
+    eprintln!("Rebuilding the directory tree...");
+    let hierarchy = Hierarchy::load(&mft, !dirs_only);
+    let scope = hierarchy.resolve(path)?;
+    let totals = hierarchy.totals(scope);
+
+    let mut root = node(&hierarchy, &totals, scope, depth, dirs_only);
+    root.name = output::display_path(&du::directory_path(&mft, scope)?);
+
+    let stdout = io::stdout();
+    let mut w = stdout.lock();
+    match output {
+        OutputFormat::Table => {
+            writeln!(w, "{}", describe(&root))?;
+            write_children(&mut w, &root, "")?;
+        }
+        OutputFormat::Json | OutputFormat::Jsonl | OutputFormat::JsonTree => {
+            writeln!(w, "{}", output::to_json(&root)?)?
+        }
+        OutputFormat::JsonPretty => writeln!(w, "{}", output::to_json_pretty(&root)?)?,
+        OutputFormat::Dot => write_dot(&mut w, &root)?,
+        _ => bail!("tree supports table, json, jsonl, json-pretty and dot output"),
+    }
+    w.flush()?;
+    Ok(())
+}
+
+/// The node of directory `number` with its entries `depth` levels down
+fn node(
+    hierarchy: &Hierarchy,
+    totals: &HashMap<u64, Totals>,
+    number: u64,
+    depth: usize,
+    dirs_only: bool,
+) -> TreeNode {
+    let directory = &hierarchy.directories[&number];
+    let total = totals.get(&number).copied().unwrap_or_default();
+    let mut children = Vec::new();
+    if depth > 0 {
+        let mut directories: Vec<u64> = directory.children.clone();
+        directories.sort_by_cached_key(|child| hierarchy.directories[child].name.to_lowercase());
+        for child in directories {
+            children.push(node(hierarchy, totals, child, depth - 1, dirs_only));
+        }
+        let mut files: Vec<&du::FileEntry> = directory.entries.iter().collect();
+        files.sort_by_cached_key(|entry| entry.name.to_lowercase());
+        children.extend(files.into_iter().map(|entry| TreeNode {
+            name: entry.name.clone(),
+            is_directory: false,
+            size: entry.size,
+            child_count: None,
+            files: None,
+            record_number: entry.record_number,
+            children: Vec::new(),
+        }));
+    }
+
+    let direct_files = if dirs_only { directory.files } else { directory.entries.len() as u64 };
+    TreeNode {
+        name: directory.name.clone(),
+        is_directory: true,
+        size: total.size,
+        child_count: Some(directory.children.len() as u64 + direct_files),
+        files: Some(total.files),
+        record_number: number,
+        children,
+    }
+}
+
+/// `name  (size, N items)` for directories, `name  (size)` for files
+fn describe(node: &TreeNode) -> String {
+    match node.child_count {
+        Some(count) => format!(
+            "{}\\  ({}, {} {})",
+            node.name.trim_end_matches('\\'),
+            output::format_size(node.size),
+            count,
+            if count == 1 { "item" } else { "items" }
+        ),
+        None => format!("{}  ({})", node.name, output::format_size(node.size)),
+    }
+}
+
+fn write_children(w: &mut dyn Write, node: &TreeNode, prefix: &str) -> io::Result<()> {
+    let last = node.children.len().saturating_sub(1);
+    for (i, child) in node.children.iter().enumerate() {
+        let (branch, indent) = if i == last { ("└── ", "    ") } else { ("├── ", "│   ") };
+        writeln!(w, "{}{}{}", prefix, branch, describe(child))?;
+        write_children(w, child, &format!("{}{}", prefix, indent))?;
+    }
+    Ok(())
+}
+
+/// Writes the tree as a Graphviz digraph with an edge from each directory
+/// to each of its entries. Nodes are labeled with name and size, and their
+/// width grows with the square root of their share of the top directory's
+/// size, so node areas compare like sizes. The byte count and record number
+/// are also node attributes, for tools like Gephi.
+fn write_dot(w: &mut dyn Write, root: &TreeNode) -> io::Result<()> {
+    writeln!(w, "digraph tree {{")?;
+    writeln!(w, "  rankdir=LR;")?;
+    write_dot_node(w, root, root.size.max(1), &mut 0)?;
+    writeln!(w, "}}")
+}
+
+/// Writes `node` and its entries, numbering nodes from `next`; returns the
+/// node's number
+fn write_dot_node(w: &mut dyn Write, node: &TreeNode, total: u64, next: &mut usize) -> io::Result<usize> {
+    let id = *next;
+    *next += 1;
+    let (name, shape) = match node.is_directory {
+        true => (format!("{}\\", node.name.trim_end_matches('\\')), "folder"),
+        false => (node.name.clone(), "note"),
+    };
+    let width = 0.75 + 2.25 * (node.size as f64 / total as f64).min(1.0).sqrt();
+    writeln!(
+        w,
+        "  n{} [label=\"{}\\n{}\", shape={}, width={:.2}, size={}, record_number={}];",
+        id,
+        dot_escape(&name),
+        dot_escape(&output::format_size(node.size)),
+        shape,
+        width,
+        node.size,
+        node.record_number
+    )?;
+    for child in &node.children {
+        let child_id = write_dot_node(w, child, total, next)?;
+        writeln!(w, "  n{} -> n{};", id, child_id)?;
+    }
+    Ok(id)
+}
+
+/// Escapes text for a double-quoted DOT string
+fn dot_escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(name: &str, size: u64, record_number: u64) -> TreeNode {
+        TreeNode {
+            name: name.to_string(),
+            is_directory: false,
+            size,
+            child_count: None,
+            files: None,
+            record_number,
+            children: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn dot_output_has_a_node_per_entry_and_an_edge_per_child() {
+        let root = TreeNode {
+            name: "C:\\Users\\".to_string(),
+            is_directory: true,
+            size: 400,
+            child_count: Some(2),
+            files: Some(2),
+            record_number: 5,
+            children: vec![file("a \"b\".txt", 100, 40), file("c.txt", 0, 41)],
+        };
+        let mut dot = Vec::new();
+        write_dot(&mut dot, &root).unwrap();
+        let dot = String::from_utf8(dot).unwrap();
+
+        assert!(dot.starts_with("digraph tree {\n"));
+        assert!(dot.ends_with("}\n"));
+        assert!(dot.contains("n0 [label=\"C:\\\\Users\\\\\\n"));
+        assert!(dot.contains("shape=folder, width=3.00, size=400, record_number=5"));
+        assert!(dot.contains("n1 [label=\"a \\\"b\\\".txt\\n"));
+        assert!(dot.contains("shape=note, width=1.88, size=100, record_number=40"));
+        assert!(dot.contains("width=0.75, size=0"));
+        assert!(dot.contains("n0 -> n1;\n  n2 [") && dot.contains("n0 -> n2;"));
+    }
+}
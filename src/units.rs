@@ -0,0 +1,133 @@
+use std::time::Duration;
+use time::format_description::well_known::Rfc3339;
+use time::{Date, Month, OffsetDateTime};
+
+/// Parses a duration such as `250ms`, `30s`, `15m`, `6h` or `2d`.
+/// A bare number is taken as seconds.
+pub fn parse_duration(s: &str) -> Result<Duration, String> {
+    let s = s.trim().to_lowercase();
+    let split = s
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(s.len());
+    let (value, unit) = s.split_at(split);
+    
+    let value: f64 = value
+        .parse()
+        .map_err(|_| format!("Invalid duration: {}", s))?;
+    let seconds = match unit.trim() {
+        "ms" => value / 1000.0,
+        "" | "s" | "sec" => value,
+        "m" | "min" => value * 60.0,
+        "h" => value * 3600.0,
+        "d" => value * 86400.0,
+        _ => return Err(format!("Invalid duration unit in: {}", s)),
+    };
+    
+    Duration::try_from_secs_f64(seconds).map_err(|_| format!("Invalid duration: {}", s))
+}
+
+/// Parses a duration like `parse_duration` for something repeated at that
+/// interval, which can't be zero
+pub fn parse_interval(s: &str) -> Result<Duration, String> {
+    let interval = parse_duration(s)?;
+    if interval.is_zero() {
+        return Err(format!("Interval must be longer than zero: {}", s.trim()));
+    }
+    Ok(interval)
+}
+
+/// Parses a byte size such as `512`, `10K`, `1.5G` or `200MB`. Suffixes are
+/// powers of 1024, with an optional trailing `B`/`iB`.
+pub fn parse_size(s: &str) -> Result<u64, String> {
+    let s = s.trim().to_lowercase();
+    let split = s
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(s.len());
+    let (value, unit) = s.split_at(split);
+    
+    let value: f64 = value
+        .parse()
+        .map_err(|_| format!("Invalid size: {}", s))?;
+    let unit = unit.trim();
+    let unit = unit.strip_suffix("ib").or_else(|| unit.strip_suffix('b')).unwrap_or(unit);
+    let multiplier: u64 = match unit {
+        "" => 1,
+        "k" => 1 << 10,
+        "m" => 1 << 20,
+        "g" => 1 << 30,
+        "t" => 1 << 40,
+        _ => return Err(format!("Invalid size unit in: {}", s)),
+    };
+    
+    Ok((value * multiplier as f64).round() as u64)
+}
+
+/// Parses a timestamp given as a date (`2024-01-31`, midnight UTC) or as
+/// RFC 3339 (`2024-01-31T12:00:00Z`)
+pub fn parse_timestamp(s: &str) -> Result<OffsetDateTime, String> {
+    let s = s.trim();
+    if let Ok(time) = OffsetDateTime::parse(s, &Rfc3339) {
+        return Ok(time);
+    }
+    
+    let parts: Vec<&str> = s.split('-').collect();
+    let [year, month, day] = parts[..] else {
+        return Err(format!("Invalid date (expected YYYY-MM-DD or RFC 3339): {}", s));
+    };
+    let invalid = || format!("Invalid date: {}", s);
+    let year: i32 = year.parse().map_err(|_| invalid())?;
+    let month: u8 = month.parse().map_err(|_| invalid())?;
+    let day: u8 = day.parse().map_err(|_| invalid())?;
+    let month = Month::try_from(month).map_err(|_| invalid())?;
+    let date = Date::from_calendar_date(year, month, day).map_err(|_| invalid())?;
+    
+    Ok(date.midnight().assume_utc())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn durations_take_units_and_default_to_seconds() {
+        assert_eq!(parse_duration("250ms"), Ok(Duration::from_millis(250)));
+        assert_eq!(parse_duration("30"), Ok(Duration::from_secs(30)));
+        assert_eq!(parse_duration(" 15 MIN "), Ok(Duration::from_secs(900)));
+        assert_eq!(parse_duration("1.5h"), Ok(Duration::from_secs(5400)));
+        assert_eq!(parse_duration("2d"), Ok(Duration::from_secs(172_800)));
+        for invalid in ["", "m", "-5s", "5y", "1.2.3s", "1e3s", "99999999999999999999999d"] {
+            assert!(parse_duration(invalid).is_err(), "{:?}", invalid);
+        }
+    }
+
+    #[test]
+    fn intervals_must_be_longer_than_zero() {
+        assert_eq!(parse_interval("6h"), Ok(Duration::from_secs(21_600)));
+        assert!(parse_interval("0").is_err());
+        assert!(parse_interval("0.0ms").is_err());
+        assert!(parse_interval("soon").is_err());
+    }
+
+    #[test]
+    fn sizes_are_powers_of_1024() {
+        assert_eq!(parse_size("512"), Ok(512));
+        assert_eq!(parse_size("10K"), Ok(10_240));
+        assert_eq!(parse_size("200MB"), Ok(200 << 20));
+        assert_eq!(parse_size("2KiB"), Ok(2048));
+        assert_eq!(parse_size("1.5g"), Ok(3 << 29));
+        for invalid in ["", "G", "10X", "10 KBB", "1..5M", "-1K"] {
+            assert!(parse_size(invalid).is_err(), "{:?}", invalid);
+        }
+    }
+
+    #[test]
+    fn timestamps_are_dates_or_rfc_3339() {
+        let midnight = parse_timestamp("2024-01-31").unwrap();
+        assert_eq!(midnight, OffsetDateTime::parse("2024-01-31T00:00:00Z", &Rfc3339).unwrap());
+        let time = parse_timestamp("2024-01-31T12:30:00+02:00").unwrap();
+        assert_eq!(time.unix_timestamp(), midnight.unix_timestamp() + 10 * 3600 + 1800);
+        for invalid in ["", "2024", "2024-02-30", "2024-13-01", "2024/01/31", "2024-01-31T25:00:00Z"] {
+            assert!(parse_timestamp(invalid).is_err(), "{:?}", invalid);
+        }
+    }
+}
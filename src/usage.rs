@@ -0,0 +1,224 @@
+use anyhow::Result;
+use ntfs_reader::api::NtfsAttributeType;
+use ntfs_reader::file::NtfsFile;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::io;
+
+use crate::filter::FileFilter;
+use crate::mft;
+use crate::output::{self, ColumnType, Field, Fields, TableRow};
+use crate::raw;
+use crate::OutputFormat;
+
+/// Attribute flag of NTFS-compressed data
+const ATTR_FLAG_COMPRESSED: u16 = 0x0001;
+
+/// Named stream holding the data of files compressed by WOF (compact.exe /EXE)
+const WOF_STREAM: &str = "WofCompressedData";
+
+/// Space used by the files of one subtree
+#[derive(Debug, Default, Serialize)]
+pub struct SubtreeUsage {
+    pub path: String,
+    pub files: u64,
+    /// Sum of the file sizes
+    pub logical_size: u64,
+    /// Space the files take on disk, after compression
+    pub allocated_size: u64,
+    /// Space saved by NTFS compression (the compressed attribute)
+    pub ntfs_compression_savings: u64,
+    /// Space saved by WOF compression, counted against the logical size
+    pub wof_savings: u64,
+    /// Share of the uncompressed allocation saved by both kinds of compression
+    pub savings_percent: f64,
+}
+
+impl SubtreeUsage {
+    /// Fields accepted by --fields, with the types of their CSV columns
+    pub const FIELDS: &'static [Field] = &[
+        ("path", ColumnType::String),
+        ("files", ColumnType::Int),
+        ("logical_size", ColumnType::Int),
+        ("allocated_size", ColumnType::Int),
+        ("ntfs_compression_savings", ColumnType::Int),
+        ("wof_savings", ColumnType::Int),
+        ("savings_percent", ColumnType::Float),
+    ];
+}
+
+impl TableRow for SubtreeUsage {
+    fn table_header() -> Vec<&'static str> {
+        vec!["FILES", "LOGICAL", "ON DISK", "SAVED", "PATH"]
+    }
+
+    fn table_row(&self) -> Vec<String> {
+        vec![
+            self.files.to_string(),
+            output::format_size(self.logical_size),
+            output::format_size(self.allocated_size),
+            format!("{:.1}%", self.savings_percent),
+            self.path.clone(),
+        ]
+    }
+
+    fn streaming_widths() -> Vec<usize> {
+        vec![8, 12, 12, 6, 0]
+    }
+}
+
+/// Sizes of one file's data
+#[derive(Default)]
+struct FileUsage {
+    logical: u64,
+    allocated: u64,
+    ntfs_savings: u64,
+    wof_savings: u64,
+}
+
+/// Adds up logical, allocated and compressed sizes of the files matching
+/// `filter` per subtree: one row per directory `depth` levels below `root`
+/// (the drive when not given), holding everything beneath it. Files in
+/// shallower directories are counted in their own directory's row.
+pub fn report(
+    volume: &str,
+    filter: &FileFilter,
+    root: Option<&str>,
+    depth: usize,
+    output: OutputFormat,
+    fields: &Fields,
+) -> Result<()> {
+    let mft = mft::open_mft(volume)?;
+    let root = root.map(|root| root.trim_end_matches('\\').to_string());
+    let mut subtrees: BTreeMap<String, SubtreeUsage> = BTreeMap::new();
+
+    eprintln!("Adding up sizes...");
+    mft::iterate_files(&mft, |file| {
+        if file.is_directory() {
+            return;
+        }
+        let Some(info) = mft::parse_file_info(&mft, file) else {
+            return;
+        };
+        if !filter.matches(&info, file) {
+            return;
+        }
+        let path = info.path.to_string_lossy();
+        let Some(subtree) = subtree_of(&path, root.as_deref(), depth) else {
+            return;
+        };
+
+        let usage = file_usage(file);
+        let entry = subtrees
+            .entry(subtree.to_lowercase())
+            .or_insert_with(|| SubtreeUsage { path: subtree, ..Default::default() });
+        entry.files += 1;
+        entry.logical_size += usage.logical;
+        entry.allocated_size += usage.allocated;
+        entry.ntfs_compression_savings += usage.ntfs_savings;
+        entry.wof_savings += usage.wof_savings;
+    });
+
+    let mut rows: Vec<SubtreeUsage> = subtrees.into_values().collect();
+    for row in &mut rows {
+        let saved = row.ntfs_compression_savings + row.wof_savings;
+        let uncompressed = row.allocated_size + saved;
+        if uncompressed > 0 {
+            row.savings_percent = (saved as f64 * 1000.0 / uncompressed as f64).round() / 10.0;
+        }
+    }
+    eprintln!("{} subtrees", rows.len());
+
+    let stdout = io::stdout();
+    output::write_report(&mut stdout.lock(), &rows, output, fields, SubtreeUsage::FIELDS)
+}
+
+/// The directory `depth` levels below `root` that contains `path`, or `None`
+/// when the path isn't under `root`
+fn subtree_of(path: &str, root: Option<&str>, depth: usize) -> Option<String> {
+    let (root, rest) = match root {
+        Some(root) => {
+            let prefix = path.get(..root.len())?;
+            let rest = path[root.len()..].strip_prefix('\\')?;
+            if !prefix.eq_ignore_ascii_case(root) {
+                return None;
+            }
+            (prefix, rest)
+        }
+        None => path.split_once('\\')?,
+    };
+
+    let directories: Vec<&str> = rest.split('\\').collect();
+    let directories = &directories[..directories.len() - 1];
+    let mut subtree = root.to_string();
+    for directory in directories.iter().take(depth) {
+        subtree.push('\\');
+        subtree.push_str(directory);
+    }
+    if subtree == root && root.ends_with(':') {
+        subtree.push('\\');
+    }
+    Some(subtree)
+}
+
+/// Logical and allocated size of a file, the allocation after compression
+pub fn data_sizes(file: &NtfsFile) -> (u64, u64) {
+    let usage = file_usage(file);
+    (usage.logical, usage.allocated)
+}
+
+/// Sizes of a file's unnamed $DATA and, for WOF-compressed files, of the
+/// stream that actually holds their data. Resident data lives inside the
+/// MFT record and takes no clusters of its own.
+fn file_usage(file: &NtfsFile) -> FileUsage {
+    let mut usage = FileUsage::default();
+    let mut wof_allocated = None;
+
+    file.attributes(|attr| {
+        if attr.header.type_id != NtfsAttributeType::Data as u32 {
+            return;
+        }
+        let data = attr.data();
+        let (logical, allocated, on_disk) = if attr.header.is_non_resident == 0 {
+            let size = attr.resident_header().map(|h| h.value_length as u64).unwrap_or(0);
+            (size, 0, 0)
+        } else {
+            let field = |offset| raw::u64_at(data, offset).unwrap_or(0);
+            let allocated = field(0x28);
+            // Compressed and sparse attributes also record the clusters actually in use
+            let on_disk = match (data.get(0x22), raw::u64_at(data, 0x40)) {
+                (Some(&unit), Some(on_disk)) if unit != 0 => on_disk,
+                _ => allocated,
+            };
+            (field(0x30), allocated, on_disk)
+        };
+
+        if attr.header.name_length == 0 {
+            usage.logical += logical;
+            usage.allocated += on_disk;
+            if raw::u16_at(data, 0x0C).unwrap_or(0) & ATTR_FLAG_COMPRESSED != 0 {
+                usage.ntfs_savings += allocated.saturating_sub(on_disk);
+            }
+        } else if stream_name(data, attr.header.name_offset as usize, attr.header.name_length as usize)
+            == WOF_STREAM
+        {
+            wof_allocated = Some(on_disk);
+        }
+    });
+
+    if let Some(wof_allocated) = wof_allocated {
+        usage.allocated += wof_allocated;
+        usage.wof_savings = usage.logical.saturating_sub(wof_allocated);
+    }
+    usage
+}
+
+fn stream_name(attr: &[u8], offset: usize, length: usize) -> String {
+    let units: Vec<u16> = attr
+        .get(offset..offset + length * 2)
+        .unwrap_or_default()
+        .chunks_exact(2)
+        .map(|chunk| u16::from_le_bytes([chunk[0], chunk[1]]))
+        .collect();
+    String::from_utf16_lossy(&units)
+}
@@ -0,0 +1,424 @@
+use anyhow::{Context, Result};
+use ntfs_reader::volume::Volume;
+use serde::Serialize;
+use std::fs::File;
+use std::io;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::output::{self, ColumnType, Field, Fields, TableRow};
+use crate::raw::{self, RawVolume};
+use crate::shadow;
+use crate::{journal, mft, OutputFormat};
+
+static BACKUP_PRIVILEGE: AtomicBool = AtomicBool::new(false);
+
+/// Live state of a volume's USN journal, as reported by FSCTL_QUERY_USN_JOURNAL
+#[derive(Debug, Clone, Copy)]
+pub struct UsnJournalData {
+    pub journal_id: u64,
+    /// Oldest USN still in the journal; older records have been purged
+    pub first_usn: i64,
+    pub next_usn: i64,
+    pub maximum_size: u64,
+    /// Bytes purged from the start of the journal each time it exceeds its maximum size
+    pub allocation_delta: u64,
+}
+
+pub fn query_usn_journal(volume_path: &str) -> Result<UsnJournalData> {
+    imp::query_usn_journal(volume_path)
+}
+
+/// Device path of a volume given without a drive letter, as containers and
+/// mounted VHDs often are: `HarddiskVolume5`, `\Device\HarddiskVolume5`,
+/// `\\.\HarddiskVolume5`, `\\?\GLOBALROOT\Device\HarddiskVolume5` or a volume
+/// GUID path (`\\?\Volume{...}\`). A trailing backslash, as printed by
+/// mountvol, is dropped: with it the path names the root directory instead
+/// of the volume. Shadow copies (`HarddiskVolumeShadowCopy3`) are only
+/// reachable through `\\?\GLOBALROOT`.
+pub fn device_path(volume: &str) -> String {
+    let volume = volume.trim_end_matches('\\');
+    if let Some(path) = shadow::device_path(volume) {
+        return path;
+    }
+    let name = volume.strip_prefix("\\Device\\").unwrap_or(volume);
+    let prefix = "HarddiskVolume";
+    if name.len() > prefix.len()
+        && name.get(..prefix.len()).is_some_and(|p| p.eq_ignore_ascii_case(prefix))
+    {
+        return format!("\\\\.\\{}", name);
+    }
+    volume.to_string()
+}
+
+/// Opens a volume device. If that fails, SeBackupPrivilege is enabled (when
+/// the account holds it, e.g. Backup Operators) and the open is retried once.
+pub fn open(volume_path: &str) -> Result<Volume> {
+    eprintln!("Opening volume: {}", volume_path);
+    
+    match Volume::new(volume_path) {
+        Ok(volume) => Ok(volume),
+        Err(first) => {
+            if BACKUP_PRIVILEGE.load(Ordering::Relaxed) || enable_backup_privilege().is_err() {
+                return Err(first).context(
+                    "Failed to open volume. Make sure you're running as Administrator \
+                     (run `capabilities` to see which features are available).",
+                );
+            }
+            
+            eprintln!("Access failed, retrying with SeBackupPrivilege enabled...");
+            // Volume::new opens the device without FILE_FLAG_BACKUP_SEMANTICS,
+            // so the privilege wouldn't apply to it
+            open_with_backup_semantics(volume_path).context(
+                "Failed to open volume even with SeBackupPrivilege enabled. Make sure you're \
+                 running as Administrator (run `capabilities` to see which features are available).",
+            )
+        }
+    }
+}
+
+/// The volume as Volume::new would read it, with the boot sector read
+/// through `open_device`
+fn open_with_backup_semantics(volume_path: &str) -> Result<Volume> {
+    let raw = RawVolume::open(volume_path)?;
+    Ok(Volume {
+        path: PathBuf::from(volume_path),
+        volume_size: raw.total_clusters * raw.bytes_per_cluster,
+        file_record_size: raw.record_size,
+        cluster_size: raw.bytes_per_cluster,
+        mft_position: raw.mft_lcn * raw.bytes_per_cluster,
+    })
+}
+
+/// Opens a volume device for reading with FILE_FLAG_BACKUP_SEMANTICS, so
+/// SeBackupPrivilege applies to the open once it is enabled
+pub fn open_device(volume_path: &str) -> io::Result<File> {
+    imp::open_device(volume_path)
+}
+
+/// Enables SeBackupPrivilege for the current process, which grants read
+/// access regardless of ACLs to accounts that hold it
+pub fn enable_backup_privilege() -> Result<()> {
+    imp::enable_backup_privilege()?;
+    BACKUP_PRIVILEGE.store(true, Ordering::Relaxed);
+    Ok(())
+}
+
+#[derive(Debug, Serialize)]
+pub struct Capabilities {
+    pub volume: String,
+    pub backup_privilege: bool,
+    /// Raw MFT reads: list-files, file-info, snapshot, resolve
+    pub mft: bool,
+    /// USN journal reads: journal, monitor-baseline
+    pub journal: bool,
+    /// Journal state queries used for lag monitoring
+    pub journal_query: bool,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub errors: Vec<String>,
+}
+
+/// Tries every kind of volume access independently instead of stopping at
+/// the first failure, so partial access can be reported precisely
+pub fn probe(volume: &str) -> Capabilities {
+    let backup_privilege = enable_backup_privilege().is_ok();
+    let mut errors = Vec::new();
+    let mut check = |name: &str, result: Result<()>| match result {
+        Ok(()) => true,
+        Err(e) => {
+            errors.push(format!("{}: {:#}", name, e));
+            false
+        }
+    };
+    
+    // Reading the boot sector shows whether the MFT is readable without
+    // loading all of it, which takes a while on large volumes
+    let mft = check(
+        "mft",
+        RawVolume::open(&mft::normalize_volume_path(volume)).map(|_| ()),
+    );
+    let journal = check("journal", journal::probe(volume));
+    let journal_query = check(
+        "journal_query",
+        query_usn_journal(&journal::normalize_volume_path(volume)).map(|_| ()),
+    );
+    
+    Capabilities {
+        volume: volume.to_string(),
+        backup_privilege,
+        mft,
+        journal,
+        journal_query,
+        errors,
+    }
+}
+
+/// Record number of $Volume, which holds the NTFS version and volume flags
+const VOLUME_RECORD: u64 = 3;
+
+/// Attribute types of $Volume
+const ATTR_VOLUME_NAME: u32 = 0x60;
+const ATTR_VOLUME_INFORMATION: u32 = 0x70;
+
+/// $VOLUME_INFORMATION flag set while the volume needs chkdsk
+const VOLUME_DIRTY: u16 = 0x0001;
+
+/// Geometry and state of an NTFS volume, from its boot sector and $Volume
+#[derive(Debug, Serialize)]
+pub struct VolumeInfo {
+    pub volume: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    /// Serial number in the XXXX-XXXX form `dir` and `vol` show
+    pub serial_number: String,
+    /// NTFS version, e.g. 3.1
+    pub ntfs_version: String,
+    /// The volume is marked for chkdsk
+    pub dirty: bool,
+    pub bytes_per_sector: u64,
+    pub sectors_per_cluster: u64,
+    pub bytes_per_cluster: u64,
+    pub total_clusters: u64,
+    pub size: u64,
+    pub mft_lcn: u64,
+    pub mft_mirror_lcn: u64,
+    pub mft_record_size: u64,
+    pub index_record_size: u64,
+}
+
+impl VolumeInfo {
+    /// Fields accepted by --fields, with the types of their CSV columns
+    pub const FIELDS: &'static [Field] = &[
+        ("volume", ColumnType::String),
+        ("label", ColumnType::String),
+        ("serial_number", ColumnType::String),
+        ("ntfs_version", ColumnType::String),
+        ("dirty", ColumnType::Bool),
+        ("bytes_per_sector", ColumnType::Int),
+        ("sectors_per_cluster", ColumnType::Int),
+        ("bytes_per_cluster", ColumnType::Int),
+        ("total_clusters", ColumnType::Int),
+        ("size", ColumnType::Size),
+        ("mft_lcn", ColumnType::Int),
+        ("mft_mirror_lcn", ColumnType::Int),
+        ("mft_record_size", ColumnType::Int),
+        ("index_record_size", ColumnType::Int),
+    ];
+}
+
+impl TableRow for VolumeInfo {
+    fn table_header() -> Vec<&'static str> {
+        vec!["SERIAL", "NTFS", "DIRTY", "CLUSTER", "CLUSTERS", "SIZE", "MFT LCN", "RECORD", "LABEL"]
+    }
+
+    fn table_row(&self) -> Vec<String> {
+        vec![
+            self.serial_number.clone(),
+            self.ntfs_version.clone(),
+            if self.dirty { "yes" } else { "no" }.to_string(),
+            self.bytes_per_cluster.to_string(),
+            self.total_clusters.to_string(),
+            output::format_size(self.size),
+            self.mft_lcn.to_string(),
+            self.mft_record_size.to_string(),
+            self.label.clone().unwrap_or_default(),
+        ]
+    }
+
+    fn streaming_widths() -> Vec<usize> {
+        vec![9, 4, 5, 7, 12, 14, 10, 6, 0]
+    }
+}
+
+/// Reads the boot sector and the $Volume record straight from the volume
+pub fn info(volume: &str, output: OutputFormat, fields: &Fields) -> Result<()> {
+    let mut raw = RawVolume::open(&mft::normalize_volume_path(volume))?;
+    let runs = raw.mft_runs()?;
+    let record = raw
+        .read_file_record(&runs, VOLUME_RECORD)
+        .context("Failed to read the $Volume record")?;
+    let information = raw::find_attribute(&record, ATTR_VOLUME_INFORMATION)
+        .and_then(raw::resident_value)
+        .filter(|value| value.len() >= 0x0C)
+        .context("$Volume has no $VOLUME_INFORMATION attribute")?;
+    let label = raw::find_attribute(&record, ATTR_VOLUME_NAME)
+        .and_then(raw::resident_value)
+        .map(raw::utf16_lossy)
+        .filter(|label| !label.is_empty());
+
+    let info = VolumeInfo {
+        volume: volume.to_string(),
+        label,
+        serial_number: format!(
+            "{:04X}-{:04X}",
+            (raw.serial_number >> 16) & 0xFFFF,
+            raw.serial_number & 0xFFFF
+        ),
+        ntfs_version: format!("{}.{}", information[0x08], information[0x09]),
+        dirty: raw::u16_at(information, 0x0A).is_some_and(|flags| flags & VOLUME_DIRTY != 0),
+        bytes_per_sector: raw.bytes_per_sector,
+        sectors_per_cluster: raw.bytes_per_cluster / raw.bytes_per_sector,
+        bytes_per_cluster: raw.bytes_per_cluster,
+        total_clusters: raw.total_clusters,
+        size: raw.total_clusters * raw.bytes_per_cluster,
+        mft_lcn: raw.mft_lcn,
+        mft_mirror_lcn: raw.mft_mirror_lcn,
+        mft_record_size: raw.record_size,
+        index_record_size: raw.index_record_size,
+    };
+
+    let stdout = std::io::stdout();
+    output::write_report(&mut stdout.lock(), &[info], output, fields, VolumeInfo::FIELDS)
+}
+
+#[cfg(windows)]
+mod imp {
+    use super::UsnJournalData;
+    use anyhow::{bail, Result};
+    use std::ffi::OsStr;
+    use std::os::windows::ffi::OsStrExt;
+    use windows_sys::Win32::Foundation::{
+        CloseHandle, GetLastError, ERROR_NOT_ALL_ASSIGNED, GENERIC_READ, HANDLE,
+        INVALID_HANDLE_VALUE, LUID,
+    };
+    use windows_sys::Win32::Security::{
+        AdjustTokenPrivileges, LookupPrivilegeValueW, LUID_AND_ATTRIBUTES, SE_BACKUP_NAME,
+        SE_PRIVILEGE_ENABLED, TOKEN_ADJUST_PRIVILEGES, TOKEN_PRIVILEGES, TOKEN_QUERY,
+    };
+    use std::fs::{File, OpenOptions};
+    use std::os::windows::fs::OpenOptionsExt;
+    use windows_sys::Win32::Storage::FileSystem::{
+        CreateFileW, FILE_FLAG_BACKUP_SEMANTICS, FILE_SHARE_READ, FILE_SHARE_WRITE, OPEN_EXISTING,
+    };
+    use windows_sys::Win32::System::Ioctl::{FSCTL_QUERY_USN_JOURNAL, USN_JOURNAL_DATA_V0};
+    use windows_sys::Win32::System::IO::DeviceIoControl;
+    use windows_sys::Win32::System::Threading::{GetCurrentProcess, OpenProcessToken};
+
+    pub fn open_device(volume_path: &str) -> std::io::Result<File> {
+        OpenOptions::new()
+            .read(true)
+            .custom_flags(FILE_FLAG_BACKUP_SEMANTICS)
+            .open(volume_path)
+    }
+
+    pub fn enable_backup_privilege() -> Result<()> {
+        // SAFETY: all pointers refer to live locals and the token handle is
+        // closed before returning.
+        unsafe {
+            let mut token: HANDLE = std::ptr::null_mut();
+            if OpenProcessToken(
+                GetCurrentProcess(),
+                TOKEN_ADJUST_PRIVILEGES | TOKEN_QUERY,
+                &mut token,
+            ) == 0
+            {
+                bail!("Failed to open process token: {}", std::io::Error::last_os_error());
+            }
+
+            let mut luid = LUID::default();
+            if LookupPrivilegeValueW(std::ptr::null(), SE_BACKUP_NAME, &mut luid) == 0 {
+                let error = std::io::Error::last_os_error();
+                CloseHandle(token);
+                bail!("Failed to look up SeBackupPrivilege: {}", error);
+            }
+
+            let privileges = TOKEN_PRIVILEGES {
+                PrivilegeCount: 1,
+                Privileges: [LUID_AND_ATTRIBUTES {
+                    Luid: luid,
+                    Attributes: SE_PRIVILEGE_ENABLED,
+                }],
+            };
+            let ok = AdjustTokenPrivileges(
+                token,
+                0,
+                &privileges,
+                0,
+                std::ptr::null_mut(),
+                std::ptr::null_mut(),
+            );
+            // AdjustTokenPrivileges succeeds even when the account doesn't hold the privilege
+            let error = GetLastError();
+            CloseHandle(token);
+
+            if ok == 0 || error == ERROR_NOT_ALL_ASSIGNED {
+                bail!("SeBackupPrivilege is not held by this account");
+            }
+            Ok(())
+        }
+    }
+
+    pub fn query_usn_journal(volume_path: &str) -> Result<UsnJournalData> {
+        let wide: Vec<u16> = OsStr::new(volume_path)
+            .encode_wide()
+            .chain(std::iter::once(0))
+            .collect();
+
+        // SAFETY: `wide` is NUL-terminated and outlives the call; the handle is
+        // closed before returning on every path.
+        unsafe {
+            let handle = CreateFileW(
+                wide.as_ptr(),
+                GENERIC_READ,
+                FILE_SHARE_READ | FILE_SHARE_WRITE,
+                std::ptr::null(),
+                OPEN_EXISTING,
+                FILE_FLAG_BACKUP_SEMANTICS,
+                std::ptr::null_mut(),
+            );
+            if handle == INVALID_HANDLE_VALUE {
+                bail!(
+                    "Failed to open {}: {}",
+                    volume_path,
+                    std::io::Error::last_os_error()
+                );
+            }
+
+            let mut data = USN_JOURNAL_DATA_V0::default();
+            let mut returned = 0u32;
+            let ok = DeviceIoControl(
+                handle,
+                FSCTL_QUERY_USN_JOURNAL,
+                std::ptr::null(),
+                0,
+                &mut data as *mut _ as *mut _,
+                std::mem::size_of::<USN_JOURNAL_DATA_V0>() as u32,
+                &mut returned,
+                std::ptr::null_mut(),
+            );
+            let error = std::io::Error::last_os_error();
+            CloseHandle(handle);
+
+            if ok == 0 {
+                bail!("Failed to query USN journal on {}: {}", volume_path, error);
+            }
+
+            Ok(UsnJournalData {
+                journal_id: data.UsnJournalID,
+                first_usn: data.FirstUsn,
+                next_usn: data.NextUsn,
+                maximum_size: data.MaximumSize,
+                allocation_delta: data.AllocationDelta,
+            })
+        }
+    }
+}
+
+#[cfg(not(windows))]
+mod imp {
+    use super::UsnJournalData;
+    use anyhow::{bail, Result};
+    use std::fs::File;
+
+    pub fn open_device(volume_path: &str) -> std::io::Result<File> {
+        File::open(volume_path)
+    }
+
+    pub fn query_usn_journal(_volume_path: &str) -> Result<UsnJournalData> {
+        bail!("Querying the USN journal is only supported on Windows")
+    }
+
+    pub fn enable_backup_privilege() -> Result<()> {
+        bail!("Privileges are only supported on Windows")
+    }
+}